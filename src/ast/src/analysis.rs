@@ -0,0 +1,393 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Analyses on the control flow graph of a function: the dominator tree, dominance frontiers
+//! and the loop nest tree. They are computed from the branches in the IR (not from the edges of
+//! the control flow analysis pass), and only consider blocks reachable from the entry.
+//! MuFunctionVersion caches them, see `MuFunctionVersion::domtree()` and
+//! `MuFunctionVersion::loop_nest()`.
+
+use ir::*;
+use ptr::P;
+
+use utils::LinkedHashMap;
+use utils::LinkedHashSet;
+
+/// cached analyses of a function version
+#[derive(Default)]
+pub struct CFGAnalyses {
+    pub domtree: Option<P<DomTree>>,
+    pub loop_nest: Option<P<LoopNest>>
+}
+
+/// the dominator tree of the blocks reachable from the entry, with dominance frontiers
+#[derive(Debug)]
+pub struct DomTree {
+    entry: MuID,
+    /// reachable blocks in reverse postorder
+    rpo: Vec<MuID>,
+    /// immediate dominators (the entry does not have one)
+    idoms: LinkedHashMap<MuID, MuID>,
+    /// blocks immediately dominated by each block, in reverse postorder
+    children: LinkedHashMap<MuID, Vec<MuID>>,
+    /// reachable predecessors of each block
+    preds: LinkedHashMap<MuID, Vec<MuID>>,
+    frontiers: LinkedHashMap<MuID, LinkedHashSet<MuID>>
+}
+
+impl DomTree {
+    /// computes the dominator tree with the iterative algorithm by Cooper, Harvey and Kennedy
+    pub fn new(f_content: &FunctionContent) -> DomTree {
+        let rpo = reverse_postorder(f_content);
+        let index: LinkedHashMap<MuID, usize> =
+            rpo.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+
+        let mut preds: LinkedHashMap<MuID, Vec<MuID>> =
+            rpo.iter().map(|id| (*id, vec![])).collect();
+        for id in rpo.iter() {
+            for succ in f_content.get_successors(*id) {
+                preds.get_mut(&succ).unwrap().push(*id);
+            }
+        }
+
+        // immediate dominators, as indices into rpo
+        let mut idoms: Vec<Option<usize>> = vec![None; rpo.len()];
+        idoms[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 1..rpo.len() {
+                let mut new_idom = None;
+                for pred in preds.get(&rpo[i]).unwrap().iter() {
+                    let pred = *index.get(pred).unwrap();
+                    if idoms[pred].is_none() {
+                        continue;
+                    }
+                    new_idom = match new_idom {
+                        None => Some(pred),
+                        Some(cur) => Some(intersect(&idoms, pred, cur))
+                    };
+                }
+                if idoms[i] != new_idom {
+                    idoms[i] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut ret = DomTree {
+            entry: f_content.entry,
+            idoms: LinkedHashMap::new(),
+            children: rpo.iter().map(|id| (*id, vec![])).collect(),
+            preds: preds,
+            frontiers: rpo.iter().map(|id| (*id, LinkedHashSet::new())).collect(),
+            rpo: vec![]
+        };
+        for i in 1..rpo.len() {
+            let idom = rpo[idoms[i].unwrap()];
+            ret.idoms.insert(rpo[i], idom);
+            ret.children.get_mut(&idom).unwrap().push(rpo[i]);
+        }
+        ret.rpo = rpo;
+        ret.compute_frontiers();
+
+        ret
+    }
+
+    fn compute_frontiers(&mut self) {
+        for id in self.rpo.iter() {
+            let ref preds = *self.preds.get(id).unwrap();
+            if preds.len() < 2 {
+                continue;
+            }
+
+            let idom = self.idom(*id);
+            for pred in preds.iter() {
+                let mut runner = *pred;
+                while Some(runner) != idom {
+                    self.frontiers.get_mut(&runner).unwrap().insert(*id);
+                    match self.idoms.get(&runner) {
+                        Some(runner_idom) => runner = *runner_idom,
+                        None => break
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn entry(&self) -> MuID {
+        self.entry
+    }
+
+    /// returns the reachable blocks in reverse postorder (a block comes before the blocks it
+    /// dominates)
+    pub fn reverse_postorder(&self) -> &Vec<MuID> {
+        &self.rpo
+    }
+
+    pub fn is_reachable(&self, id: MuID) -> bool {
+        self.preds.contains_key(&id)
+    }
+
+    /// returns the immediate dominator of a block (None for the entry)
+    pub fn idom(&self, id: MuID) -> Option<MuID> {
+        self.idoms.get(&id).cloned()
+    }
+
+    /// returns the blocks that a reachable block immediately dominates
+    pub fn children(&self, id: MuID) -> &Vec<MuID> {
+        match self.children.get(&id) {
+            Some(children) => children,
+            None => panic!("block #{} is not reachable", id)
+        }
+    }
+
+    /// returns the reachable predecessors of a reachable block
+    pub fn preds(&self, id: MuID) -> &Vec<MuID> {
+        match self.preds.get(&id) {
+            Some(preds) => preds,
+            None => panic!("block #{} is not reachable", id)
+        }
+    }
+
+    /// returns the dominance frontier of a reachable block
+    pub fn frontier(&self, id: MuID) -> &LinkedHashSet<MuID> {
+        match self.frontiers.get(&id) {
+            Some(frontier) => frontier,
+            None => panic!("block #{} is not reachable", id)
+        }
+    }
+
+    /// does block a dominate block b? (a block dominates itself)
+    pub fn dominates(&self, a: MuID, b: MuID) -> bool {
+        if !self.is_reachable(b) {
+            return false;
+        }
+
+        let mut cur = b;
+        loop {
+            if cur == a {
+                return true;
+            }
+            match self.idoms.get(&cur) {
+                Some(idom) => cur = *idom,
+                None => return false
+            }
+        }
+    }
+
+    pub fn strictly_dominates(&self, a: MuID, b: MuID) -> bool {
+        a != b && self.dominates(a, b)
+    }
+}
+
+/// returns the blocks reachable from the entry in reverse postorder
+fn reverse_postorder(f_content: &FunctionContent) -> Vec<MuID> {
+    let mut ret = vec![];
+    let mut visited = LinkedHashSet::new();
+    let mut stack = vec![(f_content.entry, f_content.get_successors(f_content.entry), 0)];
+    visited.insert(f_content.entry);
+
+    while !stack.is_empty() {
+        let next = {
+            let top = stack.last_mut().unwrap();
+            if top.2 < top.1.len() {
+                top.2 += 1;
+                Some(top.1[top.2 - 1])
+            } else {
+                None
+            }
+        };
+        match next {
+            Some(succ) => {
+                if !visited.contains(&succ) {
+                    visited.insert(succ);
+                    stack.push((succ, f_content.get_successors(succ), 0));
+                }
+            }
+            None => ret.push(stack.pop().unwrap().0)
+        }
+    }
+
+    ret.reverse();
+    ret
+}
+
+fn intersect(idoms: &Vec<Option<usize>>, a: usize, b: usize) -> usize {
+    let mut a = a;
+    let mut b = b;
+    while a != b {
+        while a > b {
+            a = idoms[a].unwrap();
+        }
+        while b > a {
+            b = idoms[b].unwrap();
+        }
+    }
+    a
+}
+
+/// a natural loop. Back edges to the same header are merged into one loop, so two loops are
+/// either nested or disjoint
+#[derive(Debug)]
+pub struct Loop {
+    pub header: MuID,
+    /// all blocks in the loop, including the header and the blocks of inner loops
+    pub blocks: LinkedHashSet<MuID>,
+    /// blocks in the loop that branch back to the header
+    pub latches: Vec<MuID>,
+    /// edges leaving the loop, as (block in the loop, block outside the loop)
+    pub exits: Vec<(MuID, MuID)>,
+    /// the only block outside the loop that branches to the header, if it does not branch
+    /// anywhere else. Passes that need a preheader have to create one if this is None
+    pub preheader: Option<MuID>,
+    /// the innermost loop that contains this loop (an index into LoopNest::loops())
+    pub parent: Option<usize>,
+    /// loops immediately nested in this loop
+    pub children: Vec<usize>,
+    /// 1 for outermost loops
+    pub depth: usize
+}
+
+impl Loop {
+    pub fn contains(&self, id: MuID) -> bool {
+        self.blocks.contains(&id)
+    }
+}
+
+/// the loop nest tree of a function
+#[derive(Debug)]
+pub struct LoopNest {
+    /// all loops. An outer loop comes before the loops nested in it
+    loops: Vec<Loop>,
+    /// the innermost loop of each block in a loop
+    innermost: LinkedHashMap<MuID, usize>
+}
+
+impl LoopNest {
+    pub fn new(f_content: &FunctionContent, domtree: &DomTree) -> LoopNest {
+        let mut loops: Vec<Loop> = vec![];
+
+        // headers in reverse postorder, so that outer loops come first
+        for header in domtree.reverse_postorder().iter() {
+            let header = *header;
+            let latches: Vec<MuID> = domtree
+                .preds(header)
+                .iter()
+                .filter(|pred| domtree.dominates(header, **pred))
+                .cloned()
+                .collect();
+            if latches.is_empty() {
+                continue;
+            }
+
+            // the loop body is the blocks that reach a latch without going through the header
+            let mut blocks = LinkedHashSet::new();
+            blocks.insert(header);
+            let mut work_list = latches.clone();
+            while let Some(id) = work_list.pop() {
+                if blocks.contains(&id) {
+                    continue;
+                }
+                blocks.insert(id);
+                work_list.extend(domtree.preds(id).iter().cloned());
+            }
+
+            let mut exits = vec![];
+            for id in blocks.iter() {
+                for succ in f_content.get_successors(*id) {
+                    if !blocks.contains(&succ) {
+                        exits.push((*id, succ));
+                    }
+                }
+            }
+
+            let outside_preds: Vec<MuID> = domtree
+                .preds(header)
+                .iter()
+                .filter(|pred| !blocks.contains(*pred))
+                .cloned()
+                .collect();
+            let preheader = if outside_preds.len() == 1 &&
+                f_content.get_successors(outside_preds[0]) == vec![header]
+            {
+                Some(outside_preds[0])
+            } else {
+                None
+            };
+
+            // the innermost enclosing loop is the last one found that contains the header
+            let parent = loops.iter().rposition(|l| l.contains(header));
+            let depth = match parent {
+                Some(parent) => loops[parent].depth + 1,
+                None => 1
+            };
+
+            let index = loops.len();
+            if let Some(parent) = parent {
+                loops[parent].children.push(index);
+            }
+            loops.push(Loop {
+                header: header,
+                blocks: blocks,
+                latches: latches,
+                exits: exits,
+                preheader: preheader,
+                parent: parent,
+                children: vec![],
+                depth: depth
+            });
+        }
+
+        let mut innermost = LinkedHashMap::new();
+        for (i, l) in loops.iter().enumerate() {
+            for id in l.blocks.iter() {
+                innermost.insert(*id, i);
+            }
+        }
+
+        LoopNest {
+            loops: loops,
+            innermost: innermost
+        }
+    }
+
+    pub fn loops(&self) -> &Vec<Loop> {
+        &self.loops
+    }
+
+    pub fn get_loop(&self, index: usize) -> &Loop {
+        &self.loops[index]
+    }
+
+    /// returns the loops that are not nested in another loop
+    pub fn outermost_loops(&self) -> Vec<usize> {
+        (0..self.loops.len())
+            .filter(|i| self.loops[*i].parent.is_none())
+            .collect()
+    }
+
+    /// returns the innermost loop that contains a block
+    pub fn innermost_loop(&self, id: MuID) -> Option<usize> {
+        self.innermost.get(&id).cloned()
+    }
+
+    /// returns how many loops contain a block (0 if it is not in a loop)
+    pub fn loop_depth(&self, id: MuID) -> usize {
+        match self.innermost_loop(id) {
+            Some(index) => self.loops[index].depth,
+            None => 0
+        }
+    }
+}
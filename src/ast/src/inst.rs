@@ -333,6 +333,103 @@ impl Instruction {
         }
     }
 
+    /// returns the destinations (blocks with arguments) that this instruction may branch to,
+    /// returns an empty vector if this instruction does not branch
+    pub fn get_destinations(&self) -> Vec<&Destination> {
+        use inst::Instruction_::*;
+        match self.v {
+            Branch1(ref dest) => vec![dest],
+            Branch2 {
+                ref true_dest,
+                ref false_dest,
+                ..
+            } => vec![true_dest, false_dest],
+            Watchpoint {
+                ref disable_dest,
+                ref resume,
+                ..
+            } => {
+                let mut ret = vec![];
+                if let &Some(ref dest) = disable_dest {
+                    ret.push(dest);
+                }
+                ret.push(&resume.normal_dest);
+                ret.push(&resume.exn_dest);
+                ret
+            }
+            WPBranch {
+                ref disable_dest,
+                ref enable_dest,
+                ..
+            } => vec![disable_dest, enable_dest],
+            Call { ref resume, .. } |
+            CCall { ref resume, .. } |
+            SwapStackExc { ref resume, .. } |
+            ExnInstruction { ref resume, .. } => vec![&resume.normal_dest, &resume.exn_dest],
+            Switch {
+                ref default,
+                ref branches,
+                ..
+            } => {
+                let mut ret = vec![default];
+                for &(_, ref dest) in branches.iter() {
+                    ret.push(dest);
+                }
+                ret
+            }
+            _ => vec![]
+        }
+    }
+
+    /// returns the destinations of a terminator for rewriting (see get_destinations())
+    pub fn get_destinations_mut(&mut self) -> Vec<&mut Destination> {
+        use inst::Instruction_::*;
+        match self.v {
+            Branch1(ref mut dest) => vec![dest],
+            Branch2 {
+                ref mut true_dest,
+                ref mut false_dest,
+                ..
+            } => vec![true_dest, false_dest],
+            Watchpoint {
+                ref mut disable_dest,
+                ref mut resume,
+                ..
+            } => {
+                let mut ret = vec![];
+                if let &mut Some(ref mut dest) = disable_dest {
+                    ret.push(dest);
+                }
+                ret.push(&mut resume.normal_dest);
+                ret.push(&mut resume.exn_dest);
+                ret
+            }
+            WPBranch {
+                ref mut disable_dest,
+                ref mut enable_dest,
+                ..
+            } => vec![disable_dest, enable_dest],
+            Call { ref mut resume, .. } |
+            CCall { ref mut resume, .. } |
+            SwapStackExc { ref mut resume, .. } |
+            ExnInstruction { ref mut resume, .. } => {
+                vec![&mut resume.normal_dest, &mut resume.exn_dest]
+            }
+            Switch {
+                ref mut default,
+                ref mut branches,
+                ..
+            } => {
+                let mut ret = vec![default];
+                for &mut (_, ref mut dest) in branches.iter_mut() {
+                    ret.push(dest);
+                }
+                ret
+            }
+            _ => vec![]
+        }
+    }
+
     /// can this instruction throw exception?
     /// (whether or not it containjs a ctach for it)
     pub fn is_potentially_throwing(&self) -> bool {
@@ -15,6 +15,7 @@
 use ptr::P;
 use types::*;
 use inst::*;
+use analysis::*;
 
 use utils::vec_utils;
 use utils::LinkedHashMap;
@@ -147,7 +148,8 @@ pub struct MuFunctionVersion {
     is_compiled: bool,
     pub context: FunctionContext,
     pub force_inline: bool,
-    pub block_trace: Option<Vec<MuID>> // only available after Trace Generation Pass
+    pub block_trace: Option<Vec<MuID>>, // only available after Trace Generation Pass
+    analyses: CFGAnalyses // cached analyses of content, dropped when the CFG changes
 }
 rodal_struct!(Callsite {
     name,
@@ -210,7 +212,8 @@ impl MuFunctionVersion {
             is_compiled: false,
             context: FunctionContext::new(),
             block_trace: None,
-            force_inline: false
+            force_inline: false,
+            analyses: CFGAnalyses::default()
         }
     }
 
@@ -232,7 +235,8 @@ impl MuFunctionVersion {
             is_compiled: false,
             context: context,
             block_trace: None,
-            force_inline: false
+            force_inline: false,
+            analyses: CFGAnalyses::default()
         }
     }
 
@@ -251,6 +255,34 @@ impl MuFunctionVersion {
         self.content = Some(content);
     }
 
+    /// returns the dominator tree of the current IR. It is computed when first asked for, and
+    /// cached until the CFG changes
+    pub fn domtree(&mut self) -> P<DomTree> {
+        if self.analyses.domtree.is_none() {
+            let domtree = DomTree::new(self.content.as_ref().unwrap());
+            self.analyses.domtree = Some(P(domtree));
+        }
+        self.analyses.domtree.as_ref().unwrap().clone()
+    }
+
+    /// returns the loop nest tree of the current IR. It is computed when first asked for, and
+    /// cached until the CFG changes
+    pub fn loop_nest(&mut self) -> P<LoopNest> {
+        if self.analyses.loop_nest.is_none() {
+            let domtree = self.domtree();
+            let loop_nest = LoopNest::new(self.content.as_ref().unwrap(), &domtree);
+            self.analyses.loop_nest = Some(P(loop_nest));
+        }
+        self.analyses.loop_nest.as_ref().unwrap().clone()
+    }
+
+    /// drops the cached CFG analyses. Passes that add or remove blocks, or change the branches
+    /// between them call this (the compiler calls it after every pass that does not declare
+    /// that it preserves the CFG)
+    pub fn invalidate_cfg_analyses(&mut self) {
+        self.analyses = CFGAnalyses::default();
+    }
+
     pub fn is_compiled(&self) -> bool {
         self.is_compiled
     }
@@ -353,6 +385,33 @@ impl MuFunctionVersion {
         ret
     }
 
+    /// gets all the functions that this function version refers to
+    /// (by funcref constants, either as a callee or as a value)
+    pub fn get_referenced_funcs(&self) -> LinkedHashSet<MuID> {
+        fn visit_node(node: &TreeNode, ret: &mut LinkedHashSet<MuID>) {
+            match node.v {
+                TreeNode_::Instruction(ref inst) => {
+                    for op in inst.ops.iter() {
+                        visit_node(op, ret);
+                    }
+                }
+                TreeNode_::Value(ref pv) => {
+                    if let Value_::Constant(Constant::FuncRef(ref func)) = pv.v {
+                        ret.insert(func.id());
+                    }
+                }
+            }
+        }
+
+        let mut ret = LinkedHashSet::new();
+        for (_, block) in self.content.as_ref().unwrap().blocks.iter() {
+            for inst in block.content.as_ref().unwrap().body.iter() {
+                visit_node(inst, &mut ret);
+            }
+        }
+        ret
+    }
+
     // TODO: It may be more efficient to compute this when the instructions
     // are added to the function version and store the result in a field
     pub fn could_throw(&self) -> bool {
@@ -463,6 +522,21 @@ impl FunctionContent {
         }
     }
 
+    /// returns the blocks that the given block branches to (normally or exceptionally),
+    /// without duplicates
+    pub fn get_successors(&self, id: MuID) -> Vec<MuID> {
+        let mut ret = vec![];
+        for node in self.get_block(id).content.as_ref().unwrap().body.iter() {
+            for dest in node.as_inst().get_destinations() {
+                let target = dest.target.id();
+                if !ret.contains(&target) {
+                    ret.push(target);
+                }
+            }
+        }
+        ret
+    }
+
     pub fn get_block_by_name(&self, name: MuName) -> &Block {
         for block in self.blocks.values() {
             if block.name() == name {
@@ -532,6 +606,9 @@ pub struct Block {
     pub content: Option<BlockContent>,
     /// a trace scheduling hint about where to layout this block
     pub trace_hint: TraceHint,
+    /// how many times this block ran in the --profile-use profile (None if it is unknown).
+    /// Passes that copy or create blocks estimate the counts of the new blocks
+    pub exec_count: Option<u64>,
     /// control flow info about this block (predecessors, successors, etc)
     pub control_flow: ControlFlow
 }
@@ -562,6 +639,7 @@ impl Block {
             hdr: entity,
             content: None,
             trace_hint: TraceHint::None,
+            exec_count: None,
             control_flow: ControlFlow::default()
         }
     }
@@ -1235,6 +1313,10 @@ impl SSAVarEntry {
     pub fn increase_use_count(&self) {
         self.use_count.fetch_add(1, Ordering::SeqCst);
     }
+    pub fn decrease_use_count(&self) {
+        let old = self.use_count.fetch_sub(1, Ordering::SeqCst);
+        debug_assert!(old > 0, "use count of {} is already 0", self.val);
+    }
     pub fn reset_use_count(&self) {
         self.use_count.store(0, Ordering::SeqCst);
     }
@@ -1344,8 +1426,8 @@ impl fmt::Display for Constant {
 }
 
 /// MemoryLocation represents a memory value
-/// This enumerate type is target dependent
-#[cfg(target_arch = "x86_64")]
+/// This enumerate type is target dependent: Address is used by x86_64, VirtualAddress and
+/// ShiftedAddress are used by aarch64, Symbolic is used by both (base is x86_64 only)
 #[derive(Debug, Clone, PartialEq)]
 pub enum MemoryLocation {
     /// addr = base + offset + index * scale
@@ -1361,14 +1443,38 @@ pub enum MemoryLocation {
         label: MuName,
         is_global: bool,
         is_native: bool
+    },
+    /// Represents how an address should be computed,
+    /// will need to be converted to a real ShiftedAddress before being used
+    VirtualAddress {
+        /// Represents base + offset*scale
+        /// With offset being interpreted as signed if 'signed' is true
+        base: P<Value>, //+8
+        offset: Option<P<Value>>, //+16
+        signed: bool,             //+1
+        scale: u64                //+24
+    },
+    /// addr = base + offset << shift
+    ShiftedAddress {
+        /// Must be a normal 64-bit register or SP
+        base: P<Value>,
+        /// Can be any GPR or a 12-bit unsigned immediate << n
+        offset: Option<P<Value>>,
+        /// valid values are 0, log2(n)
+        shift: u8,
+        /// Whether offset is signed or not (only set this if offset is a register)
+        /// Note: n is the number of bytes the adress refers two
+        signed: bool
     }
 }
 
-#[cfg(target_arch = "x86_64")]
-rodal_enum!(MemoryLocation{{Address: scale, base, offset, index},
-    {Symbolic: is_global, is_native, base, label}});
+rodal_enum!(MemoryLocation{
+    {Address: scale, base, offset, index},
+    {Symbolic: is_global, is_native, base, label},
+    {VirtualAddress: signed, base, offset, scale},
+    {ShiftedAddress: base, offset, shift, signed}}
+);
 
-#[cfg(target_arch = "x86_64")]
 impl fmt::Display for MemoryLocation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -1401,54 +1507,6 @@ impl fmt::Display for MemoryLocation {
                     write!(f, "{}", label)
                 }
             }
-        }
-    }
-}
-
-/// MemoryLocation represents a memory value
-/// This enumerate type is target dependent
-#[cfg(target_arch = "aarch64")]
-#[derive(Debug, Clone, PartialEq)]
-pub enum MemoryLocation {
-    /// Represents how an address should be computed,
-    /// will need to be converted to a real Address before being used
-    VirtualAddress {
-        /// Represents base + offset*scale
-        /// With offset being interpreted as signed if 'signed' is true
-        base: P<Value>, //+8
-        offset: Option<P<Value>>, //+16
-        signed: bool,             //+1
-        scale: u64                //+24
-    },
-    Address {
-        /// Must be a normal 64-bit register or SP
-        base: P<Value>,
-        /// Can be any GPR or a 12-bit unsigned immediate << n
-        offset: Option<P<Value>>,
-        /// valid values are 0, log2(n)
-        shift: u8,
-        /// Whether offset is signed or not (only set this if offset is a register)
-        /// Note: n is the number of bytes the adress refers two
-        signed: bool
-    },
-    Symbolic {
-        label: MuName,
-        is_global: bool,
-        is_native: bool
-    }
-}
-
-#[cfg(target_arch = "aarch64")]
-rodal_enum!(MemoryLocation{
-    {VirtualAddress: signed, base, offset, scale},
-    {Address: base, offset, shift, signed},
-    {Symbolic: is_global, is_native, label}}
-);
-
-#[cfg(target_arch = "aarch64")]
-impl fmt::Display for MemoryLocation {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
             &MemoryLocation::VirtualAddress {
                 ref base,
                 ref offset,
@@ -1465,7 +1523,7 @@ impl fmt::Display for MemoryLocation {
                 write!(f, " * {}", scale).unwrap();
                 write!(f, "]")
             }
-            &MemoryLocation::Address {
+            &MemoryLocation::ShiftedAddress {
                 ref base,
                 ref offset,
                 shift,
@@ -1483,7 +1541,6 @@ impl fmt::Display for MemoryLocation {
                 }
                 write!(f, "]")
             }
-            &MemoryLocation::Symbolic { ref label, .. } => write!(f, "{}", label)
         }
     }
 }
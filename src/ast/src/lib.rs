@@ -28,6 +28,7 @@
 //!             * Instruction
 //! * inst
 //! * op (operators)
+//! * analysis (dominator tree and loops of a function)
 //!
 //! Client should not create MuIR via this crate, use API instead.
 
@@ -74,3 +75,4 @@ pub mod inst;
 pub mod types;
 pub mod ptr;
 pub mod op;
+pub mod analysis;
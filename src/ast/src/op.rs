@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use std::fmt;
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum BinOp {
     // BinOp Int(n) Int(n) -> Int(n)
     Add,
@@ -53,7 +53,7 @@ impl BinOp {
         }
     }
 }
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum CmpOp {
     // for Int comparison
     EQ,
@@ -220,7 +220,7 @@ impl CmpOp {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ConvOp {
     TRUNC,
     ZEXT,
@@ -60,6 +60,12 @@ unsafe impl Send for ASMCode {}
 unsafe impl Sync for ASMCode {}
 
 impl ASMCode {
+    /// is the specified index a .cfi_offset directive?
+    fn is_cfi_offset(&self, index: usize) -> bool {
+        index < self.code.len() && self.code[index].is_symbol &&
+            self.code[index].code.starts_with(".cfi_offset")
+    }
+
     fn get_use_locations(&self, reg: MuID) -> Vec<ASMLocation> {
         let mut ret = vec![];
 
@@ -768,6 +774,11 @@ impl MachineCode for ASMCode {
                     if !used_callee_saved.contains(&reg) {
                         inst_to_remove.push(i);
                         regs_to_remove.insert(reg);
+
+                        // the save is followed by its .cfi_offset, which has to go as well
+                        if self.is_cfi_offset(i + 1) {
+                            inst_to_remove.push(i + 1);
+                        }
                     }
                 }
                 _ => {}
@@ -1187,7 +1198,7 @@ impl ASMCodeGen {
         let mut loc_cursor: usize = loc;
         match op.v {
             // offset(base,index,scale)
-            Value_::Memory(MemoryLocation::Address {
+            Value_::Memory(MemoryLocation::ShiftedAddress {
                 ref base,
                 ref offset,
                 shift,
@@ -1264,7 +1275,8 @@ impl ASMCodeGen {
             Value_::Memory(MemoryLocation::Symbolic {
                 ref label,
                 is_global,
-                is_native
+                is_native,
+                ..
             }) => {
                 let label = if is_native {
                     "/*C*/".to_string() + label.as_str()
@@ -2345,6 +2357,11 @@ impl CodeGenerator for ASMCodeGen {
     fn add_cfi_sections(&mut self, arg: &str) {
         self.add_asm_symbolic(format!(".cfi_sections {}", arg));
     }
+    fn add_patchable_entry(&mut self) {
+        // NOP and B can be replaced by each other while other threads execute them
+        self.add_asm_symbolic("NOP".to_string());
+    }
+
     fn add_cfi_startproc(&mut self) {
         self.add_asm_symbolic(".cfi_startproc".to_string());
     }
@@ -2367,6 +2384,28 @@ impl CodeGenerator for ASMCodeGen {
         let reg = self.asm_reg_op(reg);
         self.add_asm_symbolic(format!(".cfi_offset {}, {}", reg, offset));
     }
+    fn add_cfi_remember_state(&mut self) {
+        self.add_asm_symbolic(".cfi_remember_state".to_string());
+    }
+    fn add_cfi_restore_state(&mut self) {
+        self.add_asm_symbolic(".cfi_restore_state".to_string());
+    }
+    fn add_cfi_same_value(&mut self, reg: Reg) {
+        let reg = self.asm_reg_op(reg);
+        self.add_asm_symbolic(format!(".cfi_same_value {}", reg));
+    }
+    fn add_cfi_undefined(&mut self, reg: Reg) {
+        let reg = self.asm_reg_op(reg);
+        self.add_asm_symbolic(format!(".cfi_undefined {}", reg));
+    }
+
+    fn add_debug_file(&mut self, file: &str) {
+        self.add_asm_symbolic(format!(".file 1 \"{}\"", file));
+    }
+
+    fn add_debug_loc(&mut self, line: usize) {
+        self.add_asm_symbolic(format!(".loc 1 {} 0", line));
+    }
 
     fn emit_frame_grow(&mut self) {
         trace_emit!("\tFRAME GROW");
@@ -3443,24 +3482,26 @@ impl CodeGenerator for ASMCodeGen {
 }
 
 use compiler::backend::code_emission::create_emit_directory;
+use compiler::backend::elf;
+use compiler::backend::dwarf;
 use std::fs::File;
 
 pub fn emit_code(fv: &mut MuFunctionVersion, vm: &VM) {
     use std::io::prelude::*;
     use std::path;
 
-    let funcs = vm.funcs().read().unwrap();
-    let func = funcs.get(&fv.func_id).unwrap().read().unwrap();
-
     let compiled_funcs = vm.compiled_funcs().read().unwrap();
     let cf = compiled_funcs.get(&fv.id()).unwrap().read().unwrap();
 
+    // the symbol of the code (the function name, unless this version redefines a function)
+    let code_symbol = cf.start.to_relocatable();
+
     // create 'emit' directory
     create_emit_directory(vm);
 
     let mut file_path = path::PathBuf::new();
     file_path.push(&vm.vm_options.flag_aot_emit_dir);
-    file_path.push(func.name().to_string() + ".S");
+    file_path.push(code_symbol.to_string() + ".S");
     {
         let mut file = match File::create(file_path.as_path()) {
             Err(why) => {
@@ -3475,18 +3516,31 @@ pub fn emit_code(fv: &mut MuFunctionVersion, vm: &VM) {
 
         writeln!(file, ".arch armv8-a").unwrap();
 
-        // constants in text section
-        writeln!(file, ".text").unwrap();
+        // constants in text section (position-independent code keeps them in a section that
+        // becomes read-only after relocation, as constants may hold addresses)
+        if vm.vm_options.flag_aot_pic {
+            writeln!(file, ".section .data.rel.ro,\"aw\"").unwrap();
+        } else {
+            writeln!(file, ".text").unwrap();
+        }
 
-        write_const_min_align(&mut file);
+        if vm.vm_options.flag_aot_emit_elf {
+            // constants go to their own object file
+            elf::emit_constants(vm, &code_symbol, &cf);
+        } else {
+            write_const_min_align(&mut file);
 
-        for (id, constant) in cf.consts.iter() {
-            let mem = cf.const_mem.get(id).unwrap();
+            for (id, constant) in cf.sorted_consts() {
+                let mem = cf.const_mem.get(&id).unwrap();
 
-            write_const(&mut file, constant.clone(), mem.clone());
+                write_const(&mut file, constant.clone(), mem.clone());
+            }
         }
 
         // write code
+        if vm.vm_options.flag_aot_pic {
+            writeln!(file, ".text").unwrap();
+        }
         let code = cf.mc.as_ref().unwrap().emit();
         match file.write_all(code.as_slice()) {
             Err(why) => {
@@ -3498,13 +3552,20 @@ pub fn emit_code(fv: &mut MuFunctionVersion, vm: &VM) {
             }
             Ok(_) => info!("emit code to {}", file_path.to_str().unwrap())
         }
+
+        // write debug info
+        if vm.vm_options.flag_emit_debug_info {
+            let start = mangle_name(code_symbol.clone());
+            let end = mangle_name(cf.end.to_relocatable());
+            dwarf::emit_debug_info(&mut file, vm, fv, &cf, &start, &end);
+        }
     }
 
     // Read the file we just wrote above an demangle it
     {
         let mut demangled_path = path::PathBuf::new();
         demangled_path.push(&vm.vm_options.flag_aot_emit_dir);
-        demangled_path.push((*func.name()).clone() + ".demangled.S");
+        demangled_path.push((*code_symbol).clone() + ".demangled.S");
 
         let mut demangled_file = match File::create(demangled_path.as_path()) {
             Err(why) => {
@@ -3634,11 +3695,13 @@ fn write_const_value(f: &mut File, constant: P<Value>) {
 
 
 use std::collections::HashMap;
+use runtime::thread::{MuStack, StackImageWord, PRIMORDIAL_STACK_SYMBOL};
 
 pub fn emit_context_with_reloc(
     vm: &VM,
     symbols: HashMap<Address, MuName>,
     fields: HashMap<Address, MuName>,
+    primordial_stack: Option<Address>,
     primordial_threadlocal: Option<Address>
 ) {
     use std::path;
@@ -3672,21 +3735,34 @@ pub fn emit_context_with_reloc(
         let global_locs_lock = vm.global_locations().read().unwrap();
         let global_lock = vm.globals().read().unwrap();
 
+        // in the order of the IDs, so the heap dump is deterministic
         let global_addr_id_map = {
+            let mut ids: Vec<MuID> = global_locs_lock.keys().cloned().collect();
+            ids.sort();
+
             let mut map: LinkedHashMap<Address, MuID> = LinkedHashMap::new();
 
-            for (id, global_loc) in global_locs_lock.iter() {
-                map.insert(global_loc.to_address(), *id);
+            for id in ids {
+                map.insert(global_locs_lock.get(&id).unwrap().to_address(), id);
             }
 
             map
         };
 
         // dump heap from globals
-        let global_addrs: Vec<Address> =
-            global_locs_lock.values().map(|x| x.to_address()).collect();
+        let mut global_addrs: Vec<Address> = global_addr_id_map.keys().cloned().collect();
         debug!("going to dump these globals: {:?}", global_addrs);
 
+        // the primordial stack may refer to objects that are not reachable from globals
+        let primordial_stack: Option<&MuStack> =
+            primordial_stack.map(|a| unsafe { a.to_ptr::<MuStack>().as_ref().unwrap() });
+        let stack_refs = match primordial_stack {
+            Some(stack) => stack.heap_refs(vm),
+            None => vec![]
+        };
+        debug!("going to dump these objects from the primordial stack: {:?}", stack_refs);
+        global_addrs.extend(stack_refs.iter().cloned());
+
         // heap dump
         let mut global_dump = mm::persist_heap(global_addrs);
         debug!("Heap Dump from GC: {:?}", global_dump);
@@ -3694,103 +3770,134 @@ pub fn emit_context_with_reloc(
         let ref mut relocatable_refs = global_dump.relocatable_refs;
 
         // merge symbols with relocatable_refs
+        let mut client_labels = HashSet::new();
         for (addr, str) in symbols {
-            relocatable_refs.insert(addr, mangle_name(str));
-        }
-
-        // for all the reachable object, we write them to the boot image
-        for obj_dump in objects.values() {
-            // write object metadata
-            write_align(&mut file, 8);
-            write_obj_header(&mut file, &obj_dump.encode);
-
-            // write alignment for the object
-            write_align(&mut file, obj_dump.align);
-
-            // if this object is a global cell, we add labels so it can be accessed
-            if global_addr_id_map.contains_key(&obj_dump.addr) {
-                let global_id = global_addr_id_map.get(&obj_dump.addr).unwrap();
-                let global_value = global_lock.get(global_id).unwrap();
-
-                // .globl global_cell_name
-                // global_cell_name:
-                let demangled_name = global_value.name().clone();
-                let global_cell_name = mangle_name(demangled_name.clone());
-                writeln!(file, "\t{}", directive_globl(global_cell_name.clone())).unwrap();
-                writeln!(file, "{}:", global_cell_name.clone()).unwrap();
-
-                // .equiv global_cell_name_if_its_valid_c_ident
-                if is_valid_c_identifier(&demangled_name) {
-                    let demangled_name = (*demangled_name).clone();
-                    writeln!(file, "\t{}", directive_globl(demangled_name.clone())).unwrap();
-                    writeln!(
-                        file,
-                        "\t{}",
-                        directive_equiv(demangled_name, global_cell_name.clone())
-                    ).unwrap();
+            let label = mangle_name(str);
+            client_labels.insert(label.clone());
+            relocatable_refs.insert(addr, label);
+        }
+
+        if vm.vm_options.flag_aot_emit_elf {
+            // write the heap into an object file, the context file only keeps the VM
+            let global_names: HashMap<Address, MuName> = global_addr_id_map
+                .iter()
+                .map(|(addr, id)| (*addr, global_lock.get(id).unwrap().name()))
+                .collect();
+            // the stack image in the context file refers to these objects
+            for obj in stack_refs.iter() {
+                if let Some(label) = relocatable_refs.get(obj) {
+                    client_labels.insert(label.clone());
                 }
             }
-
-            // put dump_label for this object (so it can be referred to from other dumped objects)
-            let dump_label = relocatable_refs.get(&obj_dump.addr).unwrap().clone();
-            file.write_fmt(format_args!("{}:\n", dump_label)).unwrap();
-
-            // get ready to go through from the object start (not mem_start) to the end
-            let base = obj_dump.addr;
-            let end = obj_dump.addr + obj_dump.size;
-            assert!(base.is_aligned_to(POINTER_SIZE));
-
-            // offset as cursor
-            let mut offset = 0;
-            while offset < obj_dump.size {
-                let cur_addr = base + offset;
-
-                if obj_dump.reference_offsets.contains(&offset) {
-                    // if this offset is a reference field, we put a relocatable label
-                    // generated by the GC instead of address value
-
-                    let load_ref = unsafe { cur_addr.load::<Address>() };
-                    if load_ref.is_zero() {
-                        // null reference, write 0
-                        file.write("\t.xword 0\n".as_bytes()).unwrap();
-                    } else {
-                        // get the relocatable label
-                        let label = match relocatable_refs.get(&load_ref) {
-                            Some(label) => label,
-                            None => {
-                                panic!(
-                                    "cannot find label for address {}, it is not dumped by GC \
-                                     (why GC didn't trace to it?)",
-                                    load_ref
-                                )
-                            }
-                        };
-                        file.write_fmt(format_args!("\t.xword {}\n", label.clone()))
-                            .unwrap();
+            elf::emit_persisted_heap(
+                vm,
+                objects,
+                relocatable_refs,
+                &fields,
+                &global_names,
+                &client_labels
+            );
+        } else {
+            // for all the reachable object, we write them to the boot image
+            for obj_dump in objects.values() {
+                // write object metadata
+                write_align(&mut file, 8);
+                write_obj_header(&mut file, &obj_dump.encode);
+
+                // write alignment for the object
+                write_align(&mut file, obj_dump.align);
+
+                // if this object is a global cell, we add labels so it can be accessed
+                if global_addr_id_map.contains_key(&obj_dump.addr) {
+                    let global_id = global_addr_id_map.get(&obj_dump.addr).unwrap();
+                    let global_value = global_lock.get(global_id).unwrap();
+
+                    // .globl global_cell_name
+                    // global_cell_name:
+                    let demangled_name = global_value.name().clone();
+                    let global_cell_name = mangle_name(demangled_name.clone());
+                    writeln!(file, "\t{}", directive_globl(global_cell_name.clone())).unwrap();
+                    writeln!(file, "{}:", global_cell_name.clone()).unwrap();
+
+                    // .equiv global_cell_name_if_its_valid_c_ident
+                    if is_valid_c_identifier(&demangled_name) {
+                        let demangled_name = (*demangled_name).clone();
+                        writeln!(file, "\t{}", directive_globl(demangled_name.clone())).unwrap();
+                        writeln!(
+                            file,
+                            "\t{}",
+                            directive_equiv(demangled_name, global_cell_name.clone())
+                        ).unwrap();
                     }
-                } else if fields.contains_key(&cur_addr) {
-                    // if this offset is a field named by the client to relocatable,
-                    // we put the relocatable label given by the client
+                }
 
-                    let label = fields.get(&cur_addr).unwrap();
+                // put dump_label for this object
+                // (so it can be referred to from other dumped objects)
+                let dump_label = relocatable_refs.get(&obj_dump.addr).unwrap().clone();
+                file.write_fmt(format_args!("{}:\n", dump_label)).unwrap();
+
+                // get ready to go through from the object start (not mem_start) to the end
+                let base = obj_dump.addr;
+                let end = obj_dump.addr + obj_dump.size;
+                assert!(base.is_aligned_to(POINTER_SIZE));
+
+                // offset as cursor
+                let mut offset = 0;
+                while offset < obj_dump.size {
+                    let cur_addr = base + offset;
+
+                    if obj_dump.reference_offsets.contains(&offset) {
+                        // if this offset is a reference field, we put a relocatable label
+                        // generated by the GC instead of address value
+
+                        let load_ref = unsafe { cur_addr.load::<Address>() };
+                        if load_ref.is_zero() {
+                            // null reference, write 0
+                            file.write("\t.xword 0\n".as_bytes()).unwrap();
+                        } else {
+                            // get the relocatable label
+                            let label = match relocatable_refs.get(&load_ref) {
+                                Some(label) => label,
+                                None => {
+                                    panic!(
+                                        "cannot find label for address {}, it is not dumped by GC \
+                                         (why GC didn't trace to it?)",
+                                        load_ref
+                                    )
+                                }
+                            };
+                            file.write_fmt(format_args!("\t.xword {}\n", label.clone()))
+                                .unwrap();
+                        }
+                    } else if fields.contains_key(&cur_addr) {
+                        // if this offset is a field named by the client to relocatable,
+                        // we put the relocatable label given by the client
 
-                    file.write_fmt(format_args!("\t.xword {}\n", mangle_name(label.clone())))
-                        .unwrap();
-                } else {
-                    // otherwise this offset is plain data
+                        let label = fields.get(&cur_addr).unwrap();
 
-                    // write plain word (as bytes)
-                    let next_word_addr = cur_addr + POINTER_SIZE;
-                    if next_word_addr <= end {
-                        write_data_bytes(&mut file, cur_addr, next_word_addr);
+                        file.write_fmt(format_args!("\t.xword {}\n", mangle_name(label.clone())))
+                            .unwrap();
                     } else {
-                        write_data_bytes(&mut file, cur_addr, end);
+                        // otherwise this offset is plain data
+
+                        // write plain word (as bytes)
+                        let next_word_addr = cur_addr + POINTER_SIZE;
+                        if next_word_addr <= end {
+                            write_data_bytes(&mut file, cur_addr, next_word_addr);
+                        } else {
+                            write_data_bytes(&mut file, cur_addr, end);
+                        }
                     }
-                }
 
-                offset += POINTER_SIZE;
+                    offset += POINTER_SIZE;
+                }
             }
         }
+
+        if let Some(stack) = primordial_stack {
+            write_stack_image(&mut file, &stack.to_image(vm, relocatable_refs));
+        }
+
         primordial_threadlocal.map(|a| relocatable_refs.get(&a).unwrap().clone())
     };
     {
@@ -3800,6 +3907,8 @@ pub fn emit_context_with_reloc(
 
     // serialize vm
     trace!("start serializing vm");
+    // so that the dumped context only depends on the bundle
+    vm.order_persisted_tables();
     use rodal;
     let mut dumper = rodal::AsmDumper::new(file);
 
@@ -3827,6 +3936,38 @@ pub fn emit_context_with_reloc(
     debug!("---finish---");
 }
 
+/// writes the image of the primordial stack (see MuStack::from_image())
+fn write_stack_image(f: &mut File, image: &Vec<StackImageWord>) {
+    let label = mangle_name(Arc::new(PRIMORDIAL_STACK_SYMBOL.to_string()));
+    write_align(f, 8);
+    writeln!(f, "\t{}", directive_globl(label.clone())).unwrap();
+    writeln!(f, "{}:", label).unwrap();
+
+    let stack_addrs: Vec<usize> = image
+        .iter()
+        .enumerate()
+        .filter_map(|(i, word)| match word {
+            &StackImageWord::StackAddress(_) => Some(i),
+            _ => None
+        })
+        .collect();
+
+    writeln!(f, "\t.xword {}", image.len()).unwrap();
+    writeln!(f, "\t.xword {}", stack_addrs.len()).unwrap();
+    for word in image.iter() {
+        match word {
+            &StackImageWord::Value(val) => writeln!(f, "\t.xword {}", val),
+            &StackImageWord::StackAddress(dist) => writeln!(f, "\t.xword {}", dist),
+            &StackImageWord::Symbol(ref label, offset) => {
+                writeln!(f, "\t.xword {}+{}", label, offset)
+            }
+        }.unwrap();
+    }
+    for i in stack_addrs {
+        writeln!(f, "\t.xword {}", i).unwrap();
+    }
+}
+
 fn write_obj_header(f: &mut File, obj: &ObjectEncode) {
     // header is 8 bytes aligned, and takes 24 bytes
     write_align(f, 8);
@@ -3837,7 +3978,7 @@ fn write_obj_header(f: &mut File, obj: &ObjectEncode) {
 }
 
 pub fn emit_context(vm: &VM) {
-    emit_context_with_reloc(vm, hashmap!{}, hashmap!{}, None);
+    emit_context_with_reloc(vm, hashmap!{}, hashmap!{}, None, None);
 }
 
 fn write_data_bytes(f: &mut File, from: Address, to: Address) {
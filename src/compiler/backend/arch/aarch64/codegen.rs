@@ -35,6 +35,10 @@ pub trait CodeGenerator {
     fn start_exception_block(&mut self, block_name: MuName) -> ValueLocation;
     fn end_block(&mut self, block_name: MuName);
 
+    // add the instruction at the function entry that is patched when the function is
+    // redefined (see patch_code_entry())
+    fn add_patchable_entry(&mut self);
+
     // add CFI info
     fn add_cfi_sections(&mut self, arg: &str);
     fn add_cfi_startproc(&mut self);
@@ -43,6 +47,14 @@ pub trait CodeGenerator {
     fn add_cfi_def_cfa_register(&mut self, reg: Reg);
     fn add_cfi_def_cfa_offset(&mut self, offset: i32);
     fn add_cfi_offset(&mut self, reg: Reg, offset: i32);
+    fn add_cfi_remember_state(&mut self);
+    fn add_cfi_restore_state(&mut self);
+    fn add_cfi_same_value(&mut self, reg: Reg);
+    fn add_cfi_undefined(&mut self, reg: Reg);
+
+    // add debug line info
+    fn add_debug_file(&mut self, file: &str);
+    fn add_debug_loc(&mut self, line: usize);
 
     //===========================================================================================
 
@@ -37,6 +37,7 @@ use compiler::PROLOGUE_BLOCK_NAME;
 
 use compiler::backend::aarch64::*;
 use compiler::backend::make_block_name;
+use compiler::backend::dwarf;
 use compiler::machine_code::CompiledFunction;
 use compiler::frame::Frame;
 
@@ -71,7 +72,9 @@ pub struct InstructionSelection {
     current_stack_arg_size: usize,
     current_xr_value: Option<P<Value>>, // A temporary that holds to saved XR value (if needed)
     current_constants: HashMap<MuID, P<Value>>,
-    current_constants_locs: HashMap<MuID, P<Value>>
+    current_constants_locs: HashMap<MuID, P<Value>>,
+    // key: block/instruction id, val: line in debug source (with --emit-debug-info)
+    current_debug_lines: HashMap<MuID, usize>
 }
 
 // TODO: Move all functions that are in here that don't need access to 'self'
@@ -102,7 +105,8 @@ impl<'a> InstructionSelection {
             current_stack_arg_size: 0,
             current_xr_value: None,
             current_constants: HashMap::new(),
-            current_constants_locs: HashMap::new()
+            current_constants_locs: HashMap::new(),
+            current_debug_lines: HashMap::new()
         }
 
     }
@@ -547,6 +551,7 @@ impl<'a> InstructionSelection {
 
                         self.emit_epilogue(f_context, vm);
                         self.backend.emit_ret(&LR);
+                        self.backend.add_cfi_restore_state();
                     }
 
                     Instruction_::BinOp(op, op1, op2) => {
@@ -1383,6 +1388,68 @@ impl<'a> InstructionSelection {
                         // wheras the Mu spec says it should be 1
                         self.backend.emit_eor_imm(&res_success, &res_success, 1);
                     }
+
+                    Instruction_::AtomicRMW {
+                        order,
+                        op,
+                        mem_loc,
+                        value,
+                        ..
+                    } => {
+                        trace!("instsel on ATOMICRMW");
+
+                        let use_acquire = match order {
+                            MemoryOrder::Acquire |
+                            MemoryOrder::AcqRel |
+                            MemoryOrder::SeqCst => true,
+                            MemoryOrder::Relaxed | MemoryOrder::Release => false,
+                            _ => panic!("didnt expect order {:?} for atomicrmw", order)
+                        };
+                        let use_release = match order {
+                            MemoryOrder::Release |
+                            MemoryOrder::AcqRel |
+                            MemoryOrder::SeqCst => true,
+                            MemoryOrder::Relaxed | MemoryOrder::Acquire => false,
+                            _ => panic!("didnt expect order {:?} for atomicrmw", order)
+                        };
+
+                        let ref ops = inst.ops;
+                        let loc =
+                            self.emit_node_addr_to_value(&ops[mem_loc], f_content, f_context, vm);
+                        let val = self.emit_reg(&ops[value], f_content, f_context, vm);
+                        let res_value = self.get_result_value(node, 0);
+                        if op != AtomicRMWOp::ADD || !is_int_reg(&res_value) {
+                            unimplemented!();
+                        }
+                        let new_value = make_temporary(f_context, res_value.ty.clone(), vm);
+                        let status = make_temporary(f_context, UINT32_TYPE.clone(), vm);
+
+                        let blk_atomicrmw_start = make_block_name(&node.name(), "atomicrmw_start");
+
+                        self.finish_block();
+
+                        // atomicrmw_start:
+                        //     res_value = LDXR [loc]
+                        //     new_value = res_value + val
+                        //     status = STXR [loc] new_value
+                        //     CBNZ status atomicrmw_start (the store failed, try again)
+                        self.start_block(blk_atomicrmw_start.clone());
+                        if use_acquire {
+                            self.backend.emit_ldaxr(&res_value, &loc);
+                        } else {
+                            self.backend.emit_ldxr(&res_value, &loc);
+                        }
+                        self.backend.emit_add(&new_value, &res_value, &val);
+                        if use_release {
+                            self.backend.emit_stlxr(&loc, &status, &new_value);
+                        } else {
+                            self.backend.emit_stxr(&loc, &status, &new_value);
+                        }
+                        self.backend.emit_cbnz(&status, blk_atomicrmw_start.clone());
+                        self.finish_block();
+
+                        self.start_block(make_block_name(&node.name(), "atomicrmw_end"));
+                    }
                     Instruction_::GetIRef(_) |
                     Instruction_::GetFieldIRef { .. } |
                     Instruction_::GetElementIRef { .. } |
@@ -4535,6 +4602,17 @@ impl<'a> InstructionSelection {
         // Swap to the new stack
         self.backend.emit_mov(&SP, &new_sp);
 
+        // Until we leave this function, we run on the new stack. Its top (the saved FP and
+        // LR) describes the frame we are going to, and the callee saved registers of this
+        // frame are not there
+        self.backend.add_cfi_remember_state();
+        self.backend.add_cfi_def_cfa(&SP, 16i32);
+        self.backend.add_cfi_offset(&FP, -16i32);
+        self.backend.add_cfi_offset(&LR, -8i32);
+        for reg in CALLEE_SAVED_GPRS.iter().chain(CALLEE_SAVED_FPRS.iter()) {
+            self.backend.add_cfi_undefined(reg);
+        }
+
         if is_exception {
             // Pass the stack pointer as an extra argument
             arg_values.push(SP.clone());
@@ -4598,9 +4676,15 @@ impl<'a> InstructionSelection {
                 &SP,
                 (WORD_SIZE * CALLEE_SAVED_COUNT) as u64
             );
+            self.backend
+                .add_cfi_def_cfa_offset((16 + WORD_SIZE * CALLEE_SAVED_COUNT) as i32);
         } else {
             // Restore the FP and LR from the old stack
             self.backend.emit_pop_pair(&FP, &LR, &SP);
+            // (LR holds the resumption address)
+            self.backend.add_cfi_def_cfa_offset(0i32);
+            self.backend.add_cfi_same_value(&FP);
+            self.backend.add_cfi_same_value(&LR);
         }
 
         let potentially_excepting = Self::get_potentially_excepting(resumption, f_content);
@@ -4633,6 +4717,8 @@ impl<'a> InstructionSelection {
                 }
             }
         };
+        // Back on the stack of this frame
+        self.backend.add_cfi_restore_state();
 
         if !is_kill {
             self.record_callsite(resumption, callsite.unwrap(), res_stack_size);
@@ -4762,6 +4848,7 @@ impl<'a> InstructionSelection {
                 self.backend
                     .emit_br_call(None, &target, None, arg_regs, vec![], false);
             }
+            self.backend.add_cfi_restore_state();
         } else {
             // Emit a branch with link (i.e. a call)
             let callsite = {
@@ -4884,14 +4971,13 @@ impl<'a> InstructionSelection {
 
         // Push the frame pointer and link register onto the stack
         self.backend.emit_push_pair(&LR, &FP, &SP);
+        self.backend.add_cfi_def_cfa_offset(16i32);
+        self.backend.add_cfi_offset(&FP, -16i32);
+        self.backend.add_cfi_offset(&LR, -8i32);
 
         // Set the frame pointer to be the stack pointer
         self.backend.emit_mov(&FP, &SP);
-        if vm.vm_options.flag_emit_debug_info {
-            self.backend.add_cfi_def_cfa(&FP, 16i32);
-            self.backend.add_cfi_offset(&FP, -16i32);
-            self.backend.add_cfi_offset(&LR, -8i32);
-        }
+        self.backend.add_cfi_def_cfa_register(&FP);
 
         // reserve spaces for current frame
         self.backend.emit_frame_grow(); // will include space for callee saved registers
@@ -4925,6 +5011,7 @@ impl<'a> InstructionSelection {
                 vm
             );
             self.backend.emit_str_callee_saved(&loc, &reg);
+            self.emit_cfi_callee_saved(reg);
         }
         for i in 0..CALLEE_SAVED_FPRS.len() {
             let ref reg = CALLEE_SAVED_FPRS[i];
@@ -4942,6 +5029,7 @@ impl<'a> InstructionSelection {
                 vm
             );
             self.backend.emit_str_callee_saved(&loc, &reg);
+            self.emit_cfi_callee_saved(reg);
         }
 
         let (_, locations, stack_arg_size) =
@@ -5031,7 +5119,23 @@ impl<'a> InstructionSelection {
             }
         }
     }
+
+    /// describes where a callee saved register was stored (CFA is FP + 16)
+    fn emit_cfi_callee_saved(&mut self, reg: &P<Value>) {
+        let offset = self.current_frame
+            .as_ref()
+            .unwrap()
+            .allocated
+            .get(&reg.id())
+            .unwrap()
+            .offset - 16;
+        self.backend.add_cfi_offset(reg, offset as i32);
+    }
+
     fn emit_epilogue(&mut self, f_context: &mut FunctionContext, vm: &VM) {
+        // the unwind rules for the function body still apply after the epilogue
+        self.backend.add_cfi_remember_state();
+
         // pop all callee-saved registers
         for i in (0..CALLEE_SAVED_FPRS.len()).rev() {
             let ref reg = CALLEE_SAVED_FPRS[i];
@@ -5076,6 +5180,7 @@ impl<'a> InstructionSelection {
         // Pop the frame record
         self.backend.emit_mov(&SP, &FP);
         self.backend.emit_pop_pair(&FP, &LR, &SP);
+        self.backend.add_cfi_def_cfa(&SP, 0i32);
 
         // Note: the stack pointer should now be what it was when the function was called
     }
@@ -5501,7 +5606,7 @@ impl<'a> InstructionSelection {
                     Value_::SSAVar(_) => P(Value {
                         hdr: MuEntityHeader::unnamed(vm.next_id()),
                         ty: pv.ty.clone(),
-                        v: Value_::Memory(MemoryLocation::Address {
+                        v: Value_::Memory(MemoryLocation::ShiftedAddress {
                             base: pv.clone(),
                             offset: None,
                             shift: 0,
@@ -6013,13 +6118,18 @@ impl CompilerPass for InstructionSelection {
         self.current_fv_name = func_ver.name();
         self.current_frame = Some(Frame::new(func_ver.id()));
         self.current_func_start = Some({
-            let funcs = vm.funcs().read().unwrap();
-            let func = funcs.get(&func_ver.func_id).unwrap().read().unwrap();
-            let start_loc = self.backend.start_code(func.name(), entry_block.name());
+            let func_symbol = vm.get_code_symbol_for_func_ver(func_ver.func_id, func_ver.id());
+            let start_loc = self.backend.start_code(func_symbol.clone(), entry_block.name());
             if vm.vm_options.flag_emit_debug_info {
-                self.backend.add_cfi_sections(".eh_frame, .debug_frame");
-                self.backend.add_cfi_startproc();
+                // line info maps code to a listing of the IR
+                let (source, lines) = dwarf::write_debug_source(vm, &func_symbol, func_ver);
+                self.backend.add_debug_file(&source.to_string_lossy());
+                self.backend.add_debug_loc(1);
+                self.current_debug_lines = lines;
             }
+            self.backend.add_cfi_sections(".eh_frame, .debug_frame");
+            self.backend.add_cfi_startproc();
+            self.backend.add_patchable_entry();
 
             start_loc
         });
@@ -6063,6 +6173,10 @@ impl CompilerPass for InstructionSelection {
                 self.backend.start_block(block_label.clone());
             }
 
+            if let Some(line) = self.current_debug_lines.get(&block.id()) {
+                self.backend.add_debug_loc(*line);
+            }
+
             if block.is_receiving_exception_arg() {
                 // this block uses exception arguments
 
@@ -6074,6 +6188,9 @@ impl CompilerPass for InstructionSelection {
 
             // doing the actual instruction selection
             for inst in block_content.body.iter() {
+                if let Some(line) = self.current_debug_lines.get(&inst.id()) {
+                    self.backend.add_debug_loc(*line);
+                }
                 self.instruction_select(&inst, f_content, &mut func.context, vm);
             }
 
@@ -6087,16 +6204,10 @@ impl CompilerPass for InstructionSelection {
     fn finish_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
         self.backend.print_cur_code();
 
-        let func_name = {
-            let funcs = vm.funcs().read().unwrap();
-            let func = funcs.get(&func.func_id).unwrap().read().unwrap();
-            func.name()
-        };
+        let func_name = vm.get_code_symbol_for_func_ver(func.func_id, func.id());
 
         // have to do this before 'finish_code()'
-        if vm.vm_options.flag_emit_debug_info {
-            self.backend.add_cfi_endproc();
-        }
+        self.backend.add_cfi_endproc();
         let (mc, func_end) = self.backend.finish_code(func_name.clone());
 
         // insert exception branch info
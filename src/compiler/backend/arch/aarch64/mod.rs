@@ -279,6 +279,29 @@ pub fn get_color_for_precolored(id: MuID) -> MuID {
     }
 }
 
+/// returns the DWARF register number for a machine register
+/// (as in the DWARF for the ARM 64-bit architecture), or None if it does not have one
+pub fn get_dwarf_reg_number(id: MuID) -> Option<u16> {
+    if id >= MACHINE_ID_END {
+        return None;
+    }
+
+    let color = get_color_for_precolored(id);
+    if color < FPR_ID_START {
+        if color <= X30.id() {
+            // x0 - x30 are 0 - 30 (each 64-bit register takes two IDs with its alias)
+            Some(((color - X0.id()) / 2) as u16)
+        } else if color == SP.id() {
+            Some(31)
+        } else {
+            None
+        }
+    } else {
+        // v0 - v31 are 64 - 95
+        Some((64 + (color - D0.id()) / 2) as u16)
+    }
+}
+
 #[inline(always)]
 pub fn check_op_len(ty: &P<MuType>) -> usize {
     match ty.get_int_length() {
@@ -865,6 +888,28 @@ pub fn set_return_address(frame_pointer: Address, value: Address) {
     unsafe { (frame_pointer + 8 as ByteSize).store::<Address>(value) }
 }
 
+// Patches the entry of a compiled function so that it jumps to another entry
+// Every function starts with a NOP (see add_patchable_entry()), which we replace with an
+// unconditional branch in a single aligned 4-byte store. The architecture allows a NOP and a B
+// to be replaced by each other while other threads execute them (they see either instruction),
+// and the NOP is never a return address, so frames running the old code are not affected.
+pub fn patch_code_entry(old_entry: Address, new_entry: Address) {
+    use compiler::backend::patch_code_word;
+
+    let offset = new_entry.as_usize() as i64 - old_entry.as_usize() as i64;
+    // B has a signed 26 bits immediate (in words), i.e. +/-128MB
+    if offset < -(1i64 << 27) || offset >= (1i64 << 27) {
+        panic!(
+            "cannot patch {} to jump to {}: the distance does not fit in a B instruction",
+            old_entry,
+            new_entry
+        )
+    }
+
+    let inst: u32 = 0x14000000 | (((offset >> 2) as u32) & 0x03ffffff);
+    patch_code_word(old_entry, inst);
+}
+
 // Reg should be a 64-bit callee saved GPR or FPR
 pub fn get_callee_saved_offset(reg: MuID) -> isize {
     debug_assert!(is_callee_saved(reg));
@@ -2216,7 +2261,7 @@ pub fn emit_mem(
                     P(Value {
                         hdr: MuEntityHeader::unnamed(vm.next_id()),
                         ty: pv.ty.clone(),
-                        v: Value_::Memory(MemoryLocation::Address {
+                        v: Value_::Memory(MemoryLocation::ShiftedAddress {
                             base: base.clone(),
                             offset: offset,
                             shift: shift,
@@ -2232,7 +2277,7 @@ pub fn emit_mem(
                         P(Value {
                             hdr: MuEntityHeader::unnamed(vm.next_id()),
                             ty: pv.ty.clone(),
-                            v: Value_::Memory(MemoryLocation::Address {
+                            v: Value_::Memory(MemoryLocation::ShiftedAddress {
                                 base: temp,
                                 offset: None,
                                 shift: 0,
@@ -2308,7 +2353,7 @@ fn emit_mem_base(
                             base.clone() // trivial
                         }
                 }
-                &MemoryLocation::Address{ref base, ref offset, shift, signed} => {
+                &MemoryLocation::ShiftedAddress{ref base, ref offset, shift, signed} => {
                     if offset.is_some() {
                         let ref offset = offset.as_ref().unwrap();
 
@@ -2340,12 +2385,13 @@ fn emit_mem_base(
                     emit_addr_sym(backend, &temp, &pv, vm);
                     temp
                 },
+                &MemoryLocation::Address{..} => panic!("unexpected x86_64 memory location")
             };
 
             P(Value {
                 hdr: MuEntityHeader::unnamed(vm.next_id()),
                 ty: pv.ty.clone(),
-                v: Value_::Memory(MemoryLocation::Address {
+                v: Value_::Memory(MemoryLocation::ShiftedAddress {
                     base: base.clone(),
                     offset: None,
                     shift: 0,
@@ -2370,7 +2416,8 @@ pub fn emit_addr_sym(backend: &mut CodeGenerator, dest: &P<Value>, src: &P<Value
                 &MemoryLocation::Symbolic {
                     ref label,
                     is_global,
-                    is_native
+                    is_native,
+                    ..
                 } => {
                     if is_global {
                         // Set dest to be the page address of the entry for src in the GOT
@@ -2394,7 +2441,7 @@ pub fn emit_addr_sym(backend: &mut CodeGenerator, dest: &P<Value>, src: &P<Value
                             hdr: MuEntityHeader::unnamed(vm.next_id()),
                             ty: ADDRESS_TYPE.clone(),
                             // should be ptr<src.ty>
-                            v: Value_::Memory(MemoryLocation::Address {
+                            v: Value_::Memory(MemoryLocation::ShiftedAddress {
                                 base: dest.clone(),
                                 offset: Some(offset),
                                 shift: 0,
@@ -2442,7 +2489,7 @@ fn emit_calculate_address(backend: &mut CodeGenerator, dest: &P<Value>, src: &P<
             }
         }
         // offset(base,index,scale)
-        Value_::Memory(MemoryLocation::Address {
+        Value_::Memory(MemoryLocation::ShiftedAddress {
             ref base,
             ref offset,
             shift,
@@ -2713,6 +2760,7 @@ fn make_value_symbolic(label: MuName, global: bool, ty: &P<MuType>, vm: &VM) ->
         hdr: MuEntityHeader::unnamed(vm.next_id()),
         ty: ty.clone(),
         v: Value_::Memory(MemoryLocation::Symbolic {
+            base: None,
             label: label,
             is_global: global,
             is_native: false
@@ -149,6 +149,12 @@ enum SpillMemInfo {
 }
 
 impl ASMCode {
+    /// is the specified index a .cfi_offset directive?
+    fn is_cfi_offset(&self, index: usize) -> bool {
+        index < self.code.len() && self.code[index].is_symbol &&
+            self.code[index].code.starts_with(".cfi_offset")
+    }
+
     /// returns a vector of ASMLocation for all the uses of the given reg/temp
     fn get_use_locations(&self, reg: MuID) -> Vec<ASMLocation> {
         let mut ret = vec![];
@@ -901,6 +907,11 @@ impl MachineCode for ASMCode {
                         );
                         regs_to_remove.insert(reg);
                         inst_to_remove.push(i);
+
+                        // the save is followed by its .cfi_offset, which has to go as well
+                        if self.is_cfi_offset(i + 1) {
+                            inst_to_remove.push(i + 1);
+                        }
                     }
                 }
                 _ => {}
@@ -2391,6 +2402,9 @@ impl CodeGenerator for ASMCodeGen {
             frame_size_patchpoints: vec![]
         }));
 
+        // the patchable entry needs to be within an aligned 8-byte word
+        self.add_asm_symbolic(".p2align 3".to_string());
+
         // to link with C sources via gcc
         let func_symbol = symbol(&mangle_name(func_name.clone()));
         self.add_asm_global_label(func_symbol.clone());
@@ -2479,6 +2493,12 @@ impl CodeGenerator for ASMCodeGen {
         }
     }
 
+    fn add_patchable_entry(&mut self) {
+        // 5-byte nop (nopl 0x0(%rax,%rax,1)), we spell out the bytes so the assembler
+        // does not pick another encoding
+        self.add_asm_symbolic(".byte 0x0f, 0x1f, 0x44, 0x00, 0x00".to_string());
+    }
+
     fn add_cfi_startproc(&mut self) {
         self.add_asm_symbolic(".cfi_startproc".to_string());
     }
@@ -2486,6 +2506,10 @@ impl CodeGenerator for ASMCodeGen {
         self.add_asm_symbolic(".cfi_endproc".to_string());
     }
 
+    fn add_cfi_def_cfa(&mut self, reg: Reg, offset: i32) {
+        let reg = self.asm_reg_op(reg);
+        self.add_asm_symbolic(format!(".cfi_def_cfa {}, {}", reg, offset));
+    }
     fn add_cfi_def_cfa_register(&mut self, reg: Reg) {
         let reg = self.asm_reg_op(reg);
         self.add_asm_symbolic(format!(".cfi_def_cfa_register {}", reg));
@@ -2497,6 +2521,35 @@ impl CodeGenerator for ASMCodeGen {
         let reg = self.asm_reg_op(reg);
         self.add_asm_symbolic(format!(".cfi_offset {}, {}", reg, offset));
     }
+    fn add_cfi_remember_state(&mut self) {
+        self.add_asm_symbolic(".cfi_remember_state".to_string());
+    }
+    fn add_cfi_restore_state(&mut self) {
+        self.add_asm_symbolic(".cfi_restore_state".to_string());
+    }
+    fn add_cfi_same_value(&mut self, reg: Reg) {
+        let reg = self.asm_reg_op(reg);
+        self.add_asm_symbolic(format!(".cfi_same_value {}", reg));
+    }
+    fn add_cfi_undefined(&mut self, reg: Reg) {
+        let reg = self.asm_reg_op(reg);
+        self.add_asm_symbolic(format!(".cfi_undefined {}", reg));
+    }
+    fn add_cfi_register(&mut self, reg: Reg, in_reg: Reg) {
+        let reg = self.asm_reg_op(reg);
+        let in_reg = self.asm_reg_op(in_reg);
+        self.add_asm_symbolic(format!(".cfi_register {}, {}", reg, in_reg));
+    }
+
+    /// emits .file for the debug source of current function
+    fn add_debug_file(&mut self, file: &str) {
+        self.add_asm_symbolic(format!(".file 1 \"{}\"", file));
+    }
+
+    /// emits .loc for the following instructions
+    fn add_debug_loc(&mut self, line: usize) {
+        self.add_asm_symbolic(format!(".loc 1 {} 0", line));
+    }
 
     /// emits code to grow frame size (size is unknown at this point, use a placeholder)
     fn emit_frame_grow(&mut self) {
@@ -3470,6 +3523,35 @@ impl CodeGenerator for ASMCodeGen {
         self.add_asm_inst(asm, linked_hashmap!{}, linked_hashmap!{}, false);
     }
 
+    fn emit_lock_xadd_mem_r(&mut self, dest: &P<Value>, src: &P<Value>) {
+        let len = check_op_len(src);
+
+        let inst = "lock xadd".to_string() + &op_postfix(len);
+        trace!("emit: {} {}, {} -> {}, {}", inst, src, dest, src, dest);
+
+        let (reg, id1, loc1) = self.prepare_reg(src, inst.len() + 1);
+        let (mem, mut uses) = self.prepare_mem(dest, inst.len() + 1 + reg.len() + 1);
+
+        // the register is both used and defined
+        if uses.contains_key(&id1) {
+            let locs = uses.get_mut(&id1).unwrap();
+            vec_utils::add_unique(locs, loc1.clone());
+        } else {
+            uses.insert(id1, vec![loc1.clone()]);
+        }
+
+        let asm = format!("{} {},{}", inst, reg, mem);
+
+        self.add_asm_inst(
+            asm,
+            linked_hashmap!{
+                id1 => vec![loc1]
+            },
+            uses,
+            true
+        )
+    }
+
     fn emit_push_r64(&mut self, src: &P<Value>) {
         trace!("emit: push {}", src);
 
@@ -3786,25 +3868,26 @@ impl CodeGenerator for ASMCodeGen {
 }
 
 use compiler::backend::code_emission::create_emit_directory;
+use compiler::backend::elf;
+use compiler::backend::dwarf;
 use std::fs::File;
 
 /// emit assembly file for a function version
 pub fn emit_code(fv: &mut MuFunctionVersion, vm: &VM) {
-    // acquire lock and function
-    let funcs = vm.funcs().read().unwrap();
-    let func = funcs.get(&fv.func_id).unwrap().read().unwrap();
-
     // acquire lock and compiled function
     let compiled_funcs = vm.compiled_funcs().read().unwrap();
     let cf = compiled_funcs.get(&fv.id()).unwrap().read().unwrap();
 
+    // the symbol of the code (the function name, unless this version redefines a function)
+    let code_symbol = cf.start.to_relocatable();
+
     // create 'emit' directory
     create_emit_directory(vm);
 
     // create emit file
     let mut file_path = path::PathBuf::new();
     file_path.push(&vm.vm_options.flag_aot_emit_dir);
-    file_path.push((*func.name()).clone() + ".S");
+    file_path.push((*code_symbol).clone() + ".S");
     {
         let mut file = match File::create(file_path.as_path()) {
             Err(why) => {
@@ -3816,16 +3899,30 @@ pub fn emit_code(fv: &mut MuFunctionVersion, vm: &VM) {
             }
             Ok(file) => file
         };
-        // constants in text section
-        file.write("\t.text\n".as_bytes()).unwrap();
+        // constants in text section (position-independent code keeps them in a section that
+        // becomes read-only after relocation, as constants may hold addresses)
+        if vm.vm_options.flag_aot_pic {
+            file.write("\t.section .data.rel.ro,\"aw\"\n".as_bytes())
+                .unwrap();
+        } else {
+            file.write("\t.text\n".as_bytes()).unwrap();
+        }
 
         // write constants
-        for (id, constant) in cf.consts.iter() {
-            let mem = cf.const_mem.get(id).unwrap();
-            write_const(&mut file, constant.clone(), mem.clone());
+        if vm.vm_options.flag_aot_emit_elf {
+            // constants go to their own object file
+            elf::emit_constants(vm, &code_symbol, &cf);
+        } else {
+            for (id, constant) in cf.sorted_consts() {
+                let mem = cf.const_mem.get(&id).unwrap();
+                write_const(&mut file, constant.clone(), mem.clone());
+            }
         }
 
         // write code
+        if vm.vm_options.flag_aot_pic {
+            file.write("\t.text\n".as_bytes()).unwrap();
+        }
         let code = cf.mc.as_ref().unwrap().emit();
         match file.write_all(code.as_slice()) {
             Err(why) => {
@@ -3837,13 +3934,20 @@ pub fn emit_code(fv: &mut MuFunctionVersion, vm: &VM) {
             }
             Ok(_) => info!("emit code to {}", file_path.to_str().unwrap())
         }
+
+        // write debug info (DWARF sections are only for ELF)
+        if vm.vm_options.flag_emit_debug_info && cfg!(target_os = "linux") {
+            let start = symbol(&mangle_name(code_symbol.clone()));
+            let end = symbol(&mangle_name(cf.end.to_relocatable()));
+            dwarf::emit_debug_info(&mut file, vm, fv, &cf, &start, &end);
+        }
     }
     info!("write demangled code...");
     // Read the file we just wrote above an demangle it
     {
         let mut demangled_path = path::PathBuf::new();
         demangled_path.push(&vm.vm_options.flag_aot_emit_dir);
-        demangled_path.push((*func.name()).clone() + ".demangled.S");
+        demangled_path.push((*code_symbol).clone() + ".demangled.S");
 
         let mut demangled_file = match File::create(demangled_path.as_path()) {
             Err(why) => {
@@ -4076,7 +4180,11 @@ pub fn emit_sym_table(vm: &VM) {
 
     let mut sym_vec: Vec<String> = Vec::new();
     let compiled_funcs: &HashMap<_, _> = &vm.compiled_funcs().read().unwrap();
-    for (theID, theCFs) in compiled_funcs.iter() {
+    // in the order of function version IDs, so the table is the same for every build
+    let mut cf_ids: Vec<&MuID> = compiled_funcs.keys().collect();
+    cf_ids.sort();
+    for theID in cf_ids {
+        let theCFs = compiled_funcs.get(theID).unwrap();
         let theCF: &CompiledFunction = &theCFs.read().unwrap();
         match theCF.start {
             // CF.start can only be relocatable , otherwise panic
@@ -4156,12 +4264,14 @@ pub fn emit_sym_table(vm: &VM) {
 
 
 use std::collections::HashMap;
+use runtime::thread::{MuStack, StackImageWord, PRIMORDIAL_STACK_SYMBOL};
 
 /// emit vm context for current session, considering relocation symbols/fields from the client
 pub fn emit_context_with_reloc(
     vm: &VM,
     symbols: HashMap<Address, MuName>,
     fields: HashMap<Address, MuName>,
+    primordial_stack: Option<Address>,
     primordial_threadlocal: Option<Address>
 ) {
     // creates emit directy, and file
@@ -4195,20 +4305,32 @@ pub fn emit_context_with_reloc(
         let global_locs_lock = vm.global_locations().read().unwrap();
         let global_lock = vm.globals().read().unwrap();
 
-        // a map from address to ID
+        // a map from address to ID (in the order of the IDs, so the heap dump is deterministic)
         let global_addr_id_map = {
+            let mut ids: Vec<MuID> = global_locs_lock.keys().cloned().collect();
+            ids.sort();
+
             let mut map: LinkedHashMap<Address, MuID> = LinkedHashMap::new();
-            for (id, global_loc) in global_locs_lock.iter() {
-                map.insert(global_loc.to_address(), *id);
+            for id in ids {
+                map.insert(global_locs_lock.get(&id).unwrap().to_address(), id);
             }
             map
         };
 
         // get address of all globals so we can traverse heap from them
-        let global_addrs: Vec<Address> =
-            global_locs_lock.values().map(|x| x.to_address()).collect();
+        let mut global_addrs: Vec<Address> = global_addr_id_map.keys().cloned().collect();
         debug!("going to dump these globals: {:?}", global_addrs);
 
+        // the primordial stack may refer to objects that are not reachable from globals
+        let primordial_stack: Option<&MuStack> =
+            primordial_stack.map(|a| unsafe { a.to_ptr::<MuStack>().as_ref().unwrap() });
+        let stack_refs = match primordial_stack {
+            Some(stack) => stack.heap_refs(vm),
+            None => vec![]
+        };
+        debug!("going to dump these objects from the primordial stack: {:?}", stack_refs);
+        global_addrs.extend(stack_refs.iter().cloned());
+
         // heap dump
         let mut global_dump = mm::persist_heap(global_addrs);
         debug!("Heap Dump from GC: {:?}", global_dump);
@@ -4216,106 +4338,136 @@ pub fn emit_context_with_reloc(
         let ref mut relocatable_refs = global_dump.relocatable_refs;
 
         // merge symbols with relocatable_refs
+        let mut client_labels = HashSet::new();
         for (addr, str) in symbols {
-            relocatable_refs.insert(addr, mangle_name(str));
+            let label = mangle_name(str);
+            client_labels.insert(label.clone());
+            relocatable_refs.insert(addr, label);
         }
 
-        // for all the reachable object, we write them to the boot image
-        for obj_dump in objects.values() {
-            // write object metadata
-            write_align(&mut file, 8);
-            write_obj_header(&mut file, &obj_dump.encode);
-
-            // write alignment for the object
-            write_align(&mut file, obj_dump.align);
-
-            // if this object is a global cell, we add labels so it can be accessed
-            if global_addr_id_map.contains_key(&obj_dump.addr) {
-                let global_id = global_addr_id_map.get(&obj_dump.addr).unwrap();
-                let global_value = global_lock.get(global_id).unwrap();
-
-                // .globl global_cell_name
-                // global_cell_name:
-                let demangled_name = global_value.name().clone();
-                let global_cell_name = symbol(&mangle_name(demangled_name.clone()));
-                writeln!(file, "\t{}", directive_globl(global_cell_name.clone())).unwrap();
-                writeln!(file, "{}:", global_cell_name.clone()).unwrap();
-
-                // .equiv global_cell_name_if_its_valid_c_ident
-                if is_valid_c_identifier(&demangled_name) {
-                    let demangled_name = symbol(&*demangled_name);
-                    writeln!(file, "\t{}", directive_globl(demangled_name.clone())).unwrap();
-                    writeln!(
-                        file,
-                        "\t{}",
-                        directive_equiv(demangled_name, global_cell_name.clone())
-                    ).unwrap();
+        if vm.vm_options.flag_aot_emit_elf {
+            // write the heap into an object file, the context file only keeps the VM
+            let global_names: HashMap<Address, MuName> = global_addr_id_map
+                .iter()
+                .map(|(addr, id)| (*addr, global_lock.get(id).unwrap().name()))
+                .collect();
+            // the stack image in the context file refers to these objects
+            for obj in stack_refs.iter() {
+                if let Some(label) = relocatable_refs.get(obj) {
+                    client_labels.insert(label.clone());
                 }
             }
-
-            // put dump_label for this object (so it can be referred to from other dumped objects)
-            let dump_label = symbol(&&relocatable_refs.get(&obj_dump.addr).unwrap().clone());
-            file.write_fmt(format_args!("{}:\n", dump_label)).unwrap();
-
-            // get ready to go through from the object start (not mem_start) to the end
-            let base = obj_dump.addr;
-            let end = obj_dump.addr + obj_dump.size;
-            assert!(base.is_aligned_to(POINTER_SIZE));
-
-            // offset as cursor
-            let mut offset = 0;
-            while offset < obj_dump.size {
-                let cur_addr = base + offset;
-
-                if obj_dump.reference_offsets.contains(&offset) {
-                    // if this offset is a reference field, we put a relocatable label
-                    // generated by the GC instead of address value
-
-                    let load_ref = unsafe { cur_addr.load::<Address>() };
-                    if load_ref.is_zero() {
-                        // null reference, write 0
-                        file.write("\t.quad 0\n".as_bytes()).unwrap();
-                    } else {
-                        // get the relocatable label
-                        let label = match relocatable_refs.get(&load_ref) {
-                            Some(label) => label,
-                            None => {
-                                panic!(
-                                    "cannot find label for address {}, it is not dumped by GC \
-                                     (why GC didn't trace to it?)",
-                                    load_ref
-                                )
-                            }
-                        };
-                        file.write_fmt(format_args!("\t.quad {}\n", symbol(&label)))
-                            .unwrap();
+            elf::emit_persisted_heap(
+                vm,
+                objects,
+                relocatable_refs,
+                &fields,
+                &global_names,
+                &client_labels
+            );
+        } else {
+            // for all the reachable object, we write them to the boot image
+            for obj_dump in objects.values() {
+                // write object metadata
+                write_align(&mut file, 8);
+                write_obj_header(&mut file, &obj_dump.encode);
+
+                // write alignment for the object
+                write_align(&mut file, obj_dump.align);
+
+                // if this object is a global cell, we add labels so it can be accessed
+                if global_addr_id_map.contains_key(&obj_dump.addr) {
+                    let global_id = global_addr_id_map.get(&obj_dump.addr).unwrap();
+                    let global_value = global_lock.get(global_id).unwrap();
+
+                    // .globl global_cell_name
+                    // global_cell_name:
+                    let demangled_name = global_value.name().clone();
+                    let global_cell_name = symbol(&mangle_name(demangled_name.clone()));
+                    writeln!(file, "\t{}", directive_globl(global_cell_name.clone())).unwrap();
+                    writeln!(file, "{}:", global_cell_name.clone()).unwrap();
+
+                    // .equiv global_cell_name_if_its_valid_c_ident
+                    if is_valid_c_identifier(&demangled_name) {
+                        let demangled_name = symbol(&*demangled_name);
+                        writeln!(file, "\t{}", directive_globl(demangled_name.clone())).unwrap();
+                        writeln!(
+                            file,
+                            "\t{}",
+                            directive_equiv(demangled_name, global_cell_name.clone())
+                        ).unwrap();
                     }
-                } else if fields.contains_key(&cur_addr) {
-                    // if this offset is a field named by the client to relocatable,
-                    // we put the relocatable label given by the client
+                }
 
-                    let label = fields.get(&cur_addr).unwrap();
+                // put dump_label for this object
+                // (so it can be referred to from other dumped objects)
+                let dump_label = symbol(&&relocatable_refs.get(&obj_dump.addr).unwrap().clone());
+                file.write_fmt(format_args!("{}:\n", dump_label)).unwrap();
+
+                // get ready to go through from the object start (not mem_start) to the end
+                let base = obj_dump.addr;
+                let end = obj_dump.addr + obj_dump.size;
+                assert!(base.is_aligned_to(POINTER_SIZE));
+
+                // offset as cursor
+                let mut offset = 0;
+                while offset < obj_dump.size {
+                    let cur_addr = base + offset;
+
+                    if obj_dump.reference_offsets.contains(&offset) {
+                        // if this offset is a reference field, we put a relocatable label
+                        // generated by the GC instead of address value
+
+                        let load_ref = unsafe { cur_addr.load::<Address>() };
+                        if load_ref.is_zero() {
+                            // null reference, write 0
+                            file.write("\t.quad 0\n".as_bytes()).unwrap();
+                        } else {
+                            // get the relocatable label
+                            let label = match relocatable_refs.get(&load_ref) {
+                                Some(label) => label,
+                                None => {
+                                    panic!(
+                                        "cannot find label for address {}, it is not dumped by GC \
+                                         (why GC didn't trace to it?)",
+                                        load_ref
+                                    )
+                                }
+                            };
+                            file.write_fmt(format_args!("\t.quad {}\n", symbol(&label)))
+                                .unwrap();
+                        }
+                    } else if fields.contains_key(&cur_addr) {
+                        // if this offset is a field named by the client to relocatable,
+                        // we put the relocatable label given by the client
 
-                    file.write_fmt(format_args!(
-                        "\t.quad {}\n",
-                        symbol(&mangle_name(label.clone()))
-                    )).unwrap();
-                } else {
-                    // otherwise this offset is plain data
+                        let label = fields.get(&cur_addr).unwrap();
 
-                    // write plain word (as bytes)
-                    let next_word_addr = cur_addr + POINTER_SIZE;
-                    if next_word_addr <= end {
-                        write_data_bytes(&mut file, cur_addr, next_word_addr);
+                        file.write_fmt(format_args!(
+                            "\t.quad {}\n",
+                            symbol(&mangle_name(label.clone()))
+                        )).unwrap();
                     } else {
-                        write_data_bytes(&mut file, cur_addr, end);
+                        // otherwise this offset is plain data
+
+                        // write plain word (as bytes)
+                        let next_word_addr = cur_addr + POINTER_SIZE;
+                        if next_word_addr <= end {
+                            write_data_bytes(&mut file, cur_addr, next_word_addr);
+                        } else {
+                            write_data_bytes(&mut file, cur_addr, end);
+                        }
                     }
-                }
 
-                offset += POINTER_SIZE;
+                    offset += POINTER_SIZE;
+                }
             }
         }
 
+        if let Some(stack) = primordial_stack {
+            write_stack_image(&mut file, &stack.to_image(vm, relocatable_refs));
+        }
+
         primordial_threadlocal.map(|a| relocatable_refs.get(&a).unwrap().clone())
     };
     {
@@ -4326,6 +4478,8 @@ pub fn emit_context_with_reloc(
     // currently using rustc_serialize to persist vm as json string.
     // Deserializing from this is extremely slow, we need to fix this. See Issue #41
     trace!("start serializing vm");
+    // so that the dumped context only depends on the bundle
+    vm.order_persisted_tables();
     use rodal;
     let mut dumper = rodal::AsmDumper::new(file);
 
@@ -4352,7 +4506,39 @@ pub fn emit_context_with_reloc(
 /// emit vm context for current session,
 /// without consideration about relocation symbols/fields from the client
 pub fn emit_context(vm: &VM) {
-    emit_context_with_reloc(vm, hashmap!{}, hashmap!{}, None);
+    emit_context_with_reloc(vm, hashmap!{}, hashmap!{}, None, None);
+}
+
+/// writes the image of the primordial stack (see MuStack::from_image())
+fn write_stack_image(f: &mut File, image: &Vec<StackImageWord>) {
+    let label = symbol(&mangle_name(Arc::new(PRIMORDIAL_STACK_SYMBOL.to_string())));
+    write_align(f, 8);
+    writeln!(f, "\t{}", directive_globl(label.clone())).unwrap();
+    writeln!(f, "{}:", label).unwrap();
+
+    let stack_addrs: Vec<usize> = image
+        .iter()
+        .enumerate()
+        .filter_map(|(i, word)| match word {
+            &StackImageWord::StackAddress(_) => Some(i),
+            _ => None
+        })
+        .collect();
+
+    writeln!(f, "\t.quad {}", image.len()).unwrap();
+    writeln!(f, "\t.quad {}", stack_addrs.len()).unwrap();
+    for word in image.iter() {
+        match word {
+            &StackImageWord::Value(val) => writeln!(f, "\t.quad {}", val),
+            &StackImageWord::StackAddress(dist) => writeln!(f, "\t.quad {}", dist),
+            &StackImageWord::Symbol(ref label, offset) => {
+                writeln!(f, "\t.quad {}+{}", symbol(label), offset)
+            }
+        }.unwrap();
+    }
+    for i in stack_addrs {
+        writeln!(f, "\t.quad {}", i).unwrap();
+    }
 }
 
 /// writes header for a dumped object
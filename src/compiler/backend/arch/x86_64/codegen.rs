@@ -46,12 +46,26 @@ pub trait CodeGenerator {
     /// finishes a block (must have called start_block() or start_excpetion_block() first)
     fn end_block(&mut self, block_name: MuName);
 
+    /// adds the instruction at the function entry that is patched when the function is
+    /// redefined (see patch_code_entry())
+    fn add_patchable_entry(&mut self);
+
     // adds CFI info
     fn add_cfi_startproc(&mut self);
     fn add_cfi_endproc(&mut self);
+    fn add_cfi_def_cfa(&mut self, reg: Reg, offset: i32);
     fn add_cfi_def_cfa_register(&mut self, reg: Reg);
     fn add_cfi_def_cfa_offset(&mut self, offset: i32);
     fn add_cfi_offset(&mut self, reg: Reg, offset: i32);
+    fn add_cfi_remember_state(&mut self);
+    fn add_cfi_restore_state(&mut self);
+    fn add_cfi_same_value(&mut self, reg: Reg);
+    fn add_cfi_undefined(&mut self, reg: Reg);
+    fn add_cfi_register(&mut self, reg: Reg, in_reg: Reg);
+
+    // adds debug line info
+    fn add_debug_file(&mut self, file: &str);
+    fn add_debug_loc(&mut self, line: usize);
 
     // emit code to adjust frame size
     fn emit_frame_grow(&mut self);
@@ -361,4 +375,7 @@ pub trait CodeGenerator {
 
     // memory fence
     fn emit_mfence(&mut self);
+
+    // atomic exchange and add (src gets the old value in memory)
+    fn emit_lock_xadd_mem_r(&mut self, dest: Mem, src: Reg);
 }
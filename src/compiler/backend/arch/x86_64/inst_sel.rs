@@ -27,7 +27,8 @@ use runtime::entrypoints::RuntimeEntrypoint;
 
 use compiler::PROLOGUE_BLOCK_NAME;
 use compiler::CompilerPass;
-use compiler::backend::*;
+use compiler::backend::{make_block_name, BackendType, RegGroup};
+use compiler::backend::dwarf;
 use compiler::backend::x86_64;
 use compiler::backend::x86_64::*;
 use compiler::backend::x86_64::callconv;
@@ -160,7 +161,11 @@ pub struct InstructionSelection {
     current_constants: HashMap<MuID, P<Value>>,
     /// constants used in this function that are put to memory
     /// key: value id, val: memory location
-    current_constants_locs: HashMap<MuID, P<Value>>
+    current_constants_locs: HashMap<MuID, P<Value>>,
+    /// lines in the debug source for blocks/instructions of current function
+    /// (only used with --emit-debug-info)
+    /// key: block/instruction id, val: line
+    current_debug_lines: HashMap<MuID, usize>
 }
 
 impl<'a> InstructionSelection {
@@ -190,7 +195,8 @@ impl<'a> InstructionSelection {
             current_exn_blocks: HashMap::new(),
 
             current_constants: HashMap::new(),
-            current_constants_locs: HashMap::new()
+            current_constants_locs: HashMap::new(),
+            current_debug_lines: HashMap::new()
         }
     }
 
@@ -671,6 +677,7 @@ impl<'a> InstructionSelection {
                         self.emit_common_epilogue(inst, f_content, f_context, vm);
 
                         self.backend.emit_ret();
+                        self.backend.add_cfi_restore_state();
                     }
 
                     Instruction_::BinOp(op, op1, op2) => {
@@ -1546,6 +1553,35 @@ impl<'a> InstructionSelection {
                         }
                     }
 
+                    // lock xadd is sequentially consistent, so it is enough for every order
+                    Instruction_::AtomicRMW {
+                        op,
+                        mem_loc,
+                        value,
+                        ..
+                    } => {
+                        trace!("instsel on ATOMICRMW");
+
+                        let ref ops = inst.ops;
+                        let ref loc_op = ops[mem_loc];
+                        let ref val_op = ops[value];
+
+                        let is_ireg = self.match_ireg(node);
+                        match op {
+                            AtomicRMWOp::ADD if is_ireg => {
+                                let resolved_loc =
+                                    self.emit_node_addr_to_value(loc_op, f_content, f_context, vm);
+                                let val = self.emit_ireg(val_op, f_content, f_context, vm);
+                                let res_temp = self.get_result_value(node);
+
+                                // the result gets the old value
+                                self.backend.emit_mov_r_r(&res_temp, &val);
+                                self.backend.emit_lock_xadd_mem_r(&resolved_loc, &res_temp);
+                            }
+                            _ => unimplemented!()
+                        }
+                    }
+
                     Instruction_::Fence(order) => {
                         trace!("instsel on FENCE");
 
@@ -4544,6 +4580,18 @@ impl<'a> InstructionSelection {
         // swap to new stack
         self.backend.emit_mov_r_r(&x86_64::RSP, &new_sp);
 
+        // until we leave this function, we run on the new stack. Its top (the saved RBP and
+        // the resumption address) describes the frame we are going to, and the callee saved
+        // registers of this frame are not there
+        self.backend.add_cfi_remember_state();
+        self.backend.add_cfi_def_cfa(&x86_64::RSP, 16i32);
+        self.backend.add_cfi_offset(&x86_64::RBP, -16i32);
+        for reg in x86_64::CALLEE_SAVED_GPRS.iter() {
+            if reg.id() != x86_64::RBP.id() {
+                self.backend.add_cfi_undefined(reg);
+            }
+        }
+
         // now we are on the new stack
         // prepare arguments for continuation
 
@@ -4586,6 +4634,7 @@ impl<'a> InstructionSelection {
         if is_kill {
             // save first GPR argument
             self.backend.emit_push_r64(&x86_64::RDI);
+            self.backend.add_cfi_def_cfa_offset(24i32);
 
             // kill the old stack
             self.emit_runtime_entry(
@@ -4600,6 +4649,7 @@ impl<'a> InstructionSelection {
 
             // restore first GPR
             self.backend.emit_pop_r64(&x86_64::RDI);
+            self.backend.add_cfi_def_cfa_offset(16i32);
         }
 
         // arguments are ready, we are starting continuation
@@ -4618,6 +4668,8 @@ impl<'a> InstructionSelection {
             // callee saved registers (as in muentry_throw_exception)
             self.backend
                 .emit_sub_r_imm(&x86_64::RSP, (WORD_SIZE * CALLEE_SAVED_COUNT) as i32);
+            self.backend
+                .add_cfi_def_cfa_offset((16 + WORD_SIZE * CALLEE_SAVED_COUNT) as i32);
 
             // throws an exception
             // we are calling the internal ones as return address and base pointer are already
@@ -4633,13 +4685,18 @@ impl<'a> InstructionSelection {
         } else {
             // pop RBP
             self.backend.emit_pop_r64(&x86_64::RBP);
+            self.backend.add_cfi_def_cfa_offset(8i32);
+            self.backend.add_cfi_same_value(&x86_64::RBP);
 
             // pop resumption address into rax
             self.backend.emit_pop_r64(&x86_64::RAX);
+            self.backend.add_cfi_def_cfa_offset(0i32);
+            self.backend.add_cfi_register(&x86_64::RIP, &x86_64::RAX);
 
             // push 0 - a fake return address
             // so that SP+8 is 16 bytes aligned (the same requirement as entring a function)
             self.backend.emit_push_imm32(0i32);
+            self.backend.add_cfi_def_cfa_offset(8i32);
 
             // jmp to the resumption
             self.backend.emit_call_jmp_indirect(
@@ -4650,6 +4707,8 @@ impl<'a> InstructionSelection {
                 x86_64::ALL_USABLE_MACHINE_REGS.to_vec()
             );
         }
+        // back on the stack of this frame
+        self.backend.add_cfi_restore_state();
 
         // the resumption starts here
         if !is_kill {
@@ -4792,16 +4851,12 @@ impl<'a> InstructionSelection {
 
         // push rbp
         self.backend.emit_push_r64(&x86_64::RBP);
-        if vm.vm_options.flag_emit_debug_info {
-            self.backend.add_cfi_def_cfa_offset(16i32);
-            self.backend.add_cfi_offset(&x86_64::RBP, -16i32);
-        }
+        self.backend.add_cfi_def_cfa_offset(16i32);
+        self.backend.add_cfi_offset(&x86_64::RBP, -16i32);
 
         // mov rsp -> rbp
         self.backend.emit_mov_r_r(&x86_64::RBP, &x86_64::RSP);
-        if vm.vm_options.flag_emit_debug_info {
-            self.backend.add_cfi_def_cfa_register(&x86_64::RBP);
-        }
+        self.backend.add_cfi_def_cfa_register(&x86_64::RBP);
 
         // reserve spaces for current frame
         // add x, rbp -> rbp (x is negative, however we do not know x now)
@@ -4820,6 +4875,10 @@ impl<'a> InstructionSelection {
 
                     let loc = frame.alloc_slot_for_callee_saved_reg(reg.clone(), vm);
                     self.backend.emit_mov_mem_r_callee_saved(&loc, &reg);
+
+                    // CFA is RBP + 16
+                    let offset = frame.allocated.get(&reg.id()).unwrap().offset - 16;
+                    self.backend.add_cfi_offset(&reg, offset as i32);
                 }
             }
         }
@@ -4921,6 +4980,9 @@ impl<'a> InstructionSelection {
             }
         }
 
+        // the unwind rules for the function body still apply after the return
+        self.backend.add_cfi_remember_state();
+
         // pop all callee-saved registers - reverse order
         {
             let frame = self.current_frame.as_mut().unwrap();
@@ -4944,6 +5006,7 @@ impl<'a> InstructionSelection {
 
         // pop rbp
         self.backend.emit_pop_r64(&x86_64::RBP);
+        self.backend.add_cfi_def_cfa(&x86_64::RSP, 8i32);
     }
 
     /// matches a comparison result pattern
@@ -6393,12 +6456,17 @@ impl CompilerPass for InstructionSelection {
         self.current_sig = Some(func_ver.sig.clone());
         self.current_frame = Some(Frame::new(func_ver.id()));
         self.current_func_start = Some({
-            let funcs = vm.funcs().read().unwrap();
-            let func = funcs.get(&func_ver.func_id).unwrap().read().unwrap();
-            let start_loc = self.backend.start_code(func.name(), entry_block.name());
+            let func_symbol = vm.get_code_symbol_for_func_ver(func_ver.func_id, func_ver.id());
+            let start_loc = self.backend.start_code(func_symbol.clone(), entry_block.name());
             if vm.vm_options.flag_emit_debug_info {
-                self.backend.add_cfi_startproc();
+                // line info maps code to a listing of the IR
+                let (source, lines) = dwarf::write_debug_source(vm, &func_symbol, func_ver);
+                self.backend.add_debug_file(&source.to_string_lossy());
+                self.backend.add_debug_loc(1);
+                self.current_debug_lines = lines;
             }
+            self.backend.add_cfi_startproc();
+            self.backend.add_patchable_entry();
 
             start_loc
         });
@@ -6439,6 +6507,10 @@ impl CompilerPass for InstructionSelection {
                 self.backend.start_block(block_label.clone());
             }
 
+            if let Some(line) = self.current_debug_lines.get(&block.id()) {
+                self.backend.add_debug_loc(*line);
+            }
+
             if block.is_receiving_exception_arg() {
                 // this block uses exception arguments
                 // we need to emit landingpad for it
@@ -6450,6 +6522,9 @@ impl CompilerPass for InstructionSelection {
 
             // doing the actual instruction selection
             for inst in block_content.body.iter() {
+                if let Some(line) = self.current_debug_lines.get(&inst.id()) {
+                    self.backend.add_debug_loc(*line);
+                }
                 self.instruction_select(&inst, f_content, &mut func.context, vm);
             }
 
@@ -6467,16 +6542,10 @@ impl CompilerPass for InstructionSelection {
     fn finish_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
         self.backend.print_cur_code();
 
-        let func_name = {
-            let funcs = vm.funcs().read().unwrap();
-            let func = funcs.get(&func.func_id).unwrap().read().unwrap();
-            func.name()
-        };
+        let func_name = vm.get_code_symbol_for_func_ver(func.func_id, func.id());
 
         // have to do this before 'finish_code()'
-        if vm.vm_options.flag_emit_debug_info {
-            self.backend.add_cfi_endproc();
-        }
+        self.backend.add_cfi_endproc();
         let (mc, func_end) = self.backend.finish_code(func_name.clone());
 
         // insert exception branch info
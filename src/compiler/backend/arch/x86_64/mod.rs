@@ -252,6 +252,42 @@ pub fn get_color_for_precolored(id: MuID) -> MuID {
     }
 }
 
+/// returns the DWARF register number for a machine register
+/// (as in System V x86_64 ABI), or None if it does not have one
+pub fn get_dwarf_reg_number(id: MuID) -> Option<u16> {
+    if id >= MACHINE_ID_END {
+        return None;
+    }
+
+    let color = get_color_for_precolored(id);
+    if color < FPR_ID_START {
+        let gprs = [
+            RAX.id(),
+            RDX.id(),
+            RCX.id(),
+            RBX.id(),
+            RSI.id(),
+            RDI.id(),
+            RBP.id(),
+            RSP.id(),
+            R8.id(),
+            R9.id(),
+            R10.id(),
+            R11.id(),
+            R12.id(),
+            R13.id(),
+            R14.id(),
+            R15.id()
+        ];
+        gprs.iter().position(|r| *r == color).map(|i| i as u16)
+    } else if color <= XMM15.id() {
+        // xmm0 - xmm15 are 17 - 32
+        Some((17 + color - XMM0.id()) as u16)
+    } else {
+        None
+    }
+}
+
 /// returns register length (in bits) for an integer operand
 #[inline(always)]
 pub fn check_op_len(op: &P<Value>) -> usize {
@@ -587,6 +623,36 @@ pub fn set_return_address(frame_pointer: Address, value: Address) {
     unsafe { (frame_pointer + 8 as ByteSize).store::<Address>(value) }
 }
 
+/// patches the entry of a compiled function so that it jumps to another entry
+/// Every function starts with a 5-byte nop within an aligned 8-byte word (see
+/// add_patchable_entry()). We replace the nop with a `jmp rel32` by storing the whole word
+/// at once, so a thread that is entering the function executes either the nop or the jmp.
+/// The nop is never a return address, so frames running the old code are not affected.
+pub fn patch_code_entry(old_entry: Address, new_entry: Address) {
+    use std::i32;
+    use compiler::backend::patch_code_word;
+
+    let offset = new_entry.as_usize() as i64 - (old_entry.as_usize() + 5) as i64;
+    if offset < i32::MIN as i64 || offset > i32::MAX as i64 {
+        panic!(
+            "cannot patch {} to jump to {}: the distance does not fit in rel32",
+            old_entry,
+            new_entry
+        )
+    }
+    assert!(
+        old_entry.is_aligned_to(8),
+        "entry {} is not patchable",
+        old_entry
+    );
+
+    // the jmp takes the low 5 bytes (little endian), we keep the other 3 bytes
+    let old_word = unsafe { old_entry.load::<u64>() };
+    let jmp = 0xe9u64 | (((offset as i32) as u32 as u64) << 8);
+    let new_word = (old_word & !0xff_ffff_ffffu64) | jmp;
+    patch_code_word(old_entry, new_word);
+}
+
 /// returns offset of callee saved register
 /// Reg should be a 64-bit callee saved GPR or FPR
 pub fn get_callee_saved_offset(reg: MuID) -> isize {
@@ -0,0 +1,453 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! DWARF debug information for compiled Mu functions (with --emit-debug-info).
+//!
+//! For each function version we write a debug source (a listing of its Mu IR, one
+//! instruction per line) under the emit directory. Instruction selection puts
+//! `.file`/`.loc` directives in the generated code so the assembler builds a line
+//! table that maps machine instructions back to lines of the listing. After code
+//! emission, we write a compilation unit with a subprogram for the function version,
+//! and variables for SSA values that keep one location for the whole function.
+
+use ast::ir::*;
+use ast::ptr::P;
+use ast::types::*;
+use compiler::backend::get_dwarf_reg_number;
+use compiler::backend::is_callee_saved;
+use compiler::backend::code_emission::create_emit_directory;
+use compiler::machine_code::CompiledFunction;
+use vm::VM;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// debug source for a function is named as <code_symbol>.debug.uir
+pub const DEBUG_SOURCE_SUFFIX: &'static str = ".debug.uir";
+
+// offset from frame pointer to the canonical frame address (CFA)
+// (saved frame pointer and return address) on both x86_64 and aarch64
+const CFA_OFFSET_FROM_FP: isize = 16;
+
+// tags
+const DW_TAG_FORMAL_PARAMETER: u8 = 0x05;
+const DW_TAG_COMPILE_UNIT: u8 = 0x11;
+const DW_TAG_BASE_TYPE: u8 = 0x24;
+const DW_TAG_SUBPROGRAM: u8 = 0x2e;
+const DW_TAG_VARIABLE: u8 = 0x34;
+
+// attributes
+const DW_AT_LOCATION: u8 = 0x02;
+const DW_AT_NAME: u8 = 0x03;
+const DW_AT_BYTE_SIZE: u8 = 0x0b;
+const DW_AT_STMT_LIST: u8 = 0x10;
+const DW_AT_LOW_PC: u8 = 0x11;
+const DW_AT_HIGH_PC: u8 = 0x12;
+const DW_AT_LANGUAGE: u8 = 0x13;
+const DW_AT_COMP_DIR: u8 = 0x1b;
+const DW_AT_PRODUCER: u8 = 0x25;
+const DW_AT_ENCODING: u8 = 0x3e;
+const DW_AT_EXTERNAL: u8 = 0x3f;
+const DW_AT_FRAME_BASE: u8 = 0x40;
+const DW_AT_TYPE: u8 = 0x49;
+const DW_AT_LINKAGE_NAME: u8 = 0x6e;
+
+// forms
+const DW_FORM_ADDR: u8 = 0x01;
+const DW_FORM_DATA2: u8 = 0x05;
+const DW_FORM_DATA8: u8 = 0x07;
+const DW_FORM_STRING: u8 = 0x08;
+const DW_FORM_DATA1: u8 = 0x0b;
+const DW_FORM_REF4: u8 = 0x13;
+const DW_FORM_SEC_OFFSET: u8 = 0x17;
+const DW_FORM_EXPRLOC: u8 = 0x18;
+const DW_FORM_FLAG_PRESENT: u8 = 0x19;
+
+// base type encodings
+const DW_ATE_ADDRESS: u8 = 0x01;
+const DW_ATE_FLOAT: u8 = 0x04;
+const DW_ATE_SIGNED: u8 = 0x05;
+
+// location operations
+const DW_OP_REG0: u8 = 0x50;
+const DW_OP_REGX: u8 = 0x90;
+const DW_OP_FBREG: u8 = 0x91;
+const DW_OP_CALL_FRAME_CFA: u8 = 0x9c;
+
+// we describe Mu IR as C, so debuggers can print values with their usual syntax
+const DW_LANG_C99: u16 = 0x0c;
+
+// abbreviation codes
+const ABBREV_COMPILE_UNIT: u8 = 1;
+const ABBREV_SUBPROGRAM: u8 = 2;
+const ABBREV_BASE_TYPE: u8 = 3;
+const ABBREV_VARIABLE: u8 = 4;
+const ABBREV_FORMAL_PARAMETER: u8 = 5;
+
+/// writes the debug source (a listing of Mu IR) for a function version,
+/// returns its path and the line for each block/instruction (keyed by ID).
+/// Line 1 is the function header, which is used for the prologue
+pub fn write_debug_source(
+    vm: &VM,
+    code_symbol: &MuName,
+    fv: &MuFunctionVersion
+) -> (PathBuf, HashMap<MuID, usize>) {
+    let mut lines: Vec<String> = vec![];
+    let mut line_map = HashMap::new();
+
+    lines.push(format!(
+        ".funcdef {} VERSION {} <{}> {{",
+        vm.name_of(fv.func_id),
+        fv.name(),
+        fv.sig
+    ));
+
+    for block in fv.content.as_ref().unwrap().blocks.values() {
+        let content = block.content.as_ref().unwrap();
+        let mut args: Vec<String> = content
+            .args
+            .iter()
+            .map(|arg| format!("<{}> {}", arg.ty, arg.name()))
+            .collect();
+        if let Some(ref exn_arg) = content.exn_arg {
+            args.push(format!("[{}]", exn_arg.name()));
+        }
+        lines.push(format!("  {}({}):", block.name(), args.join(", ")));
+        line_map.insert(block.id(), lines.len());
+
+        for inst in content.body.iter() {
+            let text = match inst.v {
+                TreeNode_::Instruction(ref inst) => format!("{}", inst),
+                TreeNode_::Value(ref pv) => format!("{}", pv)
+            };
+            lines.push(format!("    {}", text.replace('\n', " ")));
+            line_map.insert(inst.id(), lines.len());
+        }
+    }
+    lines.push("}".to_string());
+
+    create_emit_directory(vm);
+    let mut path = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
+    path.push((**code_symbol).clone() + DEBUG_SOURCE_SUFFIX);
+    {
+        let mut file = match File::create(path.as_path()) {
+            Err(why) => {
+                panic!(
+                    "couldn't create debug source {}: {}",
+                    path.to_str().unwrap(),
+                    why
+                )
+            }
+            Ok(file) => file
+        };
+        for line in lines.iter() {
+            writeln!(file, "{}", line).unwrap();
+        }
+    }
+
+    // use an absolute path, so debuggers find the source from anywhere
+    let path = match path.canonicalize() {
+        Ok(abs) => abs,
+        Err(_) => path
+    };
+
+    (path, line_map)
+}
+
+/// emits DWARF debug information (abbreviations and a compilation unit) for a compiled
+/// function version as assembly. start and end are the symbols of the function code
+pub fn emit_debug_info(
+    f: &mut File,
+    vm: &VM,
+    fv: &MuFunctionVersion,
+    cf: &CompiledFunction,
+    start: &str,
+    end: &str
+) {
+    emit_debug_abbrev(f);
+
+    // the assembler puts the line table (from .loc directives) in .debug_line
+    writeln!(f, "\t.section .debug_line").unwrap();
+    writeln!(f, ".Ldebug_line0:").unwrap();
+
+    writeln!(f, "\t.section .debug_info").unwrap();
+    writeln!(f, ".Ldebug_info0:").unwrap();
+    writeln!(f, "\t.4byte .Ldebug_info0_end - .Ldebug_info0_start").unwrap();
+    writeln!(f, ".Ldebug_info0_start:").unwrap();
+    writeln!(f, "\t.2byte 4").unwrap();
+    writeln!(f, "\t.4byte .Ldebug_abbrev0").unwrap();
+    writeln!(f, "\t.byte 8").unwrap();
+
+    // compilation unit
+    let source = {
+        let mut path = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
+        path.push((*cf.start.to_relocatable()).clone() + DEBUG_SOURCE_SUFFIX);
+        match path.canonicalize() {
+            Ok(abs) => abs,
+            Err(_) => path
+        }
+    };
+    let comp_dir = match ::std::env::current_dir() {
+        Ok(dir) => dir.to_string_lossy().into_owned(),
+        Err(_) => String::new()
+    };
+    writeln!(f, "\t.uleb128 {}", ABBREV_COMPILE_UNIT).unwrap();
+    write_string(f, &source.to_string_lossy());
+    write_string(f, "Zebu");
+    writeln!(f, "\t.2byte {}", DW_LANG_C99).unwrap();
+    write_string(f, &comp_dir);
+    writeln!(f, "\t.8byte {}", start).unwrap();
+    writeln!(f, "\t.8byte {} - {}", end, start).unwrap();
+    writeln!(f, "\t.4byte .Ldebug_line0").unwrap();
+
+    // collect variables with a known location, and their types
+    let entry_args: Vec<MuID> = fv.content
+        .as_ref()
+        .unwrap()
+        .get_entry_block()
+        .content
+        .as_ref()
+        .unwrap()
+        .args
+        .iter()
+        .map(|arg| arg.id())
+        .collect();
+    let mut base_types: Vec<(String, u8, usize)> = vec![];
+    let mut vars: Vec<(bool, String, usize, Vec<u8>)> = vec![];
+    for (id, entry) in fv.context.values.iter() {
+        let value = entry.value();
+        let location = match get_location(*id, cf, vm) {
+            Some(loc) => loc,
+            None => continue
+        };
+        let base_type = get_base_type(&value.ty, vm);
+        let type_index = match base_types.iter().position(|t| *t == base_type) {
+            Some(i) => i,
+            None => {
+                base_types.push(base_type);
+                base_types.len() - 1
+            }
+        };
+        let name = value.name();
+        let short_name = name.rsplit('.').next().unwrap().to_string();
+        vars.push((entry_args.contains(id), short_name, type_index, location));
+    }
+
+    // base types
+    for (i, &(ref name, encoding, size)) in base_types.iter().enumerate() {
+        writeln!(f, ".Ldebug_type{}:", i).unwrap();
+        writeln!(f, "\t.uleb128 {}", ABBREV_BASE_TYPE).unwrap();
+        write_string(f, name);
+        writeln!(f, "\t.byte {}", encoding).unwrap();
+        writeln!(f, "\t.byte {}", size).unwrap();
+    }
+
+    // subprogram
+    writeln!(f, "\t.uleb128 {}", ABBREV_SUBPROGRAM).unwrap();
+    write_string(f, &fv.name());
+    write_string(f, start);
+    writeln!(f, "\t.8byte {}", start).unwrap();
+    writeln!(f, "\t.8byte {} - {}", end, start).unwrap();
+    writeln!(f, "\t.uleb128 1").unwrap();
+    writeln!(f, "\t.byte {}", DW_OP_CALL_FRAME_CFA).unwrap();
+
+    // parameters first, then other variables
+    for is_param in [true, false].iter() {
+        for &(param, ref name, type_index, ref location) in vars.iter() {
+            if param != *is_param {
+                continue;
+            }
+            let abbrev = if param {
+                ABBREV_FORMAL_PARAMETER
+            } else {
+                ABBREV_VARIABLE
+            };
+            writeln!(f, "\t.uleb128 {}", abbrev).unwrap();
+            write_string(f, name);
+            writeln!(f, "\t.4byte .Ldebug_type{} - .Ldebug_info0", type_index).unwrap();
+            writeln!(f, "\t.uleb128 {}", location.len()).unwrap();
+            write_bytes(f, location);
+        }
+    }
+
+    // end of subprogram children, end of compilation unit children
+    writeln!(f, "\t.byte 0").unwrap();
+    writeln!(f, "\t.byte 0").unwrap();
+    writeln!(f, ".Ldebug_info0_end:").unwrap();
+}
+
+/// emits the abbreviation table that our debug info uses
+fn emit_debug_abbrev(f: &mut File) {
+    writeln!(f, "\t.section .debug_abbrev").unwrap();
+    writeln!(f, ".Ldebug_abbrev0:").unwrap();
+
+    let abbrevs: Vec<(u8, u8, bool, Vec<(u8, u8)>)> = vec![
+        (
+            ABBREV_COMPILE_UNIT,
+            DW_TAG_COMPILE_UNIT,
+            true,
+            vec![
+                (DW_AT_NAME, DW_FORM_STRING),
+                (DW_AT_PRODUCER, DW_FORM_STRING),
+                (DW_AT_LANGUAGE, DW_FORM_DATA2),
+                (DW_AT_COMP_DIR, DW_FORM_STRING),
+                (DW_AT_LOW_PC, DW_FORM_ADDR),
+                (DW_AT_HIGH_PC, DW_FORM_DATA8),
+                (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET)
+            ]
+        ),
+        (
+            ABBREV_SUBPROGRAM,
+            DW_TAG_SUBPROGRAM,
+            true,
+            vec![
+                (DW_AT_NAME, DW_FORM_STRING),
+                (DW_AT_LINKAGE_NAME, DW_FORM_STRING),
+                (DW_AT_LOW_PC, DW_FORM_ADDR),
+                (DW_AT_HIGH_PC, DW_FORM_DATA8),
+                (DW_AT_FRAME_BASE, DW_FORM_EXPRLOC),
+                (DW_AT_EXTERNAL, DW_FORM_FLAG_PRESENT)
+            ]
+        ),
+        (
+            ABBREV_BASE_TYPE,
+            DW_TAG_BASE_TYPE,
+            false,
+            vec![
+                (DW_AT_NAME, DW_FORM_STRING),
+                (DW_AT_ENCODING, DW_FORM_DATA1),
+                (DW_AT_BYTE_SIZE, DW_FORM_DATA1)
+            ]
+        ),
+        (
+            ABBREV_VARIABLE,
+            DW_TAG_VARIABLE,
+            false,
+            vec![
+                (DW_AT_NAME, DW_FORM_STRING),
+                (DW_AT_TYPE, DW_FORM_REF4),
+                (DW_AT_LOCATION, DW_FORM_EXPRLOC)
+            ]
+        ),
+        (
+            ABBREV_FORMAL_PARAMETER,
+            DW_TAG_FORMAL_PARAMETER,
+            false,
+            vec![
+                (DW_AT_NAME, DW_FORM_STRING),
+                (DW_AT_TYPE, DW_FORM_REF4),
+                (DW_AT_LOCATION, DW_FORM_EXPRLOC)
+            ]
+        ),
+    ];
+
+    for &(code, tag, has_children, ref attrs) in abbrevs.iter() {
+        writeln!(f, "\t.uleb128 {}", code).unwrap();
+        writeln!(f, "\t.uleb128 {}", tag).unwrap();
+        writeln!(f, "\t.byte {}", if has_children { 1 } else { 0 }).unwrap();
+        for &(attr, form) in attrs.iter() {
+            writeln!(f, "\t.uleb128 {}", attr).unwrap();
+            writeln!(f, "\t.uleb128 {}", form).unwrap();
+        }
+        writeln!(f, "\t.byte 0").unwrap();
+        writeln!(f, "\t.byte 0").unwrap();
+    }
+    writeln!(f, "\t.byte 0").unwrap();
+}
+
+/// returns a DWARF location expression for a Mu value (by ID) in a compiled function:
+/// its stack slot if it is spilled, or its register if no other value is ever in the register.
+/// We do not describe values in registers that are shared with other values or clobbered by
+/// calls, as the location would only be valid while the value is live (which needs a location
+/// list), and returns None for them
+fn get_location(id: MuID, cf: &CompiledFunction, vm: &VM) -> Option<Vec<u8>> {
+    if let Some(slot) = cf.frame.allocated.get(&id) {
+        // frame base is the CFA
+        let mut ret = vec![DW_OP_FBREG];
+        push_sleb128(&mut ret, (slot.offset - CFA_OFFSET_FROM_FP) as i64);
+        return Some(ret);
+    }
+
+    match cf.temps.get(&id) {
+        Some(color) => {
+            let shared = !is_callee_saved(*color, vm) ||
+                cf.temps.iter().any(|(other, c)| *other != id && c == color);
+            if shared {
+                return None;
+            }
+            match get_dwarf_reg_number(*color, vm) {
+                Some(reg) if reg < 32 => Some(vec![DW_OP_REG0 + reg as u8]),
+                Some(reg) => {
+                    let mut ret = vec![DW_OP_REGX];
+                    push_uleb128(&mut ret, reg as u64);
+                    Some(ret)
+                }
+                None => None
+            }
+        }
+        None => None
+    }
+}
+
+/// returns name, encoding and size of the DWARF base type that describes a Mu type
+fn get_base_type(ty: &P<MuType>, vm: &VM) -> (String, u8, usize) {
+    match ty.v {
+        MuType_::Int(len) => (format!("int<{}>", len), DW_ATE_SIGNED, (len + 7) / 8),
+        MuType_::Float => ("float".to_string(), DW_ATE_FLOAT, 4),
+        MuType_::Double => ("double".to_string(), DW_ATE_FLOAT, 8),
+        _ => {
+            let size = vm.get_backend_type_size(ty.id());
+            (format!("{}", ty), DW_ATE_ADDRESS, size)
+        }
+    }
+}
+
+/// writes a null terminated string
+fn write_string(f: &mut File, s: &str) {
+    let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
+    writeln!(f, "\t.asciz \"{}\"", escaped).unwrap();
+}
+
+/// writes raw bytes
+fn write_bytes(f: &mut File, bytes: &[u8]) {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("0x{:x}", b)).collect();
+    writeln!(f, "\t.byte {}", bytes.join(",")).unwrap();
+}
+
+fn push_uleb128(buf: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        if v == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+fn push_sleb128(buf: &mut Vec<u8>, mut v: i64) {
+    loop {
+        let byte = (v & 0x7f) as u8;
+        v >>= 7;
+        let done = (v == 0 && byte & 0x40 == 0) || (v == -1 && byte & 0x40 != 0);
+        if done {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,862 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A writer for ELF64 relocatable objects (little endian, x86_64 and aarch64).
+//!
+//! The AOT compiler uses this to write data that does not need an assembler
+//! (the persisted heap and constants) straight into object files, which are
+//! then given to the linker along with the generated code. It only writes data sections:
+//! the backends produce assembly text rather than encoded instructions, so the code of
+//! compiled functions (and its relocations) still goes through the assembler.
+//! It can also read back the section sizes of an object file, for reporting what goes
+//! into a boot image.
+
+use ast::ir::*;
+use ast::ptr::P;
+use compiler::backend::TargetArch;
+use compiler::machine_code::CompiledFunction;
+use runtime::mm::common::objectdump::ObjectDump;
+use utils::Address;
+use utils::ByteSize;
+use utils::LinkedHashMap;
+use utils::POINTER_SIZE;
+use utils::math::align_up;
+use vm::VM;
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+/// object file for constants of a function is named as <code_symbol>.rodata.o
+pub const ELF_RODATA_SUFFIX: &'static str = ".rodata.o";
+/// object file for the persisted heap
+pub const AOT_EMIT_HEAP_OBJ_FILE: &'static str = "context_heap.o";
+
+const ELF_HEADER_SIZE: usize = 64;
+const SECTION_HEADER_SIZE: usize = 64;
+const SYMBOL_SIZE: usize = 24;
+const RELA_SIZE: usize = 24;
+
+const ET_REL: u16 = 1;
+const EM_X86_64: u16 = 62;
+const EM_AARCH64: u16 = 183;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHT_REL: u32 = 9;
+
+const SHF_WRITE: u64 = 0x1;
+const SHF_ALLOC: u64 = 0x2;
+const SHF_EXECINSTR: u64 = 0x4;
+const SHF_INFO_LINK: u64 = 0x40;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STB_WEAK: u8 = 2;
+
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+
+const STV_DEFAULT: u8 = 0;
+const STV_HIDDEN: u8 = 2;
+
+/// target machine of the object file
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ElfMachine {
+    X86_64,
+    AArch64
+}
+
+impl ElfMachine {
+    /// returns the machine that we are running on
+    #[cfg(target_arch = "x86_64")]
+    pub fn host() -> ElfMachine {
+        ElfMachine::X86_64
+    }
+
+    /// returns the machine that we are running on
+    #[cfg(target_arch = "aarch64")]
+    pub fn host() -> ElfMachine {
+        ElfMachine::AArch64
+    }
+
+    /// returns the machine for a target architecture
+    pub fn for_target(arch: TargetArch) -> ElfMachine {
+        match arch {
+            TargetArch::X86_64 => ElfMachine::X86_64,
+            TargetArch::AArch64 => ElfMachine::AArch64
+        }
+    }
+
+    /// returns the e_machine value for the ELF header
+    pub fn e_machine(&self) -> u16 {
+        match *self {
+            ElfMachine::X86_64 => EM_X86_64,
+            ElfMachine::AArch64 => EM_AARCH64
+        }
+    }
+}
+
+/// kinds of sections we emit (data only, see the module documentation)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SectionKind {
+    Data,
+    ROData
+}
+
+/// how a symbol is visible to the linker
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SymbolBinding {
+    /// only visible in this object
+    Local,
+    /// visible to other objects and exported from the final image
+    Global,
+    /// visible to other objects but not exported. Duplicate definitions
+    /// are allowed (used for constants that several functions may emit)
+    WeakHidden
+}
+
+/// what a symbol refers to
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SymbolType {
+    NoType,
+    Object
+}
+
+/// relocation kinds (target independent). They are mapped to the machine specific
+/// relocation types when the object is written. Data sections only need absolute addresses
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RelocKind {
+    /// 64-bits absolute address (S + A)
+    Abs64
+}
+
+impl RelocKind {
+    fn r_type(&self, machine: ElfMachine) -> u32 {
+        match (machine, *self) {
+            (ElfMachine::X86_64, RelocKind::Abs64) => 1, // R_X86_64_64
+            (ElfMachine::AArch64, RelocKind::Abs64) => 257 // R_AARCH64_ABS64
+        }
+    }
+}
+
+/// index of a section in an ElfObject
+pub type SectionIndex = usize;
+
+struct Section {
+    name: String,
+    kind: SectionKind,
+    align: ByteSize,
+    data: Vec<u8>,
+    relocs: Vec<Reloc>
+}
+
+struct Reloc {
+    offset: ByteSize,
+    symbol: String,
+    kind: RelocKind,
+    addend: i64
+}
+
+struct Symbol {
+    name: String,
+    section: SectionIndex,
+    offset: ByteSize,
+    size: ByteSize,
+    binding: SymbolBinding,
+    ty: SymbolType
+}
+
+/// an ELF64 relocatable object under construction
+pub struct ElfObject {
+    machine: ElfMachine,
+    sections: Vec<Section>,
+    symbols: Vec<Symbol>,
+    symbol_names: HashMap<String, usize>
+}
+
+impl ElfObject {
+    pub fn new(machine: ElfMachine) -> ElfObject {
+        ElfObject {
+            machine: machine,
+            sections: vec![],
+            symbols: vec![],
+            symbol_names: HashMap::new()
+        }
+    }
+
+    /// adds a new (empty) section, and returns its index
+    pub fn add_section(&mut self, name: &str, kind: SectionKind) -> SectionIndex {
+        self.sections.push(Section {
+            name: name.to_string(),
+            kind: kind,
+            align: 1,
+            data: vec![],
+            relocs: vec![]
+        });
+        self.sections.len() - 1
+    }
+
+    /// returns current size of a section (the offset where next bytes will be put)
+    pub fn offset(&self, sec: SectionIndex) -> ByteSize {
+        self.sections[sec].data.len()
+    }
+
+    /// pads a section with zeros to the given alignment
+    pub fn align(&mut self, sec: SectionIndex, align: ByteSize) {
+        debug_assert!(align.is_power_of_two());
+        let section = &mut self.sections[sec];
+        if section.align < align {
+            section.align = align;
+        }
+        let aligned = align_up(section.data.len(), align);
+        section.data.resize(aligned, 0);
+    }
+
+    /// appends bytes to a section, returns the offset of the bytes
+    pub fn append(&mut self, sec: SectionIndex, bytes: &[u8]) -> ByteSize {
+        let section = &mut self.sections[sec];
+        let offset = section.data.len();
+        section.data.extend_from_slice(bytes);
+        offset
+    }
+
+    /// appends a little endian value of the given size (1/2/4/8 bytes) to a section
+    pub fn append_uint(&mut self, sec: SectionIndex, val: u64, size: ByteSize) -> ByteSize {
+        let mut bytes = vec![];
+        for i in 0..size {
+            bytes.push((val >> (i * 8)) as u8);
+        }
+        self.append(sec, &bytes)
+    }
+
+    /// appends a 64-bits word that will be filled by the linker with the address of symbol
+    pub fn append_symbol_address(&mut self, sec: SectionIndex, symbol: &str) -> ByteSize {
+        let offset = self.append_uint(sec, 0, 8);
+        self.add_reloc(sec, offset, symbol, RelocKind::Abs64, 0);
+        offset
+    }
+
+    /// defines a symbol at the given offset of a section
+    pub fn define_symbol(
+        &mut self,
+        name: &str,
+        sec: SectionIndex,
+        offset: ByteSize,
+        size: ByteSize,
+        binding: SymbolBinding,
+        ty: SymbolType
+    ) {
+        if self.symbol_names.contains_key(name) {
+            panic!("symbol {} is defined twice in the object", name);
+        }
+        self.symbol_names
+            .insert(name.to_string(), self.symbols.len());
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            section: sec,
+            offset: offset,
+            size: size,
+            binding: binding,
+            ty: ty
+        });
+    }
+
+    /// checks if a symbol is defined in this object
+    pub fn has_symbol(&self, name: &str) -> bool {
+        self.symbol_names.contains_key(name)
+    }
+
+    /// records a relocation at the given offset of a section. The symbol does not need
+    /// to be defined in this object (it will be an undefined symbol resolved by the linker)
+    pub fn add_reloc(
+        &mut self,
+        sec: SectionIndex,
+        offset: ByteSize,
+        symbol: &str,
+        kind: RelocKind,
+        addend: i64
+    ) {
+        self.sections[sec].relocs.push(Reloc {
+            offset: offset,
+            symbol: symbol.to_string(),
+            kind: kind,
+            addend: addend
+        });
+    }
+
+    /// lays out the object and returns its bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        // symbol table: null symbol, locals, then globals (as required by ELF),
+        // then undefined symbols that are only referred to by relocations
+        let mut symtab_order: Vec<&Symbol> = vec![];
+        symtab_order.extend(
+            self.symbols
+                .iter()
+                .filter(|s| s.binding == SymbolBinding::Local)
+        );
+        let first_global = symtab_order.len() + 1;
+        symtab_order.extend(
+            self.symbols
+                .iter()
+                .filter(|s| s.binding != SymbolBinding::Local)
+        );
+
+        let mut symbol_index: HashMap<&str, usize> = HashMap::new();
+        for (i, sym) in symtab_order.iter().enumerate() {
+            symbol_index.insert(&*sym.name, i + 1);
+        }
+        let mut undefined: Vec<&str> = vec![];
+        for section in self.sections.iter() {
+            for reloc in section.relocs.iter() {
+                let name: &str = &reloc.symbol;
+                if !symbol_index.contains_key(name) {
+                    symbol_index.insert(name, symtab_order.len() + undefined.len() + 1);
+                    undefined.push(name);
+                }
+            }
+        }
+
+        // string tables
+        let mut strtab = StringTable::new();
+        let mut shstrtab = StringTable::new();
+
+        // section header indices: 0 is null, then our sections,
+        // then one .rela section for each section with relocations, then the tables
+        let n_sections = self.sections.len();
+        let rela_sections: Vec<SectionIndex> = (0..n_sections)
+            .filter(|&i| !self.sections[i].relocs.is_empty())
+            .collect();
+        let symtab_shndx = 1 + n_sections + rela_sections.len();
+        let strtab_shndx = symtab_shndx + 1;
+        let shstrtab_shndx = strtab_shndx + 1;
+        let shnum = shstrtab_shndx + 1;
+
+        // encode symbol table
+        let mut symtab: Vec<u8> = vec![0; SYMBOL_SIZE];
+        for sym in symtab_order.iter() {
+            let (bind, vis) = match sym.binding {
+                SymbolBinding::Local => (STB_LOCAL, STV_DEFAULT),
+                SymbolBinding::Global => (STB_GLOBAL, STV_DEFAULT),
+                SymbolBinding::WeakHidden => (STB_WEAK, STV_HIDDEN)
+            };
+            let ty = match sym.ty {
+                SymbolType::NoType => STT_NOTYPE,
+                SymbolType::Object => STT_OBJECT
+            };
+            let name = strtab.add(&sym.name);
+            write_symbol(
+                &mut symtab,
+                name,
+                (bind << 4) | ty,
+                vis,
+                (sym.section + 1) as u16,
+                sym.offset as u64,
+                sym.size as u64
+            );
+        }
+        for name in undefined.iter() {
+            let name = strtab.add(name);
+            write_symbol(&mut symtab, name, STB_GLOBAL << 4, STV_DEFAULT, 0, 0, 0);
+        }
+
+        // encode relocations
+        let relas: Vec<Vec<u8>> = rela_sections
+            .iter()
+            .map(|&i| {
+                let mut bytes = vec![];
+                for reloc in self.sections[i].relocs.iter() {
+                    let sym = *symbol_index.get(&*reloc.symbol).unwrap() as u64;
+                    let info = (sym << 32) | (reloc.kind.r_type(self.machine) as u64);
+                    put_u64(&mut bytes, reloc.offset as u64);
+                    put_u64(&mut bytes, info);
+                    put_u64(&mut bytes, reloc.addend as u64);
+                }
+                bytes
+            })
+            .collect();
+
+        // section headers (name, type, flags, contents, link, info, align, entsize)
+        let mut headers: Vec<(u32, u32, u64, &[u8], u32, u32, u64, u64)> = vec![];
+        for section in self.sections.iter() {
+            let flags = match section.kind {
+                SectionKind::Data => SHF_ALLOC | SHF_WRITE,
+                SectionKind::ROData => SHF_ALLOC
+            };
+            headers.push((
+                shstrtab.add(&section.name),
+                SHT_PROGBITS,
+                flags,
+                &section.data[..],
+                0,
+                0,
+                section.align as u64,
+                0
+            ));
+        }
+        for (j, &i) in rela_sections.iter().enumerate() {
+            headers.push((
+                shstrtab.add(&format!(".rela{}", self.sections[i].name)),
+                SHT_RELA,
+                SHF_INFO_LINK,
+                &relas[j][..],
+                symtab_shndx as u32,
+                (i + 1) as u32,
+                8,
+                RELA_SIZE as u64
+            ));
+        }
+        let symtab_name = shstrtab.add(".symtab");
+        let strtab_name = shstrtab.add(".strtab");
+        let shstrtab_name = shstrtab.add(".shstrtab");
+        headers.push((
+            symtab_name,
+            SHT_SYMTAB,
+            0,
+            &symtab[..],
+            strtab_shndx as u32,
+            first_global as u32,
+            8,
+            SYMBOL_SIZE as u64
+        ));
+        headers.push((strtab_name, SHT_STRTAB, 0, &strtab.bytes[..], 0, 0, 1, 0));
+        headers.push((shstrtab_name, SHT_STRTAB, 0, &shstrtab.bytes[..], 0, 0, 1, 0));
+        debug_assert!(headers.len() + 1 == shnum);
+
+        // contents of all sections follow the ELF header
+        let mut contents: Vec<u8> = vec![];
+        let mut offsets: Vec<usize> = vec![];
+        for header in headers.iter() {
+            let aligned = align_up(ELF_HEADER_SIZE + contents.len(), header.6 as usize);
+            contents.resize(aligned - ELF_HEADER_SIZE, 0);
+            offsets.push(ELF_HEADER_SIZE + contents.len());
+            contents.extend_from_slice(header.3);
+        }
+        let shoff = align_up(ELF_HEADER_SIZE + contents.len(), 8);
+        contents.resize(shoff - ELF_HEADER_SIZE, 0);
+
+        // ELF header
+        let mut ret: Vec<u8> = vec![0x7f, b'E', b'L', b'F', 2, 1, 1, 0];
+        ret.resize(16, 0);
+        put_u16(&mut ret, ET_REL);
+        put_u16(&mut ret, self.machine.e_machine());
+        put_u32(&mut ret, 1); // e_version
+        put_u64(&mut ret, 0); // e_entry
+        put_u64(&mut ret, 0); // e_phoff
+        put_u64(&mut ret, shoff as u64);
+        put_u32(&mut ret, 0); // e_flags
+        put_u16(&mut ret, ELF_HEADER_SIZE as u16);
+        put_u16(&mut ret, 0); // e_phentsize
+        put_u16(&mut ret, 0); // e_phnum
+        put_u16(&mut ret, SECTION_HEADER_SIZE as u16);
+        put_u16(&mut ret, shnum as u16);
+        put_u16(&mut ret, shstrtab_shndx as u16);
+        debug_assert!(ret.len() == ELF_HEADER_SIZE);
+
+        ret.extend_from_slice(&contents);
+
+        // section header table
+        ret.resize(ret.len() + SECTION_HEADER_SIZE, 0);
+        for (i, header) in headers.iter().enumerate() {
+            put_u32(&mut ret, header.0);
+            put_u32(&mut ret, header.1);
+            put_u64(&mut ret, header.2);
+            put_u64(&mut ret, 0); // sh_addr
+            put_u64(&mut ret, offsets[i] as u64);
+            put_u64(&mut ret, header.3.len() as u64);
+            put_u32(&mut ret, header.4);
+            put_u32(&mut ret, header.5);
+            put_u64(&mut ret, header.6);
+            put_u64(&mut ret, header.7);
+        }
+
+        ret
+    }
+
+    /// writes the object to a file
+    pub fn write_to_file(&self, path: &Path) {
+        let mut file = match File::create(path) {
+            Err(why) => panic!("couldn't create object file {}: {}", path.display(), why),
+            Ok(file) => file
+        };
+        match file.write_all(&self.to_bytes()) {
+            Err(why) => panic!("couldn't write to object file {}: {}", path.display(), why),
+            Ok(_) => info!("emit object to {}", path.display())
+        }
+    }
+}
+
+/// a string table (.strtab/.shstrtab), the first byte is always the empty string
+struct StringTable {
+    bytes: Vec<u8>,
+    offsets: HashMap<String, u32>
+}
+
+impl StringTable {
+    fn new() -> StringTable {
+        StringTable {
+            bytes: vec![0],
+            offsets: HashMap::new()
+        }
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        if let Some(offset) = self.offsets.get(s) {
+            return *offset;
+        }
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(s.as_bytes());
+        self.bytes.push(0);
+        self.offsets.insert(s.to_string(), offset);
+        offset
+    }
+}
+
+fn write_symbol(
+    buf: &mut Vec<u8>,
+    name: u32,
+    info: u8,
+    other: u8,
+    shndx: u16,
+    value: u64,
+    size: u64
+) {
+    put_u32(buf, name);
+    buf.push(info);
+    buf.push(other);
+    put_u16(buf, shndx);
+    put_u64(buf, value);
+    put_u64(buf, size);
+}
+
+fn put_u16(buf: &mut Vec<u8>, v: u16) {
+    for i in 0..2 {
+        buf.push((v >> (i * 8)) as u8);
+    }
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    for i in 0..4 {
+        buf.push((v >> (i * 8)) as u8);
+    }
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    for i in 0..8 {
+        buf.push((v >> (i * 8)) as u8);
+    }
+}
+
+/// max alignment for constants (same as the assembly backends)
+const CONST_ALIGN: ByteSize = 16;
+
+/// emits constants of a compiled function into <code_symbol>.rodata.o under the emit directory.
+/// Constants are weak hidden symbols, as the same constant may be used (and emitted)
+/// by several functions. For position-independent code, they go to .data.rel.ro instead, as
+/// addresses in constants need to be relocated by the dynamic loader
+pub fn emit_constants(vm: &VM, code_symbol: &MuName, cf: &CompiledFunction) {
+    let mut obj = ElfObject::new(ElfMachine::for_target(vm.target_arch()));
+    let rodata = if vm.vm_options.flag_aot_pic {
+        obj.add_section(".data.rel.ro", SectionKind::Data)
+    } else {
+        obj.add_section(".rodata", SectionKind::ROData)
+    };
+
+    for (id, constant) in cf.sorted_consts() {
+        let mem = cf.const_mem.get(&id).unwrap();
+        let label = match mem.v {
+            Value_::Memory(MemoryLocation::Symbolic { ref label, .. }) => label.clone(),
+            _ => {
+                panic!(
+                    "expecing a symbolic memory location for constant {}, found {}",
+                    constant,
+                    mem
+                )
+            }
+        };
+
+        obj.align(rodata, CONST_ALIGN);
+        let start = obj.offset(rodata);
+        append_const_value(&mut obj, rodata, constant);
+        let size = obj.offset(rodata) - start;
+        obj.define_symbol(
+            &mangle_name(label),
+            rodata,
+            start,
+            size,
+            SymbolBinding::WeakHidden,
+            SymbolType::Object
+        );
+    }
+
+    let mut path = Path::new(&vm.vm_options.flag_aot_emit_dir).to_path_buf();
+    path.push((**code_symbol).clone() + ELF_RODATA_SUFFIX);
+    obj.write_to_file(path.as_path());
+}
+
+/// appends a constant value based on its type and value
+fn append_const_value(obj: &mut ElfObject, sec: SectionIndex, constant: &P<Value>) {
+    use utils::mem::{f32_to_raw, f64_to_raw};
+
+    let inner = match constant.v {
+        Value_::Constant(ref c) => c,
+        _ => panic!("expected constant, found {}", constant)
+    };
+
+    match inner {
+        &Constant::Int(val) => {
+            let len = constant.ty.get_int_length().unwrap();
+            let val = if len < 64 {
+                val & ((1u64 << len) - 1)
+            } else {
+                val
+            };
+            match len {
+                1...8 => obj.append_uint(sec, val, 1),
+                9...16 => obj.append_uint(sec, val, 2),
+                17...32 => obj.append_uint(sec, val, 4),
+                33...64 => obj.append_uint(sec, val, 8),
+                _ => panic!("unimplemented int length: {}", len)
+            };
+        }
+        &Constant::IntEx(ref val) => {
+            assert!(val.len() == 2);
+            obj.append_uint(sec, val[0], 8);
+            obj.append_uint(sec, val[1], 8);
+        }
+        &Constant::Float(val) => {
+            obj.append_uint(sec, f32_to_raw(val) as u64, 4);
+        }
+        &Constant::Double(val) => {
+            obj.append_uint(sec, f64_to_raw(val) as u64, 8);
+        }
+        &Constant::NullRef => {
+            obj.append_uint(sec, 0, 8);
+        }
+        &Constant::ExternSym(ref name) => {
+            obj.append_symbol_address(sec, name);
+        }
+        &Constant::List(ref vals) => {
+            for val in vals {
+                append_const_value(obj, sec, val)
+            }
+        }
+        _ => unimplemented!()
+    }
+}
+
+/// emits the persisted heap into context_heap.o under the emit directory.
+/// This is the object file counterpart of the heap part of the assembly context file:
+/// each object is preceded by its header, global cells get global symbols (and a
+/// C-accessible alias if their name is a valid C identifier), and reference fields
+/// and client named fields are filled in by relocations.
+pub fn emit_persisted_heap(
+    vm: &VM,
+    objects: &LinkedHashMap<Address, ObjectDump>,
+    relocatable_refs: &HashMap<Address, String>,
+    fields: &HashMap<Address, MuName>,
+    global_names: &HashMap<Address, MuName>,
+    client_labels: &HashSet<String>
+) {
+    let mut obj = ElfObject::new(ElfMachine::for_target(vm.target_arch()));
+    let data = obj.add_section(".data", SectionKind::Data);
+
+    for obj_dump in objects.values() {
+        // object metadata, 8 bytes aligned, 24 bytes
+        obj.align(data, 8);
+        for word in obj_dump.encode.as_raw().iter() {
+            obj.append_uint(data, *word, 8);
+        }
+
+        obj.align(data, cmp::min(obj_dump.align, CONST_ALIGN));
+        let start = obj.offset(data);
+
+        // global cells can be accessed from other objects
+        if let Some(demangled_name) = global_names.get(&obj_dump.addr) {
+            obj.define_symbol(
+                &mangle_name(demangled_name.clone()),
+                data,
+                start,
+                obj_dump.size,
+                SymbolBinding::Global,
+                SymbolType::Object
+            );
+            if is_valid_c_identifier(demangled_name) {
+                obj.define_symbol(
+                    demangled_name,
+                    data,
+                    start,
+                    obj_dump.size,
+                    SymbolBinding::Global,
+                    SymbolType::Object
+                );
+            }
+        }
+
+        // dump label for this object (so it can be referred to from other dumped objects).
+        // Labels named by the client are exported so they can be resolved at load time
+        let dump_label = relocatable_refs.get(&obj_dump.addr).unwrap();
+        if !obj.has_symbol(dump_label) {
+            let binding = if client_labels.contains(dump_label) {
+                SymbolBinding::Global
+            } else {
+                SymbolBinding::Local
+            };
+            obj.define_symbol(
+                dump_label,
+                data,
+                start,
+                obj_dump.size,
+                binding,
+                SymbolType::Object
+            );
+        }
+
+        let base = obj_dump.addr;
+        assert!(base.is_aligned_to(POINTER_SIZE));
+
+        let mut offset = 0;
+        while offset < obj_dump.size {
+            let cur_addr = base + offset;
+
+            if obj_dump.reference_offsets.contains(&offset) {
+                let load_ref = unsafe { cur_addr.load::<Address>() };
+                if load_ref.is_zero() {
+                    obj.append_uint(data, 0, 8);
+                } else {
+                    let label = match relocatable_refs.get(&load_ref) {
+                        Some(label) => label,
+                        None => {
+                            panic!(
+                                "cannot find label for address {}, it is not dumped by GC \
+                                 (why GC didn't trace to it?)",
+                                load_ref
+                            )
+                        }
+                    };
+                    obj.append_symbol_address(data, label);
+                }
+            } else if let Some(label) = fields.get(&cur_addr) {
+                obj.append_symbol_address(data, &mangle_name(label.clone()));
+            } else {
+                // plain data (the last word may be partial)
+                let len = cmp::min(obj_dump.size - offset, POINTER_SIZE);
+                let bytes: Vec<u8> = (0..len)
+                    .map(|i| unsafe { (cur_addr + i).load::<u8>() })
+                    .collect();
+                obj.append(data, &bytes);
+            }
+
+            offset += POINTER_SIZE;
+        }
+    }
+
+    let mut path = Path::new(&vm.vm_options.flag_aot_emit_dir).to_path_buf();
+    path.push(AOT_EMIT_HEAP_OBJ_FILE);
+    obj.write_to_file(path.as_path());
+}
+
+/// sizes of the sections in an object file, by what they hold
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ObjectSizes {
+    /// bytes of executable sections
+    pub code: ByteSize,
+    /// bytes of other allocated sections (data, read-only data and bss)
+    pub data: ByteSize,
+    /// bytes of unwind tables
+    pub unwind: ByteSize,
+    /// number of relocation entries
+    pub relocations: usize
+}
+
+/// reads the section headers of an ELF64 (little endian) object file, and sums up the sizes
+/// of its sections. Returns None if the file cannot be read or is not such an object file
+pub fn read_object_sizes(path: &Path) -> Option<ObjectSizes> {
+    use std::io::Read;
+
+    let mut buf = vec![];
+    match File::open(path) {
+        Ok(mut file) => {
+            if file.read_to_end(&mut buf).is_err() {
+                return None;
+            }
+        }
+        Err(_) => return None
+    }
+    if buf.len() < ELF_HEADER_SIZE || &buf[0..4] != b"\x7fELF" || buf[4] != 2 || buf[5] != 1 {
+        return None;
+    }
+
+    let shoff = get_u64(&buf, 0x28) as usize;
+    let shentsize = get_u16(&buf, 0x3a) as usize;
+    let shnum = get_u16(&buf, 0x3c) as usize;
+    let shstrndx = get_u16(&buf, 0x3e) as usize;
+    if shentsize != SECTION_HEADER_SIZE || shstrndx >= shnum ||
+        shoff + shnum * shentsize > buf.len()
+    {
+        return None;
+    }
+    let strtab = get_u64(&buf, shoff + shstrndx * shentsize + 0x18) as usize;
+
+    let mut sizes = ObjectSizes::default();
+    for i in 0..shnum {
+        let hdr = shoff + i * shentsize;
+        let name = get_str(&buf, strtab + get_u32(&buf, hdr) as usize);
+        let sh_type = get_u32(&buf, hdr + 0x4);
+        let sh_flags = get_u64(&buf, hdr + 0x8);
+        let sh_size = get_u64(&buf, hdr + 0x20) as ByteSize;
+        let sh_entsize = get_u64(&buf, hdr + 0x38) as ByteSize;
+
+        if sh_type == SHT_RELA || sh_type == SHT_REL {
+            if sh_entsize != 0 {
+                sizes.relocations += sh_size / sh_entsize;
+            }
+        } else if name == ".eh_frame" {
+            sizes.unwind += sh_size;
+        } else if sh_flags & SHF_ALLOC != 0 {
+            if sh_flags & SHF_EXECINSTR != 0 {
+                sizes.code += sh_size;
+            } else {
+                sizes.data += sh_size;
+            }
+        }
+    }
+
+    Some(sizes)
+}
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    (0..2).fold(0, |v, i| v | (buf[offset + i] as u16) << (i * 8))
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    (0..4).fold(0, |v, i| v | (buf[offset + i] as u32) << (i * 8))
+}
+
+fn get_u64(buf: &[u8], offset: usize) -> u64 {
+    (0..8).fold(0, |v, i| v | (buf[offset + i] as u64) << (i * 8))
+}
+
+/// gets the null-terminated string at the offset (empty if it is out of the buffer)
+fn get_str(buf: &[u8], offset: usize) -> &str {
+    if offset >= buf.len() {
+        return "";
+    }
+    let len = buf[offset..].iter().position(|b| *b == 0).unwrap_or(0);
+    ::std::str::from_utf8(&buf[offset..offset + len]).unwrap_or("")
+}
@@ -12,8 +12,43 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::inst_sel::*;
+use ast::ir::*;
+use compiler::CompilerPass;
+use compiler::backend::TargetArch;
+use compiler::backend::x86_64;
+use compiler::backend::aarch64;
+use vm::VM;
+use std::any::Any;
 
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::inst_sel::*;
+/// InstructionSelection selects machine instructions for the target architecture of the VM.
+/// It holds the instruction selection pass of every backend, and runs the one for the target
+pub struct InstructionSelection {
+    x86_64: x86_64::inst_sel::InstructionSelection,
+    aarch64: aarch64::inst_sel::InstructionSelection
+}
+
+impl InstructionSelection {
+    pub fn new() -> InstructionSelection {
+        InstructionSelection {
+            x86_64: x86_64::inst_sel::InstructionSelection::new(),
+            aarch64: aarch64::inst_sel::InstructionSelection::new()
+        }
+    }
+}
+
+impl CompilerPass for InstructionSelection {
+    fn name(&self) -> &'static str {
+        "Instruction Selection"
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn execute(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        match vm.target_arch() {
+            TargetArch::X86_64 => self.x86_64.execute(vm, func),
+            TargetArch::AArch64 => self.aarch64.execute(vm, func)
+        }
+    }
+}
@@ -22,6 +22,12 @@ pub mod reg_alloc;
 pub mod peephole_opt;
 /// Code emission pass. May as well emit dot graph for IR and generated code.
 pub mod code_emission;
+/// ELF64 relocatable object writer. Used to emit the persisted heap and constants
+/// for ahead-of-time compilation without going through the assembler
+/// (function code is still emitted as assembly).
+pub mod elf;
+/// DWARF debug information for compiled functions (line tables, subprograms, variables).
+pub mod dwarf;
 
 use std;
 use utils::*;
@@ -41,49 +47,229 @@ pub const AOT_EMIT_SYM_TABLE_FILE: &'static str = "mu_sym_table.S";
 pub type Reg<'a> = &'a P<Value>;
 pub type Mem<'a> = &'a P<Value>;
 
-// re-export some common backend functions to allow target independent code
-
 /// --- X86_64 backend ---
-#[cfg(target_arch = "x86_64")]
 #[path = "arch/x86_64/mod.rs"]
 pub mod x86_64;
 
+/// --- aarch64 backend ---
+#[path = "arch/aarch64/mod.rs"]
+pub mod aarch64;
+
+/// TargetArch is the architecture that a VM generates code for. Both backends are built into
+/// Zebu, and a VM chooses one with --target-arch (which defaults to the host).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TargetArch {
+    X86_64,
+    AArch64
+}
+
+impl TargetArch {
+    /// returns the architecture that we are running on
+    #[cfg(target_arch = "x86_64")]
+    pub fn host() -> TargetArch {
+        TargetArch::X86_64
+    }
+
+    /// returns the architecture that we are running on
+    #[cfg(target_arch = "aarch64")]
+    pub fn host() -> TargetArch {
+        TargetArch::AArch64
+    }
+
+    /// gets a TargetArch from its name (as used in --target-arch)
+    pub fn from_string(s: &str) -> TargetArch {
+        match s {
+            "host" => TargetArch::host(),
+            "x86_64" => TargetArch::X86_64,
+            "aarch64" => TargetArch::AArch64,
+            _ => panic!("unrecognised target architecture {}", s)
+        }
+    }
+
+    /// returns the name of this architecture (as used in --target-arch)
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TargetArch::X86_64 => "x86_64",
+            TargetArch::AArch64 => "aarch64"
+        }
+    }
+
+    /// is this the architecture that we are running on?
+    /// (code for other architectures cannot be loaded or run in this process)
+    pub fn is_host(&self) -> bool {
+        *self == TargetArch::host()
+    }
+}
+
+impl Default for TargetArch {
+    fn default() -> TargetArch {
+        TargetArch::host()
+    }
+}
+
+rodal_value!(TargetArch);
+
+// target dependent functions used by the compiler. They dispatch to the backend of
+// the target architecture of the VM
+
 /// estimates how many machine instructions are needed for a Mu instruction
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::estimate_insts_for_ir;
+pub fn estimate_insts_for_ir(inst: &Instruction, vm: &VM) -> usize {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::estimate_insts_for_ir(inst),
+        TargetArch::AArch64 => aarch64::estimate_insts_for_ir(inst)
+    }
+}
+
 /// initializes machine registers in the function context
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::init_machine_regs_for_func;
+pub fn init_machine_regs_for_func(func_context: &mut FunctionContext, vm: &VM) {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::init_machine_regs_for_func(func_context),
+        TargetArch::AArch64 => aarch64::init_machine_regs_for_func(func_context)
+    }
+}
+
 /// checks if two machine registers are alias (the same register)
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::is_aliased;
+pub fn is_aliased(id1: MuID, id2: MuID, vm: &VM) -> bool {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::is_aliased(id1, id2),
+        TargetArch::AArch64 => aarch64::is_aliased(id1, id2)
+    }
+}
+
 /// gets color for a machine register (e.g. AH, AX, EAX all have color of RAX)
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::get_color_for_precolored;
+pub fn get_color_for_precolored(id: MuID, vm: &VM) -> MuID {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::get_color_for_precolored(id),
+        TargetArch::AArch64 => aarch64::get_color_for_precolored(id)
+    }
+}
+
 /// returns the number of registers in a given RegGroup
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::number_of_usable_regs_in_group;
+pub fn number_of_usable_regs_in_group(group: RegGroup, vm: &VM) -> usize {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::number_of_usable_regs_in_group(group),
+        TargetArch::AArch64 => aarch64::number_of_usable_regs_in_group(group)
+    }
+}
+
 /// returns the number of all machine registers
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::number_of_all_regs;
+pub fn number_of_all_regs(vm: &VM) -> usize {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::number_of_all_regs(),
+        TargetArch::AArch64 => aarch64::number_of_all_regs()
+    }
+}
+
 /// returns a hashmap of all the machine registers
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::all_regs;
+pub fn all_regs(vm: &VM) -> &'static LinkedHashMap<MuID, P<Value>> {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::all_regs(),
+        TargetArch::AArch64 => aarch64::all_regs()
+    }
+}
+
 /// returns all usable registers (machine registers that can be assigned to temporaries)
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::all_usable_regs;
+pub fn all_usable_regs(vm: &VM) -> &'static Vec<P<Value>> {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::all_usable_regs(),
+        TargetArch::AArch64 => aarch64::all_usable_regs()
+    }
+}
+
 /// returns RegGroup for a machine register
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::pick_group_for_reg;
+pub fn pick_group_for_reg(reg_id: MuID, vm: &VM) -> RegGroup {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::pick_group_for_reg(reg_id),
+        TargetArch::AArch64 => aarch64::pick_group_for_reg(reg_id)
+    }
+}
+
 /// checks if a register is callee saved
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::is_callee_saved;
+pub fn is_callee_saved(reg_id: MuID, vm: &VM) -> bool {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::is_callee_saved(reg_id),
+        TargetArch::AArch64 => aarch64::is_callee_saved(reg_id)
+    }
+}
+
+/// gets offset for callee saved registers (used for exception table)
+pub fn get_callee_saved_offset(reg: MuID, vm: &VM) -> isize {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::get_callee_saved_offset(reg),
+        TargetArch::AArch64 => aarch64::get_callee_saved_offset(reg)
+    }
+}
+
+/// emits code for a function version (the function needs to be compiled first)
+pub fn emit_code(func: &mut MuFunctionVersion, vm: &VM) {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::emit_code(func, vm),
+        TargetArch::AArch64 => aarch64::emit_code(func, vm)
+    }
+}
+
+/// emits context (persisted VM/heap/etc), should only be called after
+/// finishing compilation for all functions
+pub fn emit_context(vm: &VM) {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::emit_context(vm),
+        TargetArch::AArch64 => aarch64::emit_context(vm)
+    }
+}
+
+/// emits context with consideration of relocation info
+pub fn emit_context_with_reloc(
+    vm: &VM,
+    symbols: HashMap<Address, MuName>,
+    fields: HashMap<Address, MuName>,
+    primordial_stack: Option<Address>,
+    primordial_threadlocal: Option<Address>
+) {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::emit_context_with_reloc(
+            vm,
+            symbols,
+            fields,
+            primordial_stack,
+            primordial_threadlocal
+        ),
+        TargetArch::AArch64 => aarch64::emit_context_with_reloc(
+            vm,
+            symbols,
+            fields,
+            primordial_stack,
+            primordial_threadlocal
+        )
+    }
+}
+
+/// rewrites a compiled Mu function with given spilling info
+/// (inserting load/store for spilled temporaries)
+pub fn spill_rewrite(
+    spills: &LinkedHashMap<MuID, P<Value>>,
+    func: &mut MuFunctionVersion,
+    cf: &mut CompiledFunction,
+    vm: &VM
+) -> LinkedHashMap<MuID, MuID> {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::spill_rewrite(spills, func, cf, vm),
+        TargetArch::AArch64 => aarch64::spill_rewrite(spills, func, cf, vm)
+    }
+}
+
+/// returns the DWARF register number for a machine register (for debug info)
+pub fn get_dwarf_reg_number(id: MuID, vm: &VM) -> Option<u16> {
+    match vm.target_arch() {
+        TargetArch::X86_64 => x86_64::get_dwarf_reg_number(id),
+        TargetArch::AArch64 => aarch64::get_dwarf_reg_number(id)
+    }
+}
+
+// the runtime runs on the host, it uses the host backend to walk frames and set up stacks
+
 /// number of callee saved registers
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::CALLEE_SAVED_COUNT;
-/// gets offset for callee saved registers (used for exception table)
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::get_callee_saved_offset;
 /// gets frame pointer for previous frame
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::get_previous_frame_pointer;
@@ -99,67 +285,19 @@ pub use compiler::backend::x86_64::set_return_address;
 /// gets staci pointer for previous frame
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::get_previous_stack_pointer;
-/// emits code for a function version (the function needs to be compiled first)
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::emit_code;
-/// emits context (persisted VM/heap/etc), should only be called after
-/// finishing compilation for all functions
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::emit_context;
-/// emits context with consideration of relocation info
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::emit_context_with_reloc;
-/// rewrites a compiled Mu function with given spilling info
-/// (inserting load/store for spilled temporaries)
-#[cfg(target_arch = "x86_64")]
-pub use compiler::backend::x86_64::spill_rewrite;
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::ARGUMENT_GPRS;
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::ARGUMENT_FPRS;
 #[cfg(target_arch = "x86_64")]
 pub use compiler::backend::x86_64::call_stack_size;
+/// patches the entry of compiled code to jump to another entry (for function redefinition)
+#[cfg(target_arch = "x86_64")]
+pub use compiler::backend::x86_64::patch_code_entry;
 
-/// --- aarch64 backend ---
-#[cfg(target_arch = "aarch64")]
-#[path = "arch/aarch64/mod.rs"]
-pub mod aarch64;
-
-/// estimates how many machine instructions are needed for a Mu instruction
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::estimate_insts_for_ir;
-/// initializes machine registers in the function context
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::init_machine_regs_for_func;
-/// checks if two machine registers are alias (the same register)
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::is_aliased;
-/// gets color for a machine register
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::get_color_for_precolored;
-/// returns the number of registers in a given RegGroup
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::number_of_usable_regs_in_group;
-/// returns the number of all machine registers
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::number_of_all_regs;
-/// returns a hashmap of all the machine registers
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::all_regs;
-/// returns all usable registers (machine registers that can be assigned to temporaries)
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::all_usable_regs;
-/// returns RegGroup for a machine register
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::pick_group_for_reg;
-/// checks if a register is callee saved
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::is_callee_saved;
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::CALLEE_SAVED_COUNT;
 #[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::get_callee_saved_offset;
-#[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::get_previous_frame_pointer;
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::get_return_address;
@@ -169,31 +307,62 @@ pub use compiler::backend::aarch64::get_previous_stack_pointer;
 pub use compiler::backend::aarch64::set_previous_frame_pointer;
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::set_return_address;
-/// emits code for a function version (the function needs to be compiled first)
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::emit_code;
-/// emits context (persisted VM/heap/etc), should only be called after
-/// finishing compilation for all functions
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::emit_context;
-/// emits context with consideration of relocation info
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::emit_context_with_reloc;
-/// rewrites a compiled Mu function with given spilling info
-/// (inserting load/store for spilled temporaries)
-#[cfg(target_arch = "aarch64")]
-pub use compiler::backend::aarch64::spill_rewrite;
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::ARGUMENT_GPRS;
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::ARGUMENT_FPRS;
 #[cfg(target_arch = "aarch64")]
 pub use compiler::backend::aarch64::call_stack_size;
+/// patches the entry of compiled code to jump to another entry (for function redefinition)
+#[cfg(target_arch = "aarch64")]
+pub use compiler::backend::aarch64::patch_code_entry;
 
 use vm::VM;
 use ast::types::*;
 use ast::ptr::*;
 use ast::ir::*;
+use ast::inst::Instruction;
+use compiler::machine_code::CompiledFunction;
+use std::collections::HashMap;
+
+/// overwrites the loaded code at the given address with the given word in a single store,
+/// so that a thread executing the code sees either the old or the new word
+/// (the address needs to be aligned to the size of the word, and the pages are made writable
+/// during patching, and are made read/exec afterwards)
+pub fn patch_code_word<T: Copy>(addr: Address, word: T) {
+    use std::mem;
+    use std::ptr;
+    use utils::mem::memsec;
+    use runtime::thread::PAGE_SIZE;
+
+    let size = mem::size_of::<T>();
+    assert!(size <= 8 && addr.is_aligned_to(size));
+
+    let page_start = unsafe { Address::from_usize(addr.as_usize() & !(PAGE_SIZE - 1)) };
+    let page_end = (addr + size).align_up(PAGE_SIZE);
+    let len = page_end - page_start;
+
+    unsafe {
+        memsec::mprotect(
+            page_start.to_ptr_mut::<u8>(),
+            len,
+            memsec::Prot::ReadWriteExec
+        );
+        // an aligned store of at most 8 bytes is single-copy atomic on both x86_64 and aarch64
+        ptr::write_volatile(addr.to_ptr_mut::<T>(), word);
+        memsec::mprotect(page_start.to_ptr_mut::<u8>(), len, memsec::Prot::ReadExec);
+    }
+
+    // make sure the processor does not execute stale instructions
+    if cfg!(target_arch = "aarch64") {
+        extern "C" {
+            fn __clear_cache(start: *mut u8, end: *mut u8);
+        }
+        unsafe { __clear_cache(addr.to_ptr_mut::<u8>(), (addr + size).to_ptr_mut::<u8>()) };
+    }
+
+    trace!("patched {} bytes at {}", size, addr);
+}
 
 /// BackendType describes storage type info for a MuType, including
 /// size, alignment, struct layout, array element padded size, GC type.
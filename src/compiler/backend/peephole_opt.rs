@@ -44,7 +44,7 @@ impl CompilerPass for PeepholeOptimization {
             // if two sides of a move instruction are the same,
             // it is redundant, and can be eliminated
             trace!("trying to remove redundant move");
-            self.remove_redundant_move(i, &mut cf);
+            self.remove_redundant_move(i, &mut cf, vm);
         }
 
         // then remove jumps (because removing movs will affect this)
@@ -82,7 +82,7 @@ impl PeepholeOptimization {
         }
     }
 
-    fn remove_redundant_move(&mut self, inst: usize, cf: &mut CompiledFunction) {
+    fn remove_redundant_move(&mut self, inst: usize, cf: &mut CompiledFunction, vm: &VM) {
         // if this instruction is a move, and move from register to register (no memory operands)
         if cf.mc().is_move(inst) && !cf.mc().is_using_mem_op(inst) {
             // get source reg/temp ID
@@ -113,7 +113,7 @@ impl PeepholeOptimization {
             };
 
             // check if two registers are aliased
-            if backend::is_aliased(src_machine_reg, dst_machine_reg) {
+            if backend::is_aliased(src_machine_reg, dst_machine_reg, vm) {
                 info!(
                     "move between {} and {} is redundant! removed",
                     src_machine_reg,
@@ -132,7 +132,7 @@ impl<'a> GraphColoring<'a> {
         trace!("Initializing coloring allocator...");
         cf.mc().trace_mc();
 
-        let ig = graph_coloring::build_inteference_graph(cf, func);
+        let ig = graph_coloring::build_inteference_graph(cf, func, vm);
 
         let coloring = GraphColoring {
             func: func,
@@ -179,15 +179,15 @@ impl<'a> GraphColoring<'a> {
         let _p = hprof::enter("regalloc: graph coloring");
 
         // precolor for all machine registers
-        for reg in backend::all_regs().values() {
+        for reg in backend::all_regs(self.vm).values() {
             let reg_id = reg.extract_ssa_id().unwrap();
             self.precolored.insert(reg_id);
         }
 
         // put usable registers as available colors
-        for reg in backend::all_usable_regs().iter() {
+        for reg in backend::all_usable_regs(self.vm).iter() {
             let reg_id = reg.extract_ssa_id().unwrap();
-            let group = backend::pick_group_for_reg(reg_id);
+            let group = backend::pick_group_for_reg(reg_id, self.vm);
             self.colors.get_mut(&group).unwrap().insert(reg_id);
         }
 
@@ -519,7 +519,7 @@ impl<'a> GraphColoring<'a> {
     }
 
     fn n_regs_for_node(&self, node: MuID) -> usize {
-        backend::number_of_usable_regs_in_group(self.ig.get_group_of(node))
+        backend::number_of_usable_regs_in_group(self.ig.get_group_of(node), self.vm)
     }
 
     fn is_move_related(&mut self, node: MuID) -> bool {
@@ -691,7 +691,7 @@ impl<'a> GraphColoring<'a> {
 
         // if u or v is a machine register that is not usable/not a color, we cannot coalesce
         if precolored_u {
-            let group = backend::pick_group_for_reg(u);
+            let group = backend::pick_group_for_reg(u, self.vm);
             if !self.colors.get(&group).unwrap().contains(&u) {
                 if !precolored_v {
                     self.add_worklist(v);
@@ -702,7 +702,7 @@ impl<'a> GraphColoring<'a> {
             }
         }
         if precolored_v {
-            let group = backend::pick_group_for_reg(v);
+            let group = backend::pick_group_for_reg(v, self.vm);
             if !self.colors.get(&group).unwrap().contains(&v) {
                 if !precolored_u {
                     self.add_worklist(u);
@@ -852,7 +852,7 @@ impl<'a> GraphColoring<'a> {
     }
 
     fn add_edge(&mut self, u: MuID, v: MuID) {
-        self.ig.add_edge(u, v);
+        self.ig.add_edge(u, v, self.vm);
     }
 
     fn freeze(&mut self) {
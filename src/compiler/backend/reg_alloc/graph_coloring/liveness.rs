@@ -17,6 +17,7 @@ extern crate hprof;
 use compiler::machine_code::CompiledFunction;
 use ast::ir::*;
 use compiler::backend;
+use vm::VM;
 use utils::LinkedHashSet;
 use utils::LinkedHashMap;
 use std::fmt;
@@ -81,10 +82,10 @@ fn is_precolored(reg: MuID) -> bool {
 }
 
 #[inline(always)]
-fn is_usable(reg: MuID) -> bool {
-    if backend::all_usable_regs()
+fn is_usable(reg: MuID, vm: &VM) -> bool {
+    if backend::all_usable_regs(vm)
         .iter()
-        .any(|x| x.id() == backend::get_color_for_precolored(reg))
+        .any(|x| x.id() == backend::get_color_for_precolored(reg, vm))
     {
         true
     } else {
@@ -95,9 +96,9 @@ fn is_usable(reg: MuID) -> bool {
 #[inline(always)]
 /// checks if a reg is machine register. If so, return its color
 /// otherwise return the reg
-fn c(u: MuID) -> MuID {
+fn c(u: MuID, vm: &VM) -> MuID {
     if is_precolored(u) {
-        backend::get_color_for_precolored(u)
+        backend::get_color_for_precolored(u, vm)
     } else {
         u
     }
@@ -188,11 +189,11 @@ impl InterferenceGraph {
     }
 
     /// adds a move edge between two nodes
-    fn add_move(&mut self, src: MuID, dst: MuID) {
+    fn add_move(&mut self, src: MuID, dst: MuID, vm: &VM) {
         let src = {
             if is_precolored(src) {
                 // get the color for the machine register, e.g. rax for eax/ax/al/ah
-                backend::get_color_for_precolored(src)
+                backend::get_color_for_precolored(src, vm)
             } else {
                 src
             }
@@ -200,7 +201,7 @@ impl InterferenceGraph {
 
         let dst = {
             if is_precolored(dst) {
-                backend::get_color_for_precolored(dst)
+                backend::get_color_for_precolored(dst, vm)
             } else {
                 dst
             }
@@ -210,15 +211,15 @@ impl InterferenceGraph {
     }
 
     /// adds an interference edge between two nodes
-    pub fn add_edge(&mut self, u: MuID, v: MuID) {
+    pub fn add_edge(&mut self, u: MuID, v: MuID, vm: &VM) {
         // if one of the node is machine register, we add
         // interference edge to its alias
         // e.g. if we have %a - %edi interfered,
         // we will add %a - %rdi interference
 
         let u = if is_precolored(u) {
-            if is_usable(u) {
-                backend::get_color_for_precolored(u)
+            if is_usable(u, vm) {
+                backend::get_color_for_precolored(u, vm)
             } else {
                 // if it is not usable, we do not need to add an interference edge
                 return;
@@ -227,8 +228,8 @@ impl InterferenceGraph {
             u
         };
         let v = if is_precolored(v) {
-            if is_usable(v) {
-                backend::get_color_for_precolored(v)
+            if is_usable(v, vm) {
+                backend::get_color_for_precolored(v, vm)
             } else {
                 return;
             }
@@ -367,7 +368,8 @@ const TRACE_LIVENESS: bool = false;
 /// - CGO'06, Figure 4
 pub fn build_interference_graph_chaitin_briggs(
     cf: &mut CompiledFunction,
-    func: &MuFunctionVersion
+    func: &MuFunctionVersion,
+    vm: &VM
 ) -> InterferenceGraph {
     use compiler::backend::reg_alloc::graph_coloring::liveness::NodeType::*;
 
@@ -381,10 +383,10 @@ pub fn build_interference_graph_chaitin_briggs(
     let mut ig = InterferenceGraph::new();
 
     // precolor machine register nodes
-    for reg in backend::all_regs().values() {
-        let reg_id = c(reg.extract_ssa_id().unwrap());
+    for reg in backend::all_regs(vm).values() {
+        let reg_id = c(reg.extract_ssa_id().unwrap(), vm);
         let node = ig.new_node(reg_id, Machine, 0, &func.context);
-        let precolor = backend::get_color_for_precolored(reg_id);
+        let precolor = backend::get_color_for_precolored(reg_id, vm);
 
         ig.color_node(node, precolor);
     }
@@ -405,22 +407,22 @@ pub fn build_interference_graph_chaitin_briggs(
             // as the move instruction can be eliminated)
             if mc.is_move(i) {
                 for reg_id in mc.get_inst_reg_defines(i) {
-                    let reg_id = c(reg_id);
+                    let reg_id = c(reg_id, vm);
                     ig.new_node(reg_id, Copy, loop_depth, &func.context);
                 }
 
                 for reg_id in mc.get_inst_reg_uses(i) {
-                    let reg_id = c(reg_id);
+                    let reg_id = c(reg_id, vm);
                     ig.new_node(reg_id, Copy, loop_depth, &func.context);
                 }
             } else {
                 for reg_id in mc.get_inst_reg_defines(i) {
-                    let reg_id = c(reg_id);
+                    let reg_id = c(reg_id, vm);
                     ig.new_node(reg_id, Def, loop_depth, &func.context);
                 }
 
                 for reg_id in mc.get_inst_reg_uses(i) {
-                    let reg_id = c(reg_id);
+                    let reg_id = c(reg_id, vm);
                     ig.new_node(reg_id, Use, loop_depth, &func.context);
                 }
             }
@@ -485,10 +487,10 @@ pub fn build_interference_graph_chaitin_briggs(
                         None
                     } else {
                         if src.len() == 1 {
-                            let src = c(src[0]);
-                            let dst = c(dst[0]);
+                            let src = c(src[0], vm);
+                            let dst = c(dst[0], vm);
                             trace_if!(TRACE_LIVENESS, "add move {} -> {}", src, dst);
-                            ig.add_move(src, dst);
+                            ig.add_move(src, dst, vm);
 
                             Some(src)
                         } else {
@@ -502,7 +504,7 @@ pub fn build_interference_graph_chaitin_briggs(
 
             let defines = cf.mc().get_inst_reg_defines(i);
             for d in defines.iter() {
-                let d = c(*d);
+                let d = c(*d, vm);
                 current_live.insert(d);
             }
             if TRACE_LIVENESS {
@@ -521,7 +523,7 @@ pub fn build_interference_graph_chaitin_briggs(
                 src
             );
             for d in defines {
-                let d = c(d);
+                let d = c(d, vm);
                 // add an interference from D to every element E in Current_Live - {D}
                 // creating nodes if necessary
                 for e in current_live.iter() {
@@ -532,11 +534,11 @@ pub fn build_interference_graph_chaitin_briggs(
                         if !ig.is_same_node(from, to) && ig.is_same_group(from, to) {
                             if !ig.is_colored(from) {
                                 trace_if!(TRACE_LIVENESS, "add edge between {} and {}", d, *e);
-                                ig.add_edge(from, to);
+                                ig.add_edge(from, to, vm);
                             }
                             if !ig.is_colored(to) {
                                 trace_if!(TRACE_LIVENESS, "add edge between {} and {}", *e, d);
-                                ig.add_edge(to, from);
+                                ig.add_edge(to, from, vm);
                             }
                         }
                     }
@@ -545,7 +547,7 @@ pub fn build_interference_graph_chaitin_briggs(
 
             // for every definition D in I
             for d in cf.mc().get_inst_reg_defines(i) {
-                let d = c(d);
+                let d = c(d, vm);
                 // remove D from Current_Live
                 current_live.remove(&d);
             }
@@ -556,7 +558,7 @@ pub fn build_interference_graph_chaitin_briggs(
 
             // for every use U in I
             for u in cf.mc().get_inst_reg_uses(i) {
-                let u = c(u);
+                let u = c(u, vm);
                 // add U to Current_live
                 current_live.insert(u);
             }
@@ -664,7 +666,7 @@ fn build_cfg_nodes(cf: &mut CompiledFunction) -> LinkedHashMap<MuName, CFGBlockN
 
                 // if a reg is used but not defined before, it is a live-in
                 for reg in reg_uses {
-                    let reg = c(reg);
+                    let reg = c(reg, vm);
                     if !all_defined.contains(&reg) {
                         livein.push(reg);
                     }
@@ -672,7 +674,7 @@ fn build_cfg_nodes(cf: &mut CompiledFunction) -> LinkedHashMap<MuName, CFGBlockN
 
                 let reg_defs = mc.get_inst_reg_defines(i);
                 for reg in reg_defs {
-                    let reg = c(reg);
+                    let reg = c(reg, vm);
                     all_defined.insert(reg);
                 }
             }
@@ -60,7 +60,7 @@ impl RegisterAllocation {
         let mut cf = compiled_funcs.get(&func.id()).unwrap().write().unwrap();
 
         // initialize machine registers for the function context (we are gonna use them)
-        init_machine_regs_for_func(&mut func.context);
+        init_machine_regs_for_func(&mut func.context, vm);
 
         // do graph coloring
         let coloring = GraphColoring::start(func, &mut cf, vm);
@@ -73,7 +73,7 @@ impl RegisterAllocation {
             // we use this to validate spilling correctness
             let spill_scratch_temps = coloring.get_spill_scratch_temps();
 
-            validate::validate_regalloc(&coloring.cf, reg_assignment, spill_scratch_temps);
+            validate::validate_regalloc(&coloring.cf, reg_assignment, spill_scratch_temps, vm);
         }
 
         // use the result to replace temporaries with assigned regs
@@ -98,7 +98,7 @@ impl RegisterAllocation {
                     .temps
                     .values()
                     .map(|x| *x)
-                    .filter(|x| is_callee_saved(*x))
+                    .filter(|x| is_callee_saved(*x, vm))
                     .collect();
                 used_callee_saved.into_iter().collect()
             };
@@ -109,7 +109,7 @@ impl RegisterAllocation {
                 .mc_mut()
                 .remove_unnecessary_callee_saved(used_callee_saved);
             for reg in removed_callee_saved {
-                coloring.cf.frame.remove_record_for_callee_saved_reg(reg);
+                coloring.cf.frame.remove_record_for_callee_saved_reg(reg, vm);
             }
 
             // patch frame size
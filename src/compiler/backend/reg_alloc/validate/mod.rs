@@ -23,7 +23,9 @@ use utils::LinkedHashMap;
 use ast::ir::*;
 use ast::ptr::*;
 use compiler::machine_code::CompiledFunction;
+use vm::VM;
 use compiler::backend::get_color_for_precolored as alias;
+use compiler::backend::TargetArch;
 use compiler::PROLOGUE_BLOCK_NAME;
 
 mod alive_entry;
@@ -39,7 +41,8 @@ const VERIFY_SPILLING: bool = false;
 pub fn validate_regalloc(
     cf: &CompiledFunction,
     reg_assigned: LinkedHashMap<MuID, MuID>,
-    spill_scratch_regs: LinkedHashMap<MuID, MuID>
+    spill_scratch_regs: LinkedHashMap<MuID, MuID>,
+    vm: &VM
 ) {
     debug!("---Validating register allocation results---");
 
@@ -58,12 +61,12 @@ pub fn validate_regalloc(
     // set up initial states
 
     // machine specific regs, such as program counter, stack pointer, etc
-    add_machine_specific_regs_at_func_start(&mut alive);
+    add_machine_specific_regs_at_func_start(&mut alive, vm);
 
     // arguments with real locations
     let ref frame = cf.frame;
     for (_, reg) in frame.argument_by_reg.iter() {
-        alive.new_alive_reg(alias(reg.id()));
+        alive.new_alive_reg(alias(reg.id(), vm));
     }
 
     debug!("---alive entries in the beginning---");
@@ -463,8 +466,14 @@ fn validate_spill_load(
     }
 }
 
-#[cfg(target_arch = "x86_64")]
-fn add_machine_specific_regs_at_func_start(alive: &mut AliveEntries) {
+fn add_machine_specific_regs_at_func_start(alive: &mut AliveEntries, vm: &VM) {
+    match vm.target_arch() {
+        TargetArch::X86_64 => add_x86_64_regs_at_func_start(alive),
+        TargetArch::AArch64 => add_aarch64_regs_at_func_start(alive)
+    }
+}
+
+fn add_x86_64_regs_at_func_start(alive: &mut AliveEntries) {
     use compiler::backend::x86_64;
 
     // RIP, RSP, RBP always have valid values
@@ -480,8 +489,7 @@ fn add_machine_specific_regs_at_func_start(alive: &mut AliveEntries) {
     alive.new_alive_reg(x86_64::R15.id());
 }
 
-#[cfg(target_arch = "aarch64")]
-fn add_machine_specific_regs_at_func_start(alive: &mut AliveEntries) {
+fn add_aarch64_regs_at_func_start(alive: &mut AliveEntries) {
     use compiler::backend::aarch64;
 
     // the instruction pointer, stack pointer, link register and frame pointer
@@ -0,0 +1,541 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::ptr::*;
+use ast::types::*;
+use compiler::CompilerPolicy;
+use compiler::backend::RegGroup;
+use compiler::backend::elf::ELF_RODATA_SUFFIX;
+use compiler::frame::Frame;
+use compiler::machine_code::CompiledFunction;
+use runtime::ValueLocation;
+use utils::ByteOffset;
+use vm::VM;
+use vm::built_info::ZEBU_VERSION_STR;
+use vm::profile;
+use vm::uir_output::create_emit_directory;
+
+use libc;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// the files that code emission writes for a function (appended to its code symbol)
+const EMITTED_FILE_SUFFIXES: [&'static str; 3] = [".S", ".demangled.S", ELF_RODATA_SUFFIX];
+/// the file in a cache entry that describes the compiled function
+const ENTRY_INFO_FILE: &'static str = "compiled_func";
+
+/// CodeCache keeps the emitted code of compiled function versions in --aot-cache-dir, so a
+/// later build does not need to compile them again.
+/// Every function version is stored as a directory <code symbol>.<key>, where the key is a hash
+/// of everything its code depends on: its IR (with IDs), the IR of the functions it may inline,
+/// the layouts of the types it uses, the compiler passes, the VM options that may change code
+/// generation, the target and the version of Zebu. A function that changes gets a new key, and
+/// replaces its old entry.
+///
+/// An entry only keeps the emitted files (the code, and the constants with --aot-emit-elf), the
+/// frame and the call sites of a function version, which is all that linking a boot image needs.
+/// The machine code, temporaries and constants are not kept, so the cache is not used when a
+/// consumer of them is enabled (see new()).
+pub struct CodeCache {
+    dir: PathBuf,
+    hits: AtomicUsize,
+    misses: AtomicUsize
+}
+
+impl CodeCache {
+    /// creates the code cache for a VM, returns None if --aot-cache-dir is not set.
+    /// The cache is not used with --profile-generate, as instrumenting a function version
+    /// declares the global that holds its counters, or with --emit-debug-info, as the debug
+    /// info refers to the IR listing in the emit directory, and the variable locations are
+    /// taken from the temporaries of the compiled function
+    pub fn new(vm: &VM) -> Option<CodeCache> {
+        let dir = &vm.vm_options.flag_aot_cache_dir;
+        if dir.is_empty() {
+            return None;
+        }
+        if !vm.vm_options.flag_profile_generate.is_empty() {
+            info!("code cache is not used with --profile-generate");
+            return None;
+        }
+        if vm.vm_options.flag_emit_debug_info {
+            info!("code cache is not used with --emit-debug-info");
+            return None;
+        }
+        if let Err(e) = fs::create_dir_all(dir) {
+            panic!("failed to create code cache directory {}: {}", dir, e);
+        }
+
+        Some(CodeCache {
+            dir: PathBuf::from(dir),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0)
+        })
+    }
+
+    /// returns the number of function versions that were restored from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// returns the number of function versions that were not found in the cache
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// reports the cache hits and misses
+    pub fn report(&self) {
+        info!(
+            "code cache {}: {} hits, {} misses",
+            self.dir.display(),
+            self.hits(),
+            self.misses()
+        );
+    }
+
+    /// returns the cache entry name for a function version that is not compiled yet with the
+    /// given policy. This reads the function version, and the function versions it may inline
+    pub fn entry_name(
+        &self,
+        fv_id: MuID,
+        func_vers: &HashMap<MuID, RwLock<MuFunctionVersion>>,
+        policy: &CompilerPolicy,
+        vm: &VM
+    ) -> String {
+        let (func_id, sig) = {
+            let fv = func_vers.get(&fv_id).unwrap().read().unwrap();
+            (fv.func_id, fv.sig.clone())
+        };
+        let code_symbol = vm.get_code_symbol_for_func_ver(func_id, fv_id);
+
+        let mut text = String::new();
+        writeln!(text, "{}", *ZEBU_VERSION_STR).unwrap();
+        let passes: Vec<&str> = policy.passes.iter().map(|pass| pass.name()).collect();
+        writeln!(text, "passes {}", passes.join(" ")).unwrap();
+        writeln!(text, "{}", code_options(vm)).unwrap();
+        writeln!(
+            text,
+            "FuncVer {} of Func {} as {}",
+            fv_id,
+            func_id,
+            code_symbol
+        ).unwrap();
+
+        // the counts that the function version is compiled with
+        if let Some(profile) = profile::get_profile(vm) {
+            let func_name = vm.get_name_for_func(func_id);
+            writeln!(text, "profile {:?}", profile.get_func(&func_name)).unwrap();
+        }
+
+        let mut types = vec![];
+        collect_sig_types(&sig, &mut types);
+
+        // the function version and every function it may inline (inlining uses the original IR)
+        let funcs = vm.funcs().read().unwrap();
+        let mut visited = HashSet::new();
+        let mut work_list = vec![fv_id];
+        visited.insert(fv_id);
+        while let Some(id) = work_list.pop() {
+            let fv = func_vers.get(&id).unwrap().read().unwrap();
+            writeln!(text, "{}: {}", fv.hdr, fv.sig).unwrap();
+            let content = match fv.get_orig_ir() {
+                Some(content) => content,
+                None => continue
+            };
+            writeln!(text, "{:?}", content).unwrap();
+
+            let mut callees = vec![];
+            for block in content.blocks.values() {
+                collect_block(block, &mut callees, &mut types);
+            }
+            for callee in callees {
+                let callee = funcs.get(&callee).unwrap().read().unwrap();
+                writeln!(text, "calls {}: {}", callee.hdr, callee.sig).unwrap();
+                collect_sig_types(&callee.sig, &mut types);
+                if let Some(callee_fv) = callee.cur_ver {
+                    if visited.insert(callee_fv) {
+                        work_list.push(callee_fv);
+                    }
+                }
+            }
+        }
+
+        // the layouts of the types
+        let mut visited_types = HashSet::new();
+        for ty in types.iter() {
+            write_type(ty, &mut visited_types, &mut text);
+        }
+
+        format!("{}.{:016x}", code_symbol, fnv1a(text.as_bytes()))
+    }
+
+    /// restores a function version from its cache entry, and marks it as compiled.
+    /// Returns false (a miss) if there is no valid entry
+    pub fn restore(&self, entry: &str, fv: &mut MuFunctionVersion, vm: &VM) -> bool {
+        let mut dir = self.dir.clone();
+        dir.push(entry);
+
+        let info = match read_entry_info(&dir) {
+            Some(info) => info,
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                info!("code cache miss for {} ({})", fv, entry);
+                return false;
+            }
+        };
+
+        create_emit_directory(vm);
+        for file in info.files.iter() {
+            let mut from = dir.clone();
+            from.push(file);
+            let mut to = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
+            to.push(file);
+            if let Err(e) = fs::copy(&from, &to) {
+                panic!(
+                    "failed to copy {} to {}: {}",
+                    from.display(),
+                    to.display(),
+                    e
+                );
+            }
+        }
+
+        for callsite in info.callsites {
+            vm.add_exception_callsite(callsite, fv.id());
+        }
+        vm.add_compiled_func(CompiledFunction::restore(
+            fv.func_id,
+            fv.id(),
+            Frame::restore(fv.id(), info.frame_offset, info.callee_saved, info.ref_slots),
+            ValueLocation::Relocatable(RegGroup::GPR, Arc::new(info.start)),
+            ValueLocation::Relocatable(RegGroup::GPR, Arc::new(info.end))
+        ));
+        fv.set_compiled();
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        info!("code cache hit for {} ({})", fv, entry);
+        true
+    }
+
+    /// stores a compiled function version in the cache,
+    /// and removes the old entries of its code symbol
+    pub fn store(&self, entry: &str, fv: &MuFunctionVersion, vm: &VM) {
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        let cf = compiled_funcs.get(&fv.id()).unwrap().read().unwrap();
+        let code_symbol = cf.start.to_relocatable();
+
+        let mut info = String::new();
+        writeln!(info, "func {}", cf.func_id).unwrap();
+        writeln!(info, "func_ver {}", cf.func_ver_id).unwrap();
+        writeln!(info, "start {}", code_symbol).unwrap();
+        writeln!(info, "end {}", cf.end.to_relocatable()).unwrap();
+        writeln!(info, "frame {}", cf.frame.cur_offset()).unwrap();
+        let mut callee_saved: Vec<_> = cf.frame.callee_saved.iter().collect();
+        callee_saved.sort();
+        for (reg, offset) in callee_saved {
+            writeln!(info, "callee_saved {} {}", reg, offset).unwrap();
+        }
+        for offset in cf.frame.ref_slots.iter() {
+            writeln!(info, "ref_slot {}", offset).unwrap();
+        }
+        if let Some(callsites) = vm.callsite_table().read().unwrap().get(&fv.id()) {
+            for callsite in callsites.iter() {
+                let dest = match callsite.exception_destination {
+                    Some(ref dest) => (**dest).clone(),
+                    None => "-".to_string()
+                };
+                writeln!(
+                    info,
+                    "callsite {} {} {}",
+                    callsite.name,
+                    dest,
+                    callsite.stack_arg_size
+                ).unwrap();
+            }
+        }
+
+        // write the entry to a temporary directory, and rename it when it is complete
+        let mut tmp = self.dir.clone();
+        tmp.push(format!("{}.tmp{}", entry, unsafe { libc::getpid() }));
+        let _ = fs::remove_dir_all(&tmp);
+        if let Err(e) = fs::create_dir(&tmp) {
+            panic!("failed to create {}: {}", tmp.display(), e);
+        }
+        for suffix in EMITTED_FILE_SUFFIXES.iter() {
+            let file = (*code_symbol).clone() + suffix;
+            let mut from = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
+            from.push(&file);
+            if !from.exists() || (*suffix == ELF_RODATA_SUFFIX && !vm.vm_options.flag_aot_emit_elf)
+            {
+                continue;
+            }
+            let mut to = tmp.clone();
+            to.push(&file);
+            if let Err(e) = fs::copy(&from, &to) {
+                panic!(
+                    "failed to copy {} to {}: {}",
+                    from.display(),
+                    to.display(),
+                    e
+                );
+            }
+            writeln!(info, "file {}", file).unwrap();
+        }
+        {
+            let mut path = tmp.clone();
+            path.push(ENTRY_INFO_FILE);
+            let mut file = match File::create(&path) {
+                Ok(file) => file,
+                Err(e) => panic!("failed to create {}: {}", path.display(), e)
+            };
+            file.write_all(info.as_bytes()).unwrap();
+        }
+
+        let mut dir = self.dir.clone();
+        dir.push(entry);
+        let _ = fs::remove_dir_all(&dir);
+        if fs::rename(&tmp, &dir).is_err() {
+            // another build stored the same entry
+            let _ = fs::remove_dir_all(&tmp);
+        }
+        info!("stored {} in code cache ({})", fv, entry);
+
+        // the old entries of this function can never be used again
+        let prefix = (*code_symbol).clone() + ".";
+        if let Ok(entries) = fs::read_dir(&self.dir) {
+            for old in entries.filter_map(|e| e.ok()) {
+                let name = old.file_name().to_string_lossy().into_owned();
+                if name != entry && name.starts_with(&prefix) &&
+                    name.len() == prefix.len() + 16 &&
+                    name[prefix.len()..].chars().all(|c| c.is_digit(16))
+                {
+                    info!("removing outdated code cache entry {}", name);
+                    let _ = fs::remove_dir_all(old.path());
+                }
+            }
+        }
+    }
+}
+
+/// the content of a cache entry
+struct EntryInfo {
+    start: String,
+    end: String,
+    frame_offset: isize,
+    callee_saved: HashMap<isize, ByteOffset>,
+    ref_slots: Vec<ByteOffset>,
+    callsites: Vec<Callsite>,
+    files: Vec<String>
+}
+
+/// reads a cache entry, returns None if it does not exist or is incomplete
+fn read_entry_info(dir: &PathBuf) -> Option<EntryInfo> {
+    let mut path = dir.clone();
+    path.push(ENTRY_INFO_FILE);
+    let mut text = String::new();
+    match File::open(&path) {
+        Ok(mut file) => {
+            if file.read_to_string(&mut text).is_err() {
+                return None;
+            }
+        }
+        Err(_) => return None
+    }
+
+    let mut info = EntryInfo {
+        start: String::new(),
+        end: String::new(),
+        frame_offset: 0,
+        callee_saved: HashMap::new(),
+        ref_slots: vec![],
+        callsites: vec![],
+        files: vec![]
+    };
+    // returns None from the function if a word does not parse
+    macro_rules! parse {
+        ($word: expr) => {
+            match $word.parse() {
+                Ok(v) => v,
+                Err(_) => return None
+            }
+        }
+    }
+
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match (words.get(0).cloned(), words.len()) {
+            (Some("func"), 2) | (Some("func_ver"), 2) => {}
+            (Some("start"), 2) => info.start = words[1].to_string(),
+            (Some("end"), 2) => info.end = words[1].to_string(),
+            (Some("frame"), 2) => info.frame_offset = parse!(words[1]),
+            (Some("callee_saved"), 3) => {
+                info.callee_saved
+                    .insert(parse!(words[1]), parse!(words[2]));
+            }
+            (Some("ref_slot"), 2) => info.ref_slots.push(parse!(words[1])),
+            (Some("callsite"), 4) => {
+                let dest = match words[2] {
+                    "-" => None,
+                    dest => Some(Arc::new(dest.to_string()))
+                };
+                info.callsites.push(Callsite::new(
+                    Arc::new(words[1].to_string()),
+                    dest,
+                    parse!(words[3])
+                ));
+            }
+            (Some("file"), 2) => {
+                let mut file = dir.clone();
+                file.push(words[1]);
+                if !file.exists() {
+                    return None;
+                }
+                info.files.push(words[1].to_string());
+            }
+            _ => return None
+        }
+    }
+
+    if info.start.is_empty() || info.end.is_empty() || info.files.is_empty() {
+        None
+    } else {
+        Some(info)
+    }
+}
+
+/// describes the VM options that the code of a function may depend on. The options that are
+/// left out only say where the outputs go, how many threads compile, what is logged or
+/// reported, and what is linked
+fn code_options(vm: &VM) -> String {
+    let ref opts = vm.vm_options;
+    format!(
+        "target {} disable-inline {} disable-regalloc-validate {} disable-ir-validate {} \
+         emit-debug-info {} profile-generate {:?} profile-use {:?} aot-link-static {} \
+         aot-emit-elf {} aot-pic {} aot-strip-unreachable {} gc-disable-collection {} \
+         gc-immixspace-size {} gc-lospace-size {} gc-nthreads {}",
+        opts.flag_target_arch,
+        opts.flag_disable_inline,
+        opts.flag_disable_regalloc_validate,
+        opts.flag_disable_ir_validate,
+        opts.flag_emit_debug_info,
+        opts.flag_profile_generate,
+        opts.flag_profile_use,
+        opts.flag_aot_link_static,
+        opts.flag_aot_emit_elf,
+        opts.flag_aot_pic,
+        opts.flag_aot_strip_unreachable,
+        opts.flag_gc_disable_collection,
+        opts.flag_gc_immixspace_size,
+        opts.flag_gc_lospace_size,
+        opts.flag_gc_nthreads
+    )
+}
+
+/// collects the functions referred to in a block, and the types of its values
+fn collect_block(block: &Block, funcs: &mut Vec<MuID>, types: &mut Vec<P<MuType>>) {
+    fn visit_node(node: &TreeNode, funcs: &mut Vec<MuID>, types: &mut Vec<P<MuType>>) {
+        match node.v {
+            TreeNode_::Instruction(ref inst) => {
+                if let Some(ref values) = inst.value {
+                    for v in values.iter() {
+                        types.push(v.ty.clone());
+                    }
+                }
+                for op in inst.ops.iter() {
+                    visit_node(op, funcs, types);
+                }
+            }
+            TreeNode_::Value(ref pv) => {
+                types.push(pv.ty.clone());
+                if let Value_::Constant(Constant::FuncRef(ref func)) = pv.v {
+                    funcs.push(func.id());
+                }
+            }
+        }
+    }
+
+    let content = match block.content {
+        Some(ref content) => content,
+        None => return
+    };
+    for arg in content.args.iter() {
+        types.push(arg.ty.clone());
+    }
+    if let Some(ref exn_arg) = content.exn_arg {
+        types.push(exn_arg.ty.clone());
+    }
+    for inst in content.body.iter() {
+        visit_node(inst, funcs, types);
+    }
+}
+
+/// collects the argument and return types of a signature
+fn collect_sig_types(sig: &MuFuncSig, types: &mut Vec<P<MuType>>) {
+    types.extend(sig.arg_tys.iter().cloned());
+    types.extend(sig.ret_tys.iter().cloned());
+}
+
+/// writes a type, and the layout of the types it contains (each type is only written once)
+fn write_type(ty: &P<MuType>, visited: &mut HashSet<String>, text: &mut String) {
+    let name = format!("{}", ty);
+    if !visited.insert(name.clone()) {
+        return;
+    }
+    writeln!(text, "type {}", name).unwrap();
+
+    let inner_tys = match ty.v {
+        MuType_::Struct(ref tag) => {
+            let map = STRUCT_TAG_MAP.read().unwrap();
+            map.get(tag).unwrap().get_tys().to_vec()
+        }
+        MuType_::Hybrid(ref tag) => {
+            let map = HYBRID_TAG_MAP.read().unwrap();
+            let hybrid = map.get(tag).unwrap();
+            let mut tys = hybrid.get_fix_tys().to_vec();
+            tys.push(hybrid.get_var_ty().clone());
+            tys
+        }
+        MuType_::Ref(ref ty) |
+        MuType_::IRef(ref ty) |
+        MuType_::WeakRef(ref ty) |
+        MuType_::UPtr(ref ty) |
+        MuType_::Array(ref ty, _) |
+        MuType_::Vector(ref ty, _) => vec![ty.clone()],
+        MuType_::FuncRef(ref sig) | MuType_::UFuncPtr(ref sig) => {
+            let mut tys = vec![];
+            collect_sig_types(sig, &mut tys);
+            tys
+        }
+        _ => vec![]
+    };
+    for inner in inner_tys.iter() {
+        write_type(inner, visited, text);
+    }
+}
+
+/// 64-bit FNV-1a hash (the key needs to be stable across builds of Zebu and Rust)
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -16,6 +16,7 @@ use ast::ir::*;
 use ast::ptr::*;
 use ast::types::*;
 use compiler::backend::get_callee_saved_offset;
+use compiler::backend::TargetArch;
 use utils::ByteOffset;
 
 use std;
@@ -53,7 +54,11 @@ pub struct Frame {
     pub allocated: HashMap<MuID, FrameSlot>,
     /// mapping from callee saved id (i.e. the position in the list of callee saved registers)
     /// and offset from the frame pointer
-    pub callee_saved: HashMap<isize, ByteOffset>
+    pub callee_saved: HashMap<isize, ByteOffset>,
+    /// offsets from the frame pointer of the slots for spilled references. This is the stack
+    /// map of the frame: a reference that is live across a call (or SWAPSTACK, which clobbers
+    /// all registers) is in one of these slots or in a callee saved register
+    pub ref_slots: Vec<ByteOffset>
 }
 
 rodal_struct!(Frame {
@@ -62,7 +67,8 @@ rodal_struct!(Frame {
     argument_by_reg,
     argument_by_stack,
     allocated,
-    callee_saved
+    callee_saved,
+    ref_slots
 });
 
 impl fmt::Display for Frame {
@@ -87,10 +93,31 @@ impl Frame {
             argument_by_reg: HashMap::new(),
             argument_by_stack: HashMap::new(),
             callee_saved: HashMap::new(),
-            allocated: HashMap::new()
+            allocated: HashMap::new(),
+            ref_slots: vec![]
         }
     }
 
+    /// recreates a frame from its size, callee saved slots and reference slots
+    /// (for code restored from the code cache, the other records are only used while compiling)
+    pub fn restore(
+        func_ver_id: MuID,
+        cur_offset: isize,
+        callee_saved: HashMap<isize, ByteOffset>,
+        ref_slots: Vec<ByteOffset>
+    ) -> Frame {
+        let mut frame = Frame::new(func_ver_id);
+        frame.cur_offset = cur_offset;
+        frame.callee_saved = callee_saved;
+        frame.ref_slots = ref_slots;
+        frame
+    }
+
+    /// returns current offset to frame base pointer
+    pub fn cur_offset(&self) -> isize {
+        self.cur_offset
+    }
+
     /// returns current size,
     /// which is always a multiple of 16 bytes for x64/aarch64 (alignment requirement)
     #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
@@ -123,7 +150,7 @@ impl Frame {
             let slot = self.alloc_slot(&reg, vm);
             (slot.make_memory_op(reg.ty.clone(), vm), slot.offset)
         };
-        let o = get_callee_saved_offset(reg.id());
+        let o = get_callee_saved_offset(reg.id(), vm);
         self.callee_saved.insert(o, off);
         mem
     }
@@ -131,17 +158,23 @@ impl Frame {
     /// removes the record for a callee saved register
     /// We allocate stack slots for all the callee saved regsiter, and later
     /// remove slots for those registers that are not actually used
-    pub fn remove_record_for_callee_saved_reg(&mut self, reg: MuID) {
+    pub fn remove_record_for_callee_saved_reg(&mut self, reg: MuID, vm: &VM) {
         self.allocated.remove(&reg);
-        let id = get_callee_saved_offset(reg);
+        let id = get_callee_saved_offset(reg, vm);
         self.callee_saved.remove(&id);
     }
 
     /// allocates next stack slot for a spilled register, and returns
     /// a memory operand representing the stack slot
     pub fn alloc_slot_for_spilling(&mut self, reg: P<Value>, vm: &VM) -> P<Value> {
-        let slot = self.alloc_slot(&reg, vm);
-        slot.make_memory_op(reg.ty.clone(), vm)
+        let (mem, offset) = {
+            let slot = self.alloc_slot(&reg, vm);
+            (slot.make_memory_op(reg.ty.clone(), vm), slot.offset)
+        };
+        if reg.ty.is_heap_reference() {
+            self.ref_slots.push(offset);
+        }
+        mem
     }
 
     #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
@@ -196,48 +229,38 @@ pub struct FrameSlot {
 rodal_struct!(FrameSlot { offset, value });
 
 impl fmt::Display for FrameSlot {
-    #[cfg(target_arch = "x86_64")]
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}(RBP): {}", self.offset, self.value)
-    }
-
-    #[cfg(target_arch = "aarch64")]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[FP, #{}]: {}", self.offset, self.value)
+        write!(f, "{}(FP): {}", self.offset, self.value)
     }
 }
 
 impl FrameSlot {
     /// generates a memory operand for this frame slot
-    #[cfg(target_arch = "x86_64")]
+    /// (relative to the frame pointer of the target architecture)
     pub fn make_memory_op(&self, ty: P<MuType>, vm: &VM) -> P<Value> {
         use compiler::backend::x86_64;
+        use compiler::backend::aarch64;
 
-        P(Value {
-            hdr: MuEntityHeader::unnamed(vm.next_id()),
-            ty: ty.clone(),
-            v: Value_::Memory(MemoryLocation::Address {
+        let offset = Some(Value::make_int32_const(vm.next_id(), self.offset as u64));
+        let mem = match vm.target_arch() {
+            TargetArch::X86_64 => MemoryLocation::Address {
                 base: x86_64::RBP.clone(),
-                offset: Some(Value::make_int32_const(vm.next_id(), self.offset as u64)),
+                offset: offset,
                 index: None,
                 scale: None
-            })
-        })
-    }
-    /// generates a memory operand for this frame slot
-    #[cfg(target_arch = "aarch64")]
-    pub fn make_memory_op(&self, ty: P<MuType>, vm: &VM) -> P<Value> {
-        use compiler::backend::aarch64;
+            },
+            TargetArch::AArch64 => MemoryLocation::VirtualAddress {
+                base: aarch64::FP.clone(),
+                offset: offset,
+                scale: 1,
+                signed: true
+            }
+        };
 
         P(Value {
             hdr: MuEntityHeader::unnamed(vm.next_id()),
             ty: ty.clone(),
-            v: Value_::Memory(MemoryLocation::VirtualAddress {
-                base: aarch64::FP.clone(),
-                offset: Some(Value::make_int32_const(vm.next_id(), self.offset as u64)),
-                scale: 1,
-                signed: true
-            })
+            v: Value_::Memory(mem)
         })
     }
 }
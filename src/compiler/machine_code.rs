@@ -100,6 +100,36 @@ impl CompiledFunction {
         }
     }
 
+    /// creates a compiled function whose code was emitted by an earlier build
+    /// (it has no machine code representation, as the code cache only keeps the emitted code)
+    pub fn restore(
+        func_id: MuID,
+        fv_id: MuID,
+        frame: Frame,
+        start_loc: ValueLocation,
+        end_loc: ValueLocation
+    ) -> CompiledFunction {
+        CompiledFunction {
+            func_id: func_id,
+            func_ver_id: fv_id,
+            temps: HashMap::new(),
+            consts: HashMap::new(),
+            const_mem: HashMap::new(),
+            mc: None,
+            frame: frame,
+            start: start_loc,
+            end: end_loc,
+            loop_analysis: None
+        }
+    }
+
+    /// returns the constants used in this function in the order of their IDs
+    pub fn sorted_consts(&self) -> Vec<(MuID, &P<Value>)> {
+        let mut ret: Vec<(MuID, &P<Value>)> = self.consts.iter().map(|(id, c)| (*id, c)).collect();
+        ret.sort_by_key(|&(id, _)| id);
+        ret
+    }
+
     /// gets a reference to the machine code representation of this compiled function
     pub fn mc(&self) -> &Box<MachineCode + Send + Sync> {
         match self.mc {
@@ -129,13 +159,17 @@ pub struct CompiledCallsite {
     pub exceptional_destination: Option<Address>,
     pub stack_args_size: usize,
     pub callee_saved_registers: Arc<HashMap<isize, isize>>,
+    /// the slots (offsets from the frame pointer) that may hold references while the frame is
+    /// at this callsite (see Frame::ref_slots)
+    pub ref_slots: Arc<Vec<isize>>,
     pub function_version: MuID
 }
 impl CompiledCallsite {
     pub fn new(
         callsite: &Callsite,
         fv: MuID,
-        callee_saved_registers: Arc<HashMap<isize, isize>>
+        callee_saved_registers: Arc<HashMap<isize, isize>>,
+        ref_slots: Arc<Vec<isize>>
     ) -> CompiledCallsite {
         CompiledCallsite {
             exceptional_destination: match &callsite.exception_destination {
@@ -144,6 +178,7 @@ impl CompiledCallsite {
             },
             stack_args_size: callsite.stack_arg_size,
             callee_saved_registers: callee_saved_registers,
+            ref_slots: ref_slots,
             function_version: fv
         }
     }
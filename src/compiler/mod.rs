@@ -13,10 +13,17 @@
 // limitations under the License.
 
 extern crate hprof;
+extern crate petgraph;
 
 use ast::ir::*;
 use vm::VM;
 use std::cell::RefCell;
+use std::cmp;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
 
 /// compiler passes
 pub mod passes;
@@ -26,8 +33,11 @@ pub mod backend;
 pub mod frame;
 /// machine code representation
 pub mod machine_code;
+/// a cache of compiled functions that persists across builds
+pub mod code_cache;
 
 pub use compiler::passes::CompilerPass;
+use compiler::code_cache::CodeCache;
 
 /// name for prologue (this is not full name, but prologue name is generated from this)
 pub const PROLOGUE_BLOCK_NAME: &'static str = "prologue";
@@ -39,7 +49,10 @@ pub struct Compiler<'vm> {
     /// policy decides what passes to be executed
     policy: RefCell<CompilerPolicy>,
     /// a reference to vm, for compiler to query VM-wide info
-    vm: &'vm VM
+    vm: &'vm VM,
+    /// prints timing after compiling each function?
+    /// (compiling threads report their timing when they finish instead)
+    print_timing: bool
 }
 
 impl<'vm> Compiler<'vm> {
@@ -47,7 +60,8 @@ impl<'vm> Compiler<'vm> {
     pub fn new(policy: CompilerPolicy, vm: &VM) -> Compiler {
         Compiler {
             policy: RefCell::new(policy),
-            vm: vm
+            vm: vm,
+            print_timing: true
         }
     }
 
@@ -62,17 +76,27 @@ impl<'vm> Compiler<'vm> {
         // FIXME: should use function name here (however hprof::enter only accept &'static str)
         let _p = hprof::enter("Function Compilation");
 
+        // the IDs generated while compiling only depend on the function version
+        self.vm.start_compiler_ids(func.id());
+
         let ref mut passes = self.policy.borrow_mut().passes;
         for pass in passes.iter_mut() {
             let _p = hprof::enter(pass.name());
 
             pass.execute(self.vm, func);
+            if !pass.preserves_cfg() {
+                func.invalidate_cfg_analyses();
+            }
 
             drop(_p);
         }
 
+        self.vm.end_compiler_ids();
+
         drop(_p);
-        hprof_print_timing(hprof::profiler().root());
+        if self.print_timing {
+            Timing::current().print();
+        }
 
         func.set_compiled();
         if self.vm.is_doing_jit() {
@@ -83,6 +107,284 @@ impl<'vm> Compiler<'vm> {
     }
 }
 
+/// compiles the given function versions (the versions that are already compiled are skipped).
+/// The versions are compiled on --compile-threads threads, every thread has its own compiler
+/// passes. Function versions that call each other are compiled on the same thread, as inlining
+/// reads the callee while compiling the caller. Callees are compiled before their callers (a
+/// group is only handed to a thread once the groups that it calls are compiled).
+pub fn compile_func_vers(fv_ids: Vec<MuID>, vm: &VM) {
+    resolve_value_types(&fv_ids, vm);
+
+    let (groups, callers) = group_by_call_graph(fv_ids, vm);
+    let n_threads = cmp::min(vm.vm_options.flag_compile_threads, groups.len());
+    let cache = Arc::new(CodeCache::new(vm));
+
+    if n_threads <= 1 {
+        let compiler = Compiler::new(CompilerPolicy::default(), vm);
+        for fv_id in groups.into_iter().flat_map(|group| group.into_iter()) {
+            compile_func_ver(&compiler, (*cache).as_ref(), fv_id, vm);
+        }
+        if let Some(ref cache) = *cache {
+            cache.report();
+        }
+        return;
+    }
+
+    info!("compiling {} groups of function versions on {} threads", groups.len(), n_threads);
+
+    // every thread is joined before we return or panic, so the VM outlives them
+    let vm: &'static VM = unsafe { mem::transmute(vm) };
+    let groups = Arc::new(groups);
+    let schedule = Arc::new((Mutex::new(Schedule::new(&callers)), Condvar::new()));
+    let callers = Arc::new(callers);
+
+    let mut threads = vec![];
+    let mut spawn_failed = false;
+    for i in 0..n_threads {
+        let groups = groups.clone();
+        let callers = callers.clone();
+        let schedule = schedule.clone();
+        let cache = cache.clone();
+        let handle = thread::Builder::new()
+            .name(format!("Mu Compiler #{}", i))
+            .spawn(move || {
+                let compiler = Compiler {
+                    policy: RefCell::new(CompilerPolicy::default()),
+                    vm: vm,
+                    print_timing: false
+                };
+                let (ref lock, ref cvar) = *schedule;
+                while let Some(group) = Schedule::next(lock, cvar) {
+                    // wakes up the other threads if compiling the group panics
+                    let guard = PanicGuard(lock, cvar);
+                    for fv_id in groups[group].iter() {
+                        compile_func_ver(&compiler, (*cache).as_ref(), *fv_id, vm);
+                    }
+                    mem::forget(guard);
+                    Schedule::finish(lock, cvar, group, &callers[group]);
+                }
+                Timing::current()
+            });
+        match handle {
+            Ok(handle) => threads.push(handle),
+            Err(_) => {
+                spawn_failed = true;
+                break;
+            }
+        }
+    }
+    if spawn_failed {
+        let (ref lock, ref cvar) = *schedule;
+        Schedule::fail(lock, cvar);
+    }
+
+    // join every thread before we panic, as they refer to the VM
+    let mut timing = Timing::empty();
+    let mut thread_panicked = false;
+    for handle in threads {
+        match handle.join() {
+            Ok(t) => timing.merge(t),
+            Err(_) => thread_panicked = true
+        }
+    }
+    if spawn_failed {
+        panic!("failed to create a compiling thread");
+    }
+    if thread_panicked {
+        panic!("a compiling thread panicked");
+    }
+    timing.print();
+    if let Some(ref cache) = *cache {
+        cache.report();
+    }
+}
+
+/// the groups of function versions that are ready to be compiled by the compiling threads
+struct Schedule {
+    /// groups whose callee groups are all compiled
+    ready: Vec<usize>,
+    /// the number of callee groups that are not compiled yet, of each group
+    n_callees: Vec<usize>,
+    /// the number of groups that are not compiled yet
+    n_left: usize,
+    /// set when a compiling thread panicked (or could not be created), the other threads stop
+    failed: bool
+}
+
+impl Schedule {
+    fn new(callers: &Vec<Vec<usize>>) -> Schedule {
+        let mut n_callees = vec![0; callers.len()];
+        for group_callers in callers.iter() {
+            for caller in group_callers.iter() {
+                n_callees[*caller] += 1;
+            }
+        }
+        // the groups are taken from the end of the list, so the first groups go first
+        let ready = (0..callers.len()).rev().filter(|g| n_callees[*g] == 0).collect();
+        Schedule {
+            ready: ready,
+            n_callees: n_callees,
+            n_left: callers.len(),
+            failed: false
+        }
+    }
+
+    /// waits for a group to be ready, returns None when all groups are compiled (or a thread
+    /// failed)
+    fn next(lock: &Mutex<Schedule>, cvar: &Condvar) -> Option<usize> {
+        let mut schedule = lock.lock().unwrap();
+        loop {
+            if schedule.failed || schedule.n_left == 0 {
+                return None;
+            }
+            if let Some(group) = schedule.ready.pop() {
+                return Some(group);
+            }
+            schedule = cvar.wait(schedule).unwrap();
+        }
+    }
+
+    /// marks a group as compiled, and readies its callers whose callees are all compiled
+    fn finish(lock: &Mutex<Schedule>, cvar: &Condvar, group: usize, callers: &Vec<usize>) {
+        let mut schedule = lock.lock().unwrap();
+        schedule.n_left -= 1;
+        for caller in callers.iter() {
+            schedule.n_callees[*caller] -= 1;
+            if schedule.n_callees[*caller] == 0 {
+                schedule.ready.push(*caller);
+            }
+        }
+        trace!("compiled group {}, {} groups left", group, schedule.n_left);
+        cvar.notify_all();
+    }
+
+    /// stops the compiling threads (the lock may be poisoned by a panicking thread)
+    fn fail(lock: &Mutex<Schedule>, cvar: &Condvar) {
+        let mut schedule = match lock.lock() {
+            Ok(schedule) => schedule,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        schedule.failed = true;
+        cvar.notify_all();
+    }
+}
+
+/// fails the schedule when it is dropped by a panicking thread (so that the threads waiting for
+/// the groups it was compiling do not wait forever)
+struct PanicGuard<'a>(&'a Mutex<Schedule>, &'a Condvar);
+
+impl<'a> Drop for PanicGuard<'a> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            Schedule::fail(self.0, self.1);
+        }
+    }
+}
+
+/// compiles a function version if it is not compiled yet
+/// (or restores it from the code cache, and stores it in the cache after compiling it)
+fn compile_func_ver(compiler: &Compiler, cache: Option<&CodeCache>, fv_id: MuID, vm: &VM) {
+    let func_vers = vm.func_vers().read().unwrap();
+    if func_vers.get(&fv_id).unwrap().read().unwrap().is_compiled() {
+        return;
+    }
+
+    match cache {
+        Some(cache) => {
+            // the entry name reads the function version, so we cannot hold its write lock yet
+            let entry = cache.entry_name(fv_id, &func_vers, &compiler.policy.borrow(), vm);
+            let mut func_ver = func_vers.get(&fv_id).unwrap().write().unwrap();
+            if !cache.restore(&entry, &mut func_ver, vm) {
+                compiler.compile(&mut func_ver);
+                cache.store(&entry, &func_ver, vm);
+            }
+        }
+        None => {
+            let mut func_ver = func_vers.get(&fv_id).unwrap().write().unwrap();
+            compiler.compile(&mut func_ver);
+        }
+    }
+}
+
+/// resolves the backend types of the values in the given function versions in the order of
+/// the type IDs. Resolving a type may add a GC type, and the GC type IDs appear in the generated
+/// code, so they should not depend on the order that functions are compiled in, or on which
+/// functions are restored from the code cache
+fn resolve_value_types(fv_ids: &Vec<MuID>, vm: &VM) {
+    let mut ty_ids = BTreeSet::new();
+    {
+        let types = vm.types().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        for fv_id in fv_ids.iter() {
+            let fv = func_vers.get(fv_id).unwrap().read().unwrap();
+            for entry in fv.context.values.values() {
+                let ty = entry.ty();
+                let referent = ty.get_referent_ty();
+                for ty in Some(ty).into_iter().chain(referent.as_ref()) {
+                    // types that are not declared to the VM are resolved when they are used
+                    if types.contains_key(&ty.id()) {
+                        ty_ids.insert(ty.id());
+                    }
+                }
+            }
+        }
+    }
+
+    for ty_id in ty_ids {
+        vm.get_backend_type_info(ty_id);
+    }
+}
+
+/// groups function versions by the strongly connected components of their call graph
+/// (function versions that refer to each other are in the same group).
+/// Groups of callees are ordered before groups of their callers. Returns the groups, and the
+/// (indices of the) groups that call each group
+fn group_by_call_graph(fv_ids: Vec<MuID>, vm: &VM) -> (Vec<Vec<MuID>>, Vec<Vec<usize>>) {
+    use self::petgraph::graphmap::DiGraphMap;
+
+    let funcs = vm.funcs().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+
+    let mut graph: DiGraphMap<MuID, ()> = DiGraphMap::new();
+    for &fv_id in fv_ids.iter() {
+        graph.add_node(fv_id);
+    }
+    for &fv_id in fv_ids.iter() {
+        let fv = func_vers.get(&fv_id).unwrap().read().unwrap();
+        if fv.content.is_none() {
+            continue;
+        }
+        for callee in fv.get_referenced_funcs().iter() {
+            let callee_fv = funcs.get(callee).and_then(|f| f.read().unwrap().cur_ver);
+            match callee_fv {
+                Some(callee_fv) if graph.contains_node(callee_fv) => {
+                    graph.add_edge(fv_id, callee_fv, ());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // tarjan_scc() returns the components in reverse topological order
+    let groups = petgraph::algo::tarjan_scc(&graph);
+
+    let mut group_of = HashMap::new();
+    for (i, group) in groups.iter().enumerate() {
+        for fv_id in group.iter() {
+            group_of.insert(*fv_id, i);
+        }
+    }
+    let mut callers: Vec<Vec<usize>> = vec![vec![]; groups.len()];
+    for (caller, callee, _) in graph.all_edges() {
+        let (caller, callee) = (group_of[&caller], group_of[&callee]);
+        if caller != callee && !callers[callee].contains(&caller) {
+            callers[callee].push(caller);
+        }
+    }
+
+    (groups, callers)
+}
+
 /// CompilerPolicy specifies a list of ordered CompilerPasses
 /// the compiler will follow the list to compile each function
 pub struct CompilerPolicy {
@@ -102,10 +404,19 @@ impl Default for CompilerPolicy {
         passes.push(Box::new(passes::DotGen::new(".orig")));
 
         // ir level passes
+        passes.push(Box::new(passes::ProfileInstrumentation::new()));
         passes.push(Box::new(passes::RetSink::new()));
         passes.push(Box::new(passes::Inlining::new()));
+        passes.push(Box::new(passes::SCCP::new()));
+        passes.push(Box::new(passes::EscapeAnalysis::new()));
+        passes.push(Box::new(passes::GVN::new()));
+        passes.push(Box::new(passes::LICM::new()));
+        passes.push(Box::new(passes::StrengthReduction::new()));
+        passes.push(Box::new(passes::LoopUnrolling::new()));
+        passes.push(Box::new(passes::RLE::new()));
         passes.push(Box::new(passes::InjectRuntime::new()));
         passes.push(Box::new(passes::DefUse::new()));
+        passes.push(Box::new(passes::DCE::new()));
         passes.push(Box::new(passes::TreeGen::new()));
         passes.push(Box::new(passes::GenMovPhi::new()));
         passes.push(Box::new(passes::ControlFlowAnalysis::new()));
@@ -127,49 +438,96 @@ impl Default for CompilerPolicy {
 
 // rewrite parts of the hprof crates to print via log (instead of print!())
 use self::hprof::ProfileNode;
-use std::rc::Rc;
 
-fn hprof_print_timing(root: Rc<ProfileNode>) {
-    info!("Timing information for {}:", root.name);
-    for child in &*root.children.borrow() {
-        hprof_print_child(child, 2);
-    }
+/// Timing is the timing information of compilation, collected from hprof.
+/// hprof profiles are thread local, so we collect the timing on compiling threads
+/// and merge them
+pub struct Timing {
+    name: &'static str,
+    calls: u64,
+    total_time: u64,
+    children: Vec<Timing>
 }
 
-fn hprof_print_child(this: &ProfileNode, indent: usize) {
-    let mut indent_str = "".to_string();
-    for _ in 0..indent {
-        indent_str += " ";
-    }
-
-    let parent_time = this.parent
-        .as_ref()
-        .map(|p| p.total_time.get())
-        .unwrap_or(this.total_time.get()) as f64;
-    let percent = 100.0 * (this.total_time.get() as f64 / parent_time);
-    if percent.is_infinite() {
-        info!(
-            "{}{name} - {calls} * {each} = {total} @ {hz:.1}hz",
-            indent_str,
-            name = this.name,
-            calls = this.calls.get(),
-            each = Nanoseconds((this.total_time.get() as f64 / this.calls.get() as f64) as u64),
-            total = Nanoseconds(this.total_time.get()),
-            hz = this.calls.get() as f64 / this.total_time.get() as f64 * 1e9f64
-        );
-    } else {
-        info!(
-            "{}{name} - {calls} * {each} = {total} ({percent:.1}%)",
-            indent_str,
-            name = this.name,
-            calls = this.calls.get(),
-            each = Nanoseconds((this.total_time.get() as f64 / this.calls.get() as f64) as u64),
-            total = Nanoseconds(this.total_time.get()),
-            percent = percent
-        );
-    }
-    for c in &*this.children.borrow() {
-        hprof_print_child(c, indent + 2);
+impl Timing {
+    /// returns an empty timing (for merging timing from threads)
+    fn empty() -> Timing {
+        Timing {
+            name: "root",
+            calls: 0,
+            total_time: 0,
+            children: vec![]
+        }
+    }
+
+    /// collects the timing of the current thread
+    fn current() -> Timing {
+        Timing::from_node(&hprof::profiler().root())
+    }
+
+    fn from_node(node: &ProfileNode) -> Timing {
+        Timing {
+            name: node.name,
+            calls: node.calls.get(),
+            total_time: node.total_time.get(),
+            children: node.children
+                .borrow()
+                .iter()
+                .map(|child| Timing::from_node(child))
+                .collect()
+        }
+    }
+
+    /// adds the timing from another thread to this timing (nodes are matched by name)
+    fn merge(&mut self, other: Timing) {
+        self.calls += other.calls;
+        self.total_time += other.total_time;
+        for child in other.children {
+            match self.children.iter().position(|c| c.name == child.name) {
+                Some(i) => self.children[i].merge(child),
+                None => self.children.push(child)
+            }
+        }
+    }
+
+    fn print(&self) {
+        info!("Timing information for {}:", self.name);
+        for child in self.children.iter() {
+            child.print_child(self.total_time, 2);
+        }
+    }
+
+    fn print_child(&self, parent_time: u64, indent: usize) {
+        let mut indent_str = "".to_string();
+        for _ in 0..indent {
+            indent_str += " ";
+        }
+
+        let percent = 100.0 * (self.total_time as f64 / parent_time as f64);
+        if percent.is_infinite() {
+            info!(
+                "{}{name} - {calls} * {each} = {total} @ {hz:.1}hz",
+                indent_str,
+                name = self.name,
+                calls = self.calls,
+                each = Nanoseconds((self.total_time as f64 / self.calls as f64) as u64),
+                total = Nanoseconds(self.total_time),
+                hz = self.calls as f64 / self.total_time as f64 * 1e9f64
+            );
+        } else {
+            info!(
+                "{}{name} - {calls} * {each} = {total} ({percent:.1}%)",
+                indent_str,
+                name = self.name,
+                calls = self.calls,
+                each = Nanoseconds((self.total_time as f64 / self.calls as f64) as u64),
+                total = Nanoseconds(self.total_time),
+                percent = percent
+            );
+        }
+        for c in self.children.iter() {
+            c.print_child(self.total_time, indent + 2);
+        }
     }
 }
 
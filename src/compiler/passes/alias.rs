@@ -0,0 +1,292 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A type-based alias analysis on the Mu IR. Two memory locations (the iref/uptr operands of
+//! memory instructions) may alias unless one of these rules shows they do not:
+//!
+//! * Locations are traced back through GETIREF, GETFIELDIREF, GETELEMIREF and GETVARPARTIREF to
+//!   a root. Different globals, a global and a heap/stack object, and objects from different
+//!   allocations in this function are disjoint. Locations with the same root are disjoint if
+//!   their paths go to different fields, or to different constant elements.
+//! * Mu memory is only accessed as the type it has (or a prefix of it), so two irefs can only
+//!   overlap if the scalar components of their referent types have a type in common.
+//! * irefs (Mu memory) and uptrs (native memory) are disjoint, unless the Mu memory is pinned.
+//!   Any Mu memory may have been pinned by another function, so we only rely on this for
+//!   objects allocated in this function when the function itself never pins anything, and the
+//!   object (or an iref derived from it) is only used to access its memory. Once it is passed
+//!   to a call, stored, or used in any other way, other code may pin it.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::ptr::*;
+use ast::types::*;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+pub struct AliasAnalysis {
+    /// the instruction that defines each SSA variable
+    defs: HashMap<MuID, P<TreeNode>>,
+    /// does the function pin objects (and may access them by pointers)?
+    pins: bool,
+    /// objects allocated in this function that other code may get hold of (and pin)
+    exposed: HashSet<MuID>
+}
+
+/// where a location is derived from
+#[derive(PartialEq)]
+enum Root {
+    /// a global cell
+    Global(MuID),
+    /// an object allocated in this function (by NEW/ALLOCA or their hybrid variants)
+    Alloc(MuID),
+    /// an object whose reference comes from elsewhere
+    Object(MuID),
+    /// an iref or uptr that we know nothing about
+    Unknown(MuID)
+}
+
+enum PathElem {
+    Field(usize),
+    Element(u64),
+    AnyElement,
+    VarPart
+}
+
+struct Location {
+    root: Root,
+    /// from the root to the location
+    path: Vec<PathElem>,
+    is_ptr: bool,
+    /// the type of the memory at the location
+    ty: Option<P<MuType>>
+}
+
+impl AliasAnalysis {
+    pub fn new(f_content: &FunctionContent) -> AliasAnalysis {
+        let mut defs = HashMap::new();
+        let mut pins = false;
+        for block in f_content.blocks.values() {
+            for node in block.content.as_ref().unwrap().body.iter() {
+                let inst = node.as_inst();
+                match inst.v {
+                    Instruction_::CommonInst_Pin(_) | Instruction_::CommonInst_GetAddr(_) => {
+                        pins = true;
+                    }
+                    _ => {}
+                }
+                if let Some(ref vals) = inst.value {
+                    for val in vals.iter() {
+                        defs.insert(val.id(), node.clone());
+                    }
+                }
+            }
+        }
+
+        let mut ret = AliasAnalysis {
+            defs: defs,
+            pins: pins,
+            exposed: HashSet::new()
+        };
+
+        for block in f_content.blocks.values() {
+            for node in block.content.as_ref().unwrap().body.iter() {
+                let inst = node.as_inst();
+                // the uses of an object that location() sees through, or that only access
+                // its memory
+                let derives = match inst.v {
+                    Instruction_::GetIRef(op) => Some(op),
+                    Instruction_::GetFieldIRef { base, .. } |
+                    Instruction_::GetElementIRef { base, .. } |
+                    Instruction_::GetVarPartIRef { base, .. } => Some(base),
+                    Instruction_::Load { mem_loc, .. } |
+                    Instruction_::Store { mem_loc, .. } |
+                    Instruction_::CmpXchg { mem_loc, .. } |
+                    Instruction_::AtomicRMW { mem_loc, .. } => Some(mem_loc),
+                    _ => None
+                };
+                for (i, op) in inst.ops.iter().enumerate() {
+                    if Some(i) == derives {
+                        continue;
+                    }
+                    if let Root::Alloc(id) = ret.location(op).root {
+                        trace!("{} is exposed by {}", id, inst);
+                        ret.exposed.insert(id);
+                    }
+                }
+            }
+        }
+
+        ret
+    }
+
+    /// may the locations (iref or uptr operands) overlap?
+    pub fn may_alias(&self, a: &P<TreeNode>, b: &P<TreeNode>) -> bool {
+        let a = self.location(a);
+        let b = self.location(b);
+
+        if a.is_ptr != b.is_ptr {
+            let iref = if a.is_ptr { &b } else { &a };
+            return match iref.root {
+                Root::Alloc(id) => self.pins || self.exposed.contains(&id),
+                _ => true
+            };
+        }
+
+        if !a.is_ptr {
+            if let (&Some(ref ty_a), &Some(ref ty_b)) = (&a.ty, &b.ty) {
+                if !share_scalar_type(ty_a, ty_b) {
+                    return false;
+                }
+            }
+        }
+
+        if a.root == b.root {
+            // different fields or elements of the same location do not overlap
+            return !a.path.iter().zip(b.path.iter()).any(|pair| match pair {
+                (&PathElem::Field(x), &PathElem::Field(y)) => x != y,
+                (&PathElem::Element(x), &PathElem::Element(y)) => x != y,
+                _ => false
+            });
+        }
+
+        match (a.root, b.root) {
+            (Root::Global(_), Root::Global(_)) |
+            (Root::Global(_), Root::Alloc(_)) |
+            (Root::Alloc(_), Root::Global(_)) |
+            (Root::Global(_), Root::Object(_)) |
+            (Root::Object(_), Root::Global(_)) |
+            (Root::Alloc(_), Root::Alloc(_)) => false,
+            _ => true
+        }
+    }
+
+    fn location(&self, op: &P<TreeNode>) -> Location {
+        let val = op.clone_value();
+        let is_ptr = val.ty.is_ptr();
+        let ty = val.ty.get_referent_ty();
+
+        let mut path = vec![];
+        let mut cur = val;
+        let root = loop {
+            let id = match cur.v {
+                Value_::Global(_) => break Root::Global(cur.id()),
+                Value_::SSAVar(id) => id,
+                _ => break Root::Unknown(cur.id())
+            };
+            let def = match self.defs.get(&id) {
+                Some(def) => def.clone(),
+                None => break Root::Unknown(id)
+            };
+
+            let inst = def.as_inst();
+            let ref ops = inst.ops;
+            let base = match inst.v {
+                Instruction_::New(_) |
+                Instruction_::NewHybrid(_, _) |
+                Instruction_::AllocA(_) |
+                Instruction_::AllocAHybrid(_, _) => break Root::Alloc(id),
+                Instruction_::GetIRef(op) => {
+                    let obj = ops[op].clone_value();
+                    match self.defs.get(&obj.id()).map(|def| &def.as_inst().v) {
+                        Some(&Instruction_::New(_)) | Some(&Instruction_::NewHybrid(_, _)) => {
+                            break Root::Alloc(obj.id())
+                        }
+                        _ => break Root::Object(obj.id())
+                    }
+                }
+                Instruction_::GetFieldIRef { base, index, .. } => {
+                    path.push(PathElem::Field(index));
+                    base
+                }
+                Instruction_::GetElementIRef { base, index, .. } => {
+                    match ops[index].as_value().extract_int_const() {
+                        Some(c) => path.push(PathElem::Element(c)),
+                        None => path.push(PathElem::AnyElement)
+                    }
+                    base
+                }
+                Instruction_::GetVarPartIRef { base, .. } => {
+                    path.push(PathElem::VarPart);
+                    base
+                }
+                _ => break Root::Unknown(id)
+            };
+            cur = ops[base].clone_value();
+        };
+
+        path.reverse();
+        Location {
+            root: root,
+            path: path,
+            is_ptr: is_ptr,
+            ty: ty
+        }
+    }
+}
+
+/// do the two types have a scalar component of the same type?
+fn share_scalar_type(a: &MuType, b: &MuType) -> bool {
+    let mut scalars_a = vec![];
+    let mut scalars_b = vec![];
+    collect_scalar_types(a, &mut scalars_a);
+    collect_scalar_types(b, &mut scalars_b);
+
+    // void is not a type of memory, an iref<void> may refer to anything
+    if scalars_a.iter().chain(scalars_b.iter()).any(|ty| *ty == MuType_::Void) {
+        return true;
+    }
+    scalars_a
+        .iter()
+        .any(|ty_a| scalars_b.iter().any(|ty_b| same_scalar_type(ty_a, ty_b)))
+}
+
+/// reference types are compared without their referent types, as REFCAST may change those
+fn same_scalar_type(a: &MuType_, b: &MuType_) -> bool {
+    match (a, b) {
+        (&MuType_::Ref(_), &MuType_::Ref(_)) |
+        (&MuType_::Ref(_), &MuType_::WeakRef(_)) |
+        (&MuType_::WeakRef(_), &MuType_::Ref(_)) |
+        (&MuType_::WeakRef(_), &MuType_::WeakRef(_)) |
+        (&MuType_::IRef(_), &MuType_::IRef(_)) |
+        (&MuType_::UPtr(_), &MuType_::UPtr(_)) |
+        (&MuType_::FuncRef(_), &MuType_::FuncRef(_)) |
+        (&MuType_::UFuncPtr(_), &MuType_::UFuncPtr(_)) => true,
+        _ => a == b
+    }
+}
+
+fn collect_scalar_types(ty: &MuType, scalars: &mut Vec<MuType_>) {
+    let tys = match ty.v {
+        MuType_::Struct(ref tag) => {
+            let map = STRUCT_TAG_MAP.read().unwrap();
+            map.get(tag).unwrap().get_tys().clone()
+        }
+        MuType_::Hybrid(ref tag) => {
+            let map = HYBRID_TAG_MAP.read().unwrap();
+            let hybrid = map.get(tag).unwrap();
+            let mut tys = hybrid.get_fix_tys().clone();
+            tys.push(hybrid.get_var_ty().clone());
+            tys
+        }
+        MuType_::Array(ref elem_ty, _) | MuType_::Vector(ref elem_ty, _) => vec![elem_ty.clone()],
+        _ => {
+            scalars.push(ty.v.clone());
+            return;
+        }
+    };
+
+    for ty in tys.iter() {
+        collect_scalar_types(ty, scalars);
+    }
+}
@@ -42,6 +42,10 @@ impl CompilerPass for ControlFlowAnalysis {
         self
     }
 
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
     #[allow(unused_variables)]
     fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
         let mut stack: Vec<MuID> = vec![];
@@ -0,0 +1,208 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::ptr::*;
+use vm::VM;
+use compiler::CompilerPass;
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Dead code elimination. The pass deletes blocks that are not reachable from the entry block,
+/// and instructions without side effects whose results are never used. It relies on the use
+/// counts computed by the Def-Use pass, and keeps them up to date as it deletes code, so it
+/// needs to run after that pass. Control flow edges (if already computed) are trimmed to match
+/// the remaining branches.
+pub struct DCE {
+    name: &'static str
+}
+
+impl DCE {
+    pub fn new() -> DCE {
+        DCE {
+            name: "Dead Code Elimination"
+        }
+    }
+}
+
+impl CompilerPass for DCE {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    #[allow(unused_variables)]
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let removed_blocks = remove_unreachable_blocks(func);
+        let removed_insts = remove_dead_insts(func);
+        cleanup_control_flow(func);
+
+        debug!(
+            "removed {} unreachable blocks and {} dead instructions",
+            removed_blocks,
+            removed_insts
+        );
+        debug!("after DCE: {:?}", func);
+    }
+}
+
+/// deletes blocks that cannot be reached from the entry, returns the number of deleted blocks
+fn remove_unreachable_blocks(func: &mut MuFunctionVersion) -> usize {
+    let domtree = func.domtree();
+    let unreachable: Vec<MuID> = func.content
+        .as_ref()
+        .unwrap()
+        .blocks
+        .keys()
+        .filter(|id| !domtree.is_reachable(**id))
+        .cloned()
+        .collect();
+
+    if unreachable.is_empty() {
+        return 0;
+    }
+
+    {
+        let f_content = func.content.as_mut().unwrap();
+        let ref context = func.context;
+
+        for id in unreachable.iter() {
+            let block = f_content.blocks.remove(id).unwrap();
+            trace!("remove unreachable block {}", block);
+
+            // uses from the deleted block no longer count
+            let block_content = block.content.as_ref().unwrap();
+            for node in block_content.body.iter() {
+                for op in node.as_inst().ops.iter() {
+                    unuse_op(op, context);
+                }
+            }
+            if let Some(ref keepalives) = block_content.keepalives {
+                for val in keepalives.iter() {
+                    unuse_value(val, context);
+                }
+            }
+
+            f_content.exception_blocks.remove(id);
+        }
+    }
+
+    if let Some(ref mut trace) = func.block_trace {
+        trace.retain(|id| !unreachable.contains(id));
+    }
+
+    unreachable.len()
+}
+
+/// deletes instructions that have no side effect and whose results are all unused,
+/// returns the number of deleted instructions
+fn remove_dead_insts(func: &mut MuFunctionVersion) -> usize {
+    let f_content = func.content.as_mut().unwrap();
+    let ref context = func.context;
+
+    // deleting an instruction may make the instructions that define its operands dead,
+    // so we iterate until nothing changes
+    let mut removed = 0;
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for (_, block) in f_content.blocks.iter_mut() {
+            let ref mut body = block.content.as_mut().unwrap().body;
+
+            // visiting backwards catches chains of dead instructions within a block in one go
+            let mut i = body.len();
+            while i > 0 {
+                i -= 1;
+                if is_dead(body[i].as_inst(), context) {
+                    let node = body.remove(i);
+                    trace!("remove dead instruction {}", node);
+                    for op in node.as_inst().ops.iter() {
+                        unuse_op(op, context);
+                    }
+
+                    removed += 1;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    removed
+}
+
+fn is_dead(inst: &Instruction, context: &FunctionContext) -> bool {
+    if inst.is_terminal_inst() || inst.has_side_effect() {
+        return false;
+    }
+
+    match inst.value {
+        Some(ref vals) => {
+            vals.iter().all(|val| match val.extract_ssa_id() {
+                Some(id) => context.get_value(id).unwrap().use_count() == 0,
+                None => false
+            })
+        }
+        None => false
+    }
+}
+
+/// trims the edges computed by control flow analysis so that they only refer to remaining
+/// blocks and to branches that still exist
+fn cleanup_control_flow(func: &mut MuFunctionVersion) {
+    let targets: HashMap<MuID, HashSet<MuID>> = {
+        let f_content = func.content.as_ref().unwrap();
+        f_content
+            .blocks
+            .keys()
+            .map(|id| (*id, f_content.get_successors(*id).into_iter().collect()))
+            .collect()
+    };
+
+    let f_content = func.content.as_mut().unwrap();
+    for (id, block) in f_content.blocks.iter_mut() {
+        let ref block_targets = targets[id];
+        block
+            .control_flow
+            .succs
+            .retain(|edge| block_targets.contains(&edge.target));
+        block.control_flow.preds.retain(|pred| match targets.get(pred) {
+            Some(pred_targets) => pred_targets.contains(id),
+            None => false
+        });
+    }
+}
+
+fn unuse_op(op: &P<TreeNode>, context: &FunctionContext) {
+    match op.v {
+        TreeNode_::Value(ref val) => {
+            unuse_value(val, context);
+        }
+        _ => {} // the Def-Use pass does not count operands of nested instructions either
+    }
+}
+
+fn unuse_value(val: &P<Value>, context: &FunctionContext) {
+    match val.v {
+        Value_::SSAVar(ref id) => {
+            context.get_value(*id).unwrap().decrease_use_count();
+        }
+        _ => {}
+    }
+}
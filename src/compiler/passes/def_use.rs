@@ -39,6 +39,10 @@ impl CompilerPass for DefUse {
         self
     }
 
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
     #[allow(unused_variables)]
     fn start_block(&mut self, vm: &VM, func_context: &mut FunctionContext, block: &mut Block) {}
 
@@ -307,6 +307,10 @@ impl CompilerPass for DotGen {
         self
     }
 
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
     fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
         if EMIT_MUIR {
             emit_muir_dot(self.suffix, func, vm);
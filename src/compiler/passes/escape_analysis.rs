@@ -0,0 +1,584 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::ptr::*;
+use ast::types::*;
+use ast::analysis::*;
+use vm::VM;
+use compiler::CompilerPass;
+use compiler::backend::TargetArch;
+use compiler::passes::gvn::rewrite;
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Escape analysis and scalar replacement. An object allocated by NEW does not escape if its
+/// reference is only used by GETIREF, and the internal references derived from it (by
+/// GETFIELDIREF and GETELEMIREF) are only used to derive further internal references, or as
+/// the location of LOAD and STORE (never as the stored value). Any other use (storing it,
+/// passing it to a call or to another block, comparing it, keeping it alive) makes it escape.
+///
+/// If a non-escaping object is only accessed by non-atomic loads and stores of int (up to 64
+/// bits), float, double or ref fields at constant paths, each field becomes an SSA variable:
+/// a store defines a new value of the field, loads are replaced by the current value, and block
+/// parameters are added where values from different paths meet (the iterated dominance frontier
+/// of the stores). Fields start as zero, as NEW zeroes the object.
+///
+/// Otherwise, if the backend of the target supports it (the x86_64 backend does not implement
+/// ALLOCA yet), the object does not contain references (so the GC does not need to see it) and
+/// it is not allocated in a loop (ALLOCA grows the frame every time it is executed), NEW is
+/// replaced by ALLOCA.
+pub struct EscapeAnalysis {
+    name: &'static str
+}
+
+impl EscapeAnalysis {
+    pub fn new() -> EscapeAnalysis {
+        EscapeAnalysis {
+            name: "Escape Analysis"
+        }
+    }
+}
+
+impl CompilerPass for EscapeAnalysis {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        // block parameters may be added, but branch targets stay the same
+        true
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let domtree = func.domtree();
+        let loop_nest = func.loop_nest();
+
+        let mut allocs = vec![];
+        for (id, block) in func.content.as_ref().unwrap().blocks.iter() {
+            if !domtree.is_reachable(*id) {
+                continue;
+            }
+            for node in block.content.as_ref().unwrap().body.iter() {
+                if let Instruction_::New(_) = node.as_inst().v {
+                    allocs.push((*id, node.clone()));
+                }
+            }
+        }
+
+        let mut n_scalar_replaced = 0;
+        let mut n_stack_allocated = 0;
+        for &(block, ref node) in allocs.iter() {
+            // analyse the current IR, as replacing an object rewrites instructions
+            let obj = Object::new(func.content.as_ref().unwrap(), &domtree, block, node);
+            if obj.escapes {
+                trace!("{} escapes", node);
+            } else if obj.scalar {
+                trace!("replace {} with {} scalars", node, obj.slots.len());
+                scalar_replace(vm, func, &domtree, &obj);
+                n_scalar_replaced += 1;
+            } else if vm.target_arch() == TargetArch::AArch64 &&
+                       loop_nest.loop_depth(block) == 0 && !obj.ty.is_traced()
+            {
+                trace!("allocate {} on the stack", node);
+                stack_allocate(vm, func, &obj);
+                n_stack_allocated += 1;
+            }
+        }
+
+        debug!(
+            "{} allocations replaced by scalars, {} allocated on the stack",
+            n_scalar_replaced,
+            n_stack_allocated
+        );
+        debug!("after escape analysis: {:?}", func);
+    }
+}
+
+/// an object allocated by NEW, and how it is used
+struct Object {
+    /// the block with the NEW instruction
+    block: MuID,
+    /// the NEW instruction
+    inst: MuID,
+    /// the reference to the object
+    result: MuID,
+    ty: P<MuType>,
+    /// internal references derived from the object, and the path of field/element indices
+    /// they refer to (None if an element index is not constant)
+    irefs: HashMap<MuID, Option<Vec<usize>>>,
+    escapes: bool,
+    /// are all accesses non-atomic loads and stores of a whole scalar field at a known path?
+    scalar: bool,
+    /// the paths and types of the accessed scalar fields
+    slots: Vec<(Vec<usize>, P<MuType>)>
+}
+
+impl Object {
+    fn new(
+        f_content: &FunctionContent,
+        domtree: &DomTree,
+        block: MuID,
+        node: &P<TreeNode>
+    ) -> Object {
+        let inst = node.as_inst();
+        let mut obj = Object {
+            block: block,
+            inst: inst.id(),
+            result: inst.value.as_ref().unwrap()[0].id(),
+            ty: match inst.v {
+                Instruction_::New(ref ty) => ty.clone(),
+                _ => unreachable!()
+            },
+            irefs: HashMap::new(),
+            escapes: false,
+            scalar: true,
+            slots: vec![]
+        };
+
+        obj.find_irefs(f_content);
+        for (id, block) in f_content.blocks.iter() {
+            let block_content = block.content.as_ref().unwrap();
+            for node in block_content.body.iter() {
+                let inst = node.as_inst();
+                for (index, op) in inst.ops.iter().enumerate() {
+                    if !obj.is_tracked(op) {
+                        continue;
+                    }
+                    if domtree.dominates(obj.block, *id) {
+                        obj.check_use(inst, index);
+                    } else {
+                        // only in unreachable blocks
+                        obj.escapes = true;
+                    }
+                }
+            }
+            if let Some(ref keepalives) = block_content.keepalives {
+                let kept_alive = keepalives
+                    .iter()
+                    .any(|val| val.id() == obj.result || obj.irefs.contains_key(&val.id()));
+                if kept_alive {
+                    obj.escapes = true;
+                }
+            }
+        }
+
+        obj
+    }
+
+    /// is the operand the object or an internal reference derived from it?
+    fn is_tracked(&self, op: &P<TreeNode>) -> bool {
+        match op.extract_ssa_id() {
+            Some(id) => id == self.result || self.irefs.contains_key(&id),
+            None => false
+        }
+    }
+
+    /// returns the path an operand refers to, if it is a derived internal reference
+    fn iref_path(&self, op: &P<TreeNode>) -> Option<Option<Vec<usize>>> {
+        match op.extract_ssa_id() {
+            Some(id) => self.irefs.get(&id).cloned(),
+            None => None
+        }
+    }
+
+    fn find_irefs(&mut self, f_content: &FunctionContent) {
+        // a derived iref may be defined in a block we visit after its uses
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for block in f_content.blocks.values() {
+                for node in block.content.as_ref().unwrap().body.iter() {
+                    let inst = node.as_inst();
+                    let res = match inst.value {
+                        Some(ref vals) if vals.len() == 1 => vals[0].id(),
+                        _ => continue
+                    };
+                    if self.irefs.contains_key(&res) {
+                        continue;
+                    }
+
+                    let ref ops = inst.ops;
+                    let path = match inst.v {
+                        Instruction_::GetIRef(op) => {
+                            if ops[op].extract_ssa_id() != Some(self.result) {
+                                continue;
+                            }
+                            Some(vec![])
+                        }
+                        Instruction_::GetFieldIRef {
+                            is_ptr: false,
+                            base,
+                            index
+                        } => {
+                            match self.iref_path(&ops[base]) {
+                                Some(path) => {
+                                    path.map(|mut path| {
+                                        path.push(index);
+                                        path
+                                    })
+                                }
+                                None => continue
+                            }
+                        }
+                        Instruction_::GetElementIRef {
+                            is_ptr: false,
+                            base,
+                            index
+                        } => {
+                            let index = ops[index].as_value().extract_int_const();
+                            match self.iref_path(&ops[base]) {
+                                Some(Some(mut path)) => {
+                                    match index {
+                                        Some(index) => {
+                                            path.push(index as usize);
+                                            Some(path)
+                                        }
+                                        None => None
+                                    }
+                                }
+                                Some(None) => None,
+                                None => continue
+                            }
+                        }
+                        _ => continue
+                    };
+
+                    self.irefs.insert(res, path);
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    /// checks the use of the object (or a derived iref) as an operand of an instruction
+    fn check_use(&mut self, inst: &Instruction, index: usize) {
+        let ref ops = inst.ops;
+        if ops[index].extract_ssa_id() == Some(self.result) {
+            // the reference can only be used to get an iref
+            match inst.v {
+                Instruction_::GetIRef(_) => {}
+                _ => self.escapes = true
+            }
+            return;
+        }
+
+        let path = self.iref_path(&ops[index]).unwrap();
+        match inst.v {
+            Instruction_::GetFieldIRef { base, .. } |
+            Instruction_::GetElementIRef { base, .. } if base == index => {}
+            Instruction_::Load { order, mem_loc, .. } if mem_loc == index => {
+                let ty = inst.value.as_ref().unwrap()[0].ty.clone();
+                self.access(path, ty, order);
+            }
+            Instruction_::Store {
+                order,
+                mem_loc,
+                value,
+                ..
+            } if mem_loc == index => {
+                let ty = ops[value].as_value().ty.clone();
+                self.access(path, ty, order);
+            }
+            Instruction_::CmpXchg { mem_loc, .. } |
+            Instruction_::AtomicRMW { mem_loc, .. } if mem_loc == index => {
+                self.scalar = false;
+            }
+            _ => self.escapes = true
+        }
+    }
+
+    /// records a load or store of a field
+    fn access(&mut self, path: Option<Vec<usize>>, ty: P<MuType>, order: MemoryOrder) {
+        match path {
+            Some(path) => {
+                if order != MemoryOrder::NotAtomic || zero_value(&ty).is_none() {
+                    self.scalar = false;
+                } else if !self.slots.iter().any(|&(ref p, _)| *p == path) {
+                    self.slots.push((path, ty));
+                }
+            }
+            None => self.scalar = false
+        }
+    }
+
+    /// returns the scalar field a load or store accesses
+    fn slot_of(&self, mem_loc: &P<TreeNode>) -> Option<usize> {
+        match self.iref_path(mem_loc) {
+            Some(Some(path)) => self.slots.iter().position(|&(ref p, _)| *p == path),
+            _ => None
+        }
+    }
+
+    /// does the instruction derive an iref from the object?
+    fn defines_iref(&self, inst: &Instruction) -> bool {
+        match inst.value {
+            Some(ref vals) => vals.iter().any(|val| self.irefs.contains_key(&val.id())),
+            None => false
+        }
+    }
+}
+
+/// returns the value of a field of the type in a new object
+fn zero_value(ty: &MuType) -> Option<Constant> {
+    match ty.v {
+        MuType_::Int(len) if len <= 64 => Some(Constant::Int(0)),
+        MuType_::Float => Some(Constant::Float(0f32)),
+        MuType_::Double => Some(Constant::Double(0f64)),
+        MuType_::Ref(_) => Some(Constant::NullRef),
+        _ => None
+    }
+}
+
+/// turns the fields of an object into SSA variables, and removes the object
+fn scalar_replace(vm: &VM, func: &mut MuFunctionVersion, domtree: &DomTree, obj: &Object) {
+    // blocks that store to each field (the NEW also stores to every field)
+    let mut stores: Vec<HashSet<MuID>> = vec![HashSet::new(); obj.slots.len()];
+    for (id, block) in func.content.as_ref().unwrap().blocks.iter() {
+        for node in block.content.as_ref().unwrap().body.iter() {
+            let inst = node.as_inst();
+            match inst.v {
+                Instruction_::New(_) if inst.id() == obj.inst => {
+                    for blocks in stores.iter_mut() {
+                        blocks.insert(*id);
+                    }
+                }
+                Instruction_::Store { mem_loc, .. } => {
+                    if let Some(slot) = obj.slot_of(&inst.ops[mem_loc]) {
+                        stores[slot].insert(*id);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // fields need a block parameter on the iterated dominance frontier of the stores. Blocks that
+    // the NEW does not dominate cannot access the object
+    let mut phis: HashMap<MuID, Vec<usize>> = HashMap::new();
+    for (slot, blocks) in stores.iter().enumerate() {
+        let mut work_list: Vec<MuID> = blocks.iter().cloned().collect();
+        let mut placed = HashSet::new();
+        while let Some(id) = work_list.pop() {
+            for frontier in domtree.frontier(id).iter() {
+                if placed.contains(frontier) || !domtree.strictly_dominates(obj.block, *frontier) {
+                    continue;
+                }
+                placed.insert(*frontier);
+                phis.entry(*frontier).or_insert(vec![]).push(slot);
+                work_list.push(*frontier);
+            }
+        }
+    }
+
+    let mut params = HashMap::new();
+    for (id, slots) in phis.iter() {
+        for slot in slots.iter() {
+            let param = func.context
+                .make_temporary(vm.next_id(), obj.slots[*slot].1.clone());
+            func.content
+                .as_mut()
+                .unwrap()
+                .get_block_mut(*id)
+                .content
+                .as_mut()
+                .unwrap()
+                .args
+                .push(param.clone_value());
+            params.insert((*id, *slot), param);
+        }
+    }
+
+    let zeros = obj.slots
+        .iter()
+        .map(|&(_, ref ty)| {
+            TreeNode::new_value(P(Value {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                ty: ty.clone(),
+                v: Value_::Constant(zero_value(ty).unwrap())
+            }))
+        })
+        .collect();
+
+    let renaming = {
+        let mut renaming = Renaming {
+            obj: obj,
+            phis: &phis,
+            params: &params,
+            zeros: zeros,
+            replacements: HashMap::new(),
+            removed: HashSet::new()
+        };
+        let values = vec![None; obj.slots.len()];
+        renaming.visit_block(func.content.as_mut().unwrap(), domtree, obj.block, values);
+        (renaming.removed, renaming.replacements)
+    };
+    rewrite(func, &renaming.0, &renaming.1);
+}
+
+/// the walk over the dominator tree (from the NEW) that gives each load the current value of
+/// the field
+struct Renaming<'a> {
+    obj: &'a Object,
+    /// the fields that need a parameter in each block
+    phis: &'a HashMap<MuID, Vec<usize>>,
+    params: &'a HashMap<(MuID, usize), P<TreeNode>>,
+    zeros: Vec<P<TreeNode>>,
+    /// results of removed loads, and their values
+    replacements: HashMap<MuID, P<TreeNode>>,
+    /// the NEW, and the instructions that derive irefs from the object or access it
+    removed: HashSet<MuID>
+}
+
+impl<'a> Renaming<'a> {
+    fn visit_block(
+        &mut self,
+        f_content: &mut FunctionContent,
+        domtree: &DomTree,
+        id: MuID,
+        mut values: Vec<Option<P<TreeNode>>>
+    ) {
+        if let Some(slots) = self.phis.get(&id) {
+            for slot in slots.iter() {
+                values[*slot] = Some(self.params.get(&(id, *slot)).unwrap().clone());
+            }
+        }
+
+        let body = f_content.get_block(id).content.as_ref().unwrap().body.clone();
+        let mut new_body = Vec::with_capacity(body.len());
+        for node in body.iter() {
+            let inst = node.as_inst();
+            match inst.v {
+                Instruction_::New(_) if inst.id() == self.obj.inst => {
+                    values = self.zeros.iter().map(|zero| Some(zero.clone())).collect();
+                    self.removed.insert(inst.id());
+                }
+                Instruction_::Load { mem_loc, .. } if self.obj.is_tracked(&inst.ops[mem_loc]) => {
+                    let slot = self.obj.slot_of(&inst.ops[mem_loc]).unwrap();
+                    let result = inst.value.as_ref().unwrap()[0].id();
+                    self.replacements.insert(result, values[slot].clone().unwrap());
+                    self.removed.insert(inst.id());
+                }
+                Instruction_::Store { mem_loc, value, .. }
+                    if self.obj.is_tracked(&inst.ops[mem_loc]) =>
+                {
+                    let slot = self.obj.slot_of(&inst.ops[mem_loc]).unwrap();
+                    values[slot] = Some(self.resolve(&inst.ops[value]));
+                    self.removed.insert(inst.id());
+                }
+                _ => {
+                    if self.obj.defines_iref(inst) {
+                        self.removed.insert(inst.id());
+                    }
+                }
+            }
+
+            if inst.is_terminal_inst() {
+                new_body.push(self.pass_values(inst, &values));
+            } else {
+                new_body.push(node.clone());
+            }
+        }
+        f_content.get_block_mut(id).content.as_mut().unwrap().body = new_body;
+
+        for child in domtree.children(id).iter() {
+            self.visit_block(f_content, domtree, *child, values.clone());
+        }
+    }
+
+    /// returns the value an operand holds after loads are replaced
+    fn resolve(&self, op: &P<TreeNode>) -> P<TreeNode> {
+        match op.extract_ssa_id() {
+            Some(id) => {
+                match self.replacements.get(&id) {
+                    Some(value) => value.clone(),
+                    None => op.clone()
+                }
+            }
+            None => op.clone()
+        }
+    }
+
+    /// passes the current values of the fields to the blocks that have parameters for them
+    fn pass_values(&self, inst: &Instruction, values: &Vec<Option<P<TreeNode>>>) -> P<TreeNode> {
+        let mut inst = inst.clone();
+        let mut extra_ops = vec![];
+        let n_ops = inst.ops.len();
+        for dest in inst.get_destinations_mut() {
+            if let Some(slots) = self.phis.get(&dest.target.id()) {
+                for slot in slots.iter() {
+                    dest.args.push(DestArg::Normal(n_ops + extra_ops.len()));
+                    extra_ops.push(values[*slot].clone().unwrap());
+                }
+            }
+        }
+        inst.ops.extend(extra_ops);
+        TreeNode::new_inst(inst)
+    }
+}
+
+/// replaces NEW by ALLOCA. The object is only used by GETIREF, whose result is now the result
+/// of ALLOCA
+fn stack_allocate(vm: &VM, func: &mut MuFunctionVersion, obj: &Object) {
+    let mut getirefs = vec![];
+    for block in func.content.as_ref().unwrap().blocks.values() {
+        for node in block.content.as_ref().unwrap().body.iter() {
+            let inst = node.as_inst();
+            if let Instruction_::GetIRef(op) = inst.v {
+                if inst.ops[op].extract_ssa_id() == Some(obj.result) {
+                    getirefs.push((inst.id(), inst.value.as_ref().unwrap()[0].clone()));
+                }
+            }
+        }
+    }
+    // the object is accessed, so it has been used by at least one GETIREF
+    let iref = func.context
+        .make_temporary(vm.next_id(), getirefs[0].1.ty.clone());
+
+    let mut removed = HashSet::new();
+    let mut replacements = HashMap::new();
+    for &(id, ref result) in getirefs.iter() {
+        removed.insert(id);
+        replacements.insert(result.id(), iref.clone());
+    }
+
+    {
+        let ref mut body = func.content
+            .as_mut()
+            .unwrap()
+            .get_block_mut(obj.block)
+            .content
+            .as_mut()
+            .unwrap()
+            .body;
+        let index = body.iter()
+            .position(|node| node.as_inst().id() == obj.inst)
+            .unwrap();
+        let hdr = body[index].as_inst().hdr.clone();
+        body[index] = TreeNode::new_inst(Instruction {
+            hdr: hdr,
+            value: Some(vec![iref.clone_value()]),
+            ops: vec![],
+            v: Instruction_::AllocA(obj.ty.clone())
+        });
+    }
+    rewrite(func, &removed, &replacements);
+}
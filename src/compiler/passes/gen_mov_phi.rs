@@ -19,6 +19,7 @@ use vm::VM;
 
 use compiler::CompilerPass;
 use std::any::Any;
+use std::cmp;
 
 pub struct GenMovPhi {
     name: &'static str
@@ -36,7 +37,9 @@ struct IntermediateBlockInfo {
     blk_id: MuID,
     blk_name: MuName,
     target: MuID,
-    from_args: Vec<P<TreeNode>>
+    from_args: Vec<P<TreeNode>>,
+    /// the profiled count of the block that branches to the intermediate block
+    source_count: Option<u64>
 }
 
 impl CompilerPass for GenMovPhi {
@@ -59,6 +62,7 @@ impl CompilerPass for GenMovPhi {
         // first step - collects info on intermediate blocks
         for (blk_id, block) in f_content.blocks.iter_mut() {
             trace!("block: {}", blk_id);
+            let first_new_block = new_blocks_to_insert.len();
 
             // old block content
             let block_content = block.content.as_ref().unwrap().clone();
@@ -301,6 +305,10 @@ impl CompilerPass for GenMovPhi {
                 body: new_body,
                 keepalives: block_content.keepalives.clone()
             });
+
+            for block_info in new_blocks_to_insert[first_new_block..].iter_mut() {
+                block_info.source_count = block.exec_count;
+            }
         }
 
         // second step - insert new blocks
@@ -316,6 +324,12 @@ impl CompilerPass for GenMovPhi {
                 let target_block = f_content.get_block_mut(target_id);
                 assert!(target_block.content.is_some());
 
+                // an intermediate block runs at most as often as its source and its target
+                ret.exec_count = match (block_info.source_count, target_block.exec_count) {
+                    (Some(source), Some(target)) => Some(cmp::min(source, target)),
+                    _ => None
+                };
+
                 // if target_block is an exception block,
                 // set its exn argument to None, and set this new block as an exception block
                 let exn_arg = target_block.content.as_mut().unwrap().exn_arg.take();
@@ -422,7 +436,8 @@ fn process_dest(
             blk_id: new_blk_id,
             blk_name: new_blck_name,
             target: target.id(),
-            from_args: from_args
+            from_args: from_args,
+            source_count: None
         });
 
         dest
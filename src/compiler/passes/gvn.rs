@@ -0,0 +1,378 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::op::*;
+use ast::ptr::*;
+use ast::analysis::DomTree;
+use vm::VM;
+use compiler::CompilerPass;
+use std::any::Any;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Dominator-based global value numbering. The pass walks the dominator tree, and numbers pure
+/// instructions (arithmetic, comparisons, conversions, select and address computations) by their
+/// operation and operands. When an instruction computes the same value as an instruction in a
+/// dominating position, its result is replaced by the earlier one and the instruction is removed.
+///
+/// Reused values get more than one use, so TreeGen will not fold them into an expression tree.
+/// The pass recounts uses the way the Def-Use pass does, so this holds no matter where the pass
+/// is placed before TreeGen. Instructions whose operands are already trees are not numbered.
+pub struct GVN {
+    name: &'static str
+}
+
+impl GVN {
+    pub fn new() -> GVN {
+        GVN {
+            name: "Global Value Numbering"
+        }
+    }
+}
+
+impl CompilerPass for GVN {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    #[allow(unused_variables)]
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let domtree = func.domtree();
+        let numbering = {
+            let f_content = func.content.as_ref().unwrap();
+            let mut numbering = Numbering::new();
+            numbering.visit_block(f_content, &domtree, domtree.entry());
+            numbering
+        };
+
+        if numbering.removed.is_empty() {
+            return;
+        }
+
+        debug!("{} redundant instructions removed", numbering.removed.len());
+        rewrite(func, &numbering.removed, &numbering.replacements);
+        count_uses(func);
+        debug!("after GVN: {:?}", func);
+    }
+}
+
+/// the operation of a numbered instruction
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+enum ExprKind {
+    BinOp(BinOp),
+    CmpOp(CmpOp),
+    /// operation, from type
+    ConvOp(ConvOp, MuID),
+    Select,
+    GetIRef,
+    /// is_ptr, field index
+    GetFieldIRef(bool, usize),
+    GetElementIRef(bool),
+    ShiftIRef(bool),
+    GetVarPartIRef(bool)
+}
+
+/// an operand of a numbered instruction
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+enum Operand {
+    Var(MuID),
+    /// type, bits of an int/float constant
+    Bits(MuID, u64),
+    /// type
+    NullRef(MuID),
+    /// type, function
+    FuncRef(MuID, MuID),
+    /// type, symbol
+    ExternSym(MuID, CName)
+}
+
+/// instructions that compute the same expression yield the same value
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct Expr {
+    kind: ExprKind,
+    ty: MuID,
+    operands: Vec<Operand>
+}
+
+struct Numbering {
+    /// available expressions, and the value that holds each of them
+    table: HashMap<Expr, P<TreeNode>>,
+    /// results of removed instructions, and the values that replace them
+    replacements: HashMap<MuID, P<TreeNode>>,
+    /// IDs of removed instructions
+    removed: HashSet<MuID>
+}
+
+impl Numbering {
+    fn new() -> Numbering {
+        Numbering {
+            table: HashMap::new(),
+            replacements: HashMap::new(),
+            removed: HashSet::new()
+        }
+    }
+
+    /// numbers the instructions of a block, then the blocks it immediately dominates.
+    /// Expressions from this block are only available while visiting its dominator subtree
+    fn visit_block(
+        &mut self,
+        f_content: &FunctionContent,
+        domtree: &DomTree,
+        id: MuID
+    ) {
+        let mut scope = vec![];
+
+        for node in f_content.get_block(id).content.as_ref().unwrap().body.iter() {
+            let inst = node.as_inst();
+            let expr = match self.expr_of(inst) {
+                Some(expr) => expr,
+                None => continue
+            };
+            let result = inst.value.as_ref().unwrap()[0].clone();
+
+            let leader = self.table.get(&expr).cloned();
+            match leader {
+                Some(leader) => {
+                    trace!("{} is redundant, reuse {}", inst, leader);
+                    self.replacements.insert(result.id(), leader);
+                    self.removed.insert(inst.id());
+                }
+                None => {
+                    self.table.insert(expr.clone(), TreeNode::new_value(result));
+                    scope.push(expr);
+                }
+            }
+        }
+
+        for child in domtree.children(id).iter() {
+            self.visit_block(f_content, domtree, *child);
+        }
+
+        for expr in scope {
+            self.table.remove(&expr);
+        }
+    }
+
+    /// returns the expression a pure instruction computes, or None if it cannot be numbered
+    fn expr_of(&self, inst: &Instruction) -> Option<Expr> {
+        if inst.has_side_effect() {
+            return None;
+        }
+        let ty = match inst.value {
+            Some(ref vals) if vals.len() == 1 => vals[0].ty.id(),
+            _ => return None
+        };
+
+        let (kind, indices) = match inst.v {
+            Instruction_::BinOp(op, op1, op2) => (ExprKind::BinOp(op), vec![op1, op2]),
+            Instruction_::CmpOp(op, op1, op2) => (ExprKind::CmpOp(op), vec![op1, op2]),
+            Instruction_::ConvOp {
+                operation,
+                ref from_ty,
+                operand,
+                ..
+            } => (ExprKind::ConvOp(operation, from_ty.id()), vec![operand]),
+            Instruction_::Select {
+                cond,
+                true_val,
+                false_val
+            } => (ExprKind::Select, vec![cond, true_val, false_val]),
+            Instruction_::GetIRef(op) => (ExprKind::GetIRef, vec![op]),
+            Instruction_::GetFieldIRef {
+                is_ptr,
+                base,
+                index
+            } => (ExprKind::GetFieldIRef(is_ptr, index), vec![base]),
+            Instruction_::GetElementIRef {
+                is_ptr,
+                base,
+                index
+            } => (ExprKind::GetElementIRef(is_ptr), vec![base, index]),
+            Instruction_::ShiftIRef {
+                is_ptr,
+                base,
+                offset
+            } => (ExprKind::ShiftIRef(is_ptr), vec![base, offset]),
+            Instruction_::GetVarPartIRef { is_ptr, base } => {
+                (ExprKind::GetVarPartIRef(is_ptr), vec![base])
+            }
+            _ => return None
+        };
+
+        let mut operands = vec![];
+        for index in indices {
+            match self.operand(&inst.ops[index]) {
+                Some(operand) => operands.push(operand),
+                None => return None
+            }
+        }
+
+        // puts operands in a canonical order, so that a + b and b + a are the same expression
+        let kind = match kind {
+            ExprKind::BinOp(op) if is_commutative(op) => {
+                operands.sort();
+                kind
+            }
+            ExprKind::CmpOp(op) if operands[0] > operands[1] => {
+                operands.swap(0, 1);
+                ExprKind::CmpOp(op.swap_operands())
+            }
+            _ => kind
+        };
+
+        Some(Expr {
+            kind: kind,
+            ty: ty,
+            operands: operands
+        })
+    }
+
+    /// returns the operand for an SSA variable (or its replacement) or a constant.
+    /// Expression trees and other constants are not numbered
+    fn operand(&self, op: &P<TreeNode>) -> Option<Operand> {
+        let val = match op.v {
+            TreeNode_::Value(ref val) => val,
+            TreeNode_::Instruction(_) => return None
+        };
+
+        match val.v {
+            Value_::SSAVar(id) => {
+                match self.replacements.get(&id) {
+                    Some(leader) => Some(Operand::Var(leader.extract_ssa_id().unwrap())),
+                    None => Some(Operand::Var(id))
+                }
+            }
+            Value_::Constant(ref c) => {
+                let ty = val.ty.id();
+                match c {
+                    &Constant::Int(v) => Some(Operand::Bits(ty, v)),
+                    &Constant::Float(v) => Some(Operand::Bits(ty, v.to_bits() as u64)),
+                    &Constant::Double(v) => Some(Operand::Bits(ty, v.to_bits())),
+                    &Constant::NullRef => Some(Operand::NullRef(ty)),
+                    &Constant::FuncRef(ref func) => Some(Operand::FuncRef(ty, func.id())),
+                    &Constant::ExternSym(ref name) => Some(Operand::ExternSym(ty, name.clone())),
+                    _ => None
+                }
+            }
+            _ => None
+        }
+    }
+}
+
+fn is_commutative(op: BinOp) -> bool {
+    match op {
+        BinOp::Add | BinOp::Mul | BinOp::And | BinOp::Or | BinOp::Xor | BinOp::FAdd |
+        BinOp::FMul => true,
+        _ => false
+    }
+}
+
+/// removes instructions, and replaces uses of SSA variables (in operands and keepalives)
+pub fn rewrite(
+    func: &mut MuFunctionVersion,
+    removed: &HashSet<MuID>,
+    replacements: &HashMap<MuID, P<TreeNode>>
+) {
+    let f_content = func.content.as_mut().unwrap();
+    for (_, block) in f_content.blocks.iter_mut() {
+        let block_content = block.content.as_mut().unwrap();
+
+        let mut new_body = Vec::with_capacity(block_content.body.len());
+        for node in block_content.body.iter() {
+            if removed.contains(&node.as_inst().id()) {
+                trace!("remove instruction {}", node);
+                continue;
+            }
+            new_body.push(replace_uses(node, replacements));
+        }
+        block_content.body = new_body;
+
+        if let Some(ref mut keepalives) = block_content.keepalives {
+            for val in keepalives.iter_mut() {
+                let leader = replacements.get(&val.id()).map(|leader| leader.clone_value());
+                if let Some(leader) = leader {
+                    *val = leader;
+                }
+            }
+        }
+    }
+}
+
+/// replaces uses of removed results in an instruction, including the expression trees in it
+fn replace_uses(node: &P<TreeNode>, replacements: &HashMap<MuID, P<TreeNode>>) -> P<TreeNode> {
+    let inst = node.as_inst();
+    let new_ops: Vec<P<TreeNode>> = inst.ops
+        .iter()
+        .map(|op| match op.v {
+            TreeNode_::Value(ref val) => {
+                match replacements.get(&val.id()) {
+                    Some(leader) => leader.clone(),
+                    None => op.clone()
+                }
+            }
+            TreeNode_::Instruction(_) => replace_uses(op, replacements)
+        })
+        .collect();
+
+    if new_ops
+        .iter()
+        .zip(inst.ops.iter())
+        .all(|(new, old)| Arc::ptr_eq(new, old))
+    {
+        return node.clone();
+    }
+
+    let mut new_inst = inst.clone();
+    new_inst.ops = new_ops;
+    TreeNode::new_inst(new_inst)
+}
+
+/// recounts the uses of SSA variables the same way the Def-Use pass does
+fn count_uses(func: &mut MuFunctionVersion) {
+    let ref context = func.context;
+    for entry in context.values.values() {
+        entry.reset_use_count();
+    }
+
+    let use_value = |val: &P<Value>| if let Value_::SSAVar(id) = val.v {
+        context.get_value(id).unwrap().increase_use_count();
+    };
+
+    for (_, block) in func.content.as_ref().unwrap().blocks.iter() {
+        let block_content = block.content.as_ref().unwrap();
+        for node in block_content.body.iter() {
+            for op in node.as_inst().ops.iter() {
+                if let TreeNode_::Value(ref val) = op.v {
+                    use_value(val);
+                }
+            }
+        }
+        if let Some(ref keepalives) = block_content.keepalives {
+            for val in keepalives.iter() {
+                use_value(val);
+            }
+        }
+    }
+}
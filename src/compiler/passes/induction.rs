@@ -0,0 +1,309 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Induction variable recognition on IR loops.
+//!
+//! A basic induction variable is a parameter of a loop header that the preheader initialises,
+//! and that every latch passes back increased (ADD) or decreased (SUB) by the same integer
+//! constant. Values usually go around a Mu loop through the parameters of every block in it,
+//! so a parameter of a block in the loop (other than the header) that always receives the
+//! same value is considered a copy of that value.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::op::*;
+use ast::ptr::*;
+use ast::analysis::*;
+use compiler::passes::licm::has_usable_preheader;
+use compiler::passes::sccp::{fold_binop, fold_cmpop};
+use std::collections::HashMap;
+
+pub struct InductionVariable {
+    /// the parameter of the loop header
+    pub param: P<Value>,
+    /// the value passed by the preheader
+    pub init: P<TreeNode>,
+    /// BinOp::Add or BinOp::Sub
+    pub op: BinOp,
+    /// the integer constant added or subtracted in each iteration
+    pub step: P<TreeNode>
+}
+
+pub struct LoopInduction {
+    /// the basic induction variables, by the ID of the header parameter
+    ivs: HashMap<MuID, InductionVariable>,
+    /// block parameters in the loop that are copies of other values
+    copies: HashMap<MuID, MuID>,
+    /// the instruction that defines each SSA variable in the loop
+    defs: HashMap<MuID, P<TreeNode>>
+}
+
+impl LoopInduction {
+    /// finds the induction variables of a loop. A loop without a usable preheader has none
+    pub fn new(f_content: &FunctionContent, domtree: &DomTree, l: &Loop) -> LoopInduction {
+        let mut ret = LoopInduction {
+            ivs: HashMap::new(),
+            copies: HashMap::new(),
+            defs: HashMap::new()
+        };
+        if !has_usable_preheader(f_content, l) {
+            return ret;
+        }
+
+        for id in l.blocks.iter() {
+            for node in f_content.get_block(*id).content.as_ref().unwrap().body.iter() {
+                if let Some(ref vals) = node.as_inst().value {
+                    for val in vals.iter() {
+                        ret.defs.insert(val.id(), node.clone());
+                    }
+                }
+            }
+        }
+
+        // predecessors come first in reverse postorder (except along back edges, which only
+        // lead to headers whose parameters are not copies)
+        for id in domtree.reverse_postorder().iter() {
+            if *id == l.header || !l.contains(*id) {
+                continue;
+            }
+            let ref args = f_content.get_block(*id).content.as_ref().unwrap().args;
+            for (i, arg) in args.iter().enumerate() {
+                let incoming: Vec<Option<MuID>> = domtree
+                    .preds(*id)
+                    .iter()
+                    .flat_map(|pred| incoming_values(f_content, *pred, *id, i))
+                    .map(|op| op.and_then(|op| ret.resolve_op(&op)))
+                    .collect();
+                let first = match incoming.first() {
+                    Some(&Some(first)) => first,
+                    _ => continue
+                };
+                if incoming.iter().all(|other| *other == Some(first)) {
+                    ret.copies.insert(arg.id(), first);
+                }
+            }
+        }
+
+        let preheader = l.preheader.unwrap();
+        let ref header_args = f_content.get_block(l.header).content.as_ref().unwrap().args;
+        for (i, param) in header_args.iter().enumerate() {
+            let init = match incoming_values(f_content, preheader, l.header, i).pop() {
+                Some(Some(init)) => init,
+                _ => continue
+            };
+            let steps: Vec<Option<(BinOp, P<TreeNode>)>> = l.latches
+                .iter()
+                .flat_map(|latch| incoming_values(f_content, *latch, l.header, i))
+                .map(|op| op.and_then(|op| ret.step_of(param.id(), &op)))
+                .collect();
+            let (op, step) = match steps.first() {
+                Some(&Some((op, ref step))) => (op, step.clone()),
+                _ => continue
+            };
+            let same_step = steps.iter().all(|other| match other {
+                &Some((other_op, ref other_step)) => {
+                    other_op == op &&
+                        other_step.as_value().extract_int_const() ==
+                            step.as_value().extract_int_const()
+                }
+                &None => false
+            });
+            if !same_step {
+                continue;
+            }
+
+            trace!("induction variable {}: {:?} {}", param, op, step);
+            ret.ivs.insert(
+                param.id(),
+                InductionVariable {
+                    param: param.clone(),
+                    init: init,
+                    op: op,
+                    step: step
+                }
+            );
+        }
+
+        ret
+    }
+
+    /// returns the value that an SSA variable is a copy of (itself if it is not a copy)
+    pub fn resolve(&self, id: MuID) -> MuID {
+        match self.copies.get(&id) {
+            Some(orig) => *orig,
+            None => id
+        }
+    }
+
+    fn resolve_op(&self, op: &P<TreeNode>) -> Option<MuID> {
+        op.extract_ssa_id().map(|id| self.resolve(id))
+    }
+
+    /// returns the induction variable that an operand holds, if any
+    pub fn get_iv(&self, op: &P<TreeNode>) -> Option<&InductionVariable> {
+        match self.resolve_op(op) {
+            Some(id) => self.ivs.get(&id),
+            None => None
+        }
+    }
+
+    pub fn has_ivs(&self) -> bool {
+        !self.ivs.is_empty()
+    }
+
+    /// returns the instruction in the loop that defines the value an operand holds
+    fn def_of(&self, op: &P<TreeNode>) -> Option<&Instruction> {
+        match self.resolve_op(op) {
+            Some(id) => self.defs.get(&id).map(|node| node.as_inst()),
+            None => None
+        }
+    }
+
+    /// if a value is a parameter plus or minus a constant, returns the operation and constant
+    fn step_of(&self, param: MuID, op: &P<TreeNode>) -> Option<(BinOp, P<TreeNode>)> {
+        let inst = match self.def_of(op) {
+            Some(inst) => inst,
+            None => return None
+        };
+        let ref ops = inst.ops;
+        let is_param = |op: &P<TreeNode>| self.resolve_op(op) == Some(param);
+        let is_const = |op: &P<TreeNode>| constant_of(op).is_some();
+        match inst.v {
+            Instruction_::BinOp(BinOp::Add, a, b) => {
+                if is_param(&ops[a]) && is_const(&ops[b]) {
+                    Some((BinOp::Add, ops[b].clone()))
+                } else if is_const(&ops[a]) && is_param(&ops[b]) {
+                    Some((BinOp::Add, ops[a].clone()))
+                } else {
+                    None
+                }
+            }
+            Instruction_::BinOp(BinOp::Sub, a, b) => {
+                if is_param(&ops[a]) && is_const(&ops[b]) {
+                    Some((BinOp::Sub, ops[b].clone()))
+                } else {
+                    None
+                }
+            }
+            _ => None
+        }
+    }
+
+    /// returns how many times the loop body runs, if the loop only exits from the header by
+    /// comparing an induction variable with a constant initial value to a constant, and the
+    /// body runs at most max times
+    pub fn trip_count(&self, f_content: &FunctionContent, l: &Loop, max: usize) -> Option<usize> {
+        if l.exits.iter().any(|&(exiting, _)| exiting != l.header) {
+            return None;
+        }
+        let ref body = f_content.get_block(l.header).content.as_ref().unwrap().body;
+        let branch = body.last().unwrap().as_inst();
+        let (cond, stays_if) = match branch.v {
+            Instruction_::Branch2 {
+                cond,
+                ref true_dest,
+                ref false_dest,
+                ..
+            } => {
+                match (l.contains(true_dest.target.id()), l.contains(false_dest.target.id())) {
+                    (true, false) => (cond, true),
+                    (false, true) => (cond, false),
+                    _ => return None
+                }
+            }
+            _ => return None
+        };
+
+        let cmp = match self.def_of(&branch.ops[cond]) {
+            Some(cmp) => cmp,
+            None => return None
+        };
+        let (cmp_op, a, b) = match cmp.v {
+            Instruction_::CmpOp(cmp_op, a, b) => (cmp_op, &cmp.ops[a], &cmp.ops[b]),
+            _ => return None
+        };
+        // the induction variable may be either operand
+        let (iv, limit, iv_first) = match (self.get_iv(a), self.get_iv(b)) {
+            (Some(iv), None) => (iv, b, true),
+            (None, Some(iv)) => (iv, a, false),
+            _ => return None
+        };
+        let len = match iv.param.ty.get_int_length() {
+            Some(len) if len <= 64 => len,
+            _ => return None
+        };
+        let (mut cur, limit, step) = match (
+            constant_of(&iv.init),
+            constant_of(limit),
+            constant_of(&iv.step)
+        ) {
+            (Some(init), Some(limit), Some(step)) => (init, limit, step),
+            _ => return None
+        };
+
+        let mut count = 0;
+        loop {
+            let res = if iv_first {
+                fold_cmpop(cmp_op, len, &cur, &limit)
+            } else {
+                fold_cmpop(cmp_op, len, &limit, &cur)
+            };
+            match res {
+                Some(res) if res == stays_if => {}
+                Some(_) => return Some(count),
+                None => return None
+            }
+
+            count += 1;
+            if count > max {
+                return None;
+            }
+            cur = match fold_binop(iv.op, len, &cur, &step) {
+                Some(next) => next,
+                None => return None
+            };
+        }
+    }
+}
+
+fn constant_of(op: &P<TreeNode>) -> Option<Constant> {
+    match op.as_value().v {
+        Value_::Constant(ref c @ Constant::Int(_)) => Some(c.clone()),
+        _ => None
+    }
+}
+
+/// returns the operands that a block passes as the i-th argument of a target block (None for
+/// an exceptional value)
+pub fn incoming_values(
+    f_content: &FunctionContent,
+    from: MuID,
+    target: MuID,
+    i: usize
+) -> Vec<Option<P<TreeNode>>> {
+    let ref body = f_content.get_block(from).content.as_ref().unwrap().body;
+    let inst = match body.last() {
+        Some(node) => node.as_inst(),
+        None => return vec![]
+    };
+
+    inst.get_destinations()
+        .iter()
+        .filter(|dest| dest.target.id() == target)
+        .map(|dest| match dest.args[i] {
+            DestArg::Normal(index) => Some(inst.ops[index].clone()),
+            DestArg::Freshbound(_) => None
+        })
+        .collect()
+}
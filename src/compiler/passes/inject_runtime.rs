@@ -319,6 +319,7 @@ fn gen_allocation_sequence(
         let block_name = Arc::new(format!("new:{}:end", node.id()));
         let mut block = Block::new(MuEntityHeader::named(vm.next_id(), block_name));
         block.trace_hint = TraceHint::None;
+        block.exec_count = cur_block.exec_count;
         block.content = Some(BlockContent {
             args: vec![],
             exn_arg: None,
@@ -336,6 +337,7 @@ fn gen_allocation_sequence(
         let block_name = Arc::new(format!("new:{}:fastpath", node.id()));
         let mut block = Block::new(MuEntityHeader::named(vm.next_id(), block_name));
         block.trace_hint = TraceHint::FastPath;
+        block.exec_count = cur_block.exec_count;
         block.content = Some(BlockContent {
             args: vec![],
             exn_arg: None,
@@ -16,16 +16,53 @@ use ast::ir::*;
 use ast::ptr::*;
 use ast::inst::*;
 use vm::VM;
+use vm::profile;
 
 use compiler::CompilerPass;
 use std::any::Any;
+use std::cmp;
 use std::collections::HashMap;
-
+use std::collections::HashSet;
+
+/// a call site is inlined if the estimated instructions of the callee, minus the ones that
+/// inlining saves, are at most INLINE_THRESHOLD. The threshold doubles for each loop around the
+/// call site, up to MAX_LOOP_BONUS loops
+const INLINE_THRESHOLD: usize = 25;
+const MAX_LOOP_BONUS: usize = 3;
+/// instructions that a call costs (passing arguments, the call and the return)
+const CALL_COST: usize = 5;
+/// instructions that we expect SCCP to fold for each use of a constant argument in the callee
+const CONST_ARG_BONUS: usize = 3;
+/// with --profile-use, call sites that ran at least HOT_CALLSITE_COUNT times get the threshold of
+/// the deepest loops, and call sites that never ran are not inlined
+const HOT_CALLSITE_COUNT: u64 = 1000;
+/// inlining may add CALLER_GROWTH times the instructions of a function to it (and at least
+/// MIN_CALLER_BUDGET instructions)
+const CALLER_GROWTH: usize = 2;
+const MIN_CALLER_BUDGET: usize = 100;
+/// calls in inlined code are inlined again, up to this many levels
+const MAX_INLINE_DEPTH: usize = 3;
+
+/// Inlining with a cost model.
+///
+/// The cost of a call site is the size of the original IR of the callee (see estimate_insts()),
+/// minus the call itself and the uses of constant arguments that SCCP may fold. Call sites whose
+/// cost is within the threshold for their frequency are inlined, the most frequent and cheapest
+/// ones first, as long as the growth of the caller fits its budget. Calls in the inlined code
+/// are then considered in the same way, up to MAX_INLINE_DEPTH levels.
+///
+/// At a CALL with an exception clause, a callee may only throw with THROW, which becomes a
+/// branch to the exceptional destination of the call (see split_handlers()).
 pub struct Inlining {
     name: &'static str,
 
-    // whether a function version should be inlined
-    should_inline: HashMap<MuID, bool>
+    // whether a call site (by the ID of the call instruction) should be inlined
+    should_inline: HashMap<MuID, bool>,
+    // call sites to inline whose THROWs branch to the exceptional destination
+    throwing_sites: HashSet<MuID>,
+    // landing blocks created by split_handlers(), with the handler they branch to, and whether
+    // it takes the exception as its last argument
+    handlers: HashMap<MuID, (MuEntityHeader, bool)>
 }
 
 impl CompilerPass for Inlining {
@@ -42,11 +79,35 @@ impl CompilerPass for Inlining {
             info!("inlining is disabled");
             return;
         }
+        if !vm.vm_options.flag_profile_generate.is_empty() {
+            // so that the calls are counted in the callees
+            info!("inlining is disabled while profiling");
+            return;
+        }
 
-        if self.check(vm, func) {
-            self.inline(vm, func);
-            debug!("after inlining: {:?}", func);
+        self.handlers.clear();
+        let caller_insts = estimate_insts(func.content.as_ref().unwrap(), vm);
+        let mut budget = cmp::max(caller_insts * CALLER_GROWTH, MIN_CALLER_BUDGET);
+
+        // the blocks that we look for call sites in, with the functions that their code comes
+        // from (the caller, then the callees that it was inlined through)
+        let mut chains: HashMap<MuID, Vec<MuID>> = func.content
+            .as_ref()
+            .unwrap()
+            .blocks
+            .keys()
+            .map(|id| (*id, vec![func.func_id]))
+            .collect();
+
+        for depth in 0..MAX_INLINE_DEPTH {
+            if !self.check(vm, func, &chains, &mut budget) {
+                break;
+            }
+            chains = self.inline(vm, func, &chains);
+            func.invalidate_cfg_analyses();
+            debug!("after inlining (level {}): {:?}", depth + 1, func);
         }
+        self.remove_unused_landings(func);
     }
 }
 
@@ -54,114 +115,102 @@ impl Inlining {
     pub fn new() -> Inlining {
         Inlining {
             name: "Inlining",
-            should_inline: HashMap::new()
+            should_inline: HashMap::new(),
+            throwing_sites: HashSet::new(),
+            handlers: HashMap::new()
         }
     }
 
-    /// checks whether we need to rewrite the function because of inlining
-    fn check(&mut self, vm: &VM, func: &mut MuFunctionVersion) -> bool {
+    /// decides which call sites in the given blocks should be inlined, and takes their growth
+    /// from the budget. Returns whether we need to rewrite the function
+    fn check(
+        &mut self,
+        vm: &VM,
+        func: &mut MuFunctionVersion,
+        chains: &HashMap<MuID, Vec<MuID>>,
+        budget: &mut usize
+    ) -> bool {
         debug!("check inline");
 
-        // should_inline will store all the calls from this function,
-        // and whether they should be inlined
         self.should_inline.clear();
+        self.throwing_sites.clear();
 
-        let mut inline_something = false;
-
-        // check each call from this function
-        for (_, (func_id, has_exc)) in func.get_static_call_edges() {
-            // check a single callsite, whether it should be inlined
-            // the result is returned as boolean, and also written into 'should_inline'
-            let should_inline_this =
-                self.check_should_inline_func(has_exc, func_id, func.func_id, vm);
-            inline_something = inline_something || should_inline_this;
-        }
-
-        inline_something
-    }
-
-    /// checks whether we should inline the caller into the callee
-    fn check_should_inline_func(
-        &mut self,
-        has_exc: bool,
-        callee: MuID,
-        caller: MuID,
-        vm: &VM
-    ) -> bool {
-        // recursive call, do not inline
-        if callee == caller {
-            return false;
-        }
-
-        let funcs_guard = vm.funcs().read().unwrap();
-        let func = match funcs_guard.get(&callee) {
-            Some(func) => func.read().unwrap(),
-            None => panic!("callee {} is undeclared", callee)
-        };
-        let fv_id = match func.cur_ver {
-            Some(fv_id) => fv_id,
-            None => {
-                // the funtion is not defined
-                info!("the function is undefined, we cannot inline it. ");
-                return false;
-            }
-        };
+        let loop_nest = func.loop_nest();
+        let call_edges = func.get_static_call_edges();
 
-        // if we have checked this callee before, we use the same result
-        // (this is not optimal though. The inline criteria we use at the moment
-        // do not take caller size growth into consideration, so we will
-        // get the same result anyway. )
-        match self.should_inline.get(&fv_id) {
-            Some(flag) => {
-                trace!("func {} should be inlined (checked before)", callee);
-                return *flag;
+        let mut candidates = vec![];
+        for (id, block) in func.content.as_ref().unwrap().blocks.iter() {
+            let chain = match chains.get(id) {
+                Some(chain) => chain,
+                None => continue
+            };
+            for node in block.content.as_ref().unwrap().body.iter() {
+                let (callee, has_exc) = match call_edges.get(&node.id()) {
+                    Some(&edge) => edge,
+                    None => continue
+                };
+                let candidate = check_callsite(
+                    vm,
+                    node.as_inst(),
+                    callee,
+                    has_exc,
+                    chain,
+                    loop_nest.loop_depth(*id),
+                    // a call runs as many times as its block
+                    block.exec_count
+                );
+                if let Some(candidate) = candidate {
+                    candidates.push(candidate);
+                }
             }
-            None => {}
         }
 
-        let fv_guard = vm.func_vers().read().unwrap();
-        let fv = fv_guard.get(&fv_id).unwrap().read().unwrap();
+        // forced call sites first, then the most frequent and cheapest ones
+        candidates.sort_by_key(|c| (!c.force, MAX_LOOP_BONUS - c.level, c.cost, c.inst));
 
-        // if the function is forced inline, we inline it
-        if fv.force_inline {
-            trace!("func {} is forced as inline function", callee);
-            return true;
+        let mut inline_something = false;
+        for c in candidates.iter() {
+            let should_inline = c.force || c.growth <= *budget;
+            if should_inline {
+                *budget -= cmp::min(c.growth, *budget);
+                inline_something = true;
+                if c.throws {
+                    self.throwing_sites.insert(c.inst);
+                }
+            }
+            trace!(
+                "inline call {} (cost {}, level {})? {}, budget left: {}",
+                c.inst,
+                c.cost,
+                c.level,
+                should_inline,
+                *budget
+            );
+            self.should_inline.insert(c.inst, should_inline);
         }
 
-        // some heuristics here to decide if we should inline the function
-        let n_insts = estimate_insts(&fv);
-        let could_throw = fv.could_throw();
-        let has_tailcall = fv.has_tailcall();
-
-        // simple heuristic here:
-        // * estimated machine insts are fewer than 10 insts
-        // * leaf in call graph (no out calls)
-        // * no throw (otherwise we will need to rearrange catch)
-        let should_inline = n_insts <= 25 && !(has_exc && could_throw) && !has_tailcall;
-
-        trace!("func {} has {} insts (estimated)", callee, n_insts);
-        trace!(
-            "     has exception clause? {}, could throw? {}",
-            has_exc,
-            could_throw
-        );
-        trace!("SO func should be inlined? {}", should_inline);
-
-        self.should_inline.insert(callee, should_inline);
-
-        should_inline
+        inline_something
     }
 
-    /// inlines the callee that are marked as 'should inline'
-    fn inline(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+    /// inlines the call sites that are marked as 'should inline', returns the blocks copied from
+    /// callees with the functions that their code comes from
+    fn inline(
+        &mut self,
+        vm: &VM,
+        func: &mut MuFunctionVersion,
+        chains: &HashMap<MuID, Vec<MuID>>
+    ) -> HashMap<MuID, Vec<MuID>> {
         debug!("inlining for Function {}", func);
 
+        self.split_handlers(vm, func);
+
         let call_edges = func.get_static_call_edges();
 
         let f_content = func.content.as_mut().unwrap();
         let ref mut f_context = func.context;
 
         let mut new_blocks: Vec<Block> = vec![];
+        let mut new_chains: HashMap<MuID, Vec<MuID>> = HashMap::new();
 
         for (_, block) in f_content.blocks.iter() {
             // clone curent block, and clear its instructions
@@ -169,210 +218,236 @@ impl Inlining {
             cur_block.content.as_mut().unwrap().body.clear();
 
             let block = block.clone();
+            let block_id = block.id();
+            let block_count = block.exec_count;
 
             // iterate through instructions
             for inst in block.content.unwrap().body {
                 trace!("check inst: {}", inst);
                 let inst_id = inst.id();
-                if call_edges.contains_key(&inst_id) {
-                    let call_target = &call_edges.get(&inst_id).unwrap().0;
-                    if self.should_inline.contains_key(call_target) &&
-                        *self.should_inline.get(call_target).unwrap()
-                    {
-                        trace!("inserting inlined function at {}", inst);
-
-                        // from TreeNode into Inst (we do not need old TreeNode)
-                        let inst = inst.as_inst();
-
-                        // inline expansion starts here
-
-                        // getting the function being inlined
-                        let inlined_func = call_edges.get(&inst.id()).unwrap().0;
-                        trace!("function being inlined is {}", inlined_func);
-                        let inlined_fvid = match vm.get_cur_version_for_func(inlined_func) {
-                            Some(fvid) => fvid,
-                            None => {
-                                panic!(
-                                    "cannot resolve current version of Func {}, \
-                                     which is supposed to be inlined",
-                                    inlined_func
-                                )
+                if self.should_inline.get(&inst_id).cloned().unwrap_or(false) {
+                    trace!("inserting inlined function at {}", inst);
+
+                    // from TreeNode into Inst (we do not need old TreeNode)
+                    let inst = inst.as_inst();
+
+                    // inline expansion starts here
+
+                    // getting the function being inlined
+                    let inlined_func = call_edges.get(&inst.id()).unwrap().0;
+                    trace!("function being inlined is {}", inlined_func);
+                    let inlined_fvid = match vm.get_cur_version_for_func(inlined_func) {
+                        Some(fvid) => fvid,
+                        None => {
+                            panic!(
+                                "cannot resolve current version of Func {}, \
+                                 which is supposed to be inlined",
+                                inlined_func
+                            )
+                        }
+                    };
+                    let inlined_fvs_guard = vm.func_vers().read().unwrap();
+                    let inlined_fv_lock = inlined_fvs_guard.get(&inlined_fvid).unwrap();
+                    let inlined_fv_guard = inlined_fv_lock.read().unwrap();
+                    let inlined_fv_content = inlined_fv_guard.get_orig_ir().unwrap();
+
+                    trace!(
+                        "orig_content: {:?}",
+                        inlined_fv_guard.get_orig_ir().unwrap()
+                    );
+                    trace!(
+                        "content     : {:?}",
+                        inlined_fv_guard.content.as_ref().unwrap()
+                    );
+
+                    // the inlined blocks may be inlined into again, except for calls to the
+                    // functions that they come from
+                    let mut chain = chains.get(&block_id).unwrap().clone();
+                    chain.push(inlined_func);
+                    let inlined_counts =
+                        inlined_block_counts(vm, inlined_func, inlined_fv_content, block_count);
+
+                    // creates a new block ID
+                    // which will be the entry block for the inlined function
+                    let new_inlined_entry_hdr =
+                        new_inlined_block_name(inlined_fv_content.get_entry_block().name(), vm);
+                    // change current call instruction to a branch
+                    trace!("turning CALL instruction into a branch");
+                    let ref ops = inst.ops;
+                    match inst.v {
+                        Instruction_::ExprCall { ref data, .. } => {
+                            let arg_nodes: Vec<P<TreeNode>> =
+                                data.args.iter().map(|x| ops[*x].clone()).collect();
+                            let arg_indices: Vec<OpIndex> = (0..arg_nodes.len()).collect();
+
+                            let branch = TreeNode::new_inst(Instruction {
+                                hdr: inst.hdr.clone(),
+                                value: None,
+                                ops: arg_nodes.clone(),
+                                v: Instruction_::Branch1(Destination {
+                                    // this block doesnt exist yet, we will create it later
+                                    target: new_inlined_entry_hdr.clone(),
+                                    args: arg_indices.iter().map(|x| DestArg::Normal(*x)).collect()
+                                })
+                            });
+                            trace!("branch inst: {}", branch);
+
+                            // add branch to current block
+                            cur_block.content.as_mut().unwrap().body.push(branch);
+
+                            // finish current block
+                            new_blocks.push(cur_block.clone());
+
+                            // creates a new block after inlined part,
+                            // which will receive results from inlined function
+                            let old_name = cur_block.name();
+                            let new_name =
+                                Arc::new(format!("{}_cont_after_inline_{}", old_name, inst_id));
+                            trace!("create continue block for EXPRCALL/CCALL: {}", &new_name);
+                            cur_block = Block::new(MuEntityHeader::named(vm.next_id(), new_name));
+                            cur_block.exec_count = block_count;
+                            cur_block.content = Some(BlockContent {
+                                args: {
+                                    if inst.value.is_none() {
+                                        vec![]
+                                    } else {
+                                        inst.value.as_ref().unwrap().clone()
+                                    }
+                                },
+                                exn_arg: None,
+                                body: vec![],
+                                keepalives: None
+                            });
+
+                            // deal with the inlined function
+                            let copied = copy_inline_blocks(
+                                &mut new_blocks,
+                                cur_block.hdr.clone(),
+                                inlined_fv_content,
+                                &inlined_counts,
+                                new_inlined_entry_hdr,
+                                None,
+                                f_context,
+                                vm
+                            );
+                            for id in copied {
+                                new_chains.insert(id, chain.clone());
                             }
-                        };
-                        let inlined_fvs_guard = vm.func_vers().read().unwrap();
-                        let inlined_fv_lock = inlined_fvs_guard.get(&inlined_fvid).unwrap();
-                        let inlined_fv_guard = inlined_fv_lock.read().unwrap();
-                        let inlined_fv_content = inlined_fv_guard.get_orig_ir().unwrap();
-
-                        trace!(
-                            "orig_content: {:?}",
-                            inlined_fv_guard.get_orig_ir().unwrap()
-                        );
-                        trace!(
-                            "content     : {:?}",
-                            inlined_fv_guard.content.as_ref().unwrap()
-                        );
-                        // creates a new block ID
-                        // which will be the entry block for the inlined function
-                        let new_inlined_entry_hdr =
-                            new_inlined_block_name(inlined_fv_content.get_entry_block().name(), vm);
-                        // change current call instruction to a branch
-                        trace!("turning CALL instruction into a branch");
-                        let ref ops = inst.ops;
-                        match inst.v {
-                            Instruction_::ExprCall { ref data, .. } => {
-                                let arg_nodes: Vec<P<TreeNode>> =
-                                    data.args.iter().map(|x| ops[*x].clone()).collect();
-                                let arg_indices: Vec<OpIndex> = (0..arg_nodes.len()).collect();
-
-                                let branch = TreeNode::new_inst(Instruction {
-                                    hdr: inst.hdr.clone(),
+                        }
+
+                        Instruction_::Call {
+                            ref data,
+                            ref resume
+                        } => {
+                            let arg_nodes: Vec<P<TreeNode>> =
+                                data.args.iter().map(|x| ops[*x].clone()).collect();
+                            let arg_indices: Vec<OpIndex> = (0..arg_nodes.len()).collect();
+
+                            let branch = Instruction {
+                                hdr: inst.hdr.clone(),
+                                value: None,
+                                ops: arg_nodes,
+                                v: Instruction_::Branch1(Destination {
+                                    target: new_inlined_entry_hdr.clone(),
+                                    args: arg_indices.iter().map(|x| DestArg::Normal(*x)).collect()
+                                })
+                            };
+
+                            // add branch to current block
+                            cur_block
+                                .content
+                                .as_mut()
+                                .unwrap()
+                                .body
+                                .push(TreeNode::new_inst(branch));
+
+                            // next block
+                            let mut next_block = resume.normal_dest.target.clone();
+
+                            // if normal_dest expects arguments other than the results of
+                            // the call, we need an intermediate block to pass them
+                            let normal_dest_args = resume.normal_dest.get_arguments_as_node(&ops);
+                            let results: Vec<Option<MuID>> = match inst.value {
+                                Some(ref vals) => vals.iter().map(|val| Some(val.id())).collect(),
+                                None => vec![]
+                            };
+                            let passes_results = normal_dest_args
+                                .iter()
+                                .map(|arg| arg.extract_ssa_id())
+                                .collect::<Vec<Option<MuID>>>() ==
+                                results;
+                            if !passes_results {
+                                debug!("need an extra block for passing normal dest arguments");
+                                let int_block_name =
+                                    Arc::new(format!("inline_{}_arg_pass", inst_id));
+                                let mut intermediate_block =
+                                    Block::new(MuEntityHeader::named(vm.next_id(), int_block_name));
+                                intermediate_block.exec_count = block_count;
+
+                                // branch to normal_dest with normal_dest arguments
+                                let normal_dest_args_len = normal_dest_args.len();
+
+                                let branch = Instruction {
+                                    hdr: MuEntityHeader::unnamed(vm.next_id()),
                                     value: None,
-                                    ops: arg_nodes.clone(),
+                                    ops: normal_dest_args,
                                     v: Instruction_::Branch1(Destination {
-                                        // this block doesnt exist yet, we will create it later
-                                        target: new_inlined_entry_hdr.clone(),
-                                        args: arg_indices
-                                            .iter()
-                                            .map(|x| DestArg::Normal(*x))
+                                        target: resume.normal_dest.target.clone(),
+                                        args: (0..normal_dest_args_len)
+                                            .map(|x| DestArg::Normal(x))
                                             .collect()
                                     })
-                                });
-                                trace!("branch inst: {}", branch);
-
-                                // add branch to current block
-                                cur_block.content.as_mut().unwrap().body.push(branch);
-
-                                // finish current block
-                                new_blocks.push(cur_block.clone());
-
-                                // creates a new block after inlined part,
-                                // which will receive results from inlined function
-                                let old_name = cur_block.name();
-                                let new_name =
-                                    Arc::new(format!("{}_cont_after_inline_{}", old_name, inst_id));
-                                trace!("create continue block for EXPRCALL/CCALL: {}", &new_name);
-                                cur_block =
-                                    Block::new(MuEntityHeader::named(vm.next_id(), new_name));
-                                cur_block.content = Some(BlockContent {
+                                };
+
+                                intermediate_block.content = Some(BlockContent {
                                     args: {
-                                        if inst.value.is_none() {
-                                            vec![]
-                                        } else {
-                                            inst.value.as_ref().unwrap().clone()
+                                        match inst.value {
+                                            Some(ref vec) => vec.clone(),
+                                            None => vec![]
                                         }
                                     },
                                     exn_arg: None,
-                                    body: vec![],
+                                    body: vec![TreeNode::new_inst(branch)],
                                     keepalives: None
                                 });
 
-                                // deal with the inlined function
-                                copy_inline_blocks(
-                                    &mut new_blocks,
-                                    cur_block.hdr.clone(),
-                                    inlined_fv_content,
-                                    new_inlined_entry_hdr,
-                                    vm
-                                );
-                                copy_inline_context(f_context, &inlined_fv_guard.context);
-                            }
+                                trace!("extra block: {:?}", intermediate_block);
 
-                            Instruction_::Call {
-                                ref data,
-                                ref resume
-                            } => {
-                                let arg_nodes: Vec<P<TreeNode>> =
-                                    data.args.iter().map(|x| ops[*x].clone()).collect();
-                                let arg_indices: Vec<OpIndex> = (0..arg_nodes.len()).collect();
+                                next_block = intermediate_block.hdr.clone();
+                                new_blocks.push(intermediate_block);
+                            }
 
-                                let branch = Instruction {
-                                    hdr: inst.hdr.clone(),
-                                    value: None,
-                                    ops: arg_nodes,
-                                    v: Instruction_::Branch1(Destination {
-                                        target: new_inlined_entry_hdr.clone(),
-                                        args: arg_indices
-                                            .iter()
-                                            .map(|x| DestArg::Normal(*x))
-                                            .collect()
-                                    })
-                                };
+                            // THROW in the inlined function branches to the handler
+                            let throw_target = if self.throwing_sites.contains(&inst_id) {
+                                let landing = resume.exn_dest.target.id();
+                                let &(ref handler, takes_exception) =
+                                    self.handlers.get(&landing).unwrap();
+                                Some(ThrowTarget {
+                                    handler: handler.clone(),
+                                    args: resume.exn_dest.get_arguments_as_node(&ops),
+                                    takes_exception: takes_exception
+                                })
+                            } else {
+                                None
+                            };
 
-                                // add branch to current block
-                                cur_block
-                                    .content
-                                    .as_mut()
-                                    .unwrap()
-                                    .body
-                                    .push(TreeNode::new_inst(branch));
-
-                                // next block
-                                let mut next_block = resume.normal_dest.target.clone();
-
-                                // if normal_dest expects different number of arguments
-                                // other than the inlined function returns, we need
-                                // an intermediate block to pass extra arguments
-                                if resume.normal_dest.args.len() !=
-                                    inlined_fv_guard.sig.ret_tys.len()
-                                {
-                                    debug!("need an extra block for passing normal dest arguments");
-                                    let int_block_name =
-                                        Arc::new(format!("inline_{}_arg_pass", inst_id));
-                                    let mut intermediate_block = Block::new(
-                                        MuEntityHeader::named(vm.next_id(), int_block_name)
-                                    );
-
-                                    // branch to normal_dest with normal_dest arguments
-                                    let normal_dest_args =
-                                        resume.normal_dest.get_arguments_as_node(&ops);
-                                    let normal_dest_args_len = normal_dest_args.len();
-
-                                    let branch = Instruction {
-                                        hdr: MuEntityHeader::unnamed(vm.next_id()),
-                                        value: None,
-                                        ops: normal_dest_args,
-                                        v: Instruction_::Branch1(Destination {
-                                            target: resume.normal_dest.target.clone(),
-                                            args: (0..normal_dest_args_len)
-                                                .map(|x| DestArg::Normal(x))
-                                                .collect()
-                                        })
-                                    };
-
-                                    intermediate_block.content = Some(BlockContent {
-                                        args: {
-                                            match inst.value {
-                                                Some(ref vec) => vec.clone(),
-                                                None => vec![]
-                                            }
-                                        },
-                                        exn_arg: None,
-                                        body: vec![TreeNode::new_inst(branch)],
-                                        keepalives: None
-                                    });
-
-                                    trace!("extra block: {:?}", intermediate_block);
-
-                                    next_block = intermediate_block.hdr.clone();
-                                    new_blocks.push(intermediate_block);
-                                }
-
-                                // deal with inlined function
-                                copy_inline_blocks(
-                                    &mut new_blocks,
-                                    next_block.clone(),
-                                    inlined_fv_guard.get_orig_ir().unwrap(),
-                                    new_inlined_entry_hdr.clone(),
-                                    vm
-                                );
-                                copy_inline_context(f_context, &inlined_fv_guard.context);
+                            // deal with inlined function
+                            let copied = copy_inline_blocks(
+                                &mut new_blocks,
+                                next_block.clone(),
+                                inlined_fv_content,
+                                &inlined_counts,
+                                new_inlined_entry_hdr.clone(),
+                                throw_target.as_ref(),
+                                f_context,
+                                vm
+                            );
+                            for id in copied {
+                                new_chains.insert(id, chain.clone());
                             }
-
-                            _ => panic!("unexpected callsite: {}", inst)
                         }
-                    } else {
-                        cur_block.content.as_mut().unwrap().body.push(inst.clone());
+
+                        _ => panic!("unexpected callsite: {}", inst)
                     }
                 } else {
                     cur_block.content.as_mut().unwrap().body.push(inst.clone());
@@ -386,7 +461,316 @@ impl Inlining {
         for blk in new_blocks {
             f_content.blocks.insert(blk.id(), blk);
         }
+
+        new_chains
+    }
+
+    /// makes the exceptional destinations of throwing_sites ordinary blocks, so that THROW in
+    /// the inlined code can branch to them. The exceptional parameter of such a handler becomes
+    /// its last parameter, and a new landing block takes the exception (from all the branches
+    /// that went to the handler) and passes it on
+    fn split_handlers(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let mut split = vec![];
+        for block in func.content.as_ref().unwrap().blocks.values() {
+            for node in block.content.as_ref().unwrap().body.iter() {
+                if !self.throwing_sites.contains(&node.id()) {
+                    continue;
+                }
+                let target = node.as_inst().get_exception_target().unwrap();
+                if !self.handlers.contains_key(&target) && !split.contains(&target) {
+                    split.push(target);
+                }
+            }
+        }
+
+        for id in split {
+            let (hdr, args, exn_arg, count) = {
+                let handler = func.content.as_mut().unwrap().get_block_mut(id);
+                let count = handler.exec_count;
+                let handler_content = handler.content.as_mut().unwrap();
+                let exn_arg = handler_content.exn_arg.take();
+                let args = handler_content.args.clone();
+                if let Some(ref exn_arg) = exn_arg {
+                    handler_content.args.push(exn_arg.clone());
+                }
+                (handler.hdr.clone(), args, exn_arg, count)
+            };
+
+            // landing(args')[exn_arg']: BRANCH handler(args', exn_arg')
+            let land_args: Vec<P<Value>> = args.iter()
+                .map(|arg| {
+                    func.context
+                        .make_temporary(vm.next_id(), arg.ty.clone())
+                        .clone_value()
+                })
+                .collect();
+            let land_exn_arg = exn_arg.as_ref().map(|exn_arg| {
+                func.context
+                    .make_temporary(vm.next_id(), exn_arg.ty.clone())
+                    .clone_value()
+            });
+            let ops: Vec<P<TreeNode>> = land_args
+                .iter()
+                .chain(land_exn_arg.iter())
+                .map(|val| TreeNode::new_value(val.clone()))
+                .collect();
+            let n_ops = ops.len();
+            let branch = Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: None,
+                ops: ops,
+                v: Instruction_::Branch1(Destination {
+                    target: hdr.clone(),
+                    args: (0..n_ops).map(|x| DestArg::Normal(x)).collect()
+                })
+            };
+            let mut landing = Block::new(MuEntityHeader::named(
+                vm.next_id(),
+                Arc::new(format!("{}_landing", hdr.name()))
+            ));
+            // everything that went to the handler goes through the landing block
+            landing.exec_count = count;
+            landing.content = Some(BlockContent {
+                args: land_args,
+                exn_arg: land_exn_arg,
+                body: vec![TreeNode::new_inst(branch)],
+                keepalives: None
+            });
+            trace!("split handler {} with landing block {:?}", hdr, landing);
+
+            // everything that went to the handler goes to the landing block
+            let f_content = func.content.as_mut().unwrap();
+            let block_ids: Vec<MuID> = f_content.blocks.keys().cloned().collect();
+            for block_id in block_ids {
+                let ref mut body = f_content
+                    .get_block_mut(block_id)
+                    .content
+                    .as_mut()
+                    .unwrap()
+                    .body;
+                let last = body.len() - 1;
+                let mut inst = body[last].as_inst().clone();
+                let mut changed = false;
+                for dest in inst.get_destinations_mut() {
+                    if dest.target.id() == id {
+                        dest.target = landing.hdr.clone();
+                        changed = true;
+                    }
+                }
+                if changed {
+                    body[last] = TreeNode::new_inst(inst);
+                }
+            }
+
+            self.handlers
+                .insert(landing.id(), (hdr, exn_arg.is_some()));
+            f_content.blocks.insert(landing.id(), landing);
+        }
     }
+
+    /// removes the landing blocks of split handlers that nothing goes to any more (when all
+    /// the calls to a handler were inlined)
+    fn remove_unused_landings(&mut self, func: &mut MuFunctionVersion) {
+        let f_content = func.content.as_mut().unwrap();
+        let mut targets = HashSet::new();
+        for id in f_content.blocks.keys() {
+            targets.extend(f_content.get_successors(*id));
+        }
+        for landing in self.handlers.keys() {
+            if !targets.contains(landing) {
+                trace!("remove unused landing block {}", landing);
+                f_content.blocks.remove(landing);
+            }
+        }
+    }
+}
+
+/// a call site that may be inlined
+struct Candidate {
+    inst: MuID,
+    force: bool,
+    /// how frequent the call site is: the loops around it (MAX_LOOP_BONUS for hot ones)
+    level: usize,
+    /// the instructions of the callee, minus the ones that inlining saves
+    cost: usize,
+    /// the instructions that inlining adds to the caller
+    growth: usize,
+    /// whether THROW in the callee needs to branch to the exceptional destination
+    throws: bool
+}
+
+/// checks whether a call site can be inlined, and whether its cost is within the threshold
+fn check_callsite(
+    vm: &VM,
+    call: &Instruction,
+    callee: MuID,
+    has_exc: bool,
+    chain: &Vec<MuID>,
+    loop_depth: usize,
+    count: Option<u64>
+) -> Option<Candidate> {
+    // recursive call (also through the functions that the call was inlined from), do not inline
+    if chain.contains(&callee) {
+        return None;
+    }
+    match call.v {
+        Instruction_::ExprCall { .. } | Instruction_::Call { .. } => {}
+        _ => return None
+    }
+
+    let funcs_guard = vm.funcs().read().unwrap();
+    let func = match funcs_guard.get(&callee) {
+        Some(func) => func.read().unwrap(),
+        None => panic!("callee {} is undeclared", callee)
+    };
+    let fv_id = match func.cur_ver {
+        Some(fv_id) => fv_id,
+        None => {
+            // the funtion is not defined
+            info!("the function is undefined, we cannot inline it. ");
+            return None;
+        }
+    };
+
+    let fv_guard = vm.func_vers().read().unwrap();
+    let fv = fv_guard.get(&fv_id).unwrap().read().unwrap();
+    let callee_content = match fv.get_orig_ir() {
+        Some(content) => content,
+        None => return None
+    };
+
+    if fv.has_tailcall() {
+        trace!("func {} has tail calls", callee);
+        return None;
+    }
+    // THROW can branch to the exceptional destination of the call, but exceptions from other
+    // instructions would unwind past it
+    if has_exc && !throws_only_by_throw(callee_content) {
+        trace!("func {} could throw past the exception clause of {}", callee, call);
+        return None;
+    }
+
+    // the original IR is what gets copied (the callee may have been compiled already, and have
+    // its own callees inlined into its current content)
+    let n_insts = estimate_insts(callee_content, vm);
+    let mut candidate = Candidate {
+        inst: call.id(),
+        force: fv.force_inline,
+        level: MAX_LOOP_BONUS,
+        cost: 0,
+        growth: n_insts.saturating_sub(CALL_COST),
+        throws: has_exc && has_throw(callee_content)
+    };
+
+    // if the function is forced inline, we inline it
+    if fv.force_inline {
+        trace!("func {} is forced as inline function", callee);
+        return Some(candidate);
+    }
+
+    // with a profile, we do not inline calls that never ran, and treat hot call sites like
+    // the ones in the deepest loops
+    candidate.level = match count {
+        Some(0) => {
+            trace!("call {} never ran (profiled)", call);
+            return None;
+        }
+        Some(count) if count >= HOT_CALLSITE_COUNT => MAX_LOOP_BONUS,
+        _ => cmp::min(loop_depth, MAX_LOOP_BONUS)
+    };
+    let const_args = const_arg_uses(call, callee_content);
+    candidate.cost = n_insts.saturating_sub(CALL_COST + CONST_ARG_BONUS * const_args);
+
+    trace!(
+        "func {} has {} insts (estimated), {} uses of constant arguments",
+        callee,
+        n_insts,
+        const_args
+    );
+    trace!(
+        "     cost {}, threshold {}",
+        candidate.cost,
+        INLINE_THRESHOLD << candidate.level
+    );
+    if candidate.cost <= INLINE_THRESHOLD << candidate.level {
+        Some(candidate)
+    } else {
+        None
+    }
+}
+
+/// counts the uses of parameters that the call passes constants to in the instructions of the
+/// callee that SCCP folds
+fn const_arg_uses(call: &Instruction, callee: &FunctionContent) -> usize {
+    let data = match call.v {
+        Instruction_::ExprCall { ref data, .. } | Instruction_::Call { ref data, .. } => data,
+        _ => return 0
+    };
+    let ref params = callee.get_entry_block().content.as_ref().unwrap().args;
+    let const_params: Vec<MuID> = data.args
+        .iter()
+        .zip(params.iter())
+        .filter(|&(arg, _)| call.ops[*arg].as_value().is_const())
+        .map(|(_, param)| param.id())
+        .collect();
+    if const_params.is_empty() {
+        return 0;
+    }
+
+    let mut uses = 0;
+    for block in callee.blocks.values() {
+        for node in block.content.as_ref().unwrap().body.iter() {
+            let inst = node.as_inst();
+            match inst.v {
+                Instruction_::BinOp(..) |
+                Instruction_::CmpOp(..) |
+                Instruction_::ConvOp { .. } |
+                Instruction_::Select { .. } |
+                Instruction_::Branch2 { .. } |
+                Instruction_::Switch { .. } => {}
+                _ => continue
+            }
+            uses += inst.ops
+                .iter()
+                .filter(|op| match op.extract_ssa_id() {
+                    Some(id) => const_params.contains(&id),
+                    None => false
+                })
+                .count();
+        }
+    }
+    uses
+}
+
+/// returns whether THROW is the only way that exceptions leave a function
+fn throws_only_by_throw(f_content: &FunctionContent) -> bool {
+    f_content.blocks.values().all(|block| {
+        block.content.as_ref().unwrap().body.iter().all(|node| {
+            let inst = node.as_inst();
+            match inst.v {
+                Instruction_::Throw(_) => true,
+                _ => !inst.is_potentially_throwing() || inst.has_exception_clause()
+            }
+        })
+    })
+}
+
+fn has_throw(f_content: &FunctionContent) -> bool {
+    f_content.blocks.values().any(|block| {
+        block.content.as_ref().unwrap().body.iter().any(|node| match node.as_inst().v {
+            Instruction_::Throw(_) => true,
+            _ => false
+        })
+    })
+}
+
+/// where THROW in inlined code branches to
+struct ThrowTarget {
+    handler: MuEntityHeader,
+    /// the arguments of the exceptional destination of the call
+    args: Vec<P<TreeNode>>,
+    /// whether the exception is passed as the last argument
+    takes_exception: bool
 }
 
 fn new_inlined_block_name(old_block_name: MuName, vm: &VM) -> MuEntityHeader {
@@ -396,14 +780,20 @@ fn new_inlined_block_name(old_block_name: MuName, vm: &VM) -> MuEntityHeader {
         Arc::new(format!("{}:inlinedblock.#{}", old_block_name, new_id))
     )
 }
-/// copies blocks from callee to caller, with specified entry block and return block
+
+/// copies blocks from callee to caller, with specified entry block and return block, and the
+/// given counts of the callee blocks (see inlined_block_counts()).
+/// Returns the IDs of the copied blocks
 fn copy_inline_blocks(
     caller: &mut Vec<Block>,
     ret_block: MuEntityHeader,
     callee: &FunctionContent,
+    counts: &HashMap<MuID, u64>,
     entry_block: MuEntityHeader,
+    throw_target: Option<&ThrowTarget>,
+    context: &mut FunctionContext,
     vm: &VM
-) {
+) -> Vec<MuID> {
     trace!("trying to copy inlined function blocks to caller");
 
     // old id -> new id
@@ -417,231 +807,174 @@ fn copy_inline_blocks(
         }
     }
 
-    let fix_dest = |dest: Destination| {
-        Destination {
-            target: (*block_map.get(&dest.target.id()).unwrap()).clone(),
-            args: dest.args
+    // the SSA variables of the callee get new IDs, so that a function can be inlined more
+    // than once
+    let mut values: HashMap<MuID, P<Value>> = HashMap::new();
+    for block in callee.blocks.values() {
+        let block_content = block.content.as_ref().unwrap();
+        let defs = block_content.body.iter().flat_map(|node| match node.as_inst().value {
+            Some(ref vals) => vals.clone(),
+            None => vec![]
+        });
+        let params = block_content.args.iter().chain(block_content.exn_arg.iter()).cloned();
+        for val in params.chain(defs) {
+            let new_val = context
+                .make_temporary(vm.next_id(), val.ty.clone())
+                .clone_value();
+            values.insert(val.id(), new_val);
         }
+    }
+    let copy_val = |val: &P<Value>| match values.get(&val.id()) {
+        Some(new_val) => new_val.clone(),
+        None => val.clone()
     };
-
-    let fix_resume = |resume: ResumptionData| {
-        ResumptionData {
-            normal_dest: fix_dest(resume.normal_dest),
-            exn_dest: fix_dest(resume.exn_dest)
+    let copy_op = |op: &P<TreeNode>| match op.extract_ssa_id() {
+        Some(id) if values.contains_key(&id) => {
+            TreeNode::new_value(values.get(&id).unwrap().clone())
         }
+        _ => op.clone()
     };
 
+    let mut ret = vec![];
     for old_block in callee.blocks.values() {
         let old_id = old_block.id();
         let new_hdr = (*block_map.get(&old_block.id()).unwrap()).clone();
-        let mut block = Block {
-            hdr: new_hdr.clone(),
-            content: Some(old_block.content.as_ref().unwrap().clone_empty()),
-            trace_hint: TraceHint::None,
-            control_flow: ControlFlow::default()
-        };
+        let old_block_content = old_block.content.as_ref().unwrap();
 
         trace!("starts copying instruction from {} to {}", old_id, new_hdr);
 
-        // Create the new blocks contents
-        {
-            let old_block_content = old_block.content.as_ref().unwrap();
-            let block_content = block.content.as_mut().unwrap();
-
-            // Copy the old_block contents (minus the last one)
-            for i in 0..old_block_content.body.len() - 1 {
-                block_content.body.push(match old_block_content.body[i].v {
-                    TreeNode_::Instruction(ref inst) => {
-                        TreeNode::new_inst(inst.clone_with_id(vm.next_id()))
-                    }
-                    _ => panic!("expect instruction as block body")
-                });
+        let mut body = vec![];
+        for node in old_block_content.body.iter() {
+            let old_inst = node.as_inst();
+            match old_inst.v {
+                Instruction_::Watchpoint { .. } |
+                Instruction_::WPBranch { .. } |
+                Instruction_::ExnInstruction { .. } => unimplemented!(),
+                _ => {}
             }
 
-            // check its last instruction
-            let last_inst = old_block_content.body.last().unwrap();
             // every inst should have a unique ID
-            let inst_new_id = vm.next_id();
-            let last_inst_clone = match last_inst.v {
-                TreeNode_::Instruction(ref inst) => {
-                    TreeNode::new_inst(inst.clone_with_id(inst_new_id))
-                }
-                _ => panic!("expect instruction as block body")
+            let mut inst = Instruction {
+                hdr: old_inst.hdr.clone_with_id(vm.next_id()),
+                value: old_inst
+                    .value
+                    .as_ref()
+                    .map(|vals| vals.iter().map(|val| copy_val(val)).collect()),
+                ops: old_inst.ops.iter().map(|op| copy_op(op)).collect(),
+                v: old_inst.v.clone()
             };
+            // fix destination
+            for dest in inst.get_destinations_mut() {
+                dest.target = block_map.get(&dest.target.id()).unwrap().clone();
+            }
 
-            match &last_inst.v {
-                &TreeNode_::Instruction(ref inst) => {
-                    trace!("last instruction: {}", inst);
-
-                    let hdr = inst.hdr.clone_with_id(inst_new_id);
-                    let ref value = inst.value;
-                    let ref ops = inst.ops;
-                    let ref v = inst.v;
-
-                    match v {
-                        &Instruction_::Return(ref vec) => {
-                            // change RET to a branch
-                            let branch = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Branch1(Destination {
-                                    target: ret_block.clone(),
-                                    args: vec.iter().map(|x| DestArg::Normal(*x)).collect()
-                                })
-                            };
-
-                            trace!("rewrite to: {}", branch);
-                            block_content.body.push(TreeNode::new_inst(branch));
-                        }
-
-                        // fix destination
-                        &Instruction_::Branch1(ref dest) => {
-                            let branch = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Branch1(fix_dest(dest.clone()))
-                            };
-
-                            trace!("rewrite to: {}", branch);
-                            block_content.body.push(TreeNode::new_inst(branch));
-                        }
-                        &Instruction_::Branch2 {
-                            ref cond,
-                            ref true_dest,
-                            ref false_dest,
-                            ref true_prob
-                        } => {
-                            let branch2 = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Branch2 {
-                                    cond: *cond,
-                                    true_dest: fix_dest(true_dest.clone()),
-                                    false_dest: fix_dest(false_dest.clone()),
-                                    true_prob: *true_prob
-                                }
-                            };
-
-                            trace!("rewrite to: {}", branch2);
-                            block_content.body.push(TreeNode::new_inst(branch2));
-                        }
-                        &Instruction_::Call {
-                            ref data,
-                            ref resume
-                        } => {
-                            let call = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Call {
-                                    data: data.clone(),
-                                    resume: fix_resume(resume.clone())
-                                }
-                            };
-
-                            trace!("rewrite to: {}", call);
-                            block_content.body.push(TreeNode::new_inst(call));
-                        }
-                        &Instruction_::CCall {
-                            ref data,
-                            ref resume
-                        } => {
-                            let call = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::CCall {
-                                    data: data.clone(),
-                                    resume: fix_resume(resume.clone())
-                                }
-                            };
-
-                            trace!("rewrite to: {}", call);
-                            block_content.body.push(TreeNode::new_inst(call));
-                        }
-                        &Instruction_::Switch {
-                            ref cond,
-                            ref default,
-                            ref branches
-                        } => {
-                            let switch = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::Switch {
-                                    cond: *cond,
-                                    default: fix_dest(default.clone()),
-                                    branches: branches
-                                        .iter()
-                                        .map(|&(ref op, ref dest)| {
-                                            (op.clone(), fix_dest(dest.clone()))
-                                        })
-                                        .collect()
-                                }
-                            };
-
-                            trace!("rewrite to: {}", switch);
-                            block_content.body.push(TreeNode::new_inst(switch));
-                        }
-
-                        &Instruction_::SwapStackExc {
-                            stack,
-                            is_exception,
-                            ref args,
-                            ref resume
-                        } => {
-                            let swapstack = Instruction {
-                                hdr: hdr,
-                                value: value.clone(),
-                                ops: ops.clone(),
-                                v: Instruction_::SwapStackExc {
-                                    stack: stack,
-                                    is_exception: is_exception,
-                                    args: args.clone(),
-                                    resume: fix_resume(resume.clone())
-                                }
-                            };
-
-                            trace!("rewrite to: {}", swapstack);
-                            block_content.body.push(TreeNode::new_inst(swapstack));
-                        }
-                        &Instruction_::Watchpoint { .. } |
-                        &Instruction_::WPBranch { .. } |
-                        &Instruction_::ExnInstruction { .. } => unimplemented!(),
-
-                        _ => {
-                            block_content.body.push(last_inst_clone);
-                        }
+            let rewritten = match inst.v {
+                // change RET to a branch
+                Instruction_::Return(ref vec) => Some(Instruction {
+                    hdr: inst.hdr.clone(),
+                    value: None,
+                    ops: inst.ops.clone(),
+                    v: Instruction_::Branch1(Destination {
+                        target: ret_block.clone(),
+                        args: vec.iter().map(|x| DestArg::Normal(*x)).collect()
+                    })
+                }),
+                // change THROW to a branch to the handler
+                Instruction_::Throw(exn_obj) if throw_target.is_some() => {
+                    let target = throw_target.unwrap();
+                    let mut ops = target.args.clone();
+                    if target.takes_exception {
+                        ops.push(inst.ops[exn_obj].clone());
                     }
+                    let n_ops = ops.len();
+                    Some(Instruction {
+                        hdr: inst.hdr.clone(),
+                        value: None,
+                        ops: ops,
+                        v: Instruction_::Branch1(Destination {
+                            target: target.handler.clone(),
+                            args: (0..n_ops).map(|x| DestArg::Normal(x)).collect()
+                        })
+                    })
                 }
-                _ => {
-                    // do nothing, and directly push the instruction back
-                    block_content.body.push(last_inst_clone)
+                _ => None
+            };
+            let inst = match rewritten {
+                Some(rewritten) => {
+                    trace!("rewrite to: {}", rewritten);
+                    rewritten
                 }
-            }
+                None => inst
+            };
+            body.push(TreeNode::new_inst(inst));
         }
 
+        let block = Block {
+            hdr: new_hdr.clone(),
+            content: Some(BlockContent {
+                args: old_block_content.args.iter().map(|val| copy_val(val)).collect(),
+                exn_arg: old_block_content.exn_arg.as_ref().map(|val| copy_val(val)),
+                body: body,
+                keepalives: old_block_content
+                    .keepalives
+                    .as_ref()
+                    .map(|vals| vals.iter().map(|val| copy_val(val)).collect())
+            }),
+            trace_hint: TraceHint::None,
+            exec_count: counts.get(&old_id).cloned(),
+            control_flow: ControlFlow::default()
+        };
+
+        ret.push(new_hdr.id());
         caller.push(block);
     }
+
+    ret
 }
 
-/// copies inlined function context into caller
-fn copy_inline_context(caller: &mut FunctionContext, callee: &FunctionContext) {
-    trace!("trying to copy inlined function context to caller");
-    for (id, entry) in callee.values.iter() {
-        caller
-            .values
-            .insert(*id, SSAVarEntry::new(entry.value().clone()));
+/// estimates how many times the blocks of a callee run when they are inlined at a call site
+/// that ran call_count times: their counts in the --profile-use profile of the callee, scaled
+/// by the share of the calls to the callee that the call site makes. The counts are unknown
+/// (empty) if the call site or the callee is not in the profile
+fn inlined_block_counts(
+    vm: &VM,
+    callee: MuID,
+    callee_content: &FunctionContent,
+    call_count: Option<u64>
+) -> HashMap<MuID, u64> {
+    let mut ret = HashMap::new();
+    let call_count = match call_count {
+        Some(count) => count,
+        None => return ret
+    };
+    let profile = match profile::get_profile(vm) {
+        Some(profile) => profile,
+        None => return ret
+    };
+    let func_profile = match profile.get_func(&vm.get_name_for_func(callee)) {
+        Some(func_profile) => func_profile,
+        None => return ret
+    };
+    // the original IR has the block names of the profile
+    let entry_count = func_profile
+        .block_count(&callee_content.get_entry_block().hdr.name())
+        .unwrap_or(0);
+
+    for (id, block) in callee_content.blocks.iter() {
+        if let Some(count) = func_profile.block_count(&block.hdr.name()) {
+            let scaled = if entry_count == 0 {
+                0
+            } else {
+                (count as f64 * call_count as f64 / entry_count as f64) as u64
+            };
+            ret.insert(*id, scaled);
+        }
     }
+    ret
 }
 
-/// calculate estimate machine instruction for a Mu function
-fn estimate_insts(fv: &MuFunctionVersion) -> usize {
-    let f_content = fv.content.as_ref().unwrap();
-
+/// calculate estimate machine instruction for the content of a Mu function
+fn estimate_insts(f_content: &FunctionContent, vm: &VM) -> usize {
     let mut insts = 0;
 
     for block in f_content.blocks.values() {
@@ -653,7 +986,7 @@ fn estimate_insts(fv: &MuFunctionVersion) -> usize {
             match inst.v {
                 TreeNode_::Value(_) => unreachable!(),
                 TreeNode_::Instruction(ref inst) => {
-                    insts += backend::estimate_insts_for_ir(inst);
+                    insts += backend::estimate_insts_for_ir(inst, vm);
                 }
             }
         }
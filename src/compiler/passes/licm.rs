@@ -0,0 +1,554 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::op::*;
+use ast::ptr::*;
+use ast::analysis::*;
+use vm::VM;
+use compiler::CompilerPass;
+use compiler::passes::alias::AliasAnalysis;
+use std::any::Any;
+use std::cmp;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Loop-invariant code motion. Every loop gets a preheader (a block outside the loop whose only
+/// successor is the loop header) if it does not have one, then invariant instructions are moved
+/// from the loop to the end of the preheader, inner loops first.
+///
+/// An instruction is invariant if its operands are defined outside the loop, or are invariant.
+/// In Mu IR, values usually enter a loop as block arguments, so a parameter of a loop block is
+/// invariant when every branch to the block passes the same invariant value (e.g. the header
+/// gets x from the preheader, and the latch passes the header's own parameter back). The
+/// operands of a hoisted instruction are replaced by the values they hold in the preheader.
+///
+/// Pure instructions are always hoisted. Loads and integer division are only hoisted if they
+/// are executed in every iteration, and the loop has no side effects other than stores that
+/// cannot alias the load. Instructions yielding int<1> are not hoisted, as TreeGen always
+/// moves them to their use.
+pub struct LICM {
+    name: &'static str
+}
+
+impl LICM {
+    pub fn new() -> LICM {
+        LICM {
+            name: "Loop-Invariant Code Motion"
+        }
+    }
+}
+
+impl CompilerPass for LICM {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        create_preheaders(vm, func);
+
+        let domtree = func.domtree();
+        let loop_nest = func.loop_nest();
+        let alias = AliasAnalysis::new(func.content.as_ref().unwrap());
+
+        // inner loops come after outer loops, we visit them first so that values hoisted to
+        // the preheader of an inner loop can be hoisted further
+        let mut hoisted = 0;
+        for l in loop_nest.loops().iter().rev() {
+            hoisted += hoist_loop(func, &domtree, l, &alias);
+        }
+
+        debug!("{} instructions hoisted", hoisted);
+        debug!("after LICM: {:?}", func);
+    }
+}
+
+/// we can only insert instructions at the end of a preheader that ends with BRANCH
+pub fn has_usable_preheader(f_content: &FunctionContent, l: &Loop) -> bool {
+    match l.preheader {
+        Some(preheader) => {
+            match f_content.get_block(preheader).content.as_ref().unwrap().body.last() {
+                Some(node) => {
+                    match node.as_inst().v {
+                        Instruction_::Branch1(_) => true,
+                        _ => false
+                    }
+                }
+                None => false
+            }
+        }
+        None => false
+    }
+}
+
+/// creates a preheader for each loop that needs one
+pub fn create_preheaders(vm: &VM, func: &mut MuFunctionVersion) {
+    let mut skipped = HashSet::new();
+    loop {
+        let domtree = func.domtree();
+        let loop_nest = func.loop_nest();
+        let next = loop_nest.loops().iter().find(|l| {
+            !skipped.contains(&l.header) &&
+                !has_usable_preheader(func.content.as_ref().unwrap(), l)
+        });
+
+        match next {
+            Some(l) => {
+                if create_preheader(vm, func, &domtree, l) {
+                    func.invalidate_cfg_analyses();
+                } else {
+                    skipped.insert(l.header);
+                }
+            }
+            None => break
+        }
+    }
+}
+
+/// creates a block that takes the arguments of the loop header and branches to it, and lets
+/// all branches from outside the loop go through it. Returns false if we cannot do this
+fn create_preheader(vm: &VM, func: &mut MuFunctionVersion, domtree: &DomTree, l: &Loop) -> bool {
+    let header = l.header;
+    let (header_hdr, header_args) = {
+        let f_content = func.content.as_ref().unwrap();
+        let block = f_content.get_block(header);
+        let block_content = block.content.as_ref().unwrap();
+        // a loop at the entry has no branch from outside, and an exception block can only be
+        // reached from exceptional edges
+        if header == f_content.entry || block_content.exn_arg.is_some() {
+            debug!("cannot create a preheader for loop at {}", block);
+            return false;
+        }
+        (block.hdr.clone(), block_content.args.clone())
+    };
+
+    let preheader_hdr = MuEntityHeader::named(
+        vm.next_id(),
+        Arc::new(format!("{}_preheader", header_hdr.name()))
+    );
+    let args: Vec<P<Value>> = header_args
+        .iter()
+        .map(|arg| {
+            func.context
+                .make_temporary(vm.next_id(), arg.ty.clone())
+                .clone_value()
+        })
+        .collect();
+    let branch = TreeNode::new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: None,
+        ops: args.iter().map(|arg| TreeNode::new_value(arg.clone())).collect(),
+        v: Instruction_::Branch1(Destination {
+            target: header_hdr.clone(),
+            args: (0..args.len()).map(|i| DestArg::Normal(i)).collect()
+        })
+    });
+    let mut preheader = Block::new(preheader_hdr.clone());
+    preheader.content = Some(BlockContent {
+        args: args,
+        exn_arg: None,
+        body: vec![branch],
+        keepalives: None
+    });
+    trace!("create preheader {} for loop at {}", preheader_hdr, header_hdr);
+
+    let f_content = func.content.as_mut().unwrap();
+
+    // the preheader runs as often as the loop is entered, which is at most as often as the header,
+    // and as the blocks outside the loop that branch to it
+    let mut outside_count = Some(0);
+    for pred in domtree.preds(header).iter().filter(|pred| !l.contains(**pred)) {
+        outside_count = match (outside_count, f_content.get_block(*pred).exec_count) {
+            (Some(sum), Some(count)) => Some(sum + count),
+            _ => None
+        };
+    }
+    preheader.exec_count = match (outside_count, f_content.get_block(header).exec_count) {
+        (Some(outside), Some(header_count)) => Some(cmp::min(outside, header_count)),
+        _ => None
+    };
+
+    for pred in domtree.preds(header).iter() {
+        if l.contains(*pred) {
+            continue;
+        }
+
+        let ref mut body = f_content
+            .get_block_mut(*pred)
+            .content
+            .as_mut()
+            .unwrap()
+            .body;
+        let last = body.len() - 1;
+        let mut inst = body[last].as_inst().clone();
+        for dest in inst.get_destinations_mut() {
+            if dest.target.id() == header {
+                dest.target = preheader_hdr.clone();
+            }
+        }
+        body[last] = TreeNode::new_inst(inst);
+    }
+    f_content.blocks.insert(preheader_hdr.id(), preheader);
+
+    true
+}
+
+/// how an instruction in a loop affects memory
+enum MemoryEffect {
+    None,
+    /// writes to the given location
+    Write(P<TreeNode>),
+    /// may write anywhere (or orders other memory accesses)
+    Unknown
+}
+
+fn memory_effect(inst: &Instruction) -> MemoryEffect {
+    let ref ops = inst.ops;
+    match inst.v {
+        Instruction_::Store { mem_loc, .. } |
+        Instruction_::CmpXchg { mem_loc, .. } |
+        Instruction_::AtomicRMW { mem_loc, .. } => MemoryEffect::Write(ops[mem_loc].clone()),
+        Instruction_::Load { order, .. } => {
+            if order == MemoryOrder::NotAtomic {
+                MemoryEffect::None
+            } else {
+                MemoryEffect::Unknown
+            }
+        }
+        Instruction_::New(_) |
+        Instruction_::AllocA(_) |
+        Instruction_::NewHybrid(_, _) |
+        Instruction_::AllocAHybrid(_, _) |
+        Instruction_::Branch1(_) |
+        Instruction_::Branch2 { .. } |
+        Instruction_::Switch { .. } => MemoryEffect::None,
+        _ => {
+            if inst.has_side_effect() {
+                MemoryEffect::Unknown
+            } else {
+                MemoryEffect::None
+            }
+        }
+    }
+}
+
+/// the analysis state of a loop
+struct LoopInfo<'a> {
+    l: &'a Loop,
+    alias: &'a AliasAnalysis,
+    /// SSA variables defined in the loop
+    loop_defs: HashSet<MuID>,
+    /// SSA variables in the loop that always hold a value that is available at the end of the
+    /// preheader, and that value
+    origins: HashMap<MuID, P<TreeNode>>,
+    /// locations written in the loop
+    writes: Vec<P<TreeNode>>,
+    /// does the loop have side effects other than writing the locations above?
+    has_unknown_effect: bool,
+    /// blocks that are executed in every iteration
+    always_executed: HashSet<MuID>
+}
+
+impl<'a> LoopInfo<'a> {
+    /// returns the value an operand holds at the end of the preheader, if it is invariant
+    fn invariant_value(&self, op: &P<TreeNode>) -> Option<P<TreeNode>> {
+        match op.v {
+            TreeNode_::Value(ref val) => {
+                if !self.loop_defs.contains(&val.id()) {
+                    Some(op.clone())
+                } else {
+                    self.origins.get(&val.id()).cloned()
+                }
+            }
+            TreeNode_::Instruction(_) => None
+        }
+    }
+
+    /// finds block parameters that always receive the same invariant value
+    fn compute_param_origins(&mut self, f_content: &FunctionContent, preheader: MuID) {
+        // None: may hold different values
+        let mut params: HashMap<MuID, Option<P<TreeNode>>> = HashMap::new();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            let sources = [preheader];
+            for id in self.l.blocks.iter().chain(sources.iter()) {
+                let ref body = f_content.get_block(*id).content.as_ref().unwrap().body;
+                let inst = match body.last() {
+                    Some(node) => node.as_inst(),
+                    None => continue
+                };
+
+                for dest in inst.get_destinations() {
+                    let target = dest.target.id();
+                    if !self.l.contains(target) {
+                        continue;
+                    }
+                    let ref target_args = f_content
+                        .get_block(target)
+                        .content
+                        .as_ref()
+                        .unwrap()
+                        .args;
+
+                    for (i, arg) in dest.args.iter().enumerate() {
+                        let param = target_args[i].id();
+                        let incoming = match arg {
+                            &DestArg::Normal(index) => {
+                                let ref op = inst.ops[index];
+                                match op.extract_ssa_id() {
+                                    Some(id) if params.contains_key(&id) => {
+                                        params.get(&id).unwrap().clone()
+                                    }
+                                    // a parameter we know nothing about yet
+                                    Some(id) if !self.origins.contains_key(&id) &&
+                                        is_param(f_content, self.l, id) => continue,
+                                    _ => self.invariant_value(op)
+                                }
+                            }
+                            &DestArg::Freshbound(_) => None
+                        };
+
+                        let new = match (params.get(&param), incoming) {
+                            (None, incoming) => incoming,
+                            (Some(&None), _) | (Some(_), None) => None,
+                            (Some(&Some(ref cur)), Some(incoming)) => {
+                                if is_same_value(cur, &incoming) {
+                                    Some(cur.clone())
+                                } else {
+                                    None
+                                }
+                            }
+                        };
+                        let is_new = match params.get(&param) {
+                            None => true,
+                            Some(cur) => cur.is_some() != new.is_some()
+                        };
+                        if is_new {
+                            params.insert(param, new);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        for (param, value) in params.into_iter() {
+            if let Some(value) = value {
+                self.origins.insert(param, value);
+            }
+        }
+    }
+
+    /// returns the instruction to put in the preheader if an instruction can be hoisted
+    fn hoist(&self, inst: &Instruction, block: MuID) -> Option<Instruction> {
+        match inst.value {
+            Some(ref vals) if vals.len() == 1 && !vals[0].ty.is_int_n(1) => {}
+            _ => return None
+        }
+
+        let may_trap = match inst.v {
+            Instruction_::BinOp(op, _, _) => {
+                match op {
+                    BinOp::Sdiv | BinOp::Srem | BinOp::Udiv | BinOp::Urem => true,
+                    _ => false
+                }
+            }
+            Instruction_::Load { order, .. } => {
+                if order != MemoryOrder::NotAtomic {
+                    return None;
+                }
+                true
+            }
+            Instruction_::CmpOp(_, _, _) |
+            Instruction_::ConvOp { .. } |
+            Instruction_::Select { .. } |
+            Instruction_::GetIRef(_) |
+            Instruction_::GetFieldIRef { .. } |
+            Instruction_::GetElementIRef { .. } |
+            Instruction_::ShiftIRef { .. } |
+            Instruction_::GetVarPartIRef { .. } => false,
+            _ => return None
+        };
+
+        let mut new_ops = vec![];
+        for op in inst.ops.iter() {
+            match self.invariant_value(op) {
+                Some(value) => new_ops.push(value),
+                None => return None
+            }
+        }
+
+        if may_trap {
+            // the instruction would be executed anyway, and nothing observable happens before
+            if !self.always_executed.contains(&block) || self.has_unknown_effect {
+                return None;
+            }
+            if let Instruction_::Load { mem_loc, .. } = inst.v {
+                let ref loc = new_ops[mem_loc];
+                let aliased = self.writes.iter().any(|write| {
+                    let write = self.invariant_value(write).unwrap_or_else(|| write.clone());
+                    self.alias.may_alias(loc, &write)
+                });
+                if aliased {
+                    return None;
+                }
+            } else if !self.writes.is_empty() {
+                return None;
+            }
+        }
+
+        let mut new_inst = inst.clone();
+        new_inst.ops = new_ops;
+        Some(new_inst)
+    }
+}
+
+fn is_param(f_content: &FunctionContent, l: &Loop, id: MuID) -> bool {
+    l.blocks.iter().any(|block| {
+        f_content
+            .get_block(*block)
+            .content
+            .as_ref()
+            .unwrap()
+            .args
+            .iter()
+            .any(|arg| arg.id() == id)
+    })
+}
+
+fn is_same_value(a: &P<TreeNode>, b: &P<TreeNode>) -> bool {
+    a.as_value().id() == b.as_value().id()
+}
+
+/// hoists invariant instructions out of a loop, returns the number of hoisted instructions
+fn hoist_loop(
+    func: &mut MuFunctionVersion,
+    domtree: &DomTree,
+    l: &Loop,
+    alias: &AliasAnalysis
+) -> usize {
+    if !has_usable_preheader(func.content.as_ref().unwrap(), l) {
+        return 0;
+    }
+    let preheader = l.preheader.unwrap();
+
+    let mut hoisted: Vec<P<TreeNode>> = vec![];
+    let mut hoisted_ids = HashSet::new();
+    {
+        let f_content = func.content.as_ref().unwrap();
+
+        let mut info = LoopInfo {
+            l: l,
+            alias: alias,
+            loop_defs: HashSet::new(),
+            origins: HashMap::new(),
+            writes: vec![],
+            has_unknown_effect: false,
+            always_executed: HashSet::new()
+        };
+        for id in l.blocks.iter() {
+            let block_content = f_content.get_block(*id).content.as_ref().unwrap();
+            for arg in block_content.args.iter() {
+                info.loop_defs.insert(arg.id());
+            }
+            if let Some(ref arg) = block_content.exn_arg {
+                info.loop_defs.insert(arg.id());
+            }
+            for node in block_content.body.iter() {
+                let inst = node.as_inst();
+                if let Some(ref vals) = inst.value {
+                    for val in vals.iter() {
+                        info.loop_defs.insert(val.id());
+                    }
+                }
+                match memory_effect(inst) {
+                    MemoryEffect::None => {}
+                    MemoryEffect::Write(loc) => info.writes.push(loc),
+                    MemoryEffect::Unknown => info.has_unknown_effect = true
+                }
+            }
+
+            if l.latches.iter().all(|latch| domtree.dominates(*id, *latch)) &&
+                l.exits.iter().all(|&(exiting, _)| domtree.dominates(*id, exiting))
+            {
+                info.always_executed.insert(*id);
+            }
+        }
+
+        // hoisting an instruction may make others (and block parameters) invariant
+        let mut changed = true;
+        while changed {
+            changed = false;
+            info.compute_param_origins(f_content, preheader);
+
+            for id in domtree.reverse_postorder().iter() {
+                if !l.contains(*id) {
+                    continue;
+                }
+                for node in f_content.get_block(*id).content.as_ref().unwrap().body.iter() {
+                    let inst = node.as_inst();
+                    if hoisted_ids.contains(&inst.id()) {
+                        continue;
+                    }
+                    if let Some(new_inst) = info.hoist(inst, *id) {
+                        trace!("hoist {} to {}", inst, preheader);
+                        let result = inst.value.as_ref().unwrap()[0].clone();
+                        info.origins
+                            .insert(result.id(), TreeNode::new_value(result));
+                        hoisted.push(TreeNode::new_inst(new_inst));
+                        hoisted_ids.insert(inst.id());
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    if hoisted.is_empty() {
+        return 0;
+    }
+
+    let f_content = func.content.as_mut().unwrap();
+    for id in l.blocks.iter() {
+        f_content
+            .get_block_mut(*id)
+            .content
+            .as_mut()
+            .unwrap()
+            .body
+            .retain(|node| !hoisted_ids.contains(&node.as_inst().id()));
+    }
+
+    let ref mut body = f_content
+        .get_block_mut(preheader)
+        .content
+        .as_mut()
+        .unwrap()
+        .body;
+    let branch = body.pop().unwrap();
+    let n_hoisted = hoisted.len();
+    body.extend(hoisted);
+    body.push(branch);
+
+    n_hoisted
+}
@@ -0,0 +1,338 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::ptr::*;
+use ast::analysis::*;
+use vm::VM;
+use compiler::CompilerPass;
+use compiler::passes::gvn::rewrite;
+use compiler::passes::induction::LoopInduction;
+use compiler::passes::licm::create_preheaders;
+use std::any::Any;
+use std::cmp;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// the maximum number of instructions that the copies of an unrolled loop may have
+const UNROLL_BUDGET: usize = 128;
+
+/// Full unrolling of small loops with known trip counts.
+///
+/// An innermost loop is unrolled if it only exits from its header, by comparing an induction
+/// variable (see the induction module) against a constant, and the copies of its blocks for
+/// all iterations fit into UNROLL_BUDGET instructions. Every iteration gets a copy of the loop
+/// blocks, where the header branches to the body of the same iteration, and the latches to the
+/// header of the next iteration. The header of the last iteration branches to the exit.
+pub struct LoopUnrolling {
+    name: &'static str
+}
+
+impl LoopUnrolling {
+    pub fn new() -> LoopUnrolling {
+        LoopUnrolling {
+            name: "Loop Unrolling"
+        }
+    }
+}
+
+impl CompilerPass for LoopUnrolling {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        create_preheaders(vm, func);
+
+        // unrolling a loop changes the CFG, so we look for the next loop afterwards
+        let mut unrolled = 0;
+        loop {
+            let domtree = func.domtree();
+            let loop_nest = func.loop_nest();
+            let next = loop_nest.loops().iter().filter_map(|l| {
+                match unroll_count(func.content.as_ref().unwrap(), &domtree, l) {
+                    Some(trip_count) => Some((l, trip_count)),
+                    None => None
+                }
+            }).next();
+
+            match next {
+                Some((l, trip_count)) => {
+                    unroll_loop(vm, func, l, trip_count);
+                    func.invalidate_cfg_analyses();
+                    unrolled += 1;
+                }
+                None => break
+            }
+        }
+
+        debug!("{} loops unrolled", unrolled);
+        debug!("after loop unrolling: {:?}", func);
+    }
+}
+
+/// returns the trip count of a loop if we unroll it
+fn unroll_count(f_content: &FunctionContent, domtree: &DomTree, l: &Loop) -> Option<usize> {
+    if !l.children.is_empty() {
+        return None;
+    }
+
+    let mut size = 0;
+    for id in l.blocks.iter() {
+        let block_content = f_content.get_block(*id).content.as_ref().unwrap();
+        if block_content.exn_arg.is_some() {
+            return None;
+        }
+        size += block_content.body.len();
+    }
+    let header_size = f_content
+        .get_block(l.header)
+        .content
+        .as_ref()
+        .unwrap()
+        .body
+        .len();
+
+    let induction = LoopInduction::new(f_content, domtree, l);
+    if !induction.has_ivs() {
+        return None;
+    }
+    let max_trips = (UNROLL_BUDGET - cmp::min(header_size, UNROLL_BUDGET)) / size;
+    let trip_count = induction.trip_count(f_content, l, max_trips);
+    if let Some(trip_count) = trip_count {
+        trace!(
+            "loop at {} runs {} times, {} instructions",
+            l.header,
+            trip_count,
+            size
+        );
+    }
+    trip_count
+}
+
+/// replaces a loop by a copy of its blocks for each iteration
+fn unroll_loop(vm: &VM, func: &mut MuFunctionVersion, l: &Loop, trip_count: usize) {
+    let preheader = l.preheader.unwrap();
+    let mut new_blocks: Vec<Block> = vec![];
+    // header values that are used after the loop hold the values of the last header
+    let mut replacements: HashMap<MuID, P<TreeNode>> = HashMap::new();
+    let first_header;
+    {
+        let f_content = func.content.as_ref().unwrap();
+
+        // headers of the copies, the last one is only a header
+        let headers: Vec<MuEntityHeader> = (0..trip_count + 1)
+            .map(|i| copy_block_hdr(vm, f_content.get_block(l.header), i))
+            .collect();
+        first_header = headers[0].clone();
+
+        for i in 0..trip_count + 1 {
+            let blocks: Vec<MuID> = if i == trip_count {
+                vec![l.header]
+            } else {
+                l.blocks.iter().cloned().collect()
+            };
+
+            let mut block_hdrs: HashMap<MuID, MuEntityHeader> = HashMap::new();
+            for id in l.blocks.iter() {
+                if *id == l.header {
+                    block_hdrs.insert(*id, headers[i].clone());
+                } else {
+                    block_hdrs.insert(*id, copy_block_hdr(vm, f_content.get_block(*id), i));
+                }
+            }
+
+            // new values for all values defined in the copied blocks
+            let mut values: HashMap<MuID, P<Value>> = HashMap::new();
+            for id in blocks.iter() {
+                let block_content = f_content.get_block(*id).content.as_ref().unwrap();
+                let defs = block_content.body.iter().flat_map(|node| match node.as_inst().value {
+                    Some(ref vals) => vals.clone(),
+                    None => vec![]
+                });
+                for val in block_content.args.iter().cloned().chain(defs) {
+                    let new_val = func.context
+                        .make_temporary(vm.next_id(), val.ty.clone())
+                        .clone_value();
+                    values.insert(val.id(), new_val);
+                }
+            }
+            let copy_op = |op: &P<TreeNode>| match op.extract_ssa_id() {
+                Some(id) if values.contains_key(&id) => {
+                    TreeNode::new_value(values.get(&id).unwrap().clone())
+                }
+                _ => op.clone()
+            };
+
+            for id in blocks.iter() {
+                let block = f_content.get_block(*id);
+                let block_content = block.content.as_ref().unwrap();
+                let mut body = vec![];
+                for node in block_content.body.iter() {
+                    let inst = node.as_inst();
+                    let mut new_inst = Instruction {
+                        hdr: MuEntityHeader::unnamed(vm.next_id()),
+                        value: inst.value.as_ref().map(|vals| {
+                            vals.iter()
+                                .map(|val| values.get(&val.id()).unwrap().clone())
+                                .collect()
+                        }),
+                        ops: inst.ops.iter().map(|op| copy_op(op)).collect(),
+                        v: inst.v.clone()
+                    };
+                    // the last header only leaves the loop, see leave_header()
+                    for dest in new_inst.get_destinations_mut() {
+                        let target = dest.target.id();
+                        if i == trip_count {
+                            continue;
+                        } else if target == l.header {
+                            dest.target = headers[i + 1].clone();
+                        } else if l.contains(target) {
+                            dest.target = block_hdrs.get(&target).unwrap().clone();
+                        }
+                    }
+                    body.push(TreeNode::new_inst(new_inst));
+                }
+
+                if *id == l.header {
+                    let last = body.len() - 1;
+                    let branch = leave_header(
+                        block_content.body[last].as_inst(),
+                        body[last].as_inst(),
+                        l,
+                        i == trip_count
+                    );
+                    body[last] = TreeNode::new_inst(branch);
+                }
+
+                let mut new_block = Block::new(block_hdrs.get(id).unwrap().clone());
+                // the runs of a block are shared by its copies (the header has one more)
+                let n_copies = if *id == l.header {
+                    trip_count + 1
+                } else {
+                    trip_count
+                };
+                new_block.exec_count = block.exec_count.map(|count| count / n_copies as u64);
+                new_block.content = Some(BlockContent {
+                    args: block_content
+                        .args
+                        .iter()
+                        .map(|arg| values.get(&arg.id()).unwrap().clone())
+                        .collect(),
+                    exn_arg: None,
+                    body: body,
+                    keepalives: block_content.keepalives.as_ref().map(|keepalives| {
+                        keepalives
+                            .iter()
+                            .map(|val| copy_op(&TreeNode::new_value(val.clone())).clone_value())
+                            .collect()
+                    })
+                });
+                new_blocks.push(new_block);
+            }
+
+            if i == trip_count {
+                for (old, new) in values.iter() {
+                    replacements.insert(*old, TreeNode::new_value(new.clone()));
+                }
+            }
+        }
+    }
+
+    trace!("unroll loop at {} {} times", l.header, trip_count);
+    let f_content = func.content.as_mut().unwrap();
+    for id in l.blocks.iter() {
+        f_content.blocks.remove(id);
+    }
+    for block in new_blocks {
+        f_content.blocks.insert(block.hdr.id(), block);
+    }
+
+    {
+        let ref mut body = f_content
+            .get_block_mut(preheader)
+            .content
+            .as_mut()
+            .unwrap()
+            .body;
+        let last = body.len() - 1;
+        let mut branch = body[last].as_inst().clone();
+        for dest in branch.get_destinations_mut() {
+            if dest.target.id() == l.header {
+                dest.target = first_header.clone();
+            }
+        }
+        body[last] = TreeNode::new_inst(branch);
+    }
+
+    rewrite(func, &HashSet::new(), &replacements);
+}
+
+fn copy_block_hdr(vm: &VM, block: &Block, i: usize) -> MuEntityHeader {
+    MuEntityHeader::named(
+        vm.next_id(),
+        Arc::new(format!("{}_unroll{}", block.hdr.name(), i))
+    )
+}
+
+/// replaces the conditional branch at the end of a header copy by a branch that stays in the
+/// loop, or leaves it in the last iteration. The original branch tells which way stays
+fn leave_header(orig: &Instruction, branch: &Instruction, l: &Loop, is_last: bool) -> Instruction {
+    let stays_if_true = match orig.v {
+        Instruction_::Branch2 { ref true_dest, .. } => l.contains(true_dest.target.id()),
+        _ => panic!("expected BRANCH2 at the end of loop header, found {}", orig)
+    };
+    let dest = match branch.v {
+        Instruction_::Branch2 {
+            ref true_dest,
+            ref false_dest,
+            ..
+        } => {
+            if stays_if_true != is_last {
+                true_dest
+            } else {
+                false_dest
+            }
+        }
+        _ => unreachable!()
+    };
+
+    let mut ops = vec![];
+    let mut args = vec![];
+    for arg in dest.args.iter() {
+        match arg {
+            &DestArg::Normal(index) => {
+                args.push(DestArg::Normal(ops.len()));
+                ops.push(branch.ops[index].clone());
+            }
+            &DestArg::Freshbound(n) => args.push(DestArg::Freshbound(n))
+        }
+    }
+
+    Instruction {
+        hdr: branch.hdr.clone(),
+        value: None,
+        ops: ops,
+        v: Instruction_::Branch1(Destination {
+            target: dest.target.clone(),
+            args: args
+        })
+    }
+}
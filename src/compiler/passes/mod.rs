@@ -17,6 +17,10 @@ use ast::ir::*;
 use vm::VM;
 use std::any::Any;
 
+/// A pass to count block executions for --profile-generate
+mod profile_instrumentation;
+pub use compiler::passes::profile_instrumentation::ProfileInstrumentation;
+
 /// An inlining pass. Based on a certain criteria, the compiler chooses certain functions to be
 /// inlined in their callsite by rewriting the call into a branch with several copied blocks from
 /// the inlined function
@@ -27,6 +31,46 @@ pub use compiler::passes::inlining::Inlining;
 mod ret_sink;
 pub use compiler::passes::ret_sink::RetSink;
 
+/// A sparse conditional constant propagation pass. It folds instructions on constants, and
+/// turns branches on constants into unconditional branches
+mod sccp;
+pub use compiler::passes::sccp::SCCP;
+
+/// An escape analysis pass. It replaces objects that do not escape the function by SSA
+/// variables for their fields, or by stack allocation
+mod escape_analysis;
+pub use compiler::passes::escape_analysis::EscapeAnalysis;
+
+/// A global value numbering pass. It removes pure instructions that recompute a value already
+/// computed in a dominating position
+mod gvn;
+pub use compiler::passes::gvn::GVN;
+
+/// A loop-invariant code motion pass. It moves invariant computation out of loops into loop
+/// preheaders (creating them if needed)
+mod licm;
+pub use compiler::passes::licm::LICM;
+
+/// Induction variable recognition on loops, used by strength reduction and loop unrolling
+mod induction;
+
+/// A strength reduction pass. It replaces element address computations indexed by induction
+/// variables with addresses that are shifted in every iteration
+mod strength_reduction;
+pub use compiler::passes::strength_reduction::StrengthReduction;
+
+/// A loop unrolling pass. It fully unrolls small loops with known trip counts
+mod loop_unroll;
+pub use compiler::passes::loop_unroll::LoopUnrolling;
+
+/// A type-based alias analysis on memory locations, used by LICM and RLE
+mod alias;
+
+/// A redundant load elimination pass. It forwards stored and loaded values to later loads, and
+/// removes stores that are overwritten before they are read
+mod rle;
+pub use compiler::passes::rle::RLE;
+
 /// A pass to inject runtime fastpath into Mu IR
 mod inject_runtime;
 pub use compiler::passes::inject_runtime::InjectRuntime;
@@ -35,6 +79,11 @@ pub use compiler::passes::inject_runtime::InjectRuntime;
 mod def_use;
 pub use compiler::passes::def_use::DefUse;
 
+/// A dead code elimination pass. It deletes unreachable blocks, and instructions without side
+/// effects whose results are not used
+mod dce;
+pub use compiler::passes::dce::DCE;
+
 /// A tree generation pass. Mu IR is a flat IR instruction sequence, this pass turns it into a
 /// depth tree which is easier for instruction selection.
 mod tree_gen;
@@ -82,6 +131,12 @@ pub trait CompilerPass {
     fn name(&self) -> &'static str;
     fn as_any(&self) -> &Any;
 
+    /// does this pass keep the blocks and the branches between them unchanged? If not, the
+    /// compiler drops the cached CFG analyses of the function (e.g. dominator tree) after it
+    fn preserves_cfg(&self) -> bool {
+        false
+    }
+
     fn execute(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
         info!("---CompilerPass {} for {}---", self.name(), func);
 
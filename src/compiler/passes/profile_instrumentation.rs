@@ -0,0 +1,193 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::op::*;
+use ast::ptr::*;
+use ast::types::*;
+use vm::VM;
+use vm::profile;
+use vm::profile::{ProfileCounters, ProfiledCallsite};
+use compiler::CompilerPass;
+use std::any::Any;
+use std::sync::Arc;
+
+/// Instrumentation for --profile-generate, and block counts for --profile-use.
+///
+/// With --profile-generate, every block gets a counter in a global array of the function
+/// version, and starts by incrementing it atomically. The counters are recorded in the VM with
+/// the names of the blocks and the callees of calls, so that their values can be written as a
+/// profile (see vm::profile).
+///
+/// With --profile-use, the counts of the blocks are looked up by their names and kept in the
+/// blocks (Block::exec_count). This pass runs before the passes that copy or create blocks, so
+/// the names are still the ones in the profile, and those passes carry the counts over.
+pub struct ProfileInstrumentation {
+    name: &'static str
+}
+
+impl ProfileInstrumentation {
+    pub fn new() -> ProfileInstrumentation {
+        ProfileInstrumentation {
+            name: "Profile Instrumentation"
+        }
+    }
+}
+
+impl CompilerPass for ProfileInstrumentation {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    // only instructions are added at the start of blocks
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        set_profiled_counts(vm, func);
+        if vm.vm_options.flag_profile_generate.is_empty() {
+            return;
+        }
+
+        let global = counters_for(vm, func);
+        let iref_int64 = vm.declare_type(
+            MuEntityHeader::unnamed(vm.next_id()),
+            MuType_::iref(UINT64_TYPE.clone())
+        );
+
+        let block_ids: Vec<MuID> = func.content.as_ref().unwrap().blocks.keys().cloned().collect();
+        for (i, id) in block_ids.iter().enumerate() {
+            // %loc = GETELEMIREF <@counters @int64> @counters i
+            let loc = func.context
+                .make_temporary(vm.next_id(), iref_int64.clone())
+                .clone_value();
+            let index = Value::make_int64_const(vm.next_id(), i as u64);
+            let get_loc = Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: Some(vec![loc.clone()]),
+                ops: vec![func.new_global(global.clone()), TreeNode::new_value(index)],
+                v: Instruction_::GetElementIRef {
+                    is_ptr: false,
+                    base: 0,
+                    index: 1
+                }
+            };
+
+            // %old = ATOMICRMW RELAXED ADD <@int64> %loc 1
+            // (threads running the same code count without losing increments)
+            let old = func.context
+                .make_temporary(vm.next_id(), UINT64_TYPE.clone())
+                .clone_value();
+            let increment = Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: Some(vec![old]),
+                ops: vec![
+                    TreeNode::new_value(loc),
+                    TreeNode::new_value(Value::make_int64_const(vm.next_id(), 1)),
+                ],
+                v: Instruction_::AtomicRMW {
+                    is_ptr: false,
+                    order: MemoryOrder::Relaxed,
+                    op: AtomicRMWOp::ADD,
+                    mem_loc: 0,
+                    value: 1
+                }
+            };
+
+            let ref mut body = func.content
+                .as_mut()
+                .unwrap()
+                .get_block_mut(*id)
+                .content
+                .as_mut()
+                .unwrap()
+                .body;
+            let rest: Vec<P<TreeNode>> = body.drain(..).collect();
+            body.push(TreeNode::new_inst(get_loc));
+            body.push(TreeNode::new_inst(increment));
+            body.extend(rest);
+        }
+
+        debug!("after profile instrumentation: {:?}", func);
+    }
+}
+
+/// sets the counts of the blocks in the --profile-use profile (blocks that are not in the
+/// profile keep an unknown count)
+fn set_profiled_counts(vm: &VM, func: &mut MuFunctionVersion) {
+    let profile = match profile::get_profile(vm) {
+        Some(profile) => profile,
+        None => return
+    };
+    let func_profile = match profile.get_func(&vm.get_name_for_func(func.func_id)) {
+        Some(func_profile) => func_profile,
+        None => return
+    };
+
+    for (_, block) in func.content.as_mut().unwrap().blocks.iter_mut() {
+        block.exec_count = func_profile.block_count(&block.hdr.name());
+    }
+}
+
+/// returns the global that holds the counters of a function version, declares it (and records
+/// the blocks and call sites that it counts) if the function version has not been instrumented
+fn counters_for(vm: &VM, func: &MuFunctionVersion) -> P<Value> {
+    let mut profile_counters = vm.profile_counters().write().unwrap();
+    if let Some(counters) = profile_counters.get(&func.id()) {
+        return counters.global.clone();
+    }
+
+    let f_content = func.content.as_ref().unwrap();
+    let call_edges = func.get_static_call_edges();
+    let mut blocks = vec![];
+    let mut callsites = vec![];
+    for (i, block) in f_content.blocks.values().enumerate() {
+        blocks.push(block.hdr.name());
+        for node in block.content.as_ref().unwrap().body.iter() {
+            if let Some(&(callee, _)) = call_edges.get(&node.id()) {
+                callsites.push(ProfiledCallsite {
+                    block: i,
+                    callee: vm.get_name_for_func(callee)
+                });
+            }
+        }
+    }
+
+    let array_ty = vm.declare_type(
+        MuEntityHeader::unnamed(vm.next_id()),
+        MuType_::array(UINT64_TYPE.clone(), blocks.len())
+    );
+    let global = vm.declare_global(
+        MuEntityHeader::named(vm.next_id(), Arc::new(format!("{}:profile", func.name()))),
+        array_ty
+    );
+    trace!("count {} blocks of {} in {}", blocks.len(), func, global);
+
+    profile_counters.insert(
+        func.id(),
+        ProfileCounters {
+            func: vm.get_name_for_func(func.func_id),
+            global: global.clone(),
+            blocks: blocks,
+            callsites: callsites
+        }
+    );
+    global
+}
@@ -0,0 +1,456 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::ptr::*;
+use ast::analysis::*;
+use vm::VM;
+use compiler::CompilerPass;
+use compiler::passes::alias::AliasAnalysis;
+use compiler::passes::gvn::rewrite;
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Redundant load elimination and dead store elimination, across blocks.
+///
+/// A forward data flow analysis finds the values known to be in memory: the result of a
+/// non-atomic load, or the value of a non-atomic store. A later non-atomic load of the same
+/// location (the same iref/uptr operand, GVN unifies address computations beforehand) is
+/// replaced by the known value. Known values are killed by stores, CMPXCHG and ATOMICRMW that
+/// may alias (see the alias analysis), and all of them by calls and other instructions with
+/// unknown effects, and by atomic operations and fences with acquire semantics.
+///
+/// A backward data flow analysis finds locations that are overwritten on every path before
+/// they are read. A non-atomic store to such a location is removed. Loads that may alias read
+/// the location, and calls, fences, atomic operations with release semantics, and the end of
+/// the function make every store observable. A location is only known by its SSA variable up
+/// to the definition of the variable (in a loop, the same variable is a different address in
+/// every iteration).
+pub struct RLE {
+    name: &'static str
+}
+
+impl RLE {
+    pub fn new() -> RLE {
+        RLE {
+            name: "Redundant Load Elimination"
+        }
+    }
+}
+
+impl CompilerPass for RLE {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    #[allow(unused_variables)]
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let domtree = func.domtree();
+        let mut removed = HashSet::new();
+        let mut replacements = HashMap::new();
+        let n_loads;
+        {
+            let f_content = func.content.as_ref().unwrap();
+            let alias = AliasAnalysis::new(f_content);
+
+            remove_redundant_loads(f_content, &domtree, &alias, &mut removed, &mut replacements);
+            n_loads = removed.len();
+            remove_dead_stores(f_content, &domtree, &alias, &mut removed);
+        }
+
+        debug!(
+            "{} redundant loads and {} dead stores removed",
+            n_loads,
+            removed.len() - n_loads
+        );
+        if removed.is_empty() {
+            return;
+        }
+
+        resolve_replacements(&mut replacements);
+        rewrite(func, &removed, &replacements);
+        debug!("after RLE: {:?}", func);
+    }
+}
+
+/// does the order make later reads see writes from other threads?
+fn is_acquire(order: MemoryOrder) -> bool {
+    match order {
+        MemoryOrder::Consume |
+        MemoryOrder::Acquire |
+        MemoryOrder::AcqRel |
+        MemoryOrder::SeqCst => true,
+        _ => false
+    }
+}
+
+/// does the order make earlier writes visible to other threads?
+fn is_release(order: MemoryOrder) -> bool {
+    match order {
+        MemoryOrder::Release | MemoryOrder::AcqRel | MemoryOrder::SeqCst => true,
+        _ => false
+    }
+}
+
+/// instructions that do not touch memory that is visible to the function, though they are
+/// considered to have side effects
+fn is_memory_neutral(inst: &Instruction) -> bool {
+    match inst.v {
+        Instruction_::New(_) |
+        Instruction_::AllocA(_) |
+        Instruction_::NewHybrid(_, _) |
+        Instruction_::AllocAHybrid(_, _) |
+        Instruction_::Branch1(_) |
+        Instruction_::Branch2 { .. } |
+        Instruction_::Switch { .. } => true,
+        _ => !inst.has_side_effect()
+    }
+}
+
+/// a value known to be in memory
+#[derive(Clone)]
+struct Available {
+    loc: P<TreeNode>,
+    value: P<TreeNode>
+}
+
+/// known values, by the ID of their location
+type AvailableValues = HashMap<MuID, Available>;
+
+fn loc_id(loc: &P<TreeNode>) -> MuID {
+    loc.as_value().id()
+}
+
+/// updates the known values after an instruction. Returns the known value if the instruction
+/// is a redundant load
+fn forward(
+    values: &mut AvailableValues,
+    inst: &Instruction,
+    alias: &AliasAnalysis
+) -> Option<P<TreeNode>> {
+    let ref ops = inst.ops;
+    match inst.v {
+        Instruction_::Load { order, mem_loc, .. } => {
+            if order == MemoryOrder::NotAtomic {
+                let ref loc = ops[mem_loc];
+                let result = inst.value.as_ref().unwrap()[0].clone();
+                if let Some(avail) = values.get(&loc_id(loc)) {
+                    if avail.value.as_value().ty == result.ty {
+                        return Some(avail.value.clone());
+                    }
+                }
+                values.insert(
+                    loc_id(loc),
+                    Available {
+                        loc: loc.clone(),
+                        value: TreeNode::new_value(result)
+                    }
+                );
+            } else if is_acquire(order) {
+                values.clear();
+            }
+        }
+        Instruction_::Store {
+            order,
+            mem_loc,
+            value,
+            ..
+        } => {
+            let ref loc = ops[mem_loc];
+            values.retain(|_, avail| !alias.may_alias(&avail.loc, loc));
+            if order == MemoryOrder::NotAtomic {
+                values.insert(
+                    loc_id(loc),
+                    Available {
+                        loc: loc.clone(),
+                        value: ops[value].clone()
+                    }
+                );
+            } else if is_acquire(order) {
+                values.clear();
+            }
+        }
+        Instruction_::CmpXchg {
+            success_order,
+            fail_order,
+            mem_loc,
+            ..
+        } => {
+            let ref loc = ops[mem_loc];
+            values.retain(|_, avail| !alias.may_alias(&avail.loc, loc));
+            if is_acquire(success_order) || is_acquire(fail_order) {
+                values.clear();
+            }
+        }
+        Instruction_::AtomicRMW { order, mem_loc, .. } => {
+            let ref loc = ops[mem_loc];
+            values.retain(|_, avail| !alias.may_alias(&avail.loc, loc));
+            if is_acquire(order) {
+                values.clear();
+            }
+        }
+        Instruction_::Fence(order) => {
+            if is_acquire(order) {
+                values.clear();
+            }
+        }
+        _ => {
+            if !is_memory_neutral(inst) {
+                values.clear();
+            }
+        }
+    }
+    None
+}
+
+fn same_values(a: &AvailableValues, b: &AvailableValues) -> bool {
+    a.len() == b.len() &&
+        a.iter().all(|(id, avail)| match b.get(id) {
+            Some(other) => is_same_value(&avail.value, &other.value),
+            None => false
+        })
+}
+
+fn is_same_value(a: &P<TreeNode>, b: &P<TreeNode>) -> bool {
+    a.as_value().id() == b.as_value().id()
+}
+
+/// finds redundant loads, and the values that replace their results
+fn remove_redundant_loads(
+    f_content: &FunctionContent,
+    domtree: &DomTree,
+    alias: &AliasAnalysis,
+    removed: &mut HashSet<MuID>,
+    replacements: &mut HashMap<MuID, P<TreeNode>>
+) {
+    // known values at the end of each block, a block that is not visited yet does not limit
+    // the values known at its successors
+    let mut outs: HashMap<MuID, AvailableValues> = HashMap::new();
+    let values_in = |outs: &HashMap<MuID, AvailableValues>, id: MuID| -> AvailableValues {
+        if id == domtree.entry() {
+            return HashMap::new();
+        }
+        let mut ret: Option<AvailableValues> = None;
+        for pred in domtree.preds(id).iter() {
+            let pred_out = match outs.get(pred) {
+                Some(pred_out) => pred_out,
+                None => continue
+            };
+            ret = Some(match ret {
+                None => pred_out.clone(),
+                Some(mut values) => {
+                    values.retain(|loc, avail| match pred_out.get(loc) {
+                        Some(other) => is_same_value(&avail.value, &other.value),
+                        None => false
+                    });
+                    values
+                }
+            });
+        }
+        ret.unwrap_or_else(HashMap::new)
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in domtree.reverse_postorder().iter() {
+            let mut values = values_in(&outs, *id);
+            for node in f_content.get_block(*id).content.as_ref().unwrap().body.iter() {
+                forward(&mut values, node.as_inst(), alias);
+            }
+
+            let is_new = match outs.get(id) {
+                Some(old) => !same_values(old, &values),
+                None => true
+            };
+            if is_new {
+                outs.insert(*id, values);
+                changed = true;
+            }
+        }
+    }
+
+    for id in domtree.reverse_postorder().iter() {
+        let mut values = values_in(&outs, *id);
+        for node in f_content.get_block(*id).content.as_ref().unwrap().body.iter() {
+            let inst = node.as_inst();
+            if let Some(value) = forward(&mut values, inst, alias) {
+                trace!("{} is redundant, reuse {}", inst, value);
+                removed.insert(inst.id());
+                replacements.insert(inst.value.as_ref().unwrap()[0].id(), value);
+            }
+        }
+    }
+}
+
+/// locations that are overwritten before they are read, by the ID of the location
+type OverwrittenLocations = HashMap<MuID, P<TreeNode>>;
+
+/// updates the overwritten locations before an instruction (going backwards). Returns true if
+/// the instruction is a dead store
+fn backward(
+    locs: &mut OverwrittenLocations,
+    inst: &Instruction,
+    alias: &AliasAnalysis,
+    removed: &HashSet<MuID>
+) -> bool {
+    if let Some(ref vals) = inst.value {
+        for val in vals.iter() {
+            locs.remove(&val.id());
+        }
+    }
+
+    // removed loads do not read memory
+    if removed.contains(&inst.id()) {
+        return false;
+    }
+
+    let ref ops = inst.ops;
+    match inst.v {
+        Instruction_::Store { order, mem_loc, .. } => {
+            let ref loc = ops[mem_loc];
+            if order == MemoryOrder::NotAtomic {
+                if locs.contains_key(&loc_id(loc)) {
+                    return true;
+                }
+                locs.insert(loc_id(loc), loc.clone());
+            } else if is_release(order) {
+                locs.clear();
+            }
+        }
+        Instruction_::Load { mem_loc, .. } => {
+            let ref loc = ops[mem_loc];
+            locs.retain(|_, overwritten| !alias.may_alias(overwritten, loc));
+        }
+        Instruction_::CmpXchg {
+            success_order,
+            fail_order,
+            mem_loc,
+            ..
+        } => {
+            let ref loc = ops[mem_loc];
+            locs.retain(|_, overwritten| !alias.may_alias(overwritten, loc));
+            if is_release(success_order) || is_release(fail_order) {
+                locs.clear();
+            }
+        }
+        Instruction_::AtomicRMW { order, mem_loc, .. } => {
+            let ref loc = ops[mem_loc];
+            locs.retain(|_, overwritten| !alias.may_alias(overwritten, loc));
+            if is_release(order) {
+                locs.clear();
+            }
+        }
+        _ => {
+            if !is_memory_neutral(inst) {
+                locs.clear();
+            }
+        }
+    }
+    false
+}
+
+/// finds stores whose values are overwritten before they are read
+fn remove_dead_stores(
+    f_content: &FunctionContent,
+    domtree: &DomTree,
+    alias: &AliasAnalysis,
+    removed: &mut HashSet<MuID>
+) {
+    // overwritten locations at the start of each block. We start from none and add locations
+    // until nothing changes: an infinite loop has no block that gives a starting point, so we
+    // cannot assume everything is overwritten in blocks that are not visited yet
+    let mut ins: HashMap<MuID, OverwrittenLocations> = domtree
+        .reverse_postorder()
+        .iter()
+        .map(|id| (*id, HashMap::new()))
+        .collect();
+    // the end of the function (a block without successors) reads everything
+    let locs_out = |ins: &HashMap<MuID, OverwrittenLocations>, id: MuID| {
+        let succs = f_content.get_successors(id);
+        let mut ret: OverwrittenLocations = match succs.first() {
+            Some(succ) => ins.get(succ).unwrap().clone(),
+            None => HashMap::new()
+        };
+        for succ in succs.iter() {
+            let succ_in = ins.get(succ).unwrap();
+            ret.retain(|loc, _| succ_in.contains_key(loc));
+        }
+        ret
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for id in domtree.reverse_postorder().iter().rev() {
+            let mut locs = locs_out(&ins, *id);
+            let block_content = f_content.get_block(*id).content.as_ref().unwrap();
+            for node in block_content.body.iter().rev() {
+                backward(&mut locs, node.as_inst(), alias, removed);
+            }
+            // the parameters are bound on entry
+            for arg in block_content.args.iter().chain(block_content.exn_arg.iter()) {
+                locs.remove(&arg.id());
+            }
+
+            // locations are only added
+            if ins.get(id).unwrap().len() != locs.len() {
+                ins.insert(*id, locs);
+                changed = true;
+            }
+        }
+    }
+
+    let mut dead = vec![];
+    for id in domtree.reverse_postorder().iter() {
+        let mut locs = locs_out(&ins, *id);
+        let ref body = f_content.get_block(*id).content.as_ref().unwrap().body;
+        for node in body.iter().rev() {
+            if backward(&mut locs, node.as_inst(), alias, removed) {
+                trace!("{} is a dead store", node);
+                dead.push(node.as_inst().id());
+            }
+        }
+    }
+    removed.extend(dead);
+}
+
+/// a load may be replaced by the result of another removed load, which we replace as well
+fn resolve_replacements(replacements: &mut HashMap<MuID, P<TreeNode>>) {
+    let ids: Vec<MuID> = replacements.keys().cloned().collect();
+    for id in ids {
+        let mut value = replacements.get(&id).unwrap().clone();
+        loop {
+            let next = match value.extract_ssa_id() {
+                Some(value_id) => replacements.get(&value_id).cloned(),
+                None => None
+            };
+            match next {
+                Some(next) => value = next,
+                None => break
+            }
+        }
+        replacements.insert(id, value);
+    }
+}
@@ -0,0 +1,717 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::op::*;
+use ast::ptr::*;
+use ast::types::*;
+use vm::VM;
+use compiler::CompilerPass;
+use utils::bit_utils::bits_ones;
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Sparse conditional constant propagation (Wegman and Zadeck). The pass finds the SSA variables
+/// that always hold the same constant, only considering blocks that are reachable when constant
+/// branch conditions are taken into account. Constants flow through block arguments.
+/// Afterwards, uses of those variables are replaced by the constants, the instructions that
+/// computed them are removed, and conditional branches/switches on constants are turned into
+/// unconditional branches. Blocks that become unreachable are left in place.
+pub struct SCCP {
+    name: &'static str
+}
+
+impl SCCP {
+    pub fn new() -> SCCP {
+        SCCP {
+            name: "Sparse Conditional Constant Propagation"
+        }
+    }
+}
+
+impl CompilerPass for SCCP {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        let analysis = {
+            let f_content = func.content.as_ref().unwrap();
+            let mut analysis = Analysis::new(f_content);
+            analysis.solve(f_content);
+            analysis
+        };
+
+        rewrite(vm, func, &analysis);
+        debug!("after SCCP: {:?}", func);
+    }
+}
+
+/// the value of an SSA variable in the lattice
+#[derive(Clone, Debug)]
+enum LatticeValue {
+    /// no definition/incoming value has been seen yet
+    Undef,
+    /// always holds this constant
+    Const(Constant),
+    /// may hold different values
+    Varying
+}
+
+impl LatticeValue {
+    fn meet(&self, other: &LatticeValue) -> LatticeValue {
+        match (self, other) {
+            (&LatticeValue::Undef, _) => other.clone(),
+            (_, &LatticeValue::Undef) => self.clone(),
+            (&LatticeValue::Const(ref a), &LatticeValue::Const(ref b)) => {
+                if is_same_constant(a, b) {
+                    self.clone()
+                } else {
+                    LatticeValue::Varying
+                }
+            }
+            _ => LatticeValue::Varying
+        }
+    }
+
+    fn is_same(&self, other: &LatticeValue) -> bool {
+        match (self, other) {
+            (&LatticeValue::Undef, &LatticeValue::Undef) |
+            (&LatticeValue::Varying, &LatticeValue::Varying) => true,
+            (&LatticeValue::Const(ref a), &LatticeValue::Const(ref b)) => is_same_constant(a, b),
+            _ => false
+        }
+    }
+}
+
+/// compares floating point constants by their bits (so a NaN constant is the same as itself)
+fn is_same_constant(a: &Constant, b: &Constant) -> bool {
+    match (a, b) {
+        (&Constant::Float(a), &Constant::Float(b)) => a.to_bits() == b.to_bits(),
+        (&Constant::Double(a), &Constant::Double(b)) => a.to_bits() == b.to_bits(),
+        _ => a == b
+    }
+}
+
+/// we only fold integers up to 64 bits and floating points
+fn is_foldable_type(ty: &MuType) -> bool {
+    match ty.v {
+        MuType_::Int(len) => len <= 64,
+        MuType_::Float | MuType_::Double => true,
+        _ => false
+    }
+}
+
+struct Analysis {
+    /// lattice values of SSA variables (a variable that is not here is Undef)
+    values: HashMap<MuID, LatticeValue>,
+    /// blocks that may be executed
+    executable: HashSet<MuID>,
+    /// arguments of each block
+    block_args: HashMap<MuID, Vec<MuID>>
+}
+
+impl Analysis {
+    fn new(f_content: &FunctionContent) -> Analysis {
+        let mut block_args = HashMap::new();
+        for (id, block) in f_content.blocks.iter() {
+            let ref args = block.content.as_ref().unwrap().args;
+            block_args.insert(*id, args.iter().map(|arg| arg.id()).collect());
+        }
+
+        Analysis {
+            values: HashMap::new(),
+            executable: HashSet::new(),
+            block_args: block_args
+        }
+    }
+
+    /// iterates over the executable blocks until nothing changes
+    /// (the values only move down the lattice, so this terminates)
+    fn solve(&mut self, f_content: &FunctionContent) {
+        // the arguments of the entry block come from the caller
+        let entry_args = self.block_args.get(&f_content.entry).unwrap().clone();
+        for arg in entry_args {
+            self.lower(arg, LatticeValue::Varying);
+        }
+        self.executable.insert(f_content.entry);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for (id, block) in f_content.blocks.iter() {
+                if !self.executable.contains(id) {
+                    continue;
+                }
+
+                let block_content = block.content.as_ref().unwrap();
+                if let Some(ref exn_arg) = block_content.exn_arg {
+                    changed |= self.lower(exn_arg.id(), LatticeValue::Varying);
+                }
+                for node in block_content.body.iter() {
+                    changed |= self.visit_inst(node.as_inst());
+                }
+            }
+        }
+    }
+
+    /// moves the value of an SSA variable down the lattice, returns true if it changed
+    fn lower(&mut self, id: MuID, value: LatticeValue) -> bool {
+        let new_value = match self.values.get(&id) {
+            Some(old_value) => {
+                let new_value = old_value.meet(&value);
+                if new_value.is_same(old_value) {
+                    return false;
+                }
+                new_value
+            }
+            None => {
+                if let LatticeValue::Undef = value {
+                    return false;
+                }
+                value
+            }
+        };
+
+        trace!("SSA #{} is {:?}", id, new_value);
+        self.values.insert(id, new_value);
+        true
+    }
+
+    /// gets the lattice value of an operand
+    fn operand(&self, node: &P<TreeNode>) -> LatticeValue {
+        match node.v {
+            TreeNode_::Value(ref val) => {
+                match val.v {
+                    Value_::SSAVar(id) => {
+                        match self.values.get(&id) {
+                            Some(value) => value.clone(),
+                            None => LatticeValue::Undef
+                        }
+                    }
+                    Value_::Constant(ref c) => {
+                        match (c, &val.ty.v) {
+                            (&Constant::Int(v), &MuType_::Int(len)) if len <= 64 => {
+                                LatticeValue::Const(Constant::Int(v & bits_ones(len)))
+                            }
+                            (&Constant::Float(_), &MuType_::Float) |
+                            (&Constant::Double(_), &MuType_::Double) => {
+                                LatticeValue::Const(c.clone())
+                            }
+                            _ => LatticeValue::Varying
+                        }
+                    }
+                    _ => LatticeValue::Varying
+                }
+            }
+            TreeNode_::Instruction(_) => LatticeValue::Varying
+        }
+    }
+
+    /// evaluates an instruction in an executable block, returns true if anything changed
+    fn visit_inst(&mut self, inst: &Instruction) -> bool {
+        let ref ops = inst.ops;
+        let mut changed = false;
+
+        if let Some(ref values) = inst.value {
+            let result = if values.len() == 1 && is_foldable_type(&values[0].ty) {
+                self.evaluate(inst, &values[0].ty)
+            } else {
+                LatticeValue::Varying
+            };
+            for val in values.iter() {
+                changed |= self.lower(val.id(), result.clone());
+            }
+        }
+
+        match inst.v {
+            Instruction_::Branch2 {
+                cond,
+                ref true_dest,
+                ref false_dest,
+                ..
+            } => {
+                match self.operand(&ops[cond]) {
+                    LatticeValue::Undef => {}
+                    LatticeValue::Const(Constant::Int(c)) => {
+                        let dest = if c & 1 == 1 { true_dest } else { false_dest };
+                        changed |= self.visit_dest(dest, ops);
+                    }
+                    _ => {
+                        changed |= self.visit_dest(true_dest, ops);
+                        changed |= self.visit_dest(false_dest, ops);
+                    }
+                }
+            }
+            Instruction_::Switch {
+                cond,
+                ref default,
+                ref branches,
+                ..
+            } => {
+                match self.operand(&ops[cond]) {
+                    LatticeValue::Undef => {}
+                    LatticeValue::Const(Constant::Int(c)) => {
+                        let dest = switch_target(c, ops, default, branches);
+                        changed |= self.visit_dest(dest, ops);
+                    }
+                    _ => {
+                        for dest in inst.get_destinations() {
+                            changed |= self.visit_dest(dest, ops);
+                        }
+                    }
+                }
+            }
+            _ => {
+                for dest in inst.get_destinations() {
+                    changed |= self.visit_dest(dest, ops);
+                }
+            }
+        }
+
+        changed
+    }
+
+    /// marks the target block executable, and passes the arguments to it
+    fn visit_dest(&mut self, dest: &Destination, ops: &Vec<P<TreeNode>>) -> bool {
+        let target = dest.target.id();
+        let mut changed = self.executable.insert(target);
+
+        let args = self.block_args.get(&target).unwrap().clone();
+        for (arg, dest_arg) in args.into_iter().zip(dest.args.iter()) {
+            let value = match dest_arg {
+                &DestArg::Normal(index) => self.operand(&ops[index]),
+                &DestArg::Freshbound(_) => LatticeValue::Varying
+            };
+            changed |= self.lower(arg, value);
+        }
+
+        changed
+    }
+
+    /// computes the result of an instruction from its operands
+    fn evaluate(&self, inst: &Instruction, ty: &P<MuType>) -> LatticeValue {
+        let ref ops = inst.ops;
+
+        match inst.v {
+            Instruction_::BinOp(op, op1, op2) => {
+                let len = ty.get_int_length().unwrap_or(0);
+                self.evaluate_with(&[&ops[op1], &ops[op2]], |args| {
+                    fold_binop(op, len, &args[0], &args[1])
+                })
+            }
+            Instruction_::CmpOp(op, op1, op2) => {
+                let len = ops[op1].ty().get_int_length().unwrap_or(0);
+                self.evaluate_with(&[&ops[op1], &ops[op2]], |args| {
+                    fold_cmpop(op, len, &args[0], &args[1])
+                        .map(|res| Constant::Int(if res { 1 } else { 0 }))
+                })
+            }
+            Instruction_::ConvOp {
+                operation,
+                ref from_ty,
+                ref to_ty,
+                operand
+            } => {
+                self.evaluate_with(&[&ops[operand]], |args| {
+                    fold_convop(operation, from_ty, to_ty, &args[0])
+                })
+            }
+            Instruction_::Select {
+                cond,
+                true_val,
+                false_val
+            } => {
+                match self.operand(&ops[cond]) {
+                    LatticeValue::Undef => LatticeValue::Undef,
+                    LatticeValue::Const(Constant::Int(c)) => {
+                        self.operand(&ops[if c & 1 == 1 { true_val } else { false_val }])
+                    }
+                    // either value may be selected
+                    _ => self.operand(&ops[true_val]).meet(&self.operand(&ops[false_val]))
+                }
+            }
+            _ => LatticeValue::Varying
+        }
+    }
+
+    /// folds the operands with the given function if they are all constants
+    fn evaluate_with<F>(&self, operands: &[&P<TreeNode>], fold: F) -> LatticeValue
+    where
+        F: Fn(&Vec<Constant>) -> Option<Constant>
+    {
+        let mut args = vec![];
+        let mut any_undef = false;
+        for op in operands.iter() {
+            match self.operand(op) {
+                LatticeValue::Const(c) => args.push(c),
+                LatticeValue::Undef => any_undef = true,
+                LatticeValue::Varying => return LatticeValue::Varying
+            }
+        }
+
+        if any_undef {
+            LatticeValue::Undef
+        } else {
+            match fold(&args) {
+                Some(c) => LatticeValue::Const(c),
+                // the result is undefined/implementation specific (such as division by zero),
+                // we leave it to the runtime
+                None => LatticeValue::Varying
+            }
+        }
+    }
+}
+
+/// finds the destination of a switch for the given (constant) condition
+fn switch_target<'a>(
+    cond: u64,
+    ops: &Vec<P<TreeNode>>,
+    default: &'a Destination,
+    branches: &'a Vec<(OpIndex, Destination)>
+) -> &'a Destination {
+    for &(case, ref dest) in branches.iter() {
+        let ref case = ops[case];
+        let len = case.ty().get_int_length().unwrap_or(64);
+        match case.as_value().extract_int_const() {
+            Some(v) if v & bits_ones(len) == cond => return dest,
+            _ => {}
+        }
+    }
+    default
+}
+
+/// sign-extends a len-bit integer
+fn sext(v: u64, len: usize) -> i64 {
+    let shift = 64 - len;
+    ((v << shift) as i64) >> shift
+}
+
+pub fn fold_binop(op: BinOp, len: usize, a: &Constant, b: &Constant) -> Option<Constant> {
+    use ast::op::BinOp::*;
+
+    match (a, b) {
+        (&Constant::Int(a), &Constant::Int(b)) => {
+            let (sa, sb) = (sext(a, len), sext(b, len));
+            let min = sext(1 << (len - 1), len);
+            let res = match op {
+                Add => a.wrapping_add(b),
+                Sub => a.wrapping_sub(b),
+                Mul => a.wrapping_mul(b),
+                // division by zero and overflowing division are left to the runtime
+                Sdiv if sb != 0 && !(sa == min && sb == -1) => sa.wrapping_div(sb) as u64,
+                Srem if sb != 0 && !(sa == min && sb == -1) => sa.wrapping_rem(sb) as u64,
+                Udiv if b != 0 => a / b,
+                Urem if b != 0 => a % b,
+                And => a & b,
+                Or => a | b,
+                Xor => a ^ b,
+                // so are shifts by the integer length or more
+                Shl if b < len as u64 => a << b,
+                Lshr if b < len as u64 => a >> b,
+                Ashr if b < len as u64 => (sa >> b) as u64,
+                _ => return None
+            };
+            Some(Constant::Int(res & bits_ones(len)))
+        }
+        (&Constant::Float(a), &Constant::Float(b)) => {
+            let res = match op {
+                FAdd => a + b,
+                FSub => a - b,
+                FMul => a * b,
+                FDiv => a / b,
+                FRem => a % b,
+                _ => return None
+            };
+            Some(Constant::Float(res))
+        }
+        (&Constant::Double(a), &Constant::Double(b)) => {
+            let res = match op {
+                FAdd => a + b,
+                FSub => a - b,
+                FMul => a * b,
+                FDiv => a / b,
+                FRem => a % b,
+                _ => return None
+            };
+            Some(Constant::Double(res))
+        }
+        _ => None
+    }
+}
+
+pub fn fold_cmpop(op: CmpOp, len: usize, a: &Constant, b: &Constant) -> Option<bool> {
+    use ast::op::CmpOp::*;
+
+    let (a, b) = match (a, b) {
+        (&Constant::Int(a), &Constant::Int(b)) => {
+            let (sa, sb) = (sext(a, len), sext(b, len));
+            return match op {
+                EQ => Some(a == b),
+                NE => Some(a != b),
+                SGE => Some(sa >= sb),
+                SGT => Some(sa > sb),
+                SLE => Some(sa <= sb),
+                SLT => Some(sa < sb),
+                UGE => Some(a >= b),
+                UGT => Some(a > b),
+                ULE => Some(a <= b),
+                ULT => Some(a < b),
+                _ => None
+            };
+        }
+        (&Constant::Float(a), &Constant::Float(b)) => (a as f64, b as f64),
+        (&Constant::Double(a), &Constant::Double(b)) => (a, b),
+        _ => return None
+    };
+
+    let ordered = !a.is_nan() && !b.is_nan();
+    match op {
+        FFALSE => Some(false),
+        FTRUE => Some(true),
+        FOEQ => Some(ordered && a == b),
+        FOGT => Some(ordered && a > b),
+        FOGE => Some(ordered && a >= b),
+        FOLT => Some(ordered && a < b),
+        FOLE => Some(ordered && a <= b),
+        FONE => Some(ordered && a != b),
+        FORD => Some(ordered),
+        FUEQ => Some(!ordered || a == b),
+        FUGT => Some(!ordered || a > b),
+        FUGE => Some(!ordered || a >= b),
+        FULT => Some(!ordered || a < b),
+        FULE => Some(!ordered || a <= b),
+        FUNE => Some(!ordered || a != b),
+        FUNO => Some(!ordered),
+        _ => None
+    }
+}
+
+fn fold_convop(
+    op: ConvOp,
+    from_ty: &P<MuType>,
+    to_ty: &P<MuType>,
+    a: &Constant
+) -> Option<Constant> {
+    use ast::op::ConvOp::*;
+
+    let from_len = from_ty.get_int_length().unwrap_or(0);
+    let to_len = to_ty.get_int_length().unwrap_or(0);
+
+    // converts a floating point to an integer if it is in the range [min, max)
+    let fp_to_int = |v: f64, min: f64, max: f64| if v.is_nan() {
+        None
+    } else {
+        let v = v.trunc();
+        if v >= min && v < max {
+            Some(v)
+        } else {
+            None
+        }
+    };
+    let fp = match *a {
+        Constant::Float(v) => Some(v as f64),
+        Constant::Double(v) => Some(v),
+        _ => None
+    };
+
+    match (op, a) {
+        (TRUNC, &Constant::Int(v)) | (ZEXT, &Constant::Int(v)) => {
+            Some(Constant::Int(v & bits_ones(to_len)))
+        }
+        (SEXT, &Constant::Int(v)) => {
+            Some(Constant::Int(sext(v, from_len) as u64 & bits_ones(to_len)))
+        }
+        (FPTRUNC, &Constant::Double(v)) => Some(Constant::Float(v as f32)),
+        (FPEXT, &Constant::Float(v)) => Some(Constant::Double(v as f64)),
+        // out of range conversions are left to the runtime
+        (FPTOSI, _) if fp.is_some() && to_len > 0 => {
+            let max = 2f64.powi(to_len as i32 - 1);
+            fp_to_int(fp.unwrap(), -max, max)
+                .map(|v| Constant::Int(v as i64 as u64 & bits_ones(to_len)))
+        }
+        (FPTOUI, _) if fp.is_some() && to_len > 0 => {
+            fp_to_int(fp.unwrap(), 0f64, 2f64.powi(to_len as i32)).map(|v| Constant::Int(v as u64))
+        }
+        (UITOFP, &Constant::Int(v)) => {
+            if to_ty.is_float() {
+                Some(Constant::Float(v as f32))
+            } else {
+                Some(Constant::Double(v as f64))
+            }
+        }
+        (SITOFP, &Constant::Int(v)) => {
+            let v = sext(v, from_len);
+            if to_ty.is_float() {
+                Some(Constant::Float(v as f32))
+            } else {
+                Some(Constant::Double(v as f64))
+            }
+        }
+        (BITCAST, &Constant::Int(v)) if to_ty.is_float() && from_len == 32 => {
+            Some(Constant::Float(f32::from_bits(v as u32)))
+        }
+        (BITCAST, &Constant::Int(v)) if to_ty.is_double() && from_len == 64 => {
+            Some(Constant::Double(f64::from_bits(v)))
+        }
+        (BITCAST, &Constant::Float(v)) if to_len == 32 => Some(Constant::Int(v.to_bits() as u64)),
+        (BITCAST, &Constant::Double(v)) if to_len == 64 => Some(Constant::Int(v.to_bits())),
+        _ => None
+    }
+}
+
+/// replaces SSA variables with their constants, removes the instructions that computed them,
+/// and turns branches on constants into unconditional branches
+fn rewrite(vm: &VM, func: &mut MuFunctionVersion, analysis: &Analysis) {
+    // creates a constant for each SSA variable that always holds one
+    // (in the order of the variables, so the IDs we use do not depend on hashing)
+    let mut constants: HashMap<MuID, P<TreeNode>> = HashMap::new();
+    let mut ids: Vec<MuID> = analysis.values.keys().map(|id| *id).collect();
+    ids.sort();
+    for id in ids {
+        if let &LatticeValue::Const(ref c) = analysis.values.get(&id).unwrap() {
+            let ty = func.context.get_value(id).unwrap().ty().clone();
+            let constant = P(Value {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                ty: ty,
+                v: Value_::Constant(c.clone())
+            });
+            trace!("SSA #{} is replaced by {}", id, constant);
+            constants.insert(id, TreeNode::new_value(constant));
+        }
+    }
+
+    let is_constant = |node: &P<TreeNode>| match node.extract_ssa_id() {
+        Some(id) => constants.contains_key(&id),
+        None => false
+    };
+
+    let f_content = func.content.as_mut().unwrap();
+    for (_, block) in f_content.blocks.iter_mut() {
+        let block_content = block.content.as_mut().unwrap();
+
+        let mut new_body = Vec::with_capacity(block_content.body.len());
+        for node in block_content.body.iter() {
+            let inst = node.as_inst();
+
+            // the instruction is folded
+            if !inst.has_side_effect() &&
+                inst.value.as_ref().map_or(false, |values| {
+                    values.len() > 0 &&
+                        values.iter().all(|val| constants.contains_key(&val.id()))
+                })
+            {
+                trace!("remove folded instruction {}", inst);
+                continue;
+            }
+
+            if !inst.ops.iter().any(|op| is_constant(op)) && fold_branch(inst).is_none() {
+                new_body.push(node.clone());
+                continue;
+            }
+
+            let mut new_inst = inst.clone();
+            for op in new_inst.ops.iter_mut() {
+                let id = op.extract_ssa_id();
+                if let Some(id) = id {
+                    if let Some(constant) = constants.get(&id) {
+                        *op = constant.clone();
+                    }
+                }
+            }
+            if let Some(branch) = fold_branch(&new_inst) {
+                trace!("replace {} with {}", new_inst, branch);
+                new_inst = branch;
+            }
+            new_body.push(TreeNode::new_inst(new_inst));
+        }
+        block_content.body = new_body;
+
+        if let Some(ref mut keepalives) = block_content.keepalives {
+            keepalives.retain(|val| match val.extract_ssa_id() {
+                Some(id) => !constants.contains_key(&id),
+                None => true
+            });
+        }
+    }
+}
+
+/// returns an unconditional branch for a branch/switch on a constant
+fn fold_branch(inst: &Instruction) -> Option<Instruction> {
+    let ref ops = inst.ops;
+    let dest = match inst.v {
+        Instruction_::Branch2 {
+            cond,
+            ref true_dest,
+            ref false_dest,
+            ..
+        } => {
+            match ops[cond].as_value().v {
+                Value_::Constant(Constant::Int(c)) => {
+                    if c & 1 == 1 {
+                        true_dest
+                    } else {
+                        false_dest
+                    }
+                }
+                _ => return None
+            }
+        }
+        Instruction_::Switch {
+            cond,
+            ref default,
+            ref branches
+        } => {
+            let ref cond = ops[cond];
+            let len = cond.ty().get_int_length().unwrap_or(64);
+            match cond.as_value().v {
+                Value_::Constant(Constant::Int(c)) => {
+                    switch_target(c & bits_ones(len), ops, default, branches)
+                }
+                _ => return None
+            }
+        }
+        _ => return None
+    };
+
+    // the branch only keeps the operands passed to the destination
+    let mut new_ops = vec![];
+    let mut args = vec![];
+    for arg in dest.args.iter() {
+        match arg {
+            &DestArg::Normal(index) => {
+                args.push(DestArg::Normal(new_ops.len()));
+                new_ops.push(ops[index].clone());
+            }
+            &DestArg::Freshbound(n) => args.push(DestArg::Freshbound(n))
+        }
+    }
+
+    Some(Instruction {
+        hdr: inst.hdr.clone(),
+        value: None,
+        ops: new_ops,
+        v: Instruction_::Branch1(Destination {
+            target: dest.target.clone(),
+            args: args
+        })
+    })
+}
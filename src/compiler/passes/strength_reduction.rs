@@ -0,0 +1,272 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ast::ir::*;
+use ast::inst::*;
+use ast::op::*;
+use ast::ptr::*;
+use ast::analysis::*;
+use vm::VM;
+use compiler::CompilerPass;
+use compiler::passes::gvn::rewrite;
+use compiler::passes::induction::LoopInduction;
+use compiler::passes::licm::create_preheaders;
+use compiler::passes::sccp::fold_binop;
+use std::any::Any;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+/// Strength reduction of element address computations in loops.
+///
+/// GETELEMIREF of a loop-invariant base with a basic induction variable as the index multiplies
+/// the index by the element size in every iteration. We replace it by a new parameter of the
+/// loop header that holds the address: the preheader computes the address of the initial
+/// element, and the latches move it by the step of the induction variable with SHIFTIREF.
+/// GETELEMIREFs with the same base and induction variable share one parameter.
+pub struct StrengthReduction {
+    name: &'static str
+}
+
+impl StrengthReduction {
+    pub fn new() -> StrengthReduction {
+        StrengthReduction {
+            name: "Strength Reduction"
+        }
+    }
+}
+
+impl CompilerPass for StrengthReduction {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    // preheaders are created (and the analyses invalidated) before the CFG is cached again
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
+    fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        create_preheaders(vm, func);
+
+        let domtree = func.domtree();
+        let loop_nest = func.loop_nest();
+        let mut reduced = 0;
+        for l in loop_nest.loops().iter() {
+            reduced += reduce_loop(vm, func, &domtree, l);
+        }
+
+        debug!("{} address computations strength-reduced", reduced);
+        debug!("after strength reduction: {:?}", func);
+    }
+}
+
+/// GETELEMIREFs with the same base and induction variable
+struct AddressGroup {
+    base: P<TreeNode>,
+    /// the header parameter of the induction variable
+    iv: MuID,
+    is_ptr: bool,
+    ty: P<MuType>,
+    init: P<TreeNode>,
+    op: BinOp,
+    step: P<TreeNode>,
+    /// the GETELEMIREF instructions, and their results
+    insts: Vec<(MuID, MuID)>
+}
+
+/// strength-reduces the address computations of a loop, returns how many are removed
+fn reduce_loop(vm: &VM, func: &mut MuFunctionVersion, domtree: &DomTree, l: &Loop) -> usize {
+    let mut groups: Vec<AddressGroup> = vec![];
+    {
+        let f_content = func.content.as_ref().unwrap();
+        let induction = LoopInduction::new(f_content, domtree, l);
+        if !induction.has_ivs() {
+            return 0;
+        }
+
+        let mut loop_defs = HashSet::new();
+        for id in l.blocks.iter() {
+            let block_content = f_content.get_block(*id).content.as_ref().unwrap();
+            for arg in block_content.args.iter() {
+                loop_defs.insert(arg.id());
+            }
+            for node in block_content.body.iter() {
+                if let Some(ref vals) = node.as_inst().value {
+                    for val in vals.iter() {
+                        loop_defs.insert(val.id());
+                    }
+                }
+            }
+        }
+
+        for id in l.blocks.iter() {
+            for node in f_content.get_block(*id).content.as_ref().unwrap().body.iter() {
+                let inst = node.as_inst();
+                let (is_ptr, base, index) = match inst.v {
+                    Instruction_::GetElementIRef {
+                        is_ptr,
+                        base,
+                        index
+                    } => (is_ptr, &inst.ops[base], &inst.ops[index]),
+                    _ => continue
+                };
+                if loop_defs.contains(&base.as_value().id()) {
+                    continue;
+                }
+                let iv = match induction.get_iv(index) {
+                    Some(iv) => iv,
+                    None => continue
+                };
+
+                let result = inst.value.as_ref().unwrap()[0].clone();
+                let found = groups.iter().position(|group| {
+                    group.base.as_value().id() == base.as_value().id() &&
+                        group.iv == iv.param.id() &&
+                        group.is_ptr == is_ptr && group.ty == result.ty
+                });
+                let group = match found {
+                    Some(i) => i,
+                    None => {
+                        groups.push(AddressGroup {
+                            base: base.clone(),
+                            iv: iv.param.id(),
+                            is_ptr: is_ptr,
+                            ty: result.ty.clone(),
+                            init: iv.init.clone(),
+                            op: iv.op,
+                            step: iv.step.clone(),
+                            insts: vec![]
+                        });
+                        groups.len() - 1
+                    }
+                };
+                trace!("strength-reduce {}", inst);
+                groups[group].insts.push((inst.id(), result.id()));
+            }
+        }
+    }
+
+    if groups.is_empty() {
+        return 0;
+    }
+
+    let preheader = l.preheader.unwrap();
+    let mut removed = HashSet::new();
+    let mut replacements = HashMap::new();
+    for group in groups.iter() {
+        let addr = func.context
+            .make_temporary(vm.next_id(), group.ty.clone())
+            .clone_value();
+        let start = func.context
+            .make_temporary(vm.next_id(), group.ty.clone())
+            .clone_value();
+        let start_inst = TreeNode::new_inst(Instruction {
+            hdr: MuEntityHeader::unnamed(vm.next_id()),
+            value: Some(vec![start.clone()]),
+            ops: vec![group.base.clone(), group.init.clone()],
+            v: Instruction_::GetElementIRef {
+                is_ptr: group.is_ptr,
+                base: 0,
+                index: 1
+            }
+        });
+        add_branch_arg(func, preheader, l.header, start_inst, start);
+
+        let step = match group.op {
+            BinOp::Sub => negate(vm, &group.step),
+            _ => group.step.clone()
+        };
+        for latch in l.latches.iter() {
+            let next = func.context
+                .make_temporary(vm.next_id(), group.ty.clone())
+                .clone_value();
+            let shift = TreeNode::new_inst(Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: Some(vec![next.clone()]),
+                ops: vec![TreeNode::new_value(addr.clone()), step.clone()],
+                v: Instruction_::ShiftIRef {
+                    is_ptr: group.is_ptr,
+                    base: 0,
+                    offset: 1
+                }
+            });
+            add_branch_arg(func, *latch, l.header, shift, next);
+        }
+
+        func.content
+            .as_mut()
+            .unwrap()
+            .get_block_mut(l.header)
+            .content
+            .as_mut()
+            .unwrap()
+            .args
+            .push(addr.clone());
+        for &(inst, result) in group.insts.iter() {
+            removed.insert(inst);
+            replacements.insert(result, TreeNode::new_value(addr.clone()));
+        }
+    }
+
+    rewrite(func, &removed, &replacements);
+    removed.len()
+}
+
+/// inserts an instruction before the branch at the end of a block, and passes its result as a
+/// new (last) argument to the target
+fn add_branch_arg(
+    func: &mut MuFunctionVersion,
+    from: MuID,
+    target: MuID,
+    inst: P<TreeNode>,
+    value: P<Value>
+) {
+    let ref mut body = func.content
+        .as_mut()
+        .unwrap()
+        .get_block_mut(from)
+        .content
+        .as_mut()
+        .unwrap()
+        .body;
+    let mut branch = body.pop().unwrap().as_inst().clone();
+    let index = branch.ops.len();
+    branch.ops.push(TreeNode::new_value(value));
+    for dest in branch.get_destinations_mut() {
+        if dest.target.id() == target {
+            dest.args.push(DestArg::Normal(index));
+        }
+    }
+    body.push(inst);
+    body.push(TreeNode::new_inst(branch));
+}
+
+/// returns the negation of an integer constant
+fn negate(vm: &VM, op: &P<TreeNode>) -> P<TreeNode> {
+    let val = op.as_value();
+    let len = val.ty.get_int_length().unwrap();
+    let c = match val.v {
+        Value_::Constant(ref c) => fold_binop(BinOp::Sub, len, &Constant::Int(0), c).unwrap(),
+        _ => panic!("expected an integer constant, found {}", val)
+    };
+    TreeNode::new_value(P(Value {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        ty: val.ty.clone(),
+        v: Value_::Constant(c)
+    }))
+}
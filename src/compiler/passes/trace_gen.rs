@@ -21,6 +21,7 @@ use vm::VM;
 use compiler::CompilerPass;
 use utils::LinkedHashSet;
 use std::any::Any;
+use std::collections::HashMap;
 
 pub struct TraceGen {
     name: &'static str
@@ -45,8 +46,26 @@ impl CompilerPass for TraceGen {
         self
     }
 
-    #[allow(unused_variables)] // vm is not used here
     fn visit_function(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
+        // with a profile, paths that never ran (such as exception handlers that never caught
+        // anything) are slow paths. We only mark the first block of such paths (see TraceHint)
+        let block_counts = profiled_block_counts(func);
+        {
+            let f_content = func.content.as_mut().unwrap();
+            for (id, count) in block_counts.iter() {
+                let block = f_content.get_block_mut(*id);
+                let ran_before = block
+                    .control_flow
+                    .preds
+                    .iter()
+                    .any(|pred| block_counts.get(pred).map_or(false, |count| *count > 0));
+                if *count == 0 && ran_before && block.trace_hint == TraceHint::None {
+                    trace_if!(LOG_TRACE_SCHEDULE, "{} never ran, set as slow path", block);
+                    block.trace_hint = TraceHint::SlowPath;
+                }
+            }
+        }
+
         // we put the high probability edge into a hot trace, and others into cold paths
         // and traverse cold_path later
         let trace = {
@@ -92,10 +111,11 @@ impl CompilerPass for TraceGen {
                     trace.push(cur_block.id());
 
                     // trying to find next block
-                    let next_block: Option<MuID> = match find_next_block(cur_block, func) {
-                        Some(id) => Some(id),
-                        None => None
-                    };
+                    let next_block: Option<MuID> =
+                        match find_next_block(cur_block, func, &block_counts) {
+                            Some(id) => Some(id),
+                            None => None
+                        };
                     trace_if!(
                         LOG_TRACE_SCHEDULE && next_block.is_some(),
                         "find next block as {} #{}",
@@ -221,12 +241,29 @@ impl CompilerPass for TraceGen {
     }
 }
 
+/// returns the execution counts of the blocks of a function in the --profile-use profile
+/// (blocks whose counts are unknown are left out, see ProfileInstrumentation)
+fn profiled_block_counts(func: &MuFunctionVersion) -> HashMap<MuID, u64> {
+    let mut ret = HashMap::new();
+    for (id, block) in func.content.as_ref().unwrap().blocks.iter() {
+        if let Some(count) = block.exec_count {
+            ret.insert(*id, count);
+        }
+    }
+    ret
+}
+
 /// returns the successor of current block.
 /// We first look at trace hint, if there is no trace hint to indicate next block,
-/// we layout the block with the highest probability as next block (in case of a tie,
+/// we layout the block that ran most often as next block if all the candidates are in the
+/// profile, otherwise the block with the highest probability (in case of a tie,
 /// returns the first met successor). If current block does not have any successor,
 /// returns None.
-fn find_next_block(cur_block: &Block, func: &MuFunctionVersion) -> Option<MuID> {
+fn find_next_block(
+    cur_block: &Block,
+    func: &MuFunctionVersion,
+    block_counts: &HashMap<MuID, u64>
+) -> Option<MuID> {
     let f_content = func.content.as_ref().unwrap();
     let ref succs = cur_block.control_flow.succs;
     let has_fastpath = succs.iter().find(|edge| {
@@ -266,8 +303,26 @@ fn find_next_block(cur_block: &Block, func: &MuFunctionVersion) -> Option<MuID>
                 ideal_successors
             );
 
+            let profiled = ideal_successors
+                .iter()
+                .all(|edge| block_counts.contains_key(&edge.target));
+
             if ideal_successors.len() == 0 {
                 None
+            } else if profiled {
+                let mut hot_blk = ideal_successors[0].target;
+                let mut hot_count = block_counts[&hot_blk];
+
+                for edge in ideal_successors.iter() {
+                    let count = block_counts[&edge.target];
+                    trace_if!(LOG_TRACE_SCHEDULE, "succ: {}/{} runs", edge.target, count);
+                    if count >= hot_count {
+                        hot_blk = edge.target;
+                        hot_count = count;
+                    }
+                }
+
+                Some(hot_blk)
             } else {
                 let mut hot_blk = ideal_successors[0].target;
                 let mut hot_prob = ideal_successors[0].probability;
@@ -123,6 +123,10 @@ impl CompilerPass for TreeGen {
         self
     }
 
+    fn preserves_cfg(&self) -> bool {
+        true
+    }
+
     fn execute(&mut self, vm: &VM, func: &mut MuFunctionVersion) {
         // We are trying to generate a depth tree from the original AST.
         // If an SSA variable is used only once, and the instruction that generates it is movable,
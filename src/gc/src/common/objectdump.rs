@@ -21,8 +21,11 @@ use heap::freelist::*;
 use std::collections::HashMap;
 use std::mem::transmute;
 
+/// a dump of the objects reachable from a list of roots.
+/// Objects are kept in the order that they are reached from the roots, and are labelled by
+/// that order, so that the dump does not depend on where the objects are in the heap
 pub struct HeapDump {
-    pub objects: HashMap<Address, ObjectDump>,
+    pub objects: LinkedHashMap<Address, ObjectDump>,
     pub relocatable_refs: HashMap<Address, String>
 }
 
@@ -39,7 +42,7 @@ impl HeapDump {
         trace!("dump heap from {:?}", roots);
         let mut work_queue: Vec<Address> = roots;
         let mut heap: HeapDump = HeapDump {
-            objects: HashMap::new(),
+            objects: LinkedHashMap::new(),
             relocatable_refs: HashMap::new()
         };
 
@@ -192,7 +195,7 @@ impl HeapDump {
         let mut count = 0;
 
         for addr in self.objects.keys() {
-            let label = format!("GCDUMP_{}_{}", count, addr);
+            let label = format!("GCDUMP_{}", count);
             self.relocatable_refs.insert(*addr, label);
 
             count += 1;
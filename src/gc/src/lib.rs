@@ -438,6 +438,14 @@ pub extern "C" fn force_gc(mutator: *mut Mutator) {
     yieldpoint(mutator);
 }
 
+/// checks if an address is in the heap
+/// (this is conservative: the address may point into the middle of an object)
+#[no_mangle]
+pub extern "C" fn is_heap_address(addr: Address) -> bool {
+    let gc_lock = MY_GC.read().unwrap();
+    gc_lock.as_ref().unwrap().is_heap_object(addr)
+}
+
 /// traces reachable objects and record them as a data structure
 /// so that the user can inspect the reachable heap and persist it in their way
 #[no_mangle]
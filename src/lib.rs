@@ -24,6 +24,7 @@ extern crate rodal;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate stderrlog;
 #[macro_use]
 extern crate maplit;
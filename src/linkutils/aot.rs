@@ -22,10 +22,43 @@ use compiler::backend;
 
 use std::path::PathBuf;
 use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// time spent in the C compiler while building a boot image
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LinkTimes {
+    /// compiling the generated assembly (and C sources) into object files
+    pub assembly: Duration,
+    /// linking the object files
+    pub linking: Duration
+}
 
 /// links generated code for the given functions, static library of Zebu,
 /// and a main function to produce an executable of the given name
 pub fn link_primordial(funcs: Vec<MuName>, out: &str, vm: &VM) -> PathBuf {
+    link_primordial_with_extra_srcs(funcs, vec![], out, vm)
+}
+
+/// links generated code for the given functions with a few external sources
+/// (C/assembly sources, object files or static archives), static library of Zebu,
+/// and a main function to produce an executable of the given name
+pub fn link_primordial_with_extra_srcs(
+    funcs: Vec<MuName>,
+    srcs: Vec<String>,
+    out: &str,
+    vm: &VM
+) -> PathBuf {
+    link_primordial_timed(funcs, srcs, out, vm, &mut LinkTimes::default())
+}
+
+/// the same as link_primordial_with_extra_srcs(), and records the time spent in the C compiler
+pub fn link_primordial_timed(
+    funcs: Vec<MuName>,
+    srcs: Vec<String>,
+    out: &str,
+    vm: &VM,
+    times: &mut LinkTimes
+) -> PathBuf {
     let emit_dir = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
 
     // prepare a list of files that need to be compiled and linked together
@@ -36,11 +69,11 @@ pub fn link_primordial(funcs: Vec<MuName>, out: &str, vm: &VM) -> PathBuf {
 
         // all interested mu funcs
         for func in funcs {
-            ret.push(get_path_for_mu_func(func, vm));
+            ret.extend(get_paths_for_mu_func(func, vm));
         }
 
         // mu context
-        ret.push(get_path_for_mu_context(vm));
+        ret.extend(get_paths_for_mu_context(vm));
 
         // copy primoridal entry
         let source = get_path_under_zebu(runtime::PRIMORDIAL_ENTRY);
@@ -59,13 +92,9 @@ pub fn link_primordial(funcs: Vec<MuName>, out: &str, vm: &VM) -> PathBuf {
         // include the primordial C main
         ret.push(dest);
 
-        // include mu static lib
-        if vm.vm_options.flag_aot_link_static {
-            ret.push(get_path_under_zebu(if cfg!(debug_assertions) {
-                "target/debug/libmu.a"
-            } else {
-                "target/release/libmu.a"
-            }));
+        // external sources (the C compiler compiles sources, and links objects/archives)
+        for src in srcs {
+            ret.push(PathBuf::from(src));
         }
 
         ret
@@ -76,10 +105,12 @@ pub fn link_primordial(funcs: Vec<MuName>, out: &str, vm: &VM) -> PathBuf {
 
     link_executable_internal(
         !vm.vm_options.flag_aot_link_static,
+        vm.vm_options.flag_aot_pic,
         files,
         &vm.vm_options.flag_bootimage_external_lib,
         &vm.vm_options.flag_bootimage_external_libpath,
-        out_path
+        out_path,
+        times
     )
 }
 
@@ -96,11 +127,11 @@ pub fn link_test_primordial(funcs: Vec<MuName>, out: &str, vm: &VM) -> PathBuf {
 
         // all interested mu funcs
         for func in funcs {
-            ret.push(get_path_for_mu_func(func, vm));
+            ret.extend(get_paths_for_mu_func(func, vm));
         }
 
         // mu context
-        ret.push(get_path_for_mu_context(vm));
+        ret.extend(get_paths_for_mu_context(vm));
 
         // copy primoridal entry
         let source = get_path_under_zebu(runtime::TEST_PRIMORDIAL_ENTRY);
@@ -119,15 +150,6 @@ pub fn link_test_primordial(funcs: Vec<MuName>, out: &str, vm: &VM) -> PathBuf {
         // include the primordial C main
         ret.push(dest);
 
-        // include mu static lib
-        if vm.vm_options.flag_aot_link_static {
-            ret.push(get_path_under_zebu(if cfg!(debug_assertions) {
-                "target/debug/libmu.a"
-            } else {
-                "target/release/libmu.a"
-            }));
-        }
-
         ret
     };
 
@@ -136,26 +158,43 @@ pub fn link_test_primordial(funcs: Vec<MuName>, out: &str, vm: &VM) -> PathBuf {
 
     link_executable_internal(
         !vm.vm_options.flag_aot_link_static,
+        vm.vm_options.flag_aot_pic,
         files,
         &vm.vm_options.flag_bootimage_external_lib,
         &vm.vm_options.flag_bootimage_external_libpath,
-        out_path
+        out_path,
+        &mut LinkTimes::default()
     )
 }
 
 /// invokes the C compiler to link code into an executable
 fn link_executable_internal(
     link_dynamicly: bool,
+    pie: bool,
     files: Vec<PathBuf>,
     lib: &Vec<String>,
     libpath: &Vec<String>,
-    out: PathBuf
+    out: PathBuf,
+    times: &mut LinkTimes
 ) -> PathBuf {
     info!("output as {:?}", out.as_path());
 
+    let start = Instant::now();
+    let object_files = compile_sources(files, pie);
+    times.assembly += start.elapsed();
+
+    let start = Instant::now();
     let mut cc = Command::new(get_c_compiler());
 
+    // all the object files
+    for obj in object_files {
+        info!("link with {:?}", obj.as_path());
+        cc.arg(obj.as_path());
+    }
+
     // external libs
+    // (they need to follow the code that uses them, as the linker only takes symbols from
+    // libraries that are needed so far)
     for path in libpath.iter() {
         cc.arg(format!("-L{}", path));
     }
@@ -163,7 +202,27 @@ fn link_executable_internal(
         cc.arg(format!("-l{}", l));
     }
 
-    if link_dynamicly {
+    if !link_dynamicly {
+        // the whole static library is linked, as we look up runtime functions by name (with
+        // dlsym) as well as referring to them from the code
+        let libmu = get_path_under_zebu(if cfg!(debug_assertions) {
+            "target/debug/libmu.a"
+        } else {
+            "target/release/libmu.a"
+        });
+        if !libmu.exists() {
+            panic!("cannot find {:?} to link the boot image statically", libmu);
+        }
+        info!("link with {:?}", libmu.as_path());
+
+        if cfg!(target_os = "macos") {
+            cc.arg(format!("-Wl,-force_load,{}", libmu.to_str().unwrap()));
+        } else {
+            cc.arg("-Wl,--whole-archive");
+            cc.arg(libmu.as_path());
+            cc.arg("-Wl,--no-whole-archive");
+        }
+    } else {
         cc.arg(format!(
             "-L{}",
             get_path_under_zebu(if cfg!(debug_assertions) {
@@ -181,6 +240,7 @@ fn link_executable_internal(
         cc.arg("-lrt");
         cc.arg("-lm");
         cc.arg("-lpthread");
+        cc.arg("-lutil");
         cc.arg("-lz");
     } else if cfg!(target_os = "macos") {
         cc.arg("-liconv");
@@ -194,21 +254,25 @@ fn link_executable_internal(
         cc.arg("-lc");
         cc.arg("-lm");
     }
-    // all the source code
-    for file in files {
-        info!("link with {:?}", file.as_path());
-        cc.arg(file.as_path());
-    }
-
     // flag to allow find symbols in the executable
     cc.arg("-rdynamic");
 
+    if pie {
+        // position independent executable with full RELRO, the code is expected to be free of
+        // text relocations (-z text makes the linker reject any that are left)
+        cc.arg("-pie");
+        cc.arg("-Wl,-z,relro");
+        cc.arg("-Wl,-z,now");
+        cc.arg("-Wl,-z,text");
+    }
+
     // specified output
     cc.arg("-o");
     cc.arg(out.as_os_str());
 
     // execute and check results
     assert!(exec_cmd(cc).status.success(), "failed to link code");
+    times.linking += start.elapsed();
     out
 }
 
@@ -225,19 +289,30 @@ pub fn link_dylib_with_extra_srcs(
     srcs: Vec<String>,
     out: &str,
     vm: &VM
+) -> PathBuf {
+    link_dylib_timed(funcs, srcs, out, vm, &mut LinkTimes::default())
+}
+
+/// the same as link_dylib_with_extra_srcs(), and records the time spent in the C compiler
+pub fn link_dylib_timed(
+    funcs: Vec<MuName>,
+    srcs: Vec<String>,
+    out: &str,
+    vm: &VM,
+    times: &mut LinkTimes
 ) -> PathBuf {
     let files = {
         let mut ret = vec![];
 
         for func in funcs {
-            ret.push(get_path_for_mu_func(func, vm));
+            ret.extend(get_paths_for_mu_func(func, vm));
         }
 
         for src in srcs {
             ret.push(PathBuf::from(src));
         }
 
-        ret.push(get_path_for_mu_context(vm));
+        ret.extend(get_paths_for_mu_context(vm));
 
         ret
     };
@@ -249,27 +324,33 @@ pub fn link_dylib_with_extra_srcs(
         files,
         &vm.vm_options.flag_bootimage_external_lib,
         &vm.vm_options.flag_bootimage_external_libpath,
-        out_path
+        out_path,
+        times
     )
 }
 
-/// invokes the C compiler to link code into a dynamic library
-fn link_dylib_internal(
-    files: Vec<PathBuf>,
-    lib: &Vec<String>,
-    libpath: &Vec<String>,
-    out: PathBuf
-) -> PathBuf {
+/// invokes the C compiler to compile each single source file into an object file
+/// (next to the source), and returns the object files to link
+fn compile_sources(files: Vec<PathBuf>, fpic: bool) -> Vec<PathBuf> {
     let mut object_files: Vec<PathBuf> = vec![];
 
-    // compile each single source file
     for file in files {
+        // object files (e.g. emitted ELF objects) and libraries do not need compiling
+        if file.extension().map_or(false, |ext| {
+            ext == "o" || ext == "a" || ext == "so" || ext == "dylib"
+        }) {
+            object_files.push(file);
+            continue;
+        }
+
         let mut cc = Command::new(get_c_compiler());
 
         // output object file
         cc.arg("-c");
-        // position independent code
-        cc.arg("-fPIC");
+        if fpic {
+            // position independent code
+            cc.arg("-fPIC");
+        }
 
         let mut out = file.clone();
         out.set_extension("o");
@@ -282,7 +363,23 @@ fn link_dylib_internal(
         exec_cmd(cc);
     }
 
+    object_files
+}
+
+/// invokes the C compiler to link code into a dynamic library
+fn link_dylib_internal(
+    files: Vec<PathBuf>,
+    lib: &Vec<String>,
+    libpath: &Vec<String>,
+    out: PathBuf,
+    times: &mut LinkTimes
+) -> PathBuf {
+    let start = Instant::now();
+    let object_files = compile_sources(files, true);
+    times.assembly += start.elapsed();
+
     // link object files into a dynamic library
+    let start = Instant::now();
     let mut cc = Command::new(get_c_compiler());
 
     // external libs
@@ -314,6 +411,7 @@ fn link_dylib_internal(
     cc.arg(out.as_os_str());
 
     exec_cmd(cc);
+    times.linking += start.elapsed();
 
     out
 }
@@ -355,7 +453,7 @@ pub fn compile_fnc<'a>(fnc_name: &'static str, build_fnc: &'a Fn() -> VM) -> ll:
     backend::emit_context(&vm);
     let libname = &get_dylib_name(fnc_name);
     let dylib = aot::link_dylib(vec![Mu(fnc_name)], libname, &vm);
-    ll::Library::new(dylib.as_os_str()).unwrap()
+    load_dylib(&dylib, &vm)
 }
 
 /// builds a bundle (that contains several functions), compiles them,
@@ -389,7 +487,29 @@ pub fn compile_fncs<'a>(
 
     let libname = &get_dylib_name(entry);
     let dylib = aot::link_dylib(fnc_names.iter().map(|x| Mu(x)).collect(), libname, &vm);
-    ll::Library::new(dylib.as_os_str()).unwrap()
+    load_dylib(&dylib, &vm)
+}
+
+/// loads a dynamic library built by compile_fnc()/compile_fncs()
+fn load_dylib(dylib: &PathBuf, vm: &VM) -> ll::Library {
+    if !vm.target_arch().is_host() {
+        panic!(
+            "cannot load {:?}: the code is compiled for {}",
+            dylib,
+            vm.target_arch().name()
+        );
+    }
+
+    let lib = ll::Library::new(dylib.as_os_str()).unwrap();
+
+    // perf entries are written for symbols that we can resolve, which requires the library
+    // to be loaded globally
+    if vm.vm_options.flag_perf_map {
+        runtime::load_library_globally(dylib.as_path());
+        vm.record_loaded_code();
+    }
+
+    lib
 }
 
 /// gets the path for the generated code of a Mu function
@@ -400,6 +520,20 @@ fn get_path_for_mu_func(f: MuName, vm: &VM) -> PathBuf {
     ret
 }
 
+/// gets the paths for all the generated files of a Mu function
+/// (the code, and its constants if they are emitted as an ELF object)
+fn get_paths_for_mu_func(f: MuName, vm: &VM) -> Vec<PathBuf> {
+    let mut ret = vec![get_path_for_mu_func(f.clone(), vm)];
+
+    if vm.vm_options.flag_aot_emit_elf {
+        let mut obj = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
+        obj.push((*f).clone() + backend::elf::ELF_RODATA_SUFFIX);
+        ret.push(obj);
+    }
+
+    ret
+}
+
 /// gets the path for generated Mu context (persisted VM/heap)
 fn get_path_for_mu_context(vm: &VM) -> PathBuf {
     let mut ret = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
@@ -407,6 +541,20 @@ fn get_path_for_mu_context(vm: &VM) -> PathBuf {
     ret
 }
 
+/// gets the paths for all the generated context files
+/// (the persisted VM, and the persisted heap if it is emitted as an ELF object)
+fn get_paths_for_mu_context(vm: &VM) -> Vec<PathBuf> {
+    let mut ret = vec![get_path_for_mu_context(vm)];
+
+    if vm.vm_options.flag_aot_emit_elf {
+        let mut obj = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
+        obj.push(backend::elf::AOT_EMIT_HEAP_OBJ_FILE);
+        ret.push(obj);
+    }
+
+    ret
+}
+
 #[cfg(not(feature = "sel4-rumprun"))]
 pub fn run_test(vm: &VM, test_name: &str, tester_name: &str) {
     let output_name = test_name.to_string() + "_" + tester_name;
@@ -41,6 +41,9 @@ pub mod math;
 pub mod entrypoints;
 /// exception handling
 pub mod exception;
+/// perf map and jitdump output for profiling Mu code with Linux perf
+#[cfg(target_os = "linux")]
+pub mod perf;
 
 lazy_static!{
     static ref UNKNOWN_FUNCTION_NAME : CName = Arc::new("UNKOWN".to_string());
@@ -113,6 +116,45 @@ pub fn resolve_symbol(symbol: MuName) -> Address {
     Address::from_ptr(ret)
 }
 
+/// returns address for a given symbol, or None if the symbol is not loaded
+#[cfg(not(feature = "sel4-rumprun-target-side"))]
+pub fn try_resolve_symbol(symbol: MuName) -> Option<Address> {
+    use std::ptr;
+
+    let c_symbol = CString::new(mangle_name(symbol)).unwrap();
+
+    let rtld_default = unsafe { dlopen(ptr::null(), 0) };
+    // clear any stale error from an earlier dl call, so that dlerror() below only reports
+    // errors from this dlsym()
+    unsafe { dlerror() };
+    let ret = unsafe { dlsym(rtld_default, c_symbol.as_ptr()) };
+
+    let error = unsafe { dlerror() };
+    if !error.is_null() || ret.is_null() {
+        None
+    } else {
+        Some(Address::from_ptr(ret))
+    }
+}
+
+/// loads a dynamic library, and makes its symbols globally visible (so that
+/// resolve_symbol() can find them)
+#[cfg(not(feature = "sel4-rumprun-target-side"))]
+pub fn load_library_globally(path: &std::path::Path) {
+    let c_path = CString::new(path.to_str().unwrap()).unwrap();
+
+    let handle = unsafe { dlopen(c_path.as_ptr(), RTLD_NOW | RTLD_GLOBAL) };
+    if handle.is_null() {
+        let error = unsafe { dlerror() };
+        let cstr = unsafe { CStr::from_ptr(error) };
+        panic!(
+            "failed to load library: {:?} ({})",
+            path,
+            cstr.to_str().unwrap()
+        );
+    }
+}
+
 use std::os::raw::c_char;
 //use std::os::raw::c_void;
 // This function is specific to sel4-rumprun platform
@@ -155,6 +197,16 @@ pub fn resolve_symbol(symbol: String) -> Address {
     Address::from_ptr(ret)
 }
 
+#[cfg(feature = "sel4-rumprun-target-side")]
+pub fn try_resolve_symbol(symbol: String) -> Option<Address> {
+    let ret = unsafe { c_resolve_symbol(CString::new(symbol).unwrap().as_ptr()) };
+    if ret.is_null() {
+        None
+    } else {
+        Some(Address::from_ptr(ret))
+    }
+}
+
 /// ValueLocation represents the runtime location for a value.
 /// The purpose of this data structure is to refer to a location in a unified way
 /// for both compile time (usually talking about symbols) and run time (talking about addresses)
@@ -290,36 +342,46 @@ pub extern "C" fn mu_main(
     } else {
         let primordial = primordial.as_ref().unwrap();
 
-        // create mu stack
-        let stack = vm.new_stack(primordial.func_id);
-
-        // if the primordial named some const arguments, we use the const args
-        // otherwise we push 'argc' and 'argv' to new stack
-        let args: Vec<ValueLocation> = if primordial.has_const_args {
-            primordial
-                .args
-                .iter()
-                .map(|arg| ValueLocation::from_constant(arg.clone()))
-                .collect()
-        } else {
-            let mut args = vec![];
-
-            // 1st arg: argc
-            args.push(ValueLocation::from_constant(Constant::Int(argc as u64)));
-
-            // 2nd arg: argv
-            args.push(ValueLocation::from_constant(Constant::Int(argv as u64)));
-
-            args
-        };
-
         let threadlocal = vm.primordial_threadlocal
             .read()
             .unwrap()
             .as_ref()
             .map(|name| resolve_symbol(Arc::new(name.clone())))
             .unwrap_or(unsafe { Address::zero() });
-        thread::MuThread::new_thread_normal(stack, threadlocal, args, vm.clone());
+
+        // create mu stack (or restore the stack persisted in the boot image)
+        let stack = if primordial.from_stack {
+            let image = resolve_symbol(Arc::new(thread::PRIMORDIAL_STACK_SYMBOL.to_string()));
+            Box::new(unsafe { thread::MuStack::from_image(vm.next_id(), image) })
+        } else {
+            vm.new_stack(primordial.func_id)
+        };
+
+        if stack.is_suspended() {
+            // a persisted stack that was suspended by SWAPSTACK is resumed as SWAPSTACK does
+            thread::MuThread::new_thread_swapped(stack, threadlocal, vm.clone());
+        } else {
+            // if the primordial named some const arguments, we use the const args
+            // otherwise we push 'argc' and 'argv' to new stack
+            let args: Vec<ValueLocation> = if primordial.has_const_args {
+                primordial
+                    .args
+                    .iter()
+                    .map(|arg| ValueLocation::from_constant(arg.clone()))
+                    .collect()
+            } else {
+                let mut args = vec![];
+
+                // 1st arg: argc
+                args.push(ValueLocation::from_constant(Constant::Int(argc as u64)));
+
+                // 2nd arg: argv
+                args.push(ValueLocation::from_constant(Constant::Int(argv as u64)));
+
+                args
+            };
+            thread::MuThread::new_thread_normal(stack, threadlocal, args, vm.clone());
+        }
 
         loop {
             let thread = vm.pop_join_handle();
@@ -329,6 +391,10 @@ pub extern "C" fn mu_main(
             thread.unwrap().join().unwrap();
         }
 
+        if !vm.vm_options.flag_profile_generate.is_empty() {
+            vm.write_profile();
+        }
+
         trace!("All threads have exited, quiting...");
     }
 }
@@ -0,0 +1,221 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Linux perf support: `perf` symbolises samples in code that is not described by an ELF
+//! symbol table by reading `/tmp/perf-<pid>.map`, and `perf inject --jit` reads jitdump
+//! records from `/tmp/jit-<pid>.dump` (see tools/perf/Documentation/jitdump-specification.txt
+//! in the Linux tree).
+
+use compiler::backend::elf::ElfMachine;
+use compiler::machine_code::CompiledFunction;
+use runtime::ValueLocation;
+use runtime::try_resolve_symbol;
+use utils::Address;
+use vm::VM;
+
+use libc::*;
+use std::collections::HashSet;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+const JITDUMP_MAGIC: u32 = 0x4A695444;
+const JITDUMP_VERSION: u32 = 1;
+const JITDUMP_HEADER_SIZE: u32 = 40;
+const JIT_CODE_LOAD: u32 = 0;
+
+/// open perf output files (shared by all VMs in this process, as perf looks them up by pid)
+struct PerfOutput {
+    map: File,
+    jitdump: Option<File>,
+    /// start addresses that we have already written entries for
+    recorded: HashSet<Address>,
+    /// jitdump requires a unique index for every code load
+    code_index: u64
+}
+
+lazy_static! {
+    static ref PERF_OUTPUT: Mutex<Option<PerfOutput>> = Mutex::new(None);
+}
+
+/// writes perf map entries (and jitdump records if --perf-jitdump is set) for every compiled
+/// function whose code is loaded. Functions that have been recorded before are skipped, so
+/// this can be called every time new code gets loaded.
+pub fn record_compiled_funcs(vm: &VM) {
+    let compiled_funcs = vm.compiled_funcs().read().unwrap();
+
+    let mut output = PERF_OUTPUT.lock().unwrap();
+    if output.is_none() {
+        *output = Some(PerfOutput::open(vm.vm_options.flag_perf_jitdump));
+    }
+    let output = output.as_mut().unwrap();
+
+    for cf in compiled_funcs.values() {
+        let cf = cf.read().unwrap();
+        let (start, end) = match (code_address(&cf.start), code_address(&cf.end)) {
+            (Some(start), Some(end)) => (start, end),
+            // the code is not loaded in this process
+            _ => continue
+        };
+        if output.recorded.contains(&start) {
+            continue;
+        }
+
+        let name = vm.name_of(cf.func_id);
+        output.record(&cf, &name, start, end);
+    }
+}
+
+/// returns the address of a code location if the code is loaded
+fn code_address(loc: &ValueLocation) -> Option<Address> {
+    match loc {
+        &ValueLocation::Relocatable(_, ref symbol) => try_resolve_symbol(symbol.clone()),
+        &ValueLocation::Direct(_, addr) => Some(addr),
+        _ => None
+    }
+}
+
+impl PerfOutput {
+    fn open(jitdump: bool) -> PerfOutput {
+        let pid = unsafe { getpid() } as u32;
+
+        let map_path = format!("/tmp/perf-{}.map", pid);
+        let map = match OpenOptions::new().create(true).append(true).open(&map_path) {
+            Ok(file) => file,
+            Err(_) => panic!("failed to create perf map {}", map_path)
+        };
+
+        let jitdump = if jitdump {
+            Some(PerfOutput::open_jitdump(pid))
+        } else {
+            None
+        };
+
+        PerfOutput {
+            map: map,
+            jitdump: jitdump,
+            recorded: HashSet::new(),
+            code_index: 0
+        }
+    }
+
+    fn open_jitdump(pid: u32) -> File {
+        let dump_path = format!("/tmp/jit-{}.dump", pid);
+        let mut file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&dump_path)
+        {
+            Ok(file) => file,
+            Err(_) => panic!("failed to create jitdump {}", dump_path)
+        };
+
+        // perf finds the dump by looking for an executable mapping of it,
+        // so we map it and never unmap it
+        let marker = unsafe {
+            mmap(
+                ptr::null_mut(),
+                sysconf(_SC_PAGESIZE) as usize,
+                PROT_READ | PROT_EXEC,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0
+            )
+        };
+        if marker == MAP_FAILED {
+            panic!("failed to map jitdump {}", dump_path);
+        }
+
+        let mut header = vec![];
+        put_u32(&mut header, JITDUMP_MAGIC);
+        put_u32(&mut header, JITDUMP_VERSION);
+        put_u32(&mut header, JITDUMP_HEADER_SIZE);
+        put_u32(&mut header, ElfMachine::host().e_machine() as u32);
+        put_u32(&mut header, 0); // pad1
+        put_u32(&mut header, pid);
+        put_u64(&mut header, timestamp());
+        put_u64(&mut header, 0); // flags
+        debug_assert!(header.len() == JITDUMP_HEADER_SIZE as usize);
+        file.write_all(&header).unwrap();
+
+        file
+    }
+
+    fn record(&mut self, cf: &CompiledFunction, name: &str, start: Address, end: Address) {
+        let size = end - start;
+        trace!(
+            "perf: {} (func ver {}) at {} ({} bytes)",
+            name,
+            cf.func_ver_id,
+            start,
+            size
+        );
+
+        writeln!(self.map, "{:x} {:x} {}", start.as_usize(), size, name).unwrap();
+        self.map.flush().unwrap();
+        self.recorded.insert(start);
+
+        if let Some(ref mut file) = self.jitdump {
+            let code = unsafe { slice::from_raw_parts(start.to_ptr::<u8>(), size) };
+            let record_size = 16 + 40 + name.len() + 1 + size;
+
+            let mut record = Vec::with_capacity(record_size);
+            put_u32(&mut record, JIT_CODE_LOAD);
+            put_u32(&mut record, record_size as u32);
+            put_u64(&mut record, timestamp());
+            put_u32(&mut record, unsafe { getpid() } as u32);
+            put_u32(&mut record, unsafe { syscall(SYS_gettid) } as u32);
+            put_u64(&mut record, start.as_usize() as u64); // vma
+            put_u64(&mut record, start.as_usize() as u64); // code_addr
+            put_u64(&mut record, size as u64);
+            put_u64(&mut record, self.code_index);
+            record.extend_from_slice(name.as_bytes());
+            record.push(0);
+            record.extend_from_slice(code);
+            debug_assert!(record.len() == record_size);
+
+            file.write_all(&record).unwrap();
+            file.flush().unwrap();
+            self.code_index += 1;
+        }
+    }
+}
+
+/// jitdump timestamps need to use the same clock as perf (perf record -k mono)
+fn timestamp() -> u64 {
+    let mut ts = timespec {
+        tv_sec: 0,
+        tv_nsec: 0
+    };
+    unsafe { clock_gettime(CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+fn put_u32(buf: &mut Vec<u8>, v: u32) {
+    for i in 0..4 {
+        buf.push((v >> (i * 8)) as u8);
+    }
+}
+
+fn put_u64(buf: &mut Vec<u8>, v: u64) {
+    for i in 0..8 {
+        buf.push((v >> (i * 8)) as u8);
+    }
+}
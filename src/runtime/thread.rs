@@ -17,6 +17,7 @@ use ast::ptr::*;
 use ast::types::*;
 use vm::VM;
 use runtime::ValueLocation;
+use runtime::try_resolve_symbol;
 use runtime::mm;
 
 use utils::ByteSize;
@@ -28,6 +29,7 @@ use utils::mem::memsec;
 
 use std;
 use std::ptr;
+use std::collections::HashMap;
 use std::thread;
 use std::thread::JoinHandle;
 use std::sync::Arc;
@@ -281,6 +283,222 @@ impl MuStack {
 
         debug!("0x{:x} | LOWER_BOUND", self.lower_bound);
     }
+
+    /// is the stack suspended by SWAPSTACK? (otherwise it has not started yet.)
+    /// A new stack has a null frame pointer at its SP, while SWAPSTACK saves the frame pointer
+    /// of the function that suspended the stack there
+    pub fn is_suspended(&self) -> bool {
+        !unsafe { self.sp.load::<Address>() }.is_zero()
+    }
+
+    /// walks the saved frames of a stack that is not running, and returns the slots that hold
+    /// frame pointers, return addresses and (possibly) references.
+    /// The SP of a suspended stack points to a frame pointer and a return address (SWAPSTACK
+    /// pushes them as a call and a prologue would), so we start with SP as a frame pointer.
+    /// A return address is a callsite of the frame that the frame pointer in front of it
+    /// belongs to, and the callsite table gives us its stack map, i.e. the slots of spilled
+    /// references and of callee saved registers (we do not know which registers hold references
+    /// so those slots are checked by the callers). The walk stops at a null frame pointer,
+    /// which is at the bottom of the stack (see MuStack::new()).
+    fn saved_frames(&self, vm: &VM) -> SavedFrames {
+        let callsite_table = vm.compiled_callsite_table().read().unwrap();
+
+        let mut ret = SavedFrames {
+            frame_links: vec![],
+            return_addrs: vec![],
+            ref_slots: vec![],
+            callee_saved_slots: vec![]
+        };
+
+        let mut frame = self.sp;
+        loop {
+            let link = unsafe { frame.load::<Address>() };
+            ret.frame_links.push(frame);
+            ret.return_addrs.push(frame + POINTER_SIZE);
+
+            if link.is_zero() {
+                break;
+            }
+
+            let return_addr = unsafe { (frame + POINTER_SIZE).load::<Address>() };
+            let callsite = match callsite_table.get(&return_addr) {
+                Some(callsite) => callsite,
+                None => {
+                    panic!(
+                        "return address {} of a saved frame of stack {} is not a callsite",
+                        return_addr,
+                        self.hdr.id()
+                    )
+                }
+            };
+            for &offset in callsite.ref_slots.iter() {
+                ret.ref_slots.push(link + offset);
+            }
+            for &offset in callsite.callee_saved_registers.values() {
+                ret.callee_saved_slots.push(link + offset);
+            }
+
+            frame = link;
+        }
+
+        ret
+    }
+
+    /// returns the heap objects that the saved frames of the stack refer to, i.e. the values
+    /// in the reference slots of their stack maps, and the values in callee saved slots that
+    /// point into the heap
+    pub fn heap_refs(&self, vm: &VM) -> Vec<Address> {
+        let frames = self.saved_frames(vm);
+
+        let mut ret = vec![];
+        for slot in frames.ref_slots.iter().chain(frames.callee_saved_slots.iter()) {
+            let val = unsafe { slot.load::<Address>() };
+            // a reference slot may not be initialized yet at this callsite
+            if mm::is_heap_address(val) && !ret.contains(&val) {
+                ret.push(val);
+            }
+        }
+
+        ret
+    }
+
+    /// returns the saved part of a stack that is not running as an image that can be
+    /// persisted in a boot image (see MuStack::from_image()).
+    /// heap_labels are the labels of persisted heap objects.
+    pub fn to_image(
+        &self,
+        vm: &VM,
+        heap_labels: &HashMap<Address, String>
+    ) -> Vec<StackImageWord> {
+        // code ranges of the compiled functions that are loaded
+        let code_ranges: Vec<(Address, Address, String)> = {
+            let compiled_funcs = vm.compiled_funcs().read().unwrap();
+            compiled_funcs
+                .values()
+                .filter_map(|cf| {
+                    let cf = cf.read().unwrap();
+                    let start = try_resolve_symbol(cf.start.to_relocatable());
+                    let end = try_resolve_symbol(cf.end.to_relocatable());
+                    match (start, end) {
+                        (Some(start), Some(end)) => {
+                            Some((start, end, mangle_name(cf.start.to_relocatable())))
+                        }
+                        _ => None
+                    }
+                })
+                .collect()
+        };
+        let frames = self.saved_frames(vm);
+
+        let mut ret = vec![];
+        let mut cursor = self.sp;
+        while cursor < self.upper_bound {
+            let val = unsafe { cursor.load::<Address>() };
+            let in_stack = val >= self.lower_bound && val <= self.upper_bound;
+
+            let word = if frames.frame_links.contains(&cursor) {
+                if val.is_zero() {
+                    StackImageWord::Value(0)
+                } else {
+                    StackImageWord::StackAddress(self.upper_bound - val)
+                }
+            } else if frames.return_addrs.contains(&cursor) {
+                // return addresses, and the entry of a stack that has not started
+                match code_ranges.iter().find(|r| val >= r.0 && val < r.1) {
+                    Some(&(start, _, ref label)) => {
+                        StackImageWord::Symbol(label.clone(), val - start)
+                    }
+                    None => StackImageWord::Value(val.as_usize() as Word)
+                }
+            } else if (frames.ref_slots.contains(&cursor) ||
+                           frames.callee_saved_slots.contains(&cursor)) &&
+                           heap_labels.contains_key(&val)
+            {
+                StackImageWord::Symbol(heap_labels.get(&val).unwrap().clone(), 0)
+            } else if in_stack {
+                // pointers to stack allocated values
+                StackImageWord::StackAddress(self.upper_bound - val)
+            } else {
+                StackImageWord::Value(val.as_usize() as Word)
+            };
+            ret.push(word);
+
+            cursor += POINTER_SIZE;
+        }
+
+        ret
+    }
+
+    /// creates a stack from an image that was persisted in a boot image.
+    /// The image is a word for the number of saved words, a word for the number of addresses
+    /// within the stack, the saved words, and then the indices of the saved words that are
+    /// addresses within the stack (they are saved as the distance from the upper bound, and
+    /// get rebased here).
+    pub unsafe fn from_image(id: MuID, image: Address) -> MuStack {
+        let mut stack = MuStack::new(id, Address::zero(), 0);
+
+        let n_words = image.load::<usize>();
+        let n_stack_addrs = (image + POINTER_SIZE).load::<usize>();
+        let words = image + 2 * POINTER_SIZE;
+        let stack_addrs = words + n_words * POINTER_SIZE;
+
+        // put the saved words at the top of the new stack
+        stack.sp = stack.upper_bound - n_words * POINTER_SIZE;
+        ptr::copy_nonoverlapping(
+            words.to_ptr::<Word>(),
+            stack.sp.to_ptr_mut::<Word>(),
+            n_words
+        );
+
+        for i in 0..n_stack_addrs {
+            let index = (stack_addrs + i * POINTER_SIZE).load::<usize>();
+            let slot = stack.sp + index * POINTER_SIZE;
+            let dist = slot.load::<usize>();
+            slot.store(stack.upper_bound - dist);
+        }
+
+        debug!("restored stack {} from image at {}", id, image);
+        if cfg!(debug_assertions) {
+            stack.print_stack(Some(20));
+        }
+
+        stack
+    }
+}
+
+/// the slots of the saved frames of a stack (see MuStack::saved_frames())
+struct SavedFrames {
+    /// slots of saved frame pointers
+    frame_links: Vec<Address>,
+    /// slots of return addresses
+    return_addrs: Vec<Address>,
+    /// slots of spilled values that may be references
+    ref_slots: Vec<Address>,
+    /// slots of callee saved registers, which may hold references of the callers
+    callee_saved_slots: Vec<Address>
+}
+
+/// the symbol of the primordial stack image in a boot image
+pub const PRIMORDIAL_STACK_SYMBOL: &'static str = "primordial_stack";
+
+/// StackImageWord is a word of a stack that is persisted in a boot image
+pub enum StackImageWord {
+    /// plain data
+    Value(Word),
+    /// an address within the stack (as the distance from its upper bound)
+    StackAddress(ByteSize),
+    /// an offset from a label (code of a compiled function, or a persisted heap object)
+    Symbol(String, ByteSize)
+}
+
+/// ThreadStart is how a new Mu thread starts executing on its stack
+enum ThreadStart {
+    /// calls the entry function of a new stack with the arguments set up on the stack
+    Normal,
+    /// throws the exception to the stack
+    Exceptional(Address),
+    /// resumes a stack that was suspended by SWAPSTACK
+    Swapped
 }
 
 /// MuStackState represents the state for a mu stack
@@ -387,6 +605,9 @@ extern "C" {
     /// new_sp: stack pointer for the mu stack
     /// old_sp_loc: the location to store native stack pointer so we can later swap back
     fn muthread_start_normal(new_sp: Address, old_sp_loc: Address);
+    /// swaps from a native stack to a mu stack that was suspended by SWAPSTACK, and resumes it
+    /// as SWAPSTACK would
+    fn muthread_start_swapped(new_sp: Address, old_sp_loc: Address);
     fn muthread_start_exceptional(exception: Address, new_sp: Address, old_sp_loc: Address);
 
     /// gets base poniter for current frame
@@ -459,8 +680,26 @@ impl MuThread {
     ) {
         // set up arguments on stack
         stack.setup_args(vals);
-        let (join_handle, _) =
-            MuThread::mu_thread_launch(vm.next_id(), stack, threadlocal, None, vm.clone());
+        let (join_handle, _) = MuThread::mu_thread_launch(
+            vm.next_id(),
+            stack,
+            threadlocal,
+            ThreadStart::Normal,
+            vm.clone()
+        );
+        vm.push_join_handle(join_handle);
+    }
+
+    /// creates a new Mu thread that resumes a stack suspended by SWAPSTACK (the stack is
+    /// expected to be waiting for no values, see MuStack::is_suspended())
+    pub fn new_thread_swapped(stack: Box<MuStack>, threadlocal: Address, vm: Arc<VM>) {
+        let (join_handle, _) = MuThread::mu_thread_launch(
+            vm.next_id(),
+            stack,
+            threadlocal,
+            ThreadStart::Swapped,
+            vm.clone()
+        );
         vm.push_join_handle(join_handle);
     }
 
@@ -469,7 +708,7 @@ impl MuThread {
         id: MuID,
         stack: Box<MuStack>,
         user_tls: Address,
-        exception: Option<Address>,
+        start: ThreadStart,
         vm: Arc<VM>
     ) -> (JoinHandle<()>, *mut MuThread) {
         let new_sp = stack.sp;
@@ -498,9 +737,16 @@ impl MuThread {
                     debug!("sp_store: 0x{:x}", sp_threadlocal_loc);
 
                     unsafe {
-                        match exception {
-                            Some(e) => muthread_start_exceptional(e, new_sp, sp_threadlocal_loc),
-                            None => muthread_start_normal(new_sp, sp_threadlocal_loc)
+                        match start {
+                            ThreadStart::Normal => {
+                                muthread_start_normal(new_sp, sp_threadlocal_loc)
+                            }
+                            ThreadStart::Exceptional(e) => {
+                                muthread_start_exceptional(e, new_sp, sp_threadlocal_loc)
+                            }
+                            ThreadStart::Swapped => {
+                                muthread_start_swapped(new_sp, sp_threadlocal_loc)
+                            }
                         }
 
                         // Thread finished, delete it's data
@@ -648,13 +894,17 @@ pub struct PrimordialThreadInfo {
     /// does user supply some contant arguments to start the primordial thread?
     pub has_const_args: bool,
     /// arguments
-    pub args: Vec<Constant>
+    pub args: Vec<Constant>,
+    /// does the primordial thread resume a stack persisted in the boot image
+    /// (PRIMORDIAL_STACK_SYMBOL) instead of starting the entry function?
+    pub from_stack: bool
 }
 
 rodal_struct!(PrimordialThreadInfo {
     func_id,
     args,
-    has_const_args
+    has_const_args,
+    from_stack
 });
 
 #[no_mangle]
@@ -683,7 +933,7 @@ pub unsafe extern "C" fn muentry_new_thread_exceptional(
         vm.next_id(),
         Box::from_raw(stack),
         thread_local,
-        Some(exception),
+        ThreadStart::Exceptional(exception),
         vm.clone()
     );
     vm.push_join_handle(join_handle);
@@ -701,7 +951,7 @@ pub unsafe extern "C" fn muentry_new_thread_normal(
         vm.next_id(),
         Box::from_raw(stack),
         thread_local,
-        None,
+        ThreadStart::Normal,
         vm.clone()
     );
     vm.push_join_handle(join_handle);
@@ -68,6 +68,10 @@ impl MuVM {
         panic!("Not implemented")
     }
 
+    pub fn write_profile(&mut self) {
+        panic!("Not implemented")
+    }
+
 }
 
 impl MuCtx {
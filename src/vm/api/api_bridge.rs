@@ -275,6 +275,11 @@ extern "C" fn _forwarder__MuVM__current_thread_as_mu_thread(mvm: *mut CMuVM, thr
     unsafe { (*_arg_mvm).current_thread_as_mu_thread(_arg_threadlocal) };
 }
 
+extern "C" fn _forwarder__MuVM__write_profile(mvm: *mut CMuVM) {
+    let mut _arg_mvm = from_MuVM_ptr(mvm);
+    unsafe { (*_arg_mvm).write_profile() };
+}
+
 extern "C" fn _forwarder__MuCtx__id_of(ctx: *mut CMuCtx, name: CMuName) -> CMuID {
     let mut _arg_ctx = from_MuCtx_ptr(ctx);
     let mut _arg_name = from_MuName(name);
@@ -2686,7 +2691,8 @@ pub fn make_new_MuVM(header: *mut c_void) -> *mut CMuVM {
         name_of: _forwarder__MuVM__name_of,
         set_trap_handler: _forwarder__MuVM__set_trap_handler,
         compile_to_sharedlib: _forwarder__MuVM__compile_to_sharedlib,
-        current_thread_as_mu_thread: _forwarder__MuVM__current_thread_as_mu_thread
+        current_thread_as_mu_thread: _forwarder__MuVM__current_thread_as_mu_thread,
+        write_profile: _forwarder__MuVM__write_profile
     });
 
     Box::into_raw(bx)
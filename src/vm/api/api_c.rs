@@ -123,7 +123,8 @@ pub struct CMuVM {
     pub name_of: extern "C" fn(*mut CMuVM, CMuID) -> CMuName,
     pub set_trap_handler: extern "C" fn(*mut CMuVM, CMuTrapHandler, CMuCPtr),
     pub compile_to_sharedlib: extern "C" fn(*mut CMuVM, CMuCString, *mut CMuCString, CMuArraySize),
-    pub current_thread_as_mu_thread: extern "C" fn(*mut CMuVM, CMuCPtr)
+    pub current_thread_as_mu_thread: extern "C" fn(*mut CMuVM, CMuCPtr),
+    pub write_profile: extern "C" fn(*mut CMuVM)
 }
 
 #[repr(C)]
@@ -344,7 +344,7 @@ impl MuCtx {
     }
 
     pub fn new_stack(&mut self, func: &APIHandle) -> *const APIHandle {
-        panic!("Not implemented")
+        prepare_handle(self.get_mvm().vm.handle_new_stack(func))
     }
 
     pub fn new_thread_nor(
@@ -2407,6 +2407,7 @@ impl<'lb, 'lvm> BundleLoader<'lb, 'lvm> {
             hdr: hdr,
             content: Some(ctn),
             trace_hint: TraceHint::None,
+            exec_count: None,
             control_flow: Default::default()
         }
     }
@@ -111,8 +111,26 @@ impl MuVM {
             vec![],
             vec![],
             extra_srcs,
-            lib_name
+            lib_name.clone()
         );
+
+        // the library contains new versions of functions that are already compiled:
+        // load it, and redirect calls from the old versions to the new ones.
+        // With --perf-map, we also load it to find out where its functions are
+        let redefining = self.vm.has_pending_redefinitions();
+        if redefining || self.vm.vm_options.flag_perf_map {
+            use std::path::PathBuf;
+            use runtime;
+
+            let mut lib_path = PathBuf::from(&self.vm.vm_options.flag_aot_emit_dir);
+            lib_path.push(&lib_name);
+            runtime::load_library_globally(lib_path.as_path());
+
+            if redefining {
+                self.vm.install_redefined_funcs();
+            }
+            self.vm.record_loaded_code();
+        }
     }
 
     pub fn current_thread_as_mu_thread(&self, threadlocal: CMuCPtr) {
@@ -123,6 +141,11 @@ impl MuVM {
             );
         }
     }
+
+    /// a JIT client does not end in mu_main(), so it writes the profile itself
+    pub fn write_profile(&self) {
+        self.vm.write_profile();
+    }
 }
 
 /**
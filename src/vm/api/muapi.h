@@ -287,6 +287,9 @@ struct MuVM {
 
     void    (*compile_to_sharedlib)(MuVM *mvm, MuCString lib_name, MuCString *extra_srcs, MuArraySize n_extra_srcs); /// MUAPIPARSER extra_srcs:array:n_extra_srcs
     void    (*current_thread_as_mu_thread)(MuVM *mvm, MuCPtr threadlocal);
+
+    // Write the counters of code compiled with --profile-generate to the profile
+    void    (*write_profile)(MuVM *mvm);
 };
 
 // A local context. It can only be used by one thread at a time. It holds many
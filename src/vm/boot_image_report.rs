@@ -0,0 +1,469 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A report on the size and composition of a boot image (see --aot-emit-report).
+//!
+//! Code and data sizes of the functions are read from the object files that the C compiler
+//! assembles from the emitted code (so they are only available for ELF targets). The
+//! persisted heap is dumped again from the same roots as the boot image, and the objects are
+//! typed by following the reference fields from the global cells.
+
+use ast::ir::*;
+use ast::ptr::*;
+use ast::types::*;
+use compiler::backend::elf;
+use compiler::backend::elf::ObjectSizes;
+use linkutils::aot::LinkTimes;
+use runtime::mm;
+use runtime::mm::common::objectdump::HeapDump;
+use runtime::thread::MuStack;
+use utils::Address;
+use utils::ByteSize;
+use utils::LinkedHashMap;
+use vm::VM;
+
+use serde_json;
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// the report is written to <output>.report.json under the emit directory
+pub const REPORT_SUFFIX: &'static str = ".report.json";
+
+/// how many of the largest heap objects are listed
+const N_LARGEST_OBJECTS: usize = 10;
+
+/// time spent in each phase of making a boot image
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PhaseTimes {
+    /// compiling the functions (including emitting their code)
+    pub compilation: Duration,
+    /// emitting the IR, and persisting the VM and the heap
+    pub emission: Duration,
+    /// assembling and linking
+    pub link: LinkTimes
+}
+
+/// writes the report for a boot image that has been made from the given functions
+pub fn emit_report(
+    vm: &VM,
+    funcs: &Vec<MuID>,
+    primordial_stack: Option<Address>,
+    n_client_symbols: usize,
+    n_client_fields: usize,
+    stripped_funcs: &Vec<MuName>,
+    times: &PhaseTimes,
+    output_file: &str
+) {
+    let (functions, code_sizes) = report_functions(vm, funcs);
+    let (globals, global_cells) = report_globals(vm);
+    let (heap, heap_refs) = report_heap(vm, primordial_stack, &global_cells);
+
+    let report = Report {
+        output: output_file.to_string(),
+        target: vm.target_arch().name(),
+        phases: Phases {
+            compilation: secs(times.compilation),
+            emission: secs(times.emission),
+            assembly: secs(times.link.assembly),
+            linking: secs(times.link.linking)
+        },
+        code: Code {
+            bytes: code_sizes.map(|s| s.code),
+            unwind_bytes: code_sizes.map(|s| s.unwind),
+            functions: functions,
+            stripped_functions: stripped_funcs.iter().map(|name| (**name).clone()).collect()
+        },
+        constants: Constants {
+            bytes: code_sizes.map(|s| s.data)
+        },
+        globals: globals,
+        heap: heap,
+        relocations: Relocations {
+            code: code_sizes.map(|s| s.relocations),
+            heap_references: heap_refs,
+            client_symbols: n_client_symbols,
+            client_fields: n_client_fields
+        }
+    };
+
+    let mut path = Path::new(&vm.vm_options.flag_aot_emit_dir).to_path_buf();
+    path.push(output_file.to_string() + REPORT_SUFFIX);
+    let mut file = match File::create(path.as_path()) {
+        Err(why) => {
+            panic!(
+                "couldn't create boot image report {}: {}",
+                path.to_str().unwrap(),
+                why
+            )
+        }
+        Ok(file) => file
+    };
+
+    serde_json::to_writer_pretty(&mut file, &report).unwrap();
+    file.write_all(b"\n").unwrap();
+
+    info!("boot image report written to {:?}", path);
+}
+
+/// reports the size of the compiled versions of the functions, and returns the sum of them
+/// (None if the size of any function is unknown)
+fn report_functions(vm: &VM, funcs: &Vec<MuID>) -> (Vec<Function>, Option<ObjectSizes>) {
+    let emit_dir = Path::new(&vm.vm_options.flag_aot_emit_dir);
+    let funcs_guard = vm.funcs().read().unwrap();
+    let compiled_funcs = vm.compiled_funcs().read().unwrap();
+
+    let mut total = Some(ObjectSizes::default());
+    let mut entries = vec![];
+    for id in funcs.iter() {
+        let func = funcs_guard.get(id).unwrap().read().unwrap();
+        for fv in func.all_vers.iter().chain(func.cur_ver.iter()) {
+            let cf = match compiled_funcs.get(fv) {
+                Some(cf) => cf.read().unwrap(),
+                None => continue
+            };
+            let symbol = cf.start.to_relocatable();
+
+            // the code (and constants) are assembled into <symbol>.o, constants go to their
+            // own object with --aot-emit-elf
+            let mut sizes = elf::read_object_sizes(&emit_dir.join((*symbol).clone() + ".o"));
+            if vm.vm_options.flag_aot_emit_elf {
+                let rodata = emit_dir.join((*symbol).clone() + elf::ELF_RODATA_SUFFIX);
+                sizes = match (sizes, elf::read_object_sizes(&rodata)) {
+                    (Some(code), Some(rodata)) => Some(add_sizes(code, rodata)),
+                    _ => None
+                };
+            }
+            total = match (total, sizes) {
+                (Some(total), Some(sizes)) => Some(add_sizes(total, sizes)),
+                _ => None
+            };
+
+            entries.push(Function {
+                name: (*func.name()).clone(),
+                func_ver: *fv,
+                symbol: (*symbol).clone(),
+                code_bytes: sizes.map(|s| s.code),
+                constant_bytes: sizes.map(|s| s.data),
+                relocations: sizes.map(|s| s.relocations)
+            });
+        }
+    }
+
+    (entries, total)
+}
+
+fn add_sizes(a: ObjectSizes, b: ObjectSizes) -> ObjectSizes {
+    ObjectSizes {
+        code: a.code + b.code,
+        data: a.data + b.data,
+        unwind: a.unwind + b.unwind,
+        relocations: a.relocations + b.relocations
+    }
+}
+
+/// reports the global cells (in the order of their IDs), and returns their addresses and types
+fn report_globals(vm: &VM) -> (Globals, LinkedHashMap<Address, P<MuType>>) {
+    let globals = vm.globals().read().unwrap();
+    let global_locs = vm.global_locations().read().unwrap();
+
+    let mut ids: Vec<MuID> = global_locs.keys().cloned().collect();
+    ids.sort();
+
+    let mut cells = LinkedHashMap::new();
+    let mut bytes = 0;
+    let mut entries = vec![];
+    for id in ids {
+        let global = globals.get(&id).unwrap();
+        let ty = global.ty.get_referent_ty().unwrap();
+        let size = vm.get_backend_type_size(ty.id());
+
+        cells.insert(global_locs.get(&id).unwrap().to_address(), ty.clone());
+        bytes += size;
+        entries.push(Global {
+            name: (*global.name()).clone(),
+            ty: ty.to_string(),
+            bytes: size
+        });
+    }
+
+    let report = Globals {
+        count: entries.len(),
+        bytes: bytes,
+        cells: entries
+    };
+    (report, cells)
+}
+
+/// reports the persisted heap (other than the global cells) by Mu type, and returns the number
+/// of references in the heap (each of which is relocated)
+fn report_heap(
+    vm: &VM,
+    primordial_stack: Option<Address>,
+    global_cells: &LinkedHashMap<Address, P<MuType>>
+) -> (Heap, usize) {
+    // the same roots as the heap dump of the boot image
+    let mut roots: Vec<Address> = global_cells.keys().cloned().collect();
+    if let Some(stack) = primordial_stack {
+        let stack = unsafe { stack.to_ptr::<MuStack>().as_ref().unwrap() };
+        roots.extend(stack.heap_refs(vm));
+    }
+    let dump = mm::persist_heap(roots);
+    let types = type_objects(vm, &dump, global_cells);
+
+    // (objects, bytes, largest) of each type, in the order that the types are first reached
+    let mut by_type: LinkedHashMap<String, (usize, ByteSize, ByteSize)> = LinkedHashMap::new();
+    // (label, type, bytes) of each object
+    let mut objects: Vec<(&String, String, ByteSize)> = vec![];
+    let mut n_refs = 0;
+    for (addr, obj) in dump.objects.iter() {
+        n_refs += obj
+            .reference_offsets
+            .iter()
+            .filter(|offset| unsafe { !(obj.addr + **offset).load::<Address>().is_zero() })
+            .count();
+        if global_cells.contains_key(addr) {
+            continue;
+        }
+
+        let ty = match types.get(addr) {
+            Some(ty) => ty.to_string(),
+            None => "<unknown>".to_string()
+        };
+        if !by_type.contains_key(&ty) {
+            by_type.insert(ty.clone(), (0, 0, 0));
+        }
+        {
+            let entry = by_type.get_mut(&ty).unwrap();
+            entry.0 += 1;
+            entry.1 += obj.size;
+            entry.2 = cmp::max(entry.2, obj.size);
+        }
+
+        objects.push((dump.relocatable_refs.get(addr).unwrap(), ty, obj.size));
+    }
+
+    // stable sort, so objects of the same size stay in the order of the dump
+    objects.sort_by(|a, b| b.2.cmp(&a.2));
+    let largest: Vec<HeapObject> = objects
+        .iter()
+        .take(N_LARGEST_OBJECTS)
+        .map(|&(label, ref ty, size)| {
+            HeapObject {
+                label: label.clone(),
+                ty: ty.clone(),
+                bytes: size
+            }
+        })
+        .collect();
+    let types: Vec<HeapType> = by_type
+        .iter()
+        .map(|(ty, &(count, bytes, max))| {
+            HeapType {
+                ty: ty.clone(),
+                objects: count,
+                bytes: bytes,
+                largest_bytes: max
+            }
+        })
+        .collect();
+
+    let report = Heap {
+        objects: objects.len(),
+        bytes: objects.iter().map(|o| o.2).sum::<ByteSize>(),
+        types: types,
+        largest_objects: largest
+    };
+    (report, n_refs)
+}
+
+/// finds out the Mu types of the dumped objects by following the reference fields from the
+/// global cells. Objects that are only reached through untyped references (e.g. from the
+/// primordial stack, or through ref<void>) are not typed
+fn type_objects(
+    vm: &VM,
+    dump: &HeapDump,
+    global_cells: &LinkedHashMap<Address, P<MuType>>
+) -> HashMap<Address, P<MuType>> {
+    let mut types: HashMap<Address, P<MuType>> = HashMap::new();
+    let mut work_list: Vec<(Address, P<MuType>)> = global_cells
+        .iter()
+        .map(|(a, ty)| (*a, ty.clone()))
+        .collect();
+
+    while let Some((addr, ty)) = work_list.pop() {
+        if types.contains_key(&addr) || !dump.objects.contains_key(&addr) {
+            continue;
+        }
+        let size = dump.objects.get(&addr).unwrap().size;
+
+        let mut fields = vec![];
+        if ty.is_hybrid() {
+            // the fixed part is laid out as a struct, followed by the var part elements
+            let info = vm.get_backend_type_info(ty.id());
+            let var_ty = ty.get_hybrid_varpart_ty().unwrap();
+            for (i, offset) in info.struct_layout.as_ref().unwrap().iter().enumerate() {
+                append_ref_fields(&mut fields, *offset, &ty.get_field_ty(i).unwrap(), vm);
+            }
+            let elem_size = info.elem_size.unwrap();
+            let mut offset = info.size;
+            while elem_size != 0 && offset + elem_size <= size {
+                append_ref_fields(&mut fields, offset, &var_ty, vm);
+                offset += elem_size;
+            }
+        } else {
+            append_ref_fields(&mut fields, 0, &ty, vm);
+        }
+
+        for (offset, referent) in fields {
+            let edge = unsafe { (addr + offset).load::<Address>() };
+            if !edge.is_zero() && !referent.is_void() {
+                work_list.push((edge, referent));
+            }
+        }
+        types.insert(addr, ty);
+    }
+
+    types
+}
+
+/// appends the reference fields (offset, referent type) in a value of the given type
+fn append_ref_fields(
+    fields: &mut Vec<(ByteSize, P<MuType>)>,
+    base: ByteSize,
+    ty: &P<MuType>,
+    vm: &VM
+) {
+    match ty.v {
+        MuType_::Ref(ref referent) | MuType_::WeakRef(ref referent) => {
+            fields.push((base, referent.clone()))
+        }
+        MuType_::Struct(_) => {
+            let info = vm.get_backend_type_info(ty.id());
+            for (i, offset) in info.struct_layout.as_ref().unwrap().iter().enumerate() {
+                append_ref_fields(fields, base + *offset, &ty.get_field_ty(i).unwrap(), vm);
+            }
+        }
+        MuType_::Array(ref elem_ty, len) => {
+            let elem_size = vm.get_backend_type_info(ty.id()).elem_size.unwrap();
+            for i in 0..len {
+                append_ref_fields(fields, base + i * elem_size, elem_ty, vm);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// durations are reported in seconds
+fn secs(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+// the report, serialized in the order of the fields (sizes that cannot be read are null)
+
+#[derive(Serialize)]
+struct Report {
+    output: String,
+    target: &'static str,
+    phases: Phases,
+    code: Code,
+    constants: Constants,
+    globals: Globals,
+    heap: Heap,
+    relocations: Relocations
+}
+
+#[derive(Serialize)]
+struct Phases {
+    compilation: f64,
+    emission: f64,
+    assembly: f64,
+    linking: f64
+}
+
+#[derive(Serialize)]
+struct Code {
+    bytes: Option<ByteSize>,
+    unwind_bytes: Option<ByteSize>,
+    functions: Vec<Function>,
+    /// the names of the functions dropped by --aot-strip-unreachable
+    stripped_functions: Vec<String>
+}
+
+#[derive(Serialize)]
+struct Function {
+    name: String,
+    func_ver: MuID,
+    symbol: String,
+    code_bytes: Option<ByteSize>,
+    constant_bytes: Option<ByteSize>,
+    relocations: Option<usize>
+}
+
+#[derive(Serialize)]
+struct Constants {
+    bytes: Option<ByteSize>
+}
+
+#[derive(Serialize)]
+struct Globals {
+    count: usize,
+    bytes: ByteSize,
+    cells: Vec<Global>
+}
+
+#[derive(Serialize)]
+struct Global {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+    bytes: ByteSize
+}
+
+#[derive(Serialize)]
+struct Heap {
+    objects: usize,
+    bytes: ByteSize,
+    types: Vec<HeapType>,
+    largest_objects: Vec<HeapObject>
+}
+
+#[derive(Serialize)]
+struct HeapType {
+    #[serde(rename = "type")]
+    ty: String,
+    objects: usize,
+    bytes: ByteSize,
+    largest_bytes: ByteSize
+}
+
+#[derive(Serialize)]
+struct HeapObject {
+    label: String,
+    #[serde(rename = "type")]
+    ty: String,
+    bytes: ByteSize
+}
+
+#[derive(Serialize)]
+struct Relocations {
+    code: Option<usize>,
+    heap_references: usize,
+    client_symbols: usize,
+    client_fields: usize
+}
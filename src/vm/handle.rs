@@ -81,8 +81,8 @@ pub enum APIHandleValue {
     FuncRef(MuID),
     /// Mu thread reference
     ThreadRef,
-    /// Mu stack reference
-    StackRef,
+    /// Mu stack reference (address of the MuStack)
+    StackRef(Address),
     /// frame cursor reference
     FCRef,
 
@@ -147,7 +147,7 @@ impl fmt::Debug for APIHandleValue {
             &TagRef64(val) => write!(f, "tagref64 0x{:x}", val),
             &FuncRef(id) => write!(f, "funcref to #{}", id),
             &ThreadRef => write!(f, "threadref"),
-            &StackRef => write!(f, "stackref"),
+            &StackRef(addr) => write!(f, "stackref to {}", addr),
             &FCRef => write!(f, "framecursorref"),
             &Bundle => write!(f, "IR.bundle"),
             &Type(id) => write!(f, "IR.type to #{}", id),
@@ -248,6 +248,14 @@ impl APIHandleValue {
         }
     }
 
+    /// matches the handle as stack reference
+    pub fn as_stack(&self) -> Address {
+        match self {
+            &APIHandleValue::StackRef(addr) => addr,
+            _ => panic!("expected StackRef")
+        }
+    }
+
     /// matches the handle as function reference
     pub fn as_funcref(&self) -> MuID {
         match self {
@@ -19,8 +19,12 @@ mod vm;
 /// and other runtime table (exception table etc)
 pub use vm::vm::VM;
 pub mod uir_output;
+/// boot_image_report writes the size and composition of a boot image
+mod boot_image_report;
 /// vm_options defines commandline flags to create a new Zebu instance
 mod vm_options;
+/// profile records and reads execution counts for profile-guided optimisation
+pub mod profile;
 
 /// built info from cargo
 pub mod built_info;
@@ -0,0 +1,233 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Execution profiles for profile-guided optimisation.
+//!
+//! With --profile-generate=<file>, the ProfileInstrumentation pass gives every function version
+//! it compiles a global array with a counter for each block, and the counts are written to the
+//! file when a boot image ends (see write_profile()), or when a JIT client calls write_profile
+//! through the API. A call site runs as many times as its block, so the count of a call site is
+//! the count of its block.
+//!
+//! A later compilation with --profile-use=<file> reads the profile. ProfileInstrumentation keeps
+//! the count of each block in the block (Block::exec_count) before any pass renames blocks, and
+//! the passes that copy or create blocks estimate the counts of the new ones. TraceGen lays out
+//! blocks by their counts and moves blocks that never ran to the slow path, and Inlining does not
+//! inline call sites that never ran, but inlines larger callees at hot call sites. Counts are
+//! recorded by the names of functions, blocks and callees, so a profile can be used by a VM that
+//! loads the same bundles again:
+//!
+//! ```text
+//! func <function>
+//! block <block> <count>
+//! call <block> <callee> <count>
+//! ```
+
+use ast::ir::*;
+use ast::ptr::*;
+use runtime::resolve_symbol;
+use vm::VM;
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// the counters of an instrumented function version
+pub struct ProfileCounters {
+    /// the function that the version belongs to
+    pub func: MuName,
+    /// the global array with a counter for each block
+    pub global: P<Value>,
+    /// the names of the blocks, in the order of their counters
+    pub blocks: Vec<MuName>,
+    /// the call sites with a constant callee
+    pub callsites: Vec<ProfiledCallsite>
+}
+
+rodal_struct!(ProfileCounters {
+    func,
+    global,
+    blocks,
+    callsites
+});
+
+pub struct ProfiledCallsite {
+    /// the index of the block that the call site is in
+    pub block: usize,
+    pub callee: MuName
+}
+
+rodal_struct!(ProfiledCallsite { block, callee });
+
+/// the execution counts of a function
+#[derive(Debug, Default)]
+pub struct FuncProfile {
+    blocks: BTreeMap<String, u64>,
+    /// counts of call sites, by their block and callee
+    calls: BTreeMap<(String, String), u64>
+}
+
+impl FuncProfile {
+    /// returns how many times a block ran, None if it is not in the profile
+    pub fn block_count(&self, block: &str) -> Option<u64> {
+        self.blocks.get(block).cloned()
+    }
+
+    /// returns how many times a block called a function, None if it is not in the profile
+    pub fn call_count(&self, block: &str, callee: &str) -> Option<u64> {
+        self.calls
+            .get(&(block.to_string(), callee.to_string()))
+            .cloned()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Profile {
+    funcs: BTreeMap<String, FuncProfile>
+}
+
+impl Profile {
+    /// returns the counts of a function, None if it is not in the profile
+    pub fn get_func(&self, func: &str) -> Option<&FuncProfile> {
+        self.funcs.get(func)
+    }
+
+    fn func_mut(&mut self, func: &str) -> &mut FuncProfile {
+        self.funcs
+            .entry(func.to_string())
+            .or_insert_with(FuncProfile::default)
+    }
+
+    /// reads a profile file
+    pub fn read(path: &str) -> Profile {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => panic!("failed to open profile {}: {}", path, e)
+        };
+
+        let mut ret = Profile::default();
+        let mut cur_func: Option<String> = None;
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => panic!("failed to read profile {}: {}", path, e)
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let count = |word: &str| match word.parse::<u64>() {
+                Ok(count) => count,
+                Err(_) => panic!("{}:{}: expected a count, found {}", path, i + 1, word)
+            };
+            match (words.first().cloned(), cur_func.clone()) {
+                (None, _) => {}
+                (Some("func"), _) if words.len() == 2 => {
+                    cur_func = Some(words[1].to_string());
+                }
+                (Some("block"), Some(ref func)) if words.len() == 3 => {
+                    let count = count(words[2]);
+                    *ret.func_mut(func)
+                        .blocks
+                        .entry(words[1].to_string())
+                        .or_insert(0) += count;
+                }
+                (Some("call"), Some(ref func)) if words.len() == 4 => {
+                    let count = count(words[3]);
+                    *ret.func_mut(func)
+                        .calls
+                        .entry((words[1].to_string(), words[2].to_string()))
+                        .or_insert(0) += count;
+                }
+                _ => panic!("{}:{}: unexpected line in profile: {}", path, i + 1, line)
+            }
+        }
+
+        ret
+    }
+
+    /// writes the profile to a file
+    pub fn write(&self, path: &str) {
+        let mut file = match File::create(path) {
+            Ok(file) => file,
+            Err(e) => panic!("failed to create profile {}: {}", path, e)
+        };
+
+        for (func, func_profile) in self.funcs.iter() {
+            writeln!(file, "func {}", func).unwrap();
+            for (block, count) in func_profile.blocks.iter() {
+                writeln!(file, "block {} {}", block, count).unwrap();
+            }
+            for (&(ref block, ref callee), count) in func_profile.calls.iter() {
+                writeln!(file, "call {} {} {}", block, callee, count).unwrap();
+            }
+        }
+    }
+}
+
+lazy_static! {
+    /// profiles that have been read (by their path)
+    static ref PROFILES: Mutex<HashMap<String, Arc<Profile>>> = Mutex::new(HashMap::new());
+}
+
+/// returns the profile given by --profile-use, None if it is not set.
+/// Each profile file is read once in a process
+pub fn get_profile(vm: &VM) -> Option<Arc<Profile>> {
+    let path = &vm.vm_options.flag_profile_use;
+    if path.is_empty() {
+        return None;
+    }
+
+    let mut profiles = PROFILES.lock().unwrap();
+    if !profiles.contains_key(path) {
+        info!("read profile {}", path);
+        profiles.insert(path.clone(), Arc::new(Profile::read(path)));
+    }
+    profiles.get(path).cloned()
+}
+
+/// writes the counts of all instrumented function versions to the --profile-generate file
+pub fn write_profile(vm: &VM) {
+    let path = &vm.vm_options.flag_profile_generate;
+    let counters = vm.profile_counters().read().unwrap();
+    let global_locs = vm.global_locations().read().unwrap();
+
+    let mut profile = Profile::default();
+    for fv_counters in counters.values() {
+        let start = match global_locs.get(&fv_counters.global.id()) {
+            Some(loc) => loc.to_address(),
+            // a boot image refers to its globals by their symbols
+            None => resolve_symbol(fv_counters.global.name())
+        };
+        let counts: Vec<u64> = (0..fv_counters.blocks.len())
+            .map(|i| unsafe { start.shift::<u64>(i as isize).load::<u64>() })
+            .collect();
+
+        let func = profile.func_mut(&fv_counters.func);
+        for (block, count) in fv_counters.blocks.iter().zip(counts.iter()) {
+            *func.blocks.entry((**block).clone()).or_insert(0) += *count;
+        }
+        for callsite in fv_counters.callsites.iter() {
+            let block = (*fv_counters.blocks[callsite.block]).clone();
+            *func.calls
+                .entry((block, (*callsite.callee).clone()))
+                .or_insert(0) += counts[callsite.block];
+        }
+    }
+
+    profile.write(path);
+    info!("wrote profile of {} function versions to {}", counters.len(), path);
+}
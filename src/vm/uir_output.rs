@@ -1,4 +1,5 @@
 use ast::ir::*;
+use ast::ptr::*;
 use vm::VM;
 use std::path;
 use std::io::prelude::*;
@@ -20,15 +21,15 @@ fn emit_mu_types(suffix: &str, vm: &VM) {
     let mut file_path = path::PathBuf::new();
     file_path.push(&vm.vm_options.flag_aot_emit_dir);
     file_path.push("___types".to_string() + suffix + ".uir");
-    let mut file = match File::create(file_path.as_path()) {
+    let mut file = match File::create(file_path.as_path()) {
         Err(why) => {
             panic!(
                 "couldn't create mu types file {}: {}",
                 file_path.to_str().unwrap(),
                 why
             )
-        }
-        Ok(file) => file
+        }
+        Ok(file) => file
     };
 
     {
@@ -38,7 +39,12 @@ fn emit_mu_types(suffix: &str, vm: &VM) {
         let struct_map = STRUCT_TAG_MAP.read().unwrap();
         let hybrid_map = HYBRID_TAG_MAP.read().unwrap();
 
-        for ty in ty_guard.values() {
+        // in the order of IDs (this also resolves the backend types, and their GC types,
+        // in a deterministic order)
+        let mut tys: Vec<&P<MuType>> = ty_guard.values().collect();
+        tys.sort_by_key(|ty| ty.id());
+
+        for ty in tys {
             if ty.is_struct() {
                 write!(file, ".typedef {} = ", ty.hdr).unwrap();
 
@@ -66,20 +72,23 @@ fn emit_mu_globals(suffix: &str, vm: &VM) {
     let mut file_path = path::PathBuf::new();
     file_path.push(&vm.vm_options.flag_aot_emit_dir);
     file_path.push("___globals".to_string() + suffix + ".uir");
-    let mut file = match File::create(file_path.as_path()) {
+    let mut file = match File::create(file_path.as_path()) {
         Err(why) => {
             panic!(
                 "couldn't create mu globals file {}: {}",
                 file_path.to_str().unwrap(),
                 why
             )
-        }
-        Ok(file) => file
+        }
+        Ok(file) => file
     };
 
     let global_guard = vm.globals().read().unwrap();
 
-    for g in global_guard.values() {
+    let mut globals: Vec<&P<Value>> = global_guard.values().collect();
+    globals.sort_by_key(|g| g.id());
+
+    for g in globals {
         writeln!(
             file,
             ".global {}<{}>",
@@ -94,29 +103,32 @@ fn emit_mu_funcdecls(suffix: &str, vm: &VM) {
     let mut file_path = path::PathBuf::new();
     file_path.push(&vm.vm_options.flag_aot_emit_dir);
     file_path.push("___funcdecls".to_string() + suffix + ".uir");
-    let mut file = match File::create(file_path.as_path()) {
+    let mut file = match File::create(file_path.as_path()) {
         Err(why) => {
             panic!(
                 "couldn't create mu funcdecls file {}: {}",
                 file_path.to_str().unwrap(),
                 why
             )
-        }
-        Ok(file) => file
+        }
+        Ok(file) => file
     };
 
     let funcs_guard = vm.funcs().read().unwrap();
 
-    for f in funcs_guard.values() {
-        let f_lock = f.read().unwrap();
+    let mut func_ids: Vec<MuID> = funcs_guard.keys().cloned().collect();
+    func_ids.sort();
+
+    for id in func_ids {
+        let f_lock = funcs_guard.get(&id).unwrap().read().unwrap();
         writeln!(file, ".funcdecl {}<{}>", f_lock.name(), f_lock.sig).unwrap();
     }
 }
 
 pub fn create_emit_directory(vm: &VM) {
     use std::fs;
-    match fs::create_dir(&vm.vm_options.flag_aot_emit_dir) {
-        Ok(_) => {}
-        Err(_) => {}
+    match fs::create_dir(&vm.vm_options.flag_aot_emit_dir) {
+        Ok(_) => {}
+        Err(_) => {}
     }
 }
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
 
 use rodal;
 use ast::ptr::*;
@@ -20,21 +21,28 @@ use ast::ir::*;
 use ast::inst::*;
 use ast::types;
 use ast::types::*;
-use compiler::{Compiler, CompilerPolicy};
+use compiler;
 use compiler::backend;
 use compiler::backend::BackendType;
+use compiler::backend::TargetArch;
 use compiler::machine_code::{CompiledFunction, CompiledCallsite};
+use linkutils::aot::LinkTimes;
 
 use runtime::thread::*;
 use runtime::*;
 use utils::ByteSize;
 use utils::BitSize;
 use utils::Address;
+use utils::LinkedHashSet;
 use runtime::mm as gc;
 use self::gc::*;
 use vm::handle::*;
+use vm::boot_image_report;
+use vm::boot_image_report::PhaseTimes;
 use vm::vm_options::VMOptions;
 use vm::vm_options::MuLogLevel;
+use vm::profile;
+use vm::profile::ProfileCounters;
 
 use log::LogLevel;
 use std::sync::Arc;
@@ -44,9 +52,26 @@ use std::sync::RwLockWriteGuard;
 use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
 use std::thread::JoinHandle;
 use std::collections::LinkedList;
+use std::cell::Cell;
+use std::time::Instant;
+use std::hash::Hash;
+use std::mem;
 use std;
 use utils::bit_utils::{bits_ones, u64_asr};
 
+/// IDs generated while compiling a function version are taken from a range of its own,
+/// so the names generated from them do not depend on the order (or the threads) that functions
+/// are compiled in, or on which functions are restored from the code cache
+const COMPILER_ID_START: MuID = 1 << 40;
+/// the number of IDs in the range of a function version
+const COMPILER_IDS_PER_FUNC_VER: MuID = 1 << 20;
+
+thread_local! {
+    /// the next ID and the end of the ID range for the function version that
+    /// this thread is compiling
+    static COMPILER_IDS: Cell<Option<(MuID, MuID)>> = Cell::new(None);
+}
+
 /// The VM struct. This stores metadata for the currently running Zebu instance.
 /// This struct gets persisted in the boot image, and when the boot image is loaded,
 /// everything should be back to the same status as before persisting.
@@ -128,7 +153,17 @@ pub struct VM {
     callsite_count: AtomicUsize,
 
     /// A list of all threads currently waiting to be joined
-    pub pending_joins: Mutex<LinkedList<JoinHandle<()>>>
+    pub pending_joins: Mutex<LinkedList<JoinHandle<()>>>,
+
+    /// compiled function versions whose entry has been patched to jump to a newer version
+    /// of the same function (a map from the old version to the version it jumps to).
+    /// This map does not get persisted, as the patches need to be re-applied to the loaded
+    /// code of a boot image (see install_redefined_funcs())
+    patched_func_vers: RwLock<HashMap<MuID, MuID>>,
+
+    /// counters of the function versions compiled with --profile-generate (by the ID of the
+    /// function version). This map is persisted, so a boot image can write its profile
+    profile_counters: RwLock<HashMap<MuID, ProfileCounters>>
 }
 
 rodal_named!(VM);
@@ -179,9 +214,37 @@ unsafe impl rodal::Dump for VM {
         dumper.dump_padding(&self.pending_joins);
         let pending_joins = Mutex::new(rodal::EmptyLinkedList::<JoinHandle<()>>::new());
         dumper.dump_object_here(&pending_joins);
+
+        dumper.dump_padding(&self.patched_func_vers);
+        let patched_func_vers = RwLock::new(rodal::EmptyHashMap::<MuID, MuID>::new());
+        dumper.dump_object_here(&patched_func_vers);
+
+        dumper.dump_object(&self.profile_counters);
     }
 }
 
+/// rebuilds a hash map with fixed hash keys, inserting its entries in the given order, so that
+/// the layout of its table (which rodal dumps as it is) only depends on its contents
+fn rebuild_map_in_order<K, V, O, F>(map: &mut HashMap<K, V>, order: F)
+where
+    K: Hash + Eq,
+    O: Ord,
+    F: Fn(&K, &V) -> O
+{
+    let mut entries: Vec<(K, V)> = map.drain().collect();
+    entries.sort_by_key(|entry| order(&entry.0, &entry.1));
+
+    // std does not let us choose the keys of a RandomState, but it is just the two keys
+    assert!(mem::size_of::<RandomState>() == 2 * mem::size_of::<u64>());
+    let fixed_state = unsafe { mem::transmute::<[u64; 2], RandomState>([0, 0]) };
+
+    let mut rebuilt = HashMap::with_capacity_and_hasher(entries.len(), fixed_state);
+    for (k, v) in entries {
+        rebuilt.insert(k, v);
+    }
+    *map = rebuilt;
+}
+
 /// a fake funcref to store for AOT when client tries to store a funcref via API
 //  For AOT scenario, when client tries to store funcref to the heap, the store
 //  happens before we have an actual address for the function so we store a fake
@@ -252,7 +315,9 @@ impl<'a> VM {
             compiled_callsite_table: RwLock::new(HashMap::new()),
             primordial_threadlocal: RwLock::new(None),
             callsite_count: ATOMIC_USIZE_INIT,
-            pending_joins: Mutex::new(LinkedList::new())
+            pending_joins: Mutex::new(LinkedList::new()),
+            patched_func_vers: RwLock::new(HashMap::new()),
+            profile_counters: RwLock::new(HashMap::new())
         };
 
         // insert all internal types
@@ -299,7 +364,9 @@ impl<'a> VM {
             primordial: RwLock::new(None),
             aot_pending_funcref_store: RwLock::new(HashMap::new()),
             compiled_callsite_table: RwLock::new(HashMap::new()),
-            callsite_count: ATOMIC_USIZE_INIT
+            callsite_count: ATOMIC_USIZE_INIT,
+            patched_func_vers: RwLock::new(HashMap::new()),
+            profile_counters: RwLock::new(HashMap::new())
         };
 
         // currently, the default sizes don't work on sel4-rumprun platform
@@ -440,9 +507,26 @@ impl<'a> VM {
 
         // construct exception table
         vm.build_callsite_table();
+
+        // redirect obsolete versions of redefined functions to their current version
+        vm.install_redefined_funcs();
+
+        vm.record_loaded_code();
         vm
     }
 
+    /// tells perf about compiled functions whose code is now loaded (with --perf-map)
+    #[cfg(target_os = "linux")]
+    pub fn record_loaded_code(&self) {
+        if self.vm_options.flag_perf_map {
+            perf::record_compiled_funcs(self);
+        }
+    }
+
+    /// tells perf about compiled functions whose code is now loaded (with --perf-map)
+    #[cfg(not(target_os = "linux"))]
+    pub fn record_loaded_code(&self) {}
+
     /// builds a succinct exception table for fast query during exception unwinding
     /// We need this step because for AOT compilation, we do not know symbol address at compile,
     /// and resolving symbol address during exception handling is expensive. Thus when boot image
@@ -456,33 +540,163 @@ impl<'a> VM {
         compiled_callsite_table.reserve(self.callsite_count.load(Ordering::Relaxed));
         for (fv, callsite_list) in callsite_table.iter() {
             let compiled_func = compiled_funcs.get(fv).unwrap().read().unwrap();
-            let callee_saved_table = Arc::new(compiled_func.frame.callee_saved.clone());
-            for callsite in callsite_list.iter() {
-                compiled_callsite_table.insert(
-                    resolve_symbol(callsite.name.clone()),
-                    CompiledCallsite::new(
-                        &callsite,
-                        compiled_func.func_ver_id,
-                        callee_saved_table.clone()
-                    )
+            VM::insert_compiled_callsites(
+                &compiled_func,
+                callsite_list,
+                &mut compiled_callsite_table
+            );
+        }
+    }
+
+    /// resolves the callsites of a compiled function, and adds them to the compiled callsite table
+    fn insert_compiled_callsites(
+        compiled_func: &CompiledFunction,
+        callsite_list: &Vec<Callsite>,
+        compiled_callsite_table: &mut HashMap<Address, CompiledCallsite>
+    ) {
+        let callee_saved_table = Arc::new(compiled_func.frame.callee_saved.clone());
+        let ref_slots = Arc::new(compiled_func.frame.ref_slots.clone());
+        for callsite in callsite_list.iter() {
+            compiled_callsite_table.insert(
+                resolve_symbol(callsite.name.clone()),
+                CompiledCallsite::new(
+                    &callsite,
+                    compiled_func.func_ver_id,
+                    callee_saved_table.clone(),
+                    ref_slots.clone()
+                )
+            );
+        }
+    }
+
+    /// redirects calls to redefined functions to their current version.
+    /// For every function whose current version is compiled, we patch the entry of its
+    /// older compiled versions to jump to the current version. Existing frames keep running
+    /// the old code, and the old CompiledFunction (with its callsites) stays in the VM so that
+    /// those frames can still be unwound. Every entry is patched with a single store, so other
+    /// threads may be running (and calling) the functions meanwhile. The code of all the
+    /// versions needs to be loaded before calling this function.
+    pub fn install_redefined_funcs(&self) {
+        let funcs = self.funcs.read().unwrap();
+        let callsite_table = self.callsite_table.read().unwrap();
+        let compiled_funcs = self.compiled_funcs.read().unwrap();
+        let mut compiled_callsite_table = self.compiled_callsite_table.write().unwrap();
+        let mut patched = self.patched_func_vers.write().unwrap();
+
+        for func in funcs.values() {
+            let func = func.read().unwrap();
+            let cur_ver = match func.cur_ver {
+                Some(v) => v,
+                None => continue
+            };
+            let cur_cf = match compiled_funcs.get(&cur_ver) {
+                Some(cf) => cf.read().unwrap(),
+                None => continue
+            };
+
+            let mut new_entry = None;
+            for old_ver in func.all_vers.iter() {
+                if patched.get(old_ver) == Some(&cur_ver) || !compiled_funcs.contains_key(old_ver) {
+                    continue;
+                }
+                let old_cf = compiled_funcs.get(old_ver).unwrap().read().unwrap();
+
+                if new_entry.is_none() {
+                    new_entry = Some(resolve_symbol(cur_cf.start.to_relocatable()));
+
+                    // the new version may be called from now on, we need its callsites
+                    // for exception handling
+                    if let Some(callsite_list) = callsite_table.get(&cur_ver) {
+                        VM::insert_compiled_callsites(
+                            &cur_cf,
+                            callsite_list,
+                            &mut compiled_callsite_table
+                        );
+                    }
+                }
+
+                let old_entry = resolve_symbol(old_cf.start.to_relocatable());
+                info!(
+                    "redirect {} (version #{} at {}) to version #{} at {}",
+                    func,
+                    old_ver,
+                    old_entry,
+                    cur_ver,
+                    new_entry.unwrap()
                 );
+                backend::patch_code_entry(old_entry, new_entry.unwrap());
+                patched.insert(*old_ver, cur_ver);
             }
         }
     }
 
+    /// checks if any function has a current version that replaces an already compiled version,
+    /// and has not been installed yet with install_redefined_funcs()
+    pub fn has_pending_redefinitions(&self) -> bool {
+        let funcs = self.funcs.read().unwrap();
+        let compiled_funcs = self.compiled_funcs.read().unwrap();
+        let patched = self.patched_func_vers.read().unwrap();
+
+        for func in funcs.values() {
+            let func = func.read().unwrap();
+            if let Some(cur_ver) = func.cur_ver {
+                for old_ver in func.all_vers.iter() {
+                    if compiled_funcs.contains_key(old_ver) &&
+                        patched.get(old_ver) != Some(&cur_ver)
+                    {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
     /// returns a valid ID for use next
     pub fn next_id(&self) -> MuID {
+        let compiler_id = COMPILER_IDS.with(|ids| match ids.get() {
+            Some((next, end)) => {
+                if next >= end {
+                    panic!("running out of IDs for the function version being compiled");
+                }
+                ids.set(Some((next + 1, end)));
+                Some(next)
+            }
+            None => None
+        });
+        if let Some(id) = compiler_id {
+            return id;
+        }
+
         // This only needs to be atomic, and does not need to be a synchronisation operation. The
         // only requirement for IDs is that all IDs obtained from `next_id()` are different. So
         // `Ordering::Relaxed` is sufficient.
         self.next_id.fetch_add(1, Ordering::Relaxed)
     }
 
+    /// makes next_id() on the current thread return IDs from the range of the given function
+    /// version, until end_compiler_ids() is called
+    pub fn start_compiler_ids(&self, fv_id: MuID) {
+        let start = COMPILER_ID_START + fv_id * COMPILER_IDS_PER_FUNC_VER;
+        COMPILER_IDS.with(|ids| ids.set(Some((start, start + COMPILER_IDS_PER_FUNC_VER))));
+    }
+
+    /// makes next_id() on the current thread return IDs from the VM again
+    pub fn end_compiler_ids(&self) {
+        COMPILER_IDS.with(|ids| ids.set(None));
+    }
+
     /// are we doing AOT compilation? (feature = aot when building Zebu)
     pub fn is_doing_aot(&self) -> bool {
         return cfg!(feature = "aot");
     }
 
+    /// returns the architecture that this VM generates code for
+    pub fn target_arch(&self) -> TargetArch {
+        self.vm_options.target_arch
+    }
+
     /// are we doing JIT compilation? (feature = jit when building Zebu)
     pub fn is_doing_jit(&self) -> bool {
         return cfg!(feature = "jit");
@@ -776,6 +990,40 @@ impl<'a> VM {
         }
     }
 
+    /// gets the symbol that the code of a function version is emitted with.
+    /// The first compiled version of a function uses the function name, so that calls to the
+    /// function resolve to it. A version that redefines an already compiled function gets its
+    /// own symbol, and the older code gets patched to jump to it (see install_redefined_funcs())
+    pub fn get_code_symbol_for_func_ver(&self, func_id: MuID, fv_id: MuID) -> MuName {
+        let funcs = self.funcs.read().unwrap();
+        let func = match funcs.get(&func_id) {
+            Some(func) => func.read().unwrap(),
+            None => panic!("cannot find Mu function #{}", func_id)
+        };
+
+        // a compiled function version keeps its symbol
+        {
+            let compiled_funcs = self.compiled_funcs.read().unwrap();
+            if let Some(cf) = compiled_funcs.get(&fv_id) {
+                return cf.read().unwrap().start.to_relocatable();
+            }
+        }
+
+        let is_redefinition = {
+            let compiled_funcs = self.compiled_funcs.read().unwrap();
+            func.all_vers
+                .iter()
+                .chain(func.cur_ver.iter())
+                .any(|v| *v != fv_id && compiled_funcs.contains_key(v))
+        };
+
+        if is_redefinition {
+            Arc::new(format!("{}:v{}", func.name(), fv_id))
+        } else {
+            func.name()
+        }
+    }
+
     /// defines a function version
     pub fn define_func_version(&self, func_ver: MuFunctionVersion) {
         info!("define function version {}", func_ver);
@@ -796,11 +1044,22 @@ impl<'a> VM {
         debug_assert!(funcs.contains_key(&func_ver.func_id));
         let mut func = funcs.get(&func_ver.func_id).unwrap().write().unwrap();
 
+        let old_ver = func.cur_ver;
         func.new_version(func_ver.id());
 
-        if self.is_doing_jit() {
-            // redefinition may happen, we need to check
-            unimplemented!()
+        // redefinition: if the old version is compiled, it may be running (or it may be
+        // called before the new version is ready). We keep the old code and its
+        // CompiledFunction, and redirect calls to the new version once it is compiled
+        // and loaded (see install_redefined_funcs())
+        if let Some(old_ver) = old_ver {
+            if self.compiled_funcs.read().unwrap().contains_key(&old_ver) {
+                info!(
+                    "{} redefines {}, whose version #{} is already compiled",
+                    func_ver,
+                    func,
+                    old_ver
+                );
+            }
         }
     }
 
@@ -1020,6 +1279,16 @@ impl<'a> VM {
         &self.compiled_funcs
     }
 
+    /// returns the lock for the counters of function versions compiled with --profile-generate
+    pub fn profile_counters(&self) -> &RwLock<HashMap<MuID, ProfileCounters>> {
+        &self.profile_counters
+    }
+
+    /// returns the lock for exception callsites of compiled function versions
+    pub fn callsite_table(&self) -> &RwLock<HashMap<MuID, Vec<Callsite>>> {
+        &self.callsite_table
+    }
+
     /// returns the lock for types
     pub fn types(&self) -> &RwLock<HashMap<MuID, P<MuType>>> {
         &self.types
@@ -1062,7 +1331,20 @@ impl<'a> VM {
         *guard = Some(PrimordialThreadInfo {
             func_id: func_id,
             has_const_args: has_const_args,
-            args: args
+            args: args,
+            from_stack: false
+        });
+    }
+
+    /// sets the primordial thread for boot image to resume a stack
+    /// (the stack is persisted by emit_context_with_reloc(), and resumed with no values)
+    pub fn set_primordial_stack(&self) {
+        let mut guard = self.primordial.write().unwrap();
+        *guard = Some(PrimordialThreadInfo {
+            func_id: 0,
+            has_const_args: true,
+            args: vec![],
+            from_stack: true
         });
     }
 
@@ -1074,15 +1356,16 @@ impl<'a> VM {
     /// 2. if the output name for the boot image has extension name for dynamic libraries
     ///    (.so or .dylib), we generate a dynamic library as boot image. Otherwise, we generate
     ///    an executable.
-    /// 3. we do not support primordial stack (as Kunshan pointed out, making boot image with a
-    ///    primordial stack may get deprecated)
+    /// 3. a primordial stack must not be running. Its saved frames are copied into the boot image,
+    ///    the references are found with the stack maps of the callsites, and other pointers
+    ///    (except to compiled code and to the stack itself) are not relocated. A stack that was
+    ///    suspended by SWAPSTACK is resumed without values when the boot image starts
     ///
     /// args:
     /// whitelist               : functions to be put into the boot image
     /// primordial_func         : starting function for the boot image
     /// primordial_stack        : starting stack for the boot image
-    ///                           (client should name either primordial_func or stack,
-    ///                            currently Zebu only supports func)
+    ///                           (client should name either primordial_func or stack)
     /// primordial_threadlocal  : thread local for the starting thread
     /// sym_fields/strings      : declare an address with symbol
     /// reloc_fields/strings    : declare an field pointing to a symbol
@@ -1131,8 +1414,25 @@ impl<'a> VM {
         output_file: String
     ) {
         info!("Making boot image...");
+        let mut times = PhaseTimes::default();
 
+        let start = Instant::now();
         super::uir_output::emit_uir("", self);
+        times.emission += start.elapsed();
+
+        // drop the functions that cannot be reached from the entry points of the boot image
+        let (whitelist, stripped_funcs) = if self.vm_options.flag_aot_strip_unreachable {
+            self.strip_unreachable_funcs(
+                whitelist,
+                primordial_func,
+                primordial_stack,
+                &reloc_strings,
+                &output_file
+            )
+        } else {
+            (whitelist, vec![])
+        };
+
         // Only store name info for whitelisted entities
         {
             let mut new_id_name_map = HashMap::<MuID, MuName>::with_capacity(whitelist.len());
@@ -1155,110 +1455,282 @@ impl<'a> VM {
 
         // compile the whitelist functions
         let whitelist_funcs = {
-            let compiler = Compiler::new(CompilerPolicy::default(), self);
-            let funcs = self.funcs().read().unwrap();
-            let func_vers = self.func_vers().read().unwrap();
-
-            // make sure all functions in whitelist are compiled
             let mut whitelist_funcs: Vec<MuID> = vec![];
-            for &id in whitelist.iter() {
-                if let Some(f) = funcs.get(&id) {
-                    whitelist_funcs.push(id);
-
-                    let f: &MuFunction = &f.read().unwrap();
-                    match f.cur_ver {
-                        Some(fv_id) => {
-                            let mut func_ver = func_vers.get(&fv_id).unwrap().write().unwrap();
-                            if !func_ver.is_compiled() {
-                                compiler.compile(&mut func_ver);
-                            }
+            let mut whitelist_func_vers: Vec<MuID> = vec![];
+            {
+                let funcs = self.funcs().read().unwrap();
+                for &id in whitelist.iter() {
+                    if let Some(f) = funcs.get(&id) {
+                        whitelist_funcs.push(id);
+
+                        let f: &MuFunction = &f.read().unwrap();
+                        match f.cur_ver {
+                            Some(fv_id) => whitelist_func_vers.push(fv_id),
+                            None => error!("whitelist function {} has no version defined", f)
                         }
-                        None => error!("whitelist function {} has no version defined", f)
                     }
                 }
             }
 
+            // make sure all functions in whitelist are compiled
+            let start = Instant::now();
+            compiler::compile_func_vers(whitelist_func_vers, self);
+            times.compilation += start.elapsed();
+
             whitelist_funcs
         };
 
         let has_primordial_func = primordial_func.is_some();
         let has_primordial_stack = primordial_stack.is_some();
 
-        // we assume client will start with a function (instead of a stack)
-        if has_primordial_stack {
-            panic!(
-                "Zebu doesnt support creating primordial thread from a stack, \
-                 name a entry function instead"
-            )
+        if has_primordial_func && has_primordial_stack {
+            panic!("client should name either a primordial function or a primordial stack");
+        }
+
+        if has_primordial_func {
+            // extract func id
+            let func_id = primordial_func.unwrap().v.as_funcref();
+
+            // make primordial thread in vm
+            // no const args are passed, we will use argc/argv
+            self.set_primordial_thread(func_id, false, vec![]);
+        } else if has_primordial_stack {
+            self.set_primordial_stack();
         } else {
-            if has_primordial_func {
-                // extract func id
-                let func_id = primordial_func.unwrap().v.as_funcref();
-
-                // make primordial thread in vm
-                // no const args are passed, we will use argc/argv
-                self.set_primordial_thread(func_id, false, vec![]);
-            } else {
-                warn!("no entry function is passed");
-            }
+            warn!("no entry function is passed");
+        }
 
-            // deal with relocation symbols, zip the two vectors into a hashmap
-            assert_eq!(sym_fields.len(), sym_strings.len());
-            let symbols: HashMap<Address, MuName> = sym_fields
+        // deal with relocation symbols, zip the two vectors into a hashmap
+        assert_eq!(sym_fields.len(), sym_strings.len());
+        let symbols: HashMap<Address, MuName> = sym_fields
+            .into_iter()
+            .map(|handle| handle.v.as_address())
+            .zip(sym_strings.into_iter())
+            .collect();
+
+        // deal with relocation fields
+        // zip the two vectors into a hashmap, and add fields for pending funcref stores
+        assert_eq!(reloc_fields.len(), reloc_strings.len());
+        let fields = {
+            // init reloc fields with client-supplied field/symbol pair
+            let mut reloc_fields: HashMap<Address, MuName> = reloc_fields
                 .into_iter()
                 .map(|handle| handle.v.as_address())
-                .zip(sym_strings.into_iter())
+                .zip(reloc_strings.into_iter())
                 .collect();
 
-            // deal with relocation fields
-            // zip the two vectors into a hashmap, and add fields for pending funcref stores
-            assert_eq!(reloc_fields.len(), reloc_strings.len());
-            let fields = {
-                // init reloc fields with client-supplied field/symbol pair
-                let mut reloc_fields: HashMap<Address, MuName> = reloc_fields
-                    .into_iter()
-                    .map(|handle| handle.v.as_address())
-                    .zip(reloc_strings.into_iter())
-                    .collect();
-
-                // pending funcrefs - we want to replace them as symbol
-                {
-                    let mut pending_funcref = self.aot_pending_funcref_store.write().unwrap();
-                    for (addr, vl) in pending_funcref.drain() {
-                        reloc_fields.insert(addr, vl.to_relocatable());
-                    }
+            // pending funcrefs - we want to replace them as symbol
+            {
+                let mut pending_funcref = self.aot_pending_funcref_store.write().unwrap();
+                for (addr, vl) in pending_funcref.drain() {
+                    reloc_fields.insert(addr, vl.to_relocatable());
                 }
+            }
 
-                reloc_fields
-            };
+            reloc_fields
+        };
+
+        // emit context (persist vm, etc)
+        let (n_symbols, n_fields) = (symbols.len(), fields.len());
+        let primordial_stack = primordial_stack.map(|x| x.v.as_stack());
+        let start = Instant::now();
+        backend::emit_context_with_reloc(
+            self,
+            symbols,
+            fields,
+            primordial_stack,
+            primordial_threadlocal.map(|x| x.v.as_ref().1)
+        );
+        times.emission += start.elapsed();
+
+        // link
+        self.link_boot_image(
+            whitelist_funcs.clone(),
+            extra_sources_to_link,
+            output_file.clone(),
+            &mut times.link
+        );
 
-            // emit context (persist vm, etc)
-            backend::emit_context_with_reloc(
+        if self.vm_options.flag_aot_emit_report {
+            boot_image_report::emit_report(
                 self,
-                symbols,
-                fields,
-                primordial_threadlocal.map(|x| x.v.as_ref().1)
+                &whitelist_funcs,
+                primordial_stack,
+                n_symbols,
+                n_fields,
+                &stripped_funcs,
+                &times,
+                &output_file
             );
+        }
+    }
+
+    /// rebuilds the hash maps that are persisted with the VM (and the ones in the persisted
+    /// compiled functions, and the struct/hybrid tag maps) in key order with fixed hash keys,
+    /// so that the dumped VM context is the same for the same bundle
+    pub fn order_persisted_tables(&self) {
+        rebuild_map_in_order(&mut self.id_name_map.write().unwrap(), |k, _| *k);
+        rebuild_map_in_order(&mut self.name_id_map.write().unwrap(), |k, _| k.clone());
+        rebuild_map_in_order(&mut self.types.write().unwrap(), |k, _| *k);
+        rebuild_map_in_order(&mut self.backend_type_info.write().unwrap(), |k, _| *k);
+        rebuild_map_in_order(&mut self.constants.write().unwrap(), |k, _| *k);
+        rebuild_map_in_order(&mut self.globals.write().unwrap(), |k, _| *k);
+        rebuild_map_in_order(&mut self.func_sigs.write().unwrap(), |k, _| *k);
+        rebuild_map_in_order(&mut self.funcs.write().unwrap(), |k, _| *k);
+        // type encodings are not ordered, but each of them has its own type ID
+        rebuild_map_in_order(&mut self.gc_type_map.write().unwrap(), |_, v| *v);
+        rebuild_map_in_order(&mut self.gc_id_map.write().unwrap(), |k, _| *k);
+        rebuild_map_in_order(&mut self.callsite_table.write().unwrap(), |k, _| *k);
+        rebuild_map_in_order(&mut self.profile_counters.write().unwrap(), |k, _| *k);
+
+        {
+            let mut compiled_funcs = self.compiled_funcs.write().unwrap();
+            for cf in compiled_funcs.values() {
+                let mut cf = cf.write().unwrap();
+                let cf = &mut *cf;
+                rebuild_map_in_order(&mut cf.temps, |k, _| *k);
+                rebuild_map_in_order(&mut cf.consts, |k, _| *k);
+                rebuild_map_in_order(&mut cf.const_mem, |k, _| *k);
+                rebuild_map_in_order(&mut cf.frame.argument_by_reg, |k, _| *k);
+                rebuild_map_in_order(&mut cf.frame.argument_by_stack, |k, _| *k);
+                rebuild_map_in_order(&mut cf.frame.allocated, |k, _| *k);
+                rebuild_map_in_order(&mut cf.frame.callee_saved, |k, _| *k);
+            }
+            rebuild_map_in_order(&mut compiled_funcs, |k, _| *k);
+        }
+
+        rebuild_map_in_order(&mut types::STRUCT_TAG_MAP.write().unwrap(), |k, _| k.clone());
+        rebuild_map_in_order(&mut types::HYBRID_TAG_MAP.write().unwrap(), |k, _| k.clone());
+    }
+
+    /// removes the functions from the whitelist that are not reachable from the entry points
+    /// of a boot image, i.e. the primordial function, the code on the primordial stack, and the
+    /// functions whose references are stored in the heap. All the whitelisted functions are
+    /// exposed by a dynamic library, so they are entry points as well, and so are the functions
+    /// that the client names as the symbols of relocation fields.
+    /// Reachability is the transitive closure of funcref constants in the function versions.
+    /// Returns the kept whitelist and the names of the dropped functions
+    fn strip_unreachable_funcs(
+        &self,
+        whitelist: Vec<MuID>,
+        primordial_func: Option<&APIHandle>,
+        primordial_stack: Option<&APIHandle>,
+        reloc_strings: &Vec<MuName>,
+        output_file: &String
+    ) -> (Vec<MuID>, Vec<MuName>) {
+        let funcs = self.funcs().read().unwrap();
+        let func_vers = self.func_vers().read().unwrap();
+
+        let mut roots: Vec<MuID> = vec![];
+        if let Some(func) = primordial_func {
+            roots.push(func.v.as_funcref());
+        }
+        if let Some(stack) = primordial_stack {
+            // return addresses (and the entry) of the frames on the stack
+            let stack = unsafe { stack.v.as_stack().to_ptr::<MuStack>().as_ref().unwrap() };
+            let image = stack.to_image(self, &HashMap::new());
+            let compiled_funcs = self.compiled_funcs().read().unwrap();
+            for word in image {
+                if let StackImageWord::Symbol(label, _) = word {
+                    for cf in compiled_funcs.values() {
+                        let cf = cf.read().unwrap();
+                        if mangle_name(cf.start.to_relocatable()) == label {
+                            roots.push(cf.func_id);
+                        }
+                    }
+                }
+            }
+        }
+        {
+            let name_id_map = self.name_id_map.read().unwrap();
+            let pending_funcref = self.aot_pending_funcref_store.read().unwrap();
+            for vl in pending_funcref.values() {
+                if let &ValueLocation::Relocatable(_, ref symbol) = vl {
+                    roots.push(*name_id_map.get(symbol).unwrap());
+                }
+            }
+            // the client may name any symbol (not only functions) for a relocation field
+            for symbol in reloc_strings.iter() {
+                if let Some(id) = name_id_map.get(symbol) {
+                    roots.push(*id);
+                }
+            }
+        }
+        if output_file.ends_with("dylib") || output_file.ends_with("so") {
+            roots.extend(whitelist.iter().filter(|id| funcs.contains_key(*id)));
+        }
+
+        let mut reachable: LinkedHashSet<MuID> = LinkedHashSet::new();
+        let mut work_list = roots;
+        while let Some(id) = work_list.pop() {
+            if reachable.contains(&id) {
+                continue;
+            }
+            reachable.insert(id);
+
+            let func = match funcs.get(&id) {
+                Some(func) => func.read().unwrap(),
+                None => continue
+            };
+            // older versions of a redefined function may still be running
+            for fv_id in func.all_vers.iter().chain(func.cur_ver.iter()) {
+                let fv = func_vers.get(fv_id).unwrap().read().unwrap();
+                if fv.content.is_some() {
+                    work_list.extend(fv.get_referenced_funcs().iter());
+                }
+            }
+        }
 
-            // link
-            self.link_boot_image(whitelist_funcs, extra_sources_to_link, output_file);
+        let (kept, dropped): (Vec<MuID>, Vec<MuID>) = whitelist
+            .into_iter()
+            .partition(|id| !funcs.contains_key(id) || reachable.contains(id));
+
+        let dropped: Vec<MuName> = dropped
+            .iter()
+            .map(|id| funcs.get(id).unwrap().read().unwrap().name())
+            .collect();
+        info!("stripped {} unreachable functions from the boot image", dropped.len());
+        for name in dropped.iter() {
+            info!("  {}", name);
         }
+
+        (kept, dropped)
     }
 
     /// links boot image (generates a dynamic library is the specified output file
     /// has dylib extension, otherwise generates an executable)
     #[cfg(feature = "aot")]
-    fn link_boot_image(&self, funcs: Vec<MuID>, extra_srcs: Vec<String>, output_file: String) {
+    fn link_boot_image(
+        &self,
+        funcs: Vec<MuID>,
+        extra_srcs: Vec<String>,
+        output_file: String,
+        times: &mut LinkTimes
+    ) {
         use linkutils;
 
         info!("Linking boot image...");
 
+        // the code of every compiled version of the functions (older versions of redefined
+        // functions are kept, as their entries get patched when the boot image is loaded)
         let func_names = {
             let funcs_guard = self.funcs().read().unwrap();
-            funcs
-                .iter()
-                .map(|x| funcs_guard.get(x).unwrap().read().unwrap().name())
-                .collect()
+            let compiled_funcs = self.compiled_funcs().read().unwrap();
+            let mut ret = vec![];
+            for id in funcs.iter() {
+                let func = funcs_guard.get(id).unwrap().read().unwrap();
+                let mut has_compiled_version = false;
+                for fv in func.all_vers.iter().chain(func.cur_ver.iter()) {
+                    if let Some(cf) = compiled_funcs.get(fv) {
+                        ret.push(cf.read().unwrap().start.to_relocatable());
+                        has_compiled_version = true;
+                    }
+                }
+                if !has_compiled_version {
+                    ret.push(func.name());
+                }
+            }
+            ret
         };
 
         trace!("functions: {:?}", func_names);
@@ -1267,13 +1739,16 @@ impl<'a> VM {
 
         if output_file.ends_with("dylib") || output_file.ends_with("so") {
             // compile as dynamic library
-            linkutils::aot::link_dylib_with_extra_srcs(func_names, extra_srcs, &output_file, self);
+            linkutils::aot::link_dylib_timed(func_names, extra_srcs, &output_file, self, times);
         } else {
-            if extra_srcs.len() != 0 {
-                panic!("trying to create an executable with linking extern sources, unimplemented");
-            }
             // compile as executable
-            linkutils::aot::link_primordial(func_names, &output_file, self);
+            linkutils::aot::link_primordial_timed(
+                func_names,
+                extra_srcs,
+                &output_file,
+                self,
+                times
+            );
         }
 
         trace!("Done!");
@@ -1292,6 +1767,16 @@ impl<'a> VM {
         Box::new(MuStack::new(self.next_id(), func_addr, stack_arg_size))
     }
 
+    /// creates a new stack with the given entry function, and returns a handle to it
+    pub fn handle_new_stack(&self, func: APIHandleArg) -> APIHandleResult {
+        let stack = self.new_stack(func.v.as_funcref());
+
+        self.new_handle(APIHandle {
+            id: self.next_id(),
+            v: APIHandleValue::StackRef(Address::from_mut_ptr(Box::into_raw(stack)))
+        })
+    }
+
     /// creates a handle that we can return to the client
     fn new_handle(&self, handle: APIHandle) -> APIHandleResult {
         let ret = Box::new(handle);
@@ -1532,6 +2017,7 @@ impl<'a> VM {
                 MuType_::IRef(ref ty) => APIHandleValue::IRef(ty.clone(), addr.load::<Address>()),
                 MuType_::UPtr(ref ty) => APIHandleValue::UPtr(ty.clone(), addr.load::<Address>()),
                 MuType_::Tagref64 => APIHandleValue::TagRef64(addr.load::<u64>()),
+                MuType_::StackRef => APIHandleValue::StackRef(addr.load::<Address>()),
 
                 _ => unimplemented!()
             }
@@ -1582,6 +2068,7 @@ impl<'a> VM {
                 APIHandleValue::Double(fval) => addr.store::<f64>(fval),
                 APIHandleValue::UPtr(_, aval) => addr.store::<Address>(aval),
                 APIHandleValue::UFP(_, aval) => addr.store::<Address>(aval),
+                APIHandleValue::StackRef(aval) => addr.store::<Address>(aval),
 
                 APIHandleValue::Struct(_) | APIHandleValue::Array(_) |
                 APIHandleValue::Vector(_) => unimplemented!(),
@@ -1773,6 +2260,16 @@ impl<'a> VM {
     pub fn pop_join_handle(&self) -> Option<JoinHandle<()>> {
         self.pending_joins.lock().unwrap().pop_front()
     }
+
+    /// writes the execution counts of the instrumented function versions to the
+    /// --profile-generate file (a boot image does this when all its threads have exited)
+    pub fn write_profile(&self) {
+        if self.vm_options.flag_profile_generate.is_empty() {
+            panic!("cannot write a profile without --profile-generate");
+        }
+        profile::write_profile(self);
+    }
+
     /// unwraps a handle to float
     pub fn handle_to_float(&self, handle: APIHandleArg) -> f32 {
         handle.v.as_float()
@@ -17,7 +17,9 @@
 extern crate docopt;
 
 use self::docopt::Docopt;
+use compiler::backend::TargetArch;
 use std;
+use libc;
 use std::default::Default;
 
 const USAGE: &'static str = "
@@ -29,17 +31,47 @@ Usage:
 VM:
   --log-level=<level>                   logging level: none, error, warn, info, debug, trace, env
                                         [default: env]
+  --perf-map                            write /tmp/perf-<pid>.map entries for compiled Mu
+                                        functions when their code is loaded (linux only)
+  --perf-jitdump                        also write jitdump records to /tmp/jit-<pid>.dump
+                                        (use with --perf-map)
 
 Compiler:
   --disable-inline                      disable compiler function inlining
   --disable-regalloc-validate           disable register allocation validation
   --disable-ir-validate                 disable IR validation
-  --emit-debug-info                     emit debugging information
+  --emit-debug-info                     emit debugging information (DWARF line tables, functions
+                                        and variables)
+  --target-arch=<arch>                  the architecture to generate code for: host, x86_64,
+                                        aarch64 (code for another architecture can be emitted,
+                                        and linked with a cross compiler given by CC, but cannot
+                                        be loaded) [default: host]
+  --compile-threads=<n>                 number of threads to compile functions for boot images
+                                        (0 for one thread per CPU) [default: 0]
+  --profile-generate=<file>             count the executions of blocks in compiled functions, and
+                                        write them to this file when the program ends
+                                        [default: ]
+  --profile-use=<file>                  use a profile written by --profile-generate for block
+                                        layout and inlining decisions [default: ]
 
 AOT Compiler:
   --aot-emit-dir=<dir>                  the emit directory for ahead-of-time compiling
                                         [default: emit]
+  --aot-cache-dir=<dir>                 keep compiled functions in this directory, and reuse them
+                                        in later builds if their IR, the types they use, the
+                                        target and the compiler options are unchanged
+                                        (empty for no cache) [default: ]
   --aot-link-static                     link boot image to libmu statically (defaults to dynamic)
+  --aot-emit-elf                        write persisted heap and constants as ELF objects
+                                        instead of assembly (linux only, the code of
+                                        functions is still assembled)
+  --aot-pic                             generate position-independent code without text
+                                        relocations, and link executable boot images as PIE
+                                        with full RELRO (linux only)
+  --aot-strip-unreachable               only compile and link the whitelisted functions that are
+                                        reachable from the boot image entry points
+  --aot-emit-report                     write the size and composition of the boot image as JSON
+                                        to <output>.report.json in the emit directory
   --bootimage-external-lib=<lib> ...       library that will be linked against when making bootimage
                                            [default: ]
   --bootimage-external-libpath=<path> ...  path for the libraries during bootimage generation
@@ -59,16 +91,30 @@ pub struct VMOptions {
     // The comments here indicate the offset into the struct
     // VM
     pub flag_log_level: MuLogLevel,
+    pub flag_perf_map: bool,
+    pub flag_perf_jitdump: bool,
 
     // Compiler
     pub flag_disable_inline: bool,
     pub flag_disable_regalloc_validate: bool,
     pub flag_disable_ir_validate: bool,
     pub flag_emit_debug_info: bool,
+    pub flag_target_arch: String,
+    // flag_target_arch, parsed once in init()
+    #[serde(skip_deserializing)]
+    pub target_arch: TargetArch,
+    pub flag_compile_threads: usize,
+    pub flag_profile_generate: String,
+    pub flag_profile_use: String,
 
     // AOT compiler
     pub flag_aot_emit_dir: String,
+    pub flag_aot_cache_dir: String,
     pub flag_aot_link_static: bool,
+    pub flag_aot_emit_elf: bool,
+    pub flag_aot_pic: bool,
+    pub flag_aot_strip_unreachable: bool,
+    pub flag_aot_emit_report: bool,
     pub flag_bootimage_external_lib: Vec<String>,
     pub flag_bootimage_external_libpath: Vec<String>,
 
@@ -81,18 +127,30 @@ pub struct VMOptions {
 
 // The fields need to be listed here in the order rust stores them in
 rodal_struct!(VMOptions {
+    flag_target_arch,
+    flag_compile_threads,
+    flag_profile_generate,
+    flag_profile_use,
     flag_aot_emit_dir,
+    flag_aot_cache_dir,
     flag_bootimage_external_lib,
     flag_bootimage_external_libpath,
     flag_gc_immixspace_size,
     flag_gc_lospace_size,
     flag_gc_nthreads,
     flag_log_level,
+    flag_perf_map,
+    flag_perf_jitdump,
     flag_disable_inline,
     flag_disable_regalloc_validate,
     flag_disable_ir_validate,
     flag_emit_debug_info,
+    target_arch,
     flag_aot_link_static,
+    flag_aot_emit_elf,
+    flag_aot_pic,
+    flag_aot_strip_unreachable,
+    flag_aot_emit_report,
     flag_gc_disable_collection
 });
 
@@ -135,6 +193,15 @@ impl VMOptions {
 
         info!("parsed as {:?}", ret);
 
+        // resolve 'host' to the architecture we are running on (this also checks the name)
+        ret.target_arch = TargetArch::from_string(&ret.flag_target_arch);
+        ret.flag_target_arch = ret.target_arch.name().to_string();
+
+        if ret.flag_compile_threads == 0 {
+            let n_cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+            ret.flag_compile_threads = if n_cpus > 0 { n_cpus as usize } else { 1 };
+        }
+
         // at the moment disable collection for debugging
         // also because currently GC is buggy, and we are going to rewrite the GC
         // See Issue #12
@@ -143,12 +210,6 @@ impl VMOptions {
             ret.flag_gc_disable_collection = true;
         }
 
-        // at the moment always emit debug info
-        if !ret.flag_emit_debug_info {
-            warn!("emit-debug-info is forced to true (opposite to user setting)");
-            ret.flag_emit_debug_info = true;
-        }
-
         // always disable register validation
         // register validation is buggy. See Issue #19
         if !ret.flag_disable_regalloc_validate {
@@ -161,6 +222,21 @@ impl VMOptions {
                 warn!("link-statically is forced to true (opposite to user setting)");
                 ret.flag_aot_link_static = true;
             }
+
+            if ret.flag_aot_emit_elf {
+                warn!("aot-emit-elf is forced to false (opposite to user setting)");
+                ret.flag_aot_emit_elf = false;
+            }
+
+            if ret.flag_aot_pic {
+                warn!("aot-pic is forced to false (opposite to user setting)");
+                ret.flag_aot_pic = false;
+            }
+
+            if ret.flag_perf_map {
+                warn!("perf-map is forced to false (opposite to user setting)");
+                ret.flag_perf_map = false;
+            }
         }
 
         ret
@@ -31,3 +31,7 @@ mod test_convop;
 mod test_int128;
 mod test_misc;
 mod test_opt;
+mod test_redefine;
+mod test_elf;
+mod test_debug_info;
+mod test_perf;
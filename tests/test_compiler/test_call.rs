@@ -23,6 +23,7 @@ use mu::vm::*;
 use mu::compiler::*;
 
 use std::sync::Arc;
+use std::path::PathBuf;
 use mu::linkutils;
 use mu::linkutils::aot;
 use mu::utils::LinkedHashMap;
@@ -121,6 +122,98 @@ fn ccall_exit() -> VM {
     vm
 }
 
+#[test]
+fn test_ccall_extra_src() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(ccall_extra_src());
+
+    // a C helper that gets linked into the boot image
+    let src = {
+        use std::fs::File;
+        use std::io::Write;
+
+        let mut path = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
+        path.push("ccall_extra_src_helper.c");
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "#include <stdlib.h>").unwrap();
+        writeln!(file, "void extra_src_exit(long code) {{ exit(code + 1); }}").unwrap();
+        path.to_str().unwrap().to_string()
+    };
+
+    let func_id = vm.id_of("ccall_extra_src");
+    let func_handle = vm.handle_from_func(func_id);
+    vm.make_boot_image_internal(
+        vec![func_id],
+        Some(&func_handle),
+        None,
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        vec![src],
+        "ccall_extra_src_test".to_string()
+    );
+
+    let executable = {
+        let mut path = PathBuf::from(&vm.vm_options.flag_aot_emit_dir);
+        path.push("ccall_extra_src_test");
+        path
+    };
+    let output = linkutils::exec_path_nocheck(executable);
+
+    assert!(output.status.code().is_some());
+
+    let ret_code = output.status.code().unwrap();
+    println!("return code: {}", ret_code);
+    assert!(ret_code == 11);
+}
+
+fn ccall_extra_src() -> VM {
+    let vm = VM::new();
+
+    typedef!((vm) int64 = mu_int(64));
+    funcsig!((vm) extra_src_exit_sig = (int64) -> ());
+    typedef!((vm) ufp_extra_src_exit = mu_ufuncptr(extra_src_exit_sig));
+
+    constdef!((vm) <int64> int64_10 = Constant::Int(10));
+    // .const @extra_src_exit = EXTERN SYMBOL "extra_src_exit"
+    constdef!((vm) <ufp_extra_src_exit> const_extra_src_exit =
+        Constant::ExternSym(C ("extra_src_exit")));
+
+    funcsig!((vm) ccall_extra_src_sig = () -> ());
+    funcdecl!((vm) <ccall_extra_src_sig> ccall_extra_src);
+    funcdef!((vm) <ccall_extra_src_sig> ccall_extra_src VERSION ccall_extra_src_v1);
+
+    // %entry():
+    block!((vm, ccall_extra_src_v1) blk_entry);
+
+    // exprCCALL %const_extra_src_exit (%int64_10)
+    consta!((vm, ccall_extra_src_v1) int64_10_local = int64_10);
+    consta!((vm, ccall_extra_src_v1) const_extra_src_exit_local = const_extra_src_exit);
+    inst!((vm, ccall_extra_src_v1) blk_entry_ccall:
+        EXPRCCALL (CallConvention::Foreign(ForeignFFI::C), is_abort: false)
+            const_extra_src_exit_local (int64_10_local)
+    );
+
+    // RET
+    inst!((vm, ccall_extra_src_v1) blk_entry_ret:
+        RET
+    );
+
+    define_block!((vm, ccall_extra_src_v1) blk_entry() {
+        blk_entry_ccall,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) ccall_extra_src_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
 #[test]
 fn test_pass_1arg_by_stack() {
     build_and_run_test!(pass_1arg_by_stack AND foo7, pass_1arg_by_stack_test1);
@@ -0,0 +1,164 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate mu;
+extern crate libloading;
+
+use self::mu::ast::types::*;
+use self::mu::ast::ir::*;
+use self::mu::ast::inst::*;
+use self::mu::ast::op::*;
+use self::mu::vm::*;
+use self::mu::utils::LinkedHashMap;
+
+use std::sync::Arc;
+use std::fs::File;
+use std::io::Read;
+use mu::linkutils;
+
+#[test]
+fn test_debug_info() {
+    let lib = linkutils::aot::compile_fnc("debug_info_add", &debug_info_add);
+
+    unsafe {
+        let debug_info_add: libloading::Symbol<unsafe extern "C" fn(u64, u64) -> u64> =
+            lib.get(b"debug_info_add").unwrap();
+
+        let res = debug_info_add(1, 2);
+        println!("debug_info_add(1, 2) = {}", res);
+        assert!(res == 3);
+    }
+
+    // the IR listing is written, and the code refers to it for line info
+    let mut listing = String::new();
+    File::open("emit/debug_info_add.debug.uir")
+        .unwrap()
+        .read_to_string(&mut listing)
+        .unwrap();
+    assert!(listing.contains("debug_info_add_v1"));
+
+    let mut code = String::new();
+    File::open("emit/debug_info_add.S")
+        .unwrap()
+        .read_to_string(&mut code)
+        .unwrap();
+    assert!(code.contains(".file 1"));
+    assert!(code.contains(".loc 1"));
+    if cfg!(target_os = "linux") {
+        assert!(code.contains(".debug_info"));
+        // %r is in the return register, which is not only used by %r
+        assert!(!code.contains("\t.asciz \"r\""));
+    }
+}
+
+fn debug_info_add() -> VM {
+    let vm = VM::new_with_opts("init_mu --emit-debug-info");
+
+    typedef!        ((vm) int64 = mu_int(64));
+
+    funcsig!        ((vm) sig = (int64, int64) -> (int64));
+    funcdecl!       ((vm) <sig> debug_info_add);
+    funcdef!        ((vm) <sig> debug_info_add VERSION debug_info_add_v1);
+
+    // %entry(<@int64> %a, <@int64> %b):
+    block!          ((vm, debug_info_add_v1) blk_entry);
+    ssa!            ((vm, debug_info_add_v1) <int64> a);
+    ssa!            ((vm, debug_info_add_v1) <int64> b);
+
+    // %r = ADD %a %b
+    ssa!            ((vm, debug_info_add_v1) <int64> r);
+    inst!           ((vm, debug_info_add_v1) blk_entry_add:
+        r = BINOP (BinOp::Add) a b
+    );
+
+    // RET %r
+    inst!           ((vm, debug_info_add_v1) blk_entry_ret:
+        RET (r)
+    );
+
+    define_block!   ((vm, debug_info_add_v1) blk_entry(a, b) {
+        blk_entry_add,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) debug_info_add_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
+#[test]
+fn test_cfi_without_debug_info() {
+    let lib = linkutils::aot::compile_fnc("cfi_add", &cfi_add);
+
+    unsafe {
+        let cfi_add: libloading::Symbol<unsafe extern "C" fn(u64, u64) -> u64> =
+            lib.get(b"cfi_add").unwrap();
+
+        let res = cfi_add(1, 2);
+        println!("cfi_add(1, 2) = {}", res);
+        assert!(res == 3);
+    }
+
+    // unwind info is always emitted, and the epilogue restores the body's rules
+    let mut code = String::new();
+    File::open("emit/cfi_add.S")
+        .unwrap()
+        .read_to_string(&mut code)
+        .unwrap();
+    assert!(code.contains(".cfi_startproc"));
+    assert!(code.contains(".cfi_def_cfa_register"));
+    assert!(code.contains(".cfi_remember_state"));
+    assert!(code.contains(".cfi_restore_state"));
+    assert!(code.contains(".cfi_endproc"));
+    assert!(!code.contains(".loc 1"));
+}
+
+fn cfi_add() -> VM {
+    let vm = VM::new();
+
+    typedef!        ((vm) int64 = mu_int(64));
+
+    funcsig!        ((vm) sig = (int64, int64) -> (int64));
+    funcdecl!       ((vm) <sig> cfi_add);
+    funcdef!        ((vm) <sig> cfi_add VERSION cfi_add_v1);
+
+    // %entry(<@int64> %a, <@int64> %b):
+    block!          ((vm, cfi_add_v1) blk_entry);
+    ssa!            ((vm, cfi_add_v1) <int64> a);
+    ssa!            ((vm, cfi_add_v1) <int64> b);
+
+    // %r = ADD %a %b
+    ssa!            ((vm, cfi_add_v1) <int64> r);
+    inst!           ((vm, cfi_add_v1) blk_entry_add:
+        r = BINOP (BinOp::Add) a b
+    );
+
+    // RET %r
+    inst!           ((vm, cfi_add_v1) blk_entry_ret:
+        RET (r)
+    );
+
+    define_block!   ((vm, cfi_add_v1) blk_entry(a, b) {
+        blk_entry_add,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) cfi_add_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
@@ -0,0 +1,112 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate mu;
+extern crate libloading;
+
+use self::mu::ast::types::*;
+use self::mu::ast::ir::*;
+use self::mu::ast::inst::*;
+use self::mu::ast::op::*;
+use self::mu::vm::*;
+use self::mu::linkutils;
+use self::mu::compiler::backend::elf::*;
+use mu::utils::LinkedHashMap;
+
+use std::sync::Arc;
+
+#[test]
+fn test_elf_object_layout() {
+    let mut obj = ElfObject::new(ElfMachine::host());
+    let data = obj.add_section(".data", SectionKind::Data);
+
+    obj.align(data, 8);
+    let start = obj.append_uint(data, 42, 8);
+    obj.append_symbol_address(data, "elf_layout_cell");
+    obj.define_symbol(
+        "elf_layout_cell",
+        data,
+        start,
+        16,
+        SymbolBinding::Global,
+        SymbolType::Object
+    );
+
+    let bytes = obj.to_bytes();
+
+    // ELF64, little endian, relocatable
+    assert_eq!(&bytes[0..6], &[0x7f, b'E', b'L', b'F', 2, 1]);
+    assert_eq!(bytes[16], 1);
+
+    // null, .data, .rela.data, .symtab, .strtab, .shstrtab
+    let shnum = bytes[60] as usize | (bytes[61] as usize) << 8;
+    assert_eq!(shnum, 6);
+
+    // the symbol name and relocation section name are in the string tables
+    let contains = |s: &[u8]| bytes.windows(s.len()).any(|w| w == s);
+    assert!(contains(b"elf_layout_cell\0"));
+    assert!(contains(b".rela.data\0"));
+}
+
+#[test]
+fn test_elf_constants() {
+    let lib = linkutils::aot::compile_fnc("elf_fadd_const", &elf_fadd_const);
+
+    unsafe {
+        let elf_fadd_const: libloading::Symbol<unsafe extern "C" fn(f64) -> f64> =
+            lib.get(b"elf_fadd_const").unwrap();
+
+        let res = elf_fadd_const(1f64);
+        println!("elf_fadd_const(1) = {}", res);
+        assert!(res == 2.5f64);
+    }
+}
+
+fn elf_fadd_const() -> VM {
+    let vm = VM::new_with_opts("init_mu --aot-emit-elf");
+
+    typedef!        ((vm) double = mu_double);
+    constdef!       ((vm) <double> double_1_5 = Constant::Double(1.5f64));
+
+    funcsig!        ((vm) sig = (double) -> (double));
+    funcdecl!       ((vm) <sig> elf_fadd_const);
+    funcdef!        ((vm) <sig> elf_fadd_const VERSION elf_fadd_const_v1);
+
+    // %entry(<@double> %a):
+    block!          ((vm, elf_fadd_const_v1) blk_entry);
+    ssa!            ((vm, elf_fadd_const_v1) <double> a);
+    consta!         ((vm, elf_fadd_const_v1) double_1_5_local = double_1_5);
+
+    // %r = FADD %a @double_1_5
+    ssa!            ((vm, elf_fadd_const_v1) <double> r);
+    inst!           ((vm, elf_fadd_const_v1) blk_entry_fadd:
+        r = BINOP (BinOp::FAdd) a double_1_5_local
+    );
+
+    // RET %r
+    inst!           ((vm, elf_fadd_const_v1) blk_entry_ret:
+        RET (r)
+    );
+
+    define_block!   ((vm, elf_fadd_const_v1) blk_entry(a) {
+        blk_entry_fadd,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) elf_fadd_const_v1(entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
@@ -32,6 +32,11 @@ use mu::vm::handle;
 use mu::linkutils;
 
 use std::sync::Arc;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
 
 #[test]
 fn test_global_access() {
@@ -61,6 +66,71 @@ fn test_global_access() {
     backend::emit_context(&vm);
 }
 
+#[test]
+fn test_global_access_cross_target() {
+    VM::start_logging_trace();
+
+    // generate code for the architecture that we are not running on. Accessing the global
+    // takes its address from the GOT (as a memory operand on x86_64, with ADRP and LDR on
+    // aarch64), and then stores the constant to it
+    let (target, expect) = if cfg!(target_arch = "x86_64") {
+        ("aarch64", vec!["ADRP ", ":got:__mu_a", "LDR ", ":got_lo12:__mu_a", "STR "])
+    } else {
+        ("x86_64", vec!["__mu_a@GOTPCREL(%rip)", "movq $1,("])
+    };
+    let emit_dir = format!("emit/cross_{}", target);
+    let vm = Arc::new(VM::new_with_opts(&format!(
+        "init_mu --target-arch={} --aot-emit-dir={}",
+        target,
+        emit_dir
+    )));
+    global_access(&vm);
+
+    let compiler = Compiler::new(CompilerPolicy::default(), &vm);
+    {
+        let func_id = vm.id_of("global_access");
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+    }
+    backend::emit_context(&vm);
+
+    // the code of the function has the instructions of the target architecture (in order)
+    let path = Path::new(&emit_dir).join("global_access.S");
+    let mut code = String::new();
+    File::open(&path)
+        .unwrap()
+        .read_to_string(&mut code)
+        .unwrap();
+    let mut rest = code.as_str();
+    for pattern in expect.iter() {
+        match rest.find(pattern) {
+            Some(i) => rest = &rest[i + pattern.len()..],
+            None => panic!("{:?} is not found in order in {}", pattern, code)
+        }
+    }
+
+    // and it assembles for the target, if we have an assembler for it
+    let assembler = format!("{}-linux-gnu-as", target);
+    let object = Path::new(&emit_dir).join("global_access.o");
+    match Command::new(&assembler)
+        .arg(&path)
+        .arg("-o")
+        .arg(&object)
+        .status()
+    {
+        Ok(status) => assert!(status.success()),
+        Err(_) => info!("{} is not available, skip assembling", assembler)
+    }
+}
+
 #[test]
 fn test_set_global_by_api() {
     VM::start_logging_trace();
@@ -266,6 +336,28 @@ fn test_persist_linked_list() {
     }
 
     // create a linked list by api
+    build_linked_list(&vm);
+
+    // then emit context (global will be put into context.s
+    vm.set_primordial_thread(func_id, true, vec![]);
+    backend::emit_context(&vm);
+
+    // link
+    let executable = aot::link_primordial(
+        vec![Mu("persist_linked_list")],
+        "persist_linked_list_test",
+        &vm
+    );
+    let output = linkutils::exec_path_nocheck(executable);
+
+    assert!(output.status.code().is_some());
+
+    let ret_code = output.status.code().unwrap();
+    println!("return code: {}", ret_code);
+    assert!(ret_code == 10);
+}
+
+fn build_linked_list(vm: &VM) {
     const LINKED_LIST_SIZE: usize = 5;
     {
         let mut i = 0;
@@ -304,24 +396,179 @@ fn test_persist_linked_list() {
             last_node.as_ref().unwrap()
         );
     }
+}
 
-    // then emit context (global will be put into context.s
-    vm.set_primordial_thread(func_id, true, vec![]);
-    backend::emit_context(&vm);
+/// makes a boot image that returns the sum of a persisted linked list, and checks that it runs
+fn make_linked_list_boot_image(opts: &str, output: &str) -> Arc<VM> {
+    let vm = Arc::new(VM::new_with_opts(&format!("init_mu {}", opts)));
+    unsafe {
+        MuThread::current_thread_as_mu_thread(Address::zero(), vm.clone());
+    }
+    persist_linked_list(&vm);
+    build_linked_list(&vm);
 
-    // link
-    let executable = aot::link_primordial(
-        vec![Mu("persist_linked_list")],
-        "persist_linked_list_test",
-        &vm
+    let func_id = vm.id_of("persist_linked_list");
+    let func_handle = vm.handle_from_func(func_id);
+    vm.make_boot_image(
+        vec![func_id],
+        Some(&func_handle),
+        None,
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        output.to_string()
     );
+
+    let executable = Path::new(&vm.vm_options.flag_aot_emit_dir).join(output);
     let output = linkutils::exec_path_nocheck(executable);
+    assert!(output.status.code() == Some(10));
 
-    assert!(output.status.code().is_some());
+    vm
+}
 
-    let ret_code = output.status.code().unwrap();
-    println!("return code: {}", ret_code);
-    assert!(ret_code == 10);
+#[test]
+#[cfg(target_os = "linux")]
+fn test_boot_image_report() {
+    VM::start_logging_trace();
+
+    let vm = make_linked_list_boot_image(
+        "--aot-emit-report --aot-emit-dir=emit/test_boot_image_report",
+        "boot_image_report"
+    );
+
+    let mut report = String::new();
+    File::open(Path::new(&vm.vm_options.flag_aot_emit_dir).join("boot_image_report.report.json"))
+        .unwrap()
+        .read_to_string(&mut report)
+        .unwrap();
+    println!("{}", report);
+
+    // the code size is read from the assembled function
+    assert!(report.contains("\"symbol\": \"persist_linked_list"));
+    assert!(!report.contains("\"code_bytes\": null"));
+    // the nodes of the list are typed by following the global
+    assert!(report.contains("\"objects\": 5"));
+    assert!(!report.contains("<unknown>"));
+    // each node but the last refers to the next node, and the global refers to the first
+    assert!(report.contains("\"heap_references\": 5"));
+    for phase in ["compilation", "emission", "assembly", "linking"].iter() {
+        assert!(report.contains(&format!("\"{}\": ", phase)));
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_reproducible_boot_image() {
+    use std::env;
+    VM::start_logging_trace();
+
+    // each build runs in a new process, so that it does not share process wide state
+    // (e.g. the struct tags) with the other build or with other tests
+    let emit_dir = "emit/reproducible";
+    let build = || {
+        let status = Command::new(env::current_exe().unwrap())
+            .args(&[
+                "--ignored",
+                "--exact",
+                "test_compiler::test_global::reproducible_boot_image_build",
+            ])
+            .env("MU_REPRODUCIBLE_EMIT_DIR", emit_dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    };
+
+    // the emit directory is persisted with the VM, so both builds use the same one,
+    // and we keep the files of the first build elsewhere
+    let first = "emit/reproducible_first";
+    let _ = fs::remove_dir_all(emit_dir);
+    let _ = fs::remove_dir_all(first);
+    build();
+    fs::create_dir_all(first).unwrap();
+    for entry in fs::read_dir(emit_dir).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, Path::new(first).join(path.file_name().unwrap())).unwrap();
+    }
+    build();
+
+    let read = |path: &Path| {
+        let mut content = vec![];
+        File::open(path).unwrap().read_to_end(&mut content).unwrap();
+        content
+    };
+
+    // every emitted file, including the persisted vm context and the executable,
+    // is identical across builds
+    let mut compared = vec![];
+    for entry in fs::read_dir(first).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        assert!(
+            read(&path) == read(&Path::new(emit_dir).join(&name)),
+            "{} differs between builds",
+            name
+        );
+        compared.push(name);
+    }
+    assert!(compared.iter().any(|name| name.starts_with("context.")));
+    assert!(compared.iter().any(|name| name == "reproducible_boot_image"));
+    assert_eq!(fs::read_dir(emit_dir).unwrap().count(), compared.len());
+}
+
+/// builds the boot image of test_reproducible_boot_image in the emit directory that it names
+/// (this is run by test_reproducible_boot_image in a new process for each build)
+#[test]
+#[ignore]
+#[cfg(target_os = "linux")]
+fn reproducible_boot_image_build() {
+    use std::env;
+
+    let emit_dir = env::var("MU_REPRODUCIBLE_EMIT_DIR").unwrap();
+    make_linked_list_boot_image(
+        &format!(
+            "--aot-emit-elf --compile-threads=4 --aot-emit-dir={}",
+            emit_dir
+        ),
+        "reproducible_boot_image"
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_pie_boot_image() {
+    VM::start_logging_trace();
+
+    let check_pie = |emit_opts: &str, output: &str| {
+        let vm = make_linked_list_boot_image(emit_opts, output);
+
+        let mut elf = vec![];
+        File::open(Path::new(&vm.vm_options.flag_aot_emit_dir).join(output))
+            .unwrap()
+            .read_to_end(&mut elf)
+            .unwrap();
+        let u16_at = |i: usize| elf[i] as u16 | (elf[i + 1] as u16) << 8;
+        let u32_at = |i: usize| u16_at(i) as u32 | (u16_at(i + 2) as u32) << 16;
+
+        // e_type is ET_DYN for a position independent executable
+        assert_eq!(u16_at(16), 3);
+
+        // one of the program headers is PT_GNU_RELRO
+        let phoff = u32_at(0x20) as usize;
+        let phentsize = u16_at(0x36) as usize;
+        let phnum = u16_at(0x38) as usize;
+        assert!((0..phnum).any(|i| u32_at(phoff + i * phentsize) == 0x6474e552));
+    };
+
+    check_pie(
+        "--aot-pic --aot-emit-dir=emit/test_pie_boot_image",
+        "pie_boot_image"
+    );
+    check_pie(
+        "--aot-pic --aot-emit-elf --aot-emit-dir=emit/test_pie_boot_image_elf",
+        "pie_boot_image_elf"
+    );
 }
 
 fn persist_linked_list(vm: &VM) {
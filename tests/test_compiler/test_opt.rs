@@ -14,11 +14,20 @@
 
 extern crate libloading;
 
+use mu::ast::types::*;
 use mu::ast::ir::*;
 use mu::ast::inst::*;
+use mu::ast::op::*;
+use mu::ast::ptr::*;
 use mu::vm::*;
+use mu::vm::profile;
+use mu::compiler::*;
+use mu::linkutils::aot;
 
 use std::sync::Arc;
+use std::env;
+use std::fs::File;
+use std::io::Write;
 use mu::utils::LinkedHashMap;
 
 #[test]
@@ -144,3 +153,2478 @@ fn infinite_loop2() -> VM {
 
     vm
 }
+
+#[test]
+fn test_sccp() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::SCCP::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("sccp");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let f_content = func_ver.content.as_ref().unwrap();
+    let int_const = |node: &P<TreeNode>| node.as_value().extract_int_const();
+
+    // (1 + 2) < 10 is folded, and the branch always goes to sccp_then with 3
+    let ref entry_body = f_content
+        .get_block(vm.id_of("sccp_entry"))
+        .content
+        .as_ref()
+        .unwrap()
+        .body;
+    assert_eq!(entry_body.len(), 1);
+    let branch = entry_body[0].as_inst();
+    match branch.v {
+        Instruction_::Branch1(ref dest) => {
+            assert_eq!(dest.target.id(), vm.id_of("sccp_then"));
+            assert_eq!(int_const(&dest.get_arguments_as_node(&branch.ops)[0]), Some(3));
+        }
+        _ => panic!("expected the branch to be folded, found {}", branch)
+    }
+
+    // the argument is a constant in sccp_then, so 3 * 2 + 3 is folded
+    let ref then_body = f_content
+        .get_block(vm.id_of("sccp_then"))
+        .content
+        .as_ref()
+        .unwrap()
+        .body;
+    assert_eq!(then_body.len(), 1);
+    let ret = then_body[0].as_inst();
+    match ret.v {
+        Instruction_::Return(ref vals) => assert_eq!(int_const(&ret.ops[vals[0]]), Some(9)),
+        _ => panic!("expected a return, found {}", ret)
+    }
+}
+
+#[test]
+fn test_sccp_run() {
+    build_and_run_test!(sccp, sccp_test1);
+}
+
+fn sccp() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_1  = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_2  = Constant::Int(2));
+    constdef!   ((vm) <int64> int64_10 = Constant::Int(10));
+
+    funcsig!    ((vm) sccp_sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sccp_sig> sccp);
+    funcdef!    ((vm) <sccp_sig> sccp VERSION sccp_v1);
+
+    // %sccp_entry(<@int64> %n):
+    block!      ((vm, sccp_v1) sccp_entry);
+    ssa!        ((vm, sccp_v1) <int64> n);
+    consta!     ((vm, sccp_v1) int64_1_local  = int64_1);
+    consta!     ((vm, sccp_v1) int64_2_local  = int64_2);
+    consta!     ((vm, sccp_v1) int64_10_local = int64_10);
+
+    // %a = ADD %int64_1 %int64_2
+    ssa!        ((vm, sccp_v1) <int64> a);
+    inst!       ((vm, sccp_v1) sccp_entry_add:
+        a = BINOP (BinOp::Add) int64_1_local int64_2_local
+    );
+
+    // %cond = SLT %a %int64_10
+    ssa!        ((vm, sccp_v1) <int1> cond);
+    inst!       ((vm, sccp_v1) sccp_entry_slt:
+        cond = CMPOP (CmpOp::SLT) a int64_10_local
+    );
+
+    // BRANCH2 %cond %sccp_then(%a) %sccp_else(%n)
+    block!      ((vm, sccp_v1) sccp_then);
+    block!      ((vm, sccp_v1) sccp_else);
+    inst!       ((vm, sccp_v1) sccp_entry_branch2:
+        BRANCH2 (cond, a, n)
+            IF (OP 0)
+            THEN sccp_then (vec![1]) WITH 0.5f32,
+            ELSE sccp_else (vec![2])
+    );
+
+    define_block!((vm, sccp_v1) sccp_entry(n) {
+        sccp_entry_add,
+        sccp_entry_slt,
+        sccp_entry_branch2
+    });
+
+    // %sccp_then(<@int64> %x):
+    ssa!        ((vm, sccp_v1) <int64> x);
+
+    // %y = MUL %x %int64_2
+    ssa!        ((vm, sccp_v1) <int64> y);
+    inst!       ((vm, sccp_v1) sccp_then_mul:
+        y = BINOP (BinOp::Mul) x int64_2_local
+    );
+
+    // %z = ADD %y %x
+    ssa!        ((vm, sccp_v1) <int64> z);
+    inst!       ((vm, sccp_v1) sccp_then_add:
+        z = BINOP (BinOp::Add) y x
+    );
+
+    // RET %z
+    inst!       ((vm, sccp_v1) sccp_then_ret:
+        RET (z)
+    );
+
+    define_block!((vm, sccp_v1) sccp_then(x) {
+        sccp_then_mul,
+        sccp_then_add,
+        sccp_then_ret
+    });
+
+    // %sccp_else(<@int64> %w):
+    //     RET %w
+    ssa!        ((vm, sccp_v1) <int64> w);
+    inst!       ((vm, sccp_v1) sccp_else_ret:
+        RET (w)
+    );
+
+    define_block!((vm, sccp_v1) sccp_else(w) {
+        sccp_else_ret
+    });
+
+    define_func_ver!((vm) sccp_v1 (entry: sccp_entry) {
+        sccp_entry, sccp_then, sccp_else
+    });
+
+    emit_test! ((vm)
+        sccp, sccp_test1, sccp_test1_v1,
+        Int RET Int,
+        EQ,
+        sccp_sig,
+        int64(5) RET int64(9),
+    );
+
+    vm
+}
+
+#[test]
+fn test_dce() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(dce());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![
+            Box::new(passes::DefUse::new()),
+            Box::new(passes::ControlFlowAnalysis::new()),
+            Box::new(passes::DCE::new()),
+        ]),
+        &vm
+    );
+
+    let func_id = vm.id_of("dce");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    {
+        let f_content = func_ver.content.as_ref().unwrap();
+
+        // dce_dead is never branched to
+        assert!(!f_content.blocks.contains_key(&vm.id_of("dce_dead")));
+        assert_eq!(f_content.blocks.len(), 2);
+
+        // %b is unused, which makes %a unused as well
+        let ref entry_body = f_content
+            .get_block(vm.id_of("dce_entry"))
+            .content
+            .as_ref()
+            .unwrap()
+            .body;
+        assert_eq!(entry_body.len(), 2);
+        match entry_body[0].as_inst().v {
+            Instruction_::BinOp(BinOp::Sub, _, _) => {}
+            _ => panic!("expected SUB, found {}", entry_body[0])
+        }
+
+        let exit = f_content.get_block(vm.id_of("dce_exit"));
+        assert_eq!(exit.control_flow.preds, vec![vm.id_of("dce_entry")]);
+    }
+
+    // only SUB uses %n now
+    assert_eq!(
+        func_ver
+            .context
+            .get_value(vm.id_of("dce_n"))
+            .unwrap()
+            .use_count(),
+        1
+    );
+}
+
+#[test]
+fn test_dce_after_sccp() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(sccp());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![
+            Box::new(passes::ControlFlowAnalysis::new()),
+            Box::new(passes::SCCP::new()),
+            Box::new(passes::DefUse::new()),
+            Box::new(passes::DCE::new()),
+        ]),
+        &vm
+    );
+
+    let func_id = vm.id_of("sccp");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let f_content = func_ver.content.as_ref().unwrap();
+
+    // the folded branch never goes to sccp_else, so it is deleted along with its edge
+    assert!(!f_content.blocks.contains_key(&vm.id_of("sccp_else")));
+    let entry = f_content.get_block(vm.id_of("sccp_entry"));
+    assert_eq!(entry.control_flow.succs.len(), 1);
+    assert_eq!(entry.control_flow.succs[0].target, vm.id_of("sccp_then"));
+}
+
+#[test]
+fn test_dce_run() {
+    build_and_run_test!(dce, dce_test1);
+}
+
+fn dce() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_2 = Constant::Int(2));
+
+    funcsig!    ((vm) dce_sig = (int64) -> (int64));
+    funcdecl!   ((vm) <dce_sig> dce);
+    funcdef!    ((vm) <dce_sig> dce VERSION dce_v1);
+
+    // %dce_entry(<@int64> %n):
+    block!      ((vm, dce_v1) dce_entry);
+    ssa!        ((vm, dce_v1) <int64> dce_n);
+    consta!     ((vm, dce_v1) int64_1_local = int64_1);
+    consta!     ((vm, dce_v1) int64_2_local = int64_2);
+
+    // %a = ADD %n %int64_1
+    ssa!        ((vm, dce_v1) <int64> a);
+    inst!       ((vm, dce_v1) dce_entry_add:
+        a = BINOP (BinOp::Add) dce_n int64_1_local
+    );
+
+    // %b = MUL %a %int64_2
+    ssa!        ((vm, dce_v1) <int64> b);
+    inst!       ((vm, dce_v1) dce_entry_mul:
+        b = BINOP (BinOp::Mul) a int64_2_local
+    );
+
+    // %c = SUB %n %int64_1
+    ssa!        ((vm, dce_v1) <int64> c);
+    inst!       ((vm, dce_v1) dce_entry_sub:
+        c = BINOP (BinOp::Sub) dce_n int64_1_local
+    );
+
+    // BRANCH %dce_exit(%c)
+    block!      ((vm, dce_v1) dce_exit);
+    inst!       ((vm, dce_v1) dce_entry_branch:
+        BRANCH dce_exit (c)
+    );
+
+    define_block!((vm, dce_v1) dce_entry(dce_n) {
+        dce_entry_add,
+        dce_entry_mul,
+        dce_entry_sub,
+        dce_entry_branch
+    });
+
+    // %dce_dead(<@int64> %x):
+    //     BRANCH %dce_exit(%x)
+    block!      ((vm, dce_v1) dce_dead);
+    ssa!        ((vm, dce_v1) <int64> x);
+    inst!       ((vm, dce_v1) dce_dead_branch:
+        BRANCH dce_exit (x)
+    );
+
+    define_block!((vm, dce_v1) dce_dead(x) {
+        dce_dead_branch
+    });
+
+    // %dce_exit(<@int64> %r):
+    //     RET %r
+    ssa!        ((vm, dce_v1) <int64> r);
+    inst!       ((vm, dce_v1) dce_exit_ret:
+        RET (r)
+    );
+
+    define_block!((vm, dce_v1) dce_exit(r) {
+        dce_exit_ret
+    });
+
+    define_func_ver!((vm) dce_v1 (entry: dce_entry) {
+        dce_entry, dce_dead, dce_exit
+    });
+
+    emit_test! ((vm)
+        dce, dce_test1, dce_test1_v1,
+        Int RET Int,
+        EQ,
+        dce_sig,
+        int64(5) RET int64(4),
+    );
+
+    vm
+}
+
+#[test]
+fn test_gvn() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(gvn());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::GVN::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("gvn");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    {
+        let f_content = func_ver.content.as_ref().unwrap();
+        let body_of = |name: &str| {
+            f_content
+                .get_block(vm.id_of(name))
+                .content
+                .as_ref()
+                .unwrap()
+                .body
+                .clone()
+        };
+        let x = vm.id_of("gvn_x");
+
+        // %y = ADD %b %a is the same as %x
+        assert_eq!(body_of("gvn_entry").len(), 3);
+
+        // %z = ADD %a %b is dominated by %x, and its uses are replaced
+        let then_body = body_of("gvn_then");
+        assert_eq!(then_body.len(), 4);
+        let add = then_body[1].as_inst();
+        assert_eq!(add.ops[0].extract_ssa_id(), Some(x));
+        let add = then_body[2].as_inst();
+        assert_eq!(add.ops[1].extract_ssa_id(), Some(x));
+
+        // %m in gvn_then does not dominate gvn_else, so %n stays
+        assert_eq!(body_of("gvn_else").len(), 2);
+    }
+
+    // uses are recounted for TreeGen, so %x is not folded into a tree
+    assert_eq!(
+        func_ver
+            .context
+            .get_value(vm.id_of("gvn_x"))
+            .unwrap()
+            .use_count(),
+        2
+    );
+}
+
+#[test]
+fn test_gvn_run() {
+    build_and_run_test!(gvn, gvn_test1);
+    build_and_run_test!(gvn, gvn_test2);
+}
+
+fn gvn() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+
+    funcsig!    ((vm) gvn_sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <gvn_sig> gvn);
+    funcdef!    ((vm) <gvn_sig> gvn VERSION gvn_v1);
+
+    // %gvn_entry(<@int64> %a, <@int64> %b):
+    block!      ((vm, gvn_v1) gvn_entry);
+    ssa!        ((vm, gvn_v1) <int64> gvn_a);
+    ssa!        ((vm, gvn_v1) <int64> gvn_b);
+
+    // %x = ADD %a %b
+    ssa!        ((vm, gvn_v1) <int64> gvn_x);
+    inst!       ((vm, gvn_v1) gvn_entry_add1:
+        gvn_x = BINOP (BinOp::Add) gvn_a gvn_b
+    );
+
+    // %y = ADD %b %a
+    ssa!        ((vm, gvn_v1) <int64> gvn_y);
+    inst!       ((vm, gvn_v1) gvn_entry_add2:
+        gvn_y = BINOP (BinOp::Add) gvn_b gvn_a
+    );
+
+    // %cond = SLT %a %b
+    ssa!        ((vm, gvn_v1) <int1> gvn_cond);
+    inst!       ((vm, gvn_v1) gvn_entry_slt:
+        gvn_cond = CMPOP (CmpOp::SLT) gvn_a gvn_b
+    );
+
+    // BRANCH2 %cond %gvn_then() %gvn_else()
+    block!      ((vm, gvn_v1) gvn_then);
+    block!      ((vm, gvn_v1) gvn_else);
+    inst!       ((vm, gvn_v1) gvn_entry_branch2:
+        BRANCH2 (gvn_cond)
+            IF (OP 0)
+            THEN gvn_then (vec![]) WITH 0.5f32,
+            ELSE gvn_else (vec![])
+    );
+
+    define_block!((vm, gvn_v1) gvn_entry(gvn_a, gvn_b) {
+        gvn_entry_add1,
+        gvn_entry_add2,
+        gvn_entry_slt,
+        gvn_entry_branch2
+    });
+
+    // %gvn_then():
+    // %z = ADD %a %b
+    ssa!        ((vm, gvn_v1) <int64> gvn_z);
+    inst!       ((vm, gvn_v1) gvn_then_add1:
+        gvn_z = BINOP (BinOp::Add) gvn_a gvn_b
+    );
+
+    // %m = MUL %a %b
+    ssa!        ((vm, gvn_v1) <int64> gvn_m);
+    inst!       ((vm, gvn_v1) gvn_then_mul:
+        gvn_m = BINOP (BinOp::Mul) gvn_a gvn_b
+    );
+
+    // %s = ADD %z %m
+    ssa!        ((vm, gvn_v1) <int64> gvn_s);
+    inst!       ((vm, gvn_v1) gvn_then_add2:
+        gvn_s = BINOP (BinOp::Add) gvn_z gvn_m
+    );
+
+    // %r = ADD %s %y
+    ssa!        ((vm, gvn_v1) <int64> gvn_r);
+    inst!       ((vm, gvn_v1) gvn_then_add3:
+        gvn_r = BINOP (BinOp::Add) gvn_s gvn_y
+    );
+
+    // RET %r
+    inst!       ((vm, gvn_v1) gvn_then_ret:
+        RET (gvn_r)
+    );
+
+    define_block!((vm, gvn_v1) gvn_then() {
+        gvn_then_add1,
+        gvn_then_mul,
+        gvn_then_add2,
+        gvn_then_add3,
+        gvn_then_ret
+    });
+
+    // %gvn_else():
+    // %n = MUL %a %b
+    ssa!        ((vm, gvn_v1) <int64> gvn_n);
+    inst!       ((vm, gvn_v1) gvn_else_mul:
+        gvn_n = BINOP (BinOp::Mul) gvn_a gvn_b
+    );
+
+    // RET %n
+    inst!       ((vm, gvn_v1) gvn_else_ret:
+        RET (gvn_n)
+    );
+
+    define_block!((vm, gvn_v1) gvn_else() {
+        gvn_else_mul,
+        gvn_else_ret
+    });
+
+    define_func_ver!((vm) gvn_v1 (entry: gvn_entry) {
+        gvn_entry, gvn_then, gvn_else
+    });
+
+    // (2 + 3) + 2 * 3 + (3 + 2)
+    emit_test! ((vm)
+        gvn, gvn_test1, gvn_test1_v1,
+        Int, Int RET Int,
+        EQ,
+        gvn_sig,
+        int64(2), int64(3) RET int64(16),
+    );
+    // 5 * 4
+    emit_test! ((vm)
+        gvn, gvn_test2, gvn_test2_v1,
+        Int, Int RET Int,
+        EQ,
+        gvn_sig,
+        int64(5), int64(4) RET int64(20),
+    );
+
+    vm
+}
+
+#[test]
+fn test_licm() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(licm());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::LICM::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("licm");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let f_content = func_ver.content.as_ref().unwrap();
+    let body_of = |id: MuID| {
+        f_content
+            .get_block(id)
+            .content
+            .as_ref()
+            .unwrap()
+            .body
+            .clone()
+    };
+
+    // licm_entry also branches to licm_exit, so a preheader is created between it and the loop
+    let entry_body = body_of(vm.id_of("licm_entry"));
+    let preheader = match entry_body.last().unwrap().as_inst().v {
+        Instruction_::Branch2 { ref true_dest, .. } => true_dest.target.id(),
+        _ => panic!("expected BRANCH2")
+    };
+    assert!(preheader != vm.id_of("licm_header"));
+
+    // %gv = LOAD @g and %kk = MUL %k %k are moved to the preheader
+    let preheader_body = body_of(preheader);
+    assert_eq!(preheader_body.len(), 3);
+    assert_eq!(
+        preheader_body[0].as_inst().value.as_ref().unwrap()[0].id(),
+        vm.id_of("licm_gv")
+    );
+    let mul = preheader_body[1].as_inst();
+    assert_eq!(mul.value.as_ref().unwrap()[0].id(), vm.id_of("licm_kk"));
+
+    // %k reaches the loop as a block argument, the hoisted MUL uses the preheader's argument
+    let preheader_args = f_content
+        .get_block(preheader)
+        .content
+        .as_ref()
+        .unwrap()
+        .args
+        .clone();
+    assert_eq!(preheader_args.len(), 4);
+    assert_eq!(mul.ops[0].extract_ssa_id(), Some(preheader_args[1].id()));
+    assert_eq!(mul.ops[1].extract_ssa_id(), Some(preheader_args[1].id()));
+
+    // the comparison yields int<1>, and the rest depends on the induction variables
+    assert_eq!(body_of(vm.id_of("licm_header")).len(), 2);
+    assert_eq!(body_of(vm.id_of("licm_body")).len(), 5);
+}
+
+#[test]
+fn test_licm_run() {
+    build_and_run_test!(licm, licm_test1);
+    build_and_run_test!(licm, licm_test2);
+}
+
+fn licm() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+    globaldef!  ((vm) <int64> licm_g);
+    globaldef!  ((vm) <int64> licm_g2);
+
+    funcsig!    ((vm) licm_sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <licm_sig> licm);
+    funcdef!    ((vm) <licm_sig> licm VERSION licm_v1);
+
+    block!      ((vm, licm_v1) licm_entry);
+    block!      ((vm, licm_v1) licm_header);
+    block!      ((vm, licm_v1) licm_body);
+    block!      ((vm, licm_v1) licm_exit);
+    consta!     ((vm, licm_v1) int64_0_local = int64_0);
+    consta!     ((vm, licm_v1) int64_1_local = int64_1);
+    global!     ((vm, licm_v1) licm_g_local = licm_g);
+    global!     ((vm, licm_v1) licm_g2_local = licm_g2);
+
+    // %licm_entry(<@int64> %n, <@int64> %k):
+    ssa!        ((vm, licm_v1) <int64> licm_n);
+    ssa!        ((vm, licm_v1) <int64> licm_k);
+
+    // STORE @g %k
+    inst!       ((vm, licm_v1) licm_entry_store:
+        STORE licm_g_local licm_k (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %nonempty = SLT 0 %n
+    ssa!        ((vm, licm_v1) <int1> licm_nonempty);
+    inst!       ((vm, licm_v1) licm_entry_slt:
+        licm_nonempty = CMPOP (CmpOp::SLT) int64_0_local licm_n
+    );
+
+    // BRANCH2 %nonempty %licm_header(%n, %k, 0, 0) %licm_exit(0)
+    inst!       ((vm, licm_v1) licm_entry_branch2:
+        BRANCH2 (licm_nonempty, licm_n, licm_k, int64_0_local)
+            IF (OP 0)
+            THEN licm_header (vec![1, 2, 3, 3]) WITH 0.9f32,
+            ELSE licm_exit (vec![3])
+    );
+
+    define_block!((vm, licm_v1) licm_entry(licm_n, licm_k) {
+        licm_entry_store,
+        licm_entry_slt,
+        licm_entry_branch2
+    });
+
+    // %licm_header(<@int64> %h_n, <@int64> %h_k, <@int64> %h_i, <@int64> %h_acc):
+    ssa!        ((vm, licm_v1) <int64> licm_h_n);
+    ssa!        ((vm, licm_v1) <int64> licm_h_k);
+    ssa!        ((vm, licm_v1) <int64> licm_h_i);
+    ssa!        ((vm, licm_v1) <int64> licm_h_acc);
+
+    // %gv = LOAD @g
+    ssa!        ((vm, licm_v1) <int64> licm_gv);
+    inst!       ((vm, licm_v1) licm_header_load:
+        licm_gv = LOAD licm_g_local (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %lt = SLT %h_i %h_n
+    ssa!        ((vm, licm_v1) <int1> licm_lt);
+    inst!       ((vm, licm_v1) licm_header_slt:
+        licm_lt = CMPOP (CmpOp::SLT) licm_h_i licm_h_n
+    );
+
+    // BRANCH2 %lt %licm_body(%h_n, %h_k, %h_i, %h_acc) %licm_exit(%h_acc)
+    inst!       ((vm, licm_v1) licm_header_branch2:
+        BRANCH2 (licm_lt, licm_h_n, licm_h_k, licm_h_i, licm_h_acc)
+            IF (OP 0)
+            THEN licm_body (vec![1, 2, 3, 4]) WITH 0.9f32,
+            ELSE licm_exit (vec![4])
+    );
+
+    define_block!((vm, licm_v1) licm_header(licm_h_n, licm_h_k, licm_h_i, licm_h_acc) {
+        licm_header_load,
+        licm_header_slt,
+        licm_header_branch2
+    });
+
+    // %licm_body(<@int64> %b_n, <@int64> %b_k, <@int64> %b_i, <@int64> %b_acc):
+    ssa!        ((vm, licm_v1) <int64> licm_b_n);
+    ssa!        ((vm, licm_v1) <int64> licm_b_k);
+    ssa!        ((vm, licm_v1) <int64> licm_b_i);
+    ssa!        ((vm, licm_v1) <int64> licm_b_acc);
+
+    // %kk = MUL %b_k %b_k
+    ssa!        ((vm, licm_v1) <int64> licm_kk);
+    inst!       ((vm, licm_v1) licm_body_mul:
+        licm_kk = BINOP (BinOp::Mul) licm_b_k licm_b_k
+    );
+
+    // %t = ADD %b_acc %kk
+    ssa!        ((vm, licm_v1) <int64> licm_t);
+    inst!       ((vm, licm_v1) licm_body_add1:
+        licm_t = BINOP (BinOp::Add) licm_b_acc licm_kk
+    );
+
+    // %acc = ADD %t %gv
+    ssa!        ((vm, licm_v1) <int64> licm_acc);
+    inst!       ((vm, licm_v1) licm_body_add2:
+        licm_acc = BINOP (BinOp::Add) licm_t licm_gv
+    );
+
+    // STORE @g2 %acc (a different global, so the LOAD of @g can still be hoisted)
+    inst!       ((vm, licm_v1) licm_body_store:
+        STORE licm_g2_local licm_acc (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %i = ADD %b_i 1
+    ssa!        ((vm, licm_v1) <int64> licm_i);
+    inst!       ((vm, licm_v1) licm_body_add3:
+        licm_i = BINOP (BinOp::Add) licm_b_i int64_1_local
+    );
+
+    // BRANCH %licm_header(%b_n, %b_k, %i, %acc)
+    inst!       ((vm, licm_v1) licm_body_branch:
+        BRANCH licm_header (licm_b_n, licm_b_k, licm_i, licm_acc)
+    );
+
+    define_block!((vm, licm_v1) licm_body(licm_b_n, licm_b_k, licm_b_i, licm_b_acc) {
+        licm_body_mul,
+        licm_body_add1,
+        licm_body_add2,
+        licm_body_store,
+        licm_body_add3,
+        licm_body_branch
+    });
+
+    // %licm_exit(<@int64> %r):
+    //     RET %r
+    ssa!        ((vm, licm_v1) <int64> licm_r);
+    inst!       ((vm, licm_v1) licm_exit_ret:
+        RET (licm_r)
+    );
+
+    define_block!((vm, licm_v1) licm_exit(licm_r) {
+        licm_exit_ret
+    });
+
+    define_func_ver!((vm) licm_v1 (entry: licm_entry) {
+        licm_entry, licm_header, licm_body, licm_exit
+    });
+
+    // 3 * (2 * 2 + 2)
+    emit_test! ((vm)
+        licm, licm_test1, licm_test1_v1,
+        Int, Int RET Int,
+        EQ,
+        licm_sig,
+        int64(3), int64(2) RET int64(18),
+    );
+    // the loop is not entered
+    emit_test! ((vm)
+        licm, licm_test2, licm_test2_v1,
+        Int, Int RET Int,
+        EQ,
+        licm_sig,
+        int64(0), int64(5) RET int64(0),
+    );
+
+    vm
+}
+
+#[test]
+fn test_escape_analysis() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(escape());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::EscapeAnalysis::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("escape");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let f_content = func_ver.content.as_ref().unwrap();
+    let block_of = |name: &str| {
+        f_content
+            .get_block(vm.id_of(name))
+            .content
+            .as_ref()
+            .unwrap()
+            .clone()
+    };
+
+    // %q is stored to a global, so it stays. %p, its irefs and the store to it are removed
+    let entry = block_of("esc_entry");
+    assert_eq!(entry.body.len(), 4);
+    match entry.body[0].as_inst().v {
+        Instruction_::New(_) => {}
+        _ => panic!("expected NEW")
+    }
+
+    // field 1 holds 0 or %b when reaching esc_join, so esc_join gets a parameter for it
+    let join = block_of("esc_join");
+    assert_eq!(join.args.len(), 1);
+    assert_eq!(join.body.len(), 2);
+    let add = join.body[0].as_inst();
+    assert_eq!(add.ops[0].extract_ssa_id(), Some(vm.id_of("esc_a")));
+    assert_eq!(add.ops[1].extract_ssa_id(), Some(join.args[0].id()));
+
+    let then_branch = block_of("esc_then").body[0].clone();
+    assert_eq!(
+        then_branch.as_inst().ops[0].extract_ssa_id(),
+        Some(vm.id_of("esc_b"))
+    );
+    let else_branch = block_of("esc_else").body[0].clone();
+    assert_eq!(
+        else_branch.as_inst().ops[0]
+            .as_value()
+            .extract_int_const(),
+        Some(0)
+    );
+}
+
+#[test]
+fn test_escape_analysis_run() {
+    build_and_run_test!(escape, escape_test1);
+    build_and_run_test!(escape, escape_test2);
+}
+
+fn escape() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1       = mu_int(1));
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) ref_int64  = mu_ref(int64));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+    typedef!    ((vm) pair       = mu_struct(int64, int64));
+    typedef!    ((vm) ref_pair   = mu_ref(pair));
+    typedef!    ((vm) iref_pair  = mu_iref(pair));
+    globaldef!  ((vm) <ref_int64> esc_g);
+
+    funcsig!    ((vm) esc_sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <esc_sig> escape);
+    funcdef!    ((vm) <esc_sig> escape VERSION escape_v1);
+
+    block!      ((vm, escape_v1) esc_entry);
+    block!      ((vm, escape_v1) esc_then);
+    block!      ((vm, escape_v1) esc_else);
+    block!      ((vm, escape_v1) esc_join);
+    global!     ((vm, escape_v1) esc_g_local = esc_g);
+
+    // %esc_entry(<@int64> %a, <@int64> %b):
+    ssa!        ((vm, escape_v1) <int64> esc_a);
+    ssa!        ((vm, escape_v1) <int64> esc_b);
+
+    // %q = NEW <@int64>
+    ssa!        ((vm, escape_v1) <ref_int64> esc_q);
+    inst!       ((vm, escape_v1) esc_entry_new_q:
+        esc_q = NEW <int64>
+    );
+
+    // STORE @g %q
+    inst!       ((vm, escape_v1) esc_entry_store_q:
+        STORE esc_g_local esc_q (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %p = NEW <@pair>
+    ssa!        ((vm, escape_v1) <ref_pair> esc_p);
+    inst!       ((vm, escape_v1) esc_entry_new_p:
+        esc_p = NEW <pair>
+    );
+
+    // %ip = GETIREF %p
+    ssa!        ((vm, escape_v1) <iref_pair> esc_ip);
+    inst!       ((vm, escape_v1) esc_entry_getiref:
+        esc_ip = GETIREF esc_p
+    );
+
+    // %f0 = GETFIELDIREF %ip 0
+    ssa!        ((vm, escape_v1) <iref_int64> esc_f0);
+    inst!       ((vm, escape_v1) esc_entry_getfieldiref0:
+        esc_f0 = GETFIELDIREF esc_ip (is_ptr: false, index: 0)
+    );
+
+    // %f1 = GETFIELDIREF %ip 1
+    ssa!        ((vm, escape_v1) <iref_int64> esc_f1);
+    inst!       ((vm, escape_v1) esc_entry_getfieldiref1:
+        esc_f1 = GETFIELDIREF esc_ip (is_ptr: false, index: 1)
+    );
+
+    // STORE %f0 %a
+    inst!       ((vm, escape_v1) esc_entry_store:
+        STORE esc_f0 esc_a (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %cond = SLT %a %b
+    ssa!        ((vm, escape_v1) <int1> esc_cond);
+    inst!       ((vm, escape_v1) esc_entry_slt:
+        esc_cond = CMPOP (CmpOp::SLT) esc_a esc_b
+    );
+
+    // BRANCH2 %cond %esc_then() %esc_else()
+    inst!       ((vm, escape_v1) esc_entry_branch2:
+        BRANCH2 (esc_cond)
+            IF (OP 0)
+            THEN esc_then (vec![]) WITH 0.5f32,
+            ELSE esc_else (vec![])
+    );
+
+    define_block!((vm, escape_v1) esc_entry(esc_a, esc_b) {
+        esc_entry_new_q,
+        esc_entry_store_q,
+        esc_entry_new_p,
+        esc_entry_getiref,
+        esc_entry_getfieldiref0,
+        esc_entry_getfieldiref1,
+        esc_entry_store,
+        esc_entry_slt,
+        esc_entry_branch2
+    });
+
+    // %esc_then():
+    //     STORE %f1 %b
+    //     BRANCH %esc_join()
+    inst!       ((vm, escape_v1) esc_then_store:
+        STORE esc_f1 esc_b (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, escape_v1) esc_then_branch:
+        BRANCH esc_join ()
+    );
+
+    define_block!((vm, escape_v1) esc_then() {
+        esc_then_store,
+        esc_then_branch
+    });
+
+    // %esc_else():
+    //     BRANCH %esc_join()
+    inst!       ((vm, escape_v1) esc_else_branch:
+        BRANCH esc_join ()
+    );
+
+    define_block!((vm, escape_v1) esc_else() {
+        esc_else_branch
+    });
+
+    // %esc_join():
+    // %x = LOAD %f0
+    ssa!        ((vm, escape_v1) <int64> esc_x);
+    inst!       ((vm, escape_v1) esc_join_load0:
+        esc_x = LOAD esc_f0 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %y = LOAD %f1
+    ssa!        ((vm, escape_v1) <int64> esc_y);
+    inst!       ((vm, escape_v1) esc_join_load1:
+        esc_y = LOAD esc_f1 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %r = ADD %x %y
+    ssa!        ((vm, escape_v1) <int64> esc_r);
+    inst!       ((vm, escape_v1) esc_join_add:
+        esc_r = BINOP (BinOp::Add) esc_x esc_y
+    );
+
+    // RET %r
+    inst!       ((vm, escape_v1) esc_join_ret:
+        RET (esc_r)
+    );
+
+    define_block!((vm, escape_v1) esc_join() {
+        esc_join_load0,
+        esc_join_load1,
+        esc_join_add,
+        esc_join_ret
+    });
+
+    define_func_ver!((vm) escape_v1 (entry: esc_entry) {
+        esc_entry, esc_then, esc_else, esc_join
+    });
+
+    // 2 + 3
+    emit_test! ((vm)
+        escape, escape_test1, escape_test1_v1,
+        Int, Int RET Int,
+        EQ,
+        esc_sig,
+        int64(2), int64(3) RET int64(5),
+    );
+    // 7 + 0
+    emit_test! ((vm)
+        escape, escape_test2, escape_test2_v1,
+        Int, Int RET Int,
+        EQ,
+        esc_sig,
+        int64(7), int64(4) RET int64(7),
+    );
+
+    vm
+}
+
+#[test]
+fn test_rle() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(rle());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::RLE::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("rle");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let f_content = func_ver.content.as_ref().unwrap();
+    let body_of = |name: &str| {
+        f_content
+            .get_block(vm.id_of(name))
+            .content
+            .as_ref()
+            .unwrap()
+            .body
+            .clone()
+    };
+
+    // the first STORE @h is overwritten before it is read, and LOAD @g gets the stored %a
+    let entry = body_of("rle_entry");
+    assert_eq!(entry.len(), 4);
+    let store_h = entry[1].as_inst();
+    assert_eq!(store_h.ops[1].extract_ssa_id(), Some(vm.id_of("rle_a")));
+
+    // LOAD @h gets %x, which is in turn replaced by %a
+    let then = body_of("rle_then");
+    assert_eq!(then.len(), 2);
+    assert_eq!(
+        then[0].as_inst().ops[0].extract_ssa_id(),
+        Some(vm.id_of("rle_a"))
+    );
+
+    // @g holds %a on both paths to rle_join
+    let join = body_of("rle_join");
+    assert_eq!(join.len(), 2);
+    assert_eq!(
+        join[0].as_inst().ops[0].extract_ssa_id(),
+        Some(vm.id_of("rle_a"))
+    );
+}
+
+#[test]
+fn test_rle_run() {
+    build_and_run_test!(rle, rle_test1);
+    build_and_run_test!(rle, rle_test2);
+}
+
+fn rle() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    globaldef!  ((vm) <int64> rle_g);
+    globaldef!  ((vm) <int64> rle_h);
+
+    funcsig!    ((vm) rle_sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <rle_sig> rle);
+    funcdef!    ((vm) <rle_sig> rle VERSION rle_v1);
+
+    block!      ((vm, rle_v1) rle_entry);
+    block!      ((vm, rle_v1) rle_then);
+    block!      ((vm, rle_v1) rle_else);
+    block!      ((vm, rle_v1) rle_join);
+    global!     ((vm, rle_v1) rle_g_local = rle_g);
+    global!     ((vm, rle_v1) rle_h_local = rle_h);
+
+    // %rle_entry(<@int64> %a, <@int64> %b):
+    ssa!        ((vm, rle_v1) <int64> rle_a);
+    ssa!        ((vm, rle_v1) <int64> rle_b);
+
+    // STORE @h %b
+    inst!       ((vm, rle_v1) rle_entry_store_h1:
+        STORE rle_h_local rle_b (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // STORE @g %a
+    inst!       ((vm, rle_v1) rle_entry_store_g:
+        STORE rle_g_local rle_a (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %x = LOAD @g
+    ssa!        ((vm, rle_v1) <int64> rle_x);
+    inst!       ((vm, rle_v1) rle_entry_load:
+        rle_x = LOAD rle_g_local (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // STORE @h %x
+    inst!       ((vm, rle_v1) rle_entry_store_h2:
+        STORE rle_h_local rle_x (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %cond = SLT %a %b
+    ssa!        ((vm, rle_v1) <int1> rle_cond);
+    inst!       ((vm, rle_v1) rle_entry_slt:
+        rle_cond = CMPOP (CmpOp::SLT) rle_a rle_b
+    );
+
+    // BRANCH2 %cond %rle_then(%b) %rle_else(%b)
+    inst!       ((vm, rle_v1) rle_entry_branch2:
+        BRANCH2 (rle_cond, rle_b)
+            IF (OP 0)
+            THEN rle_then (vec![1]) WITH 0.5f32,
+            ELSE rle_else (vec![1])
+    );
+
+    define_block!((vm, rle_v1) rle_entry(rle_a, rle_b) {
+        rle_entry_store_h1,
+        rle_entry_store_g,
+        rle_entry_load,
+        rle_entry_store_h2,
+        rle_entry_slt,
+        rle_entry_branch2
+    });
+
+    // %rle_then(<@int64> %t_b):
+    ssa!        ((vm, rle_v1) <int64> rle_t_b);
+
+    // %y = LOAD @h
+    ssa!        ((vm, rle_v1) <int64> rle_y);
+    inst!       ((vm, rle_v1) rle_then_load:
+        rle_y = LOAD rle_h_local (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %s = ADD %y %t_b
+    ssa!        ((vm, rle_v1) <int64> rle_s);
+    inst!       ((vm, rle_v1) rle_then_add:
+        rle_s = BINOP (BinOp::Add) rle_y rle_t_b
+    );
+
+    // BRANCH %rle_join(%s)
+    inst!       ((vm, rle_v1) rle_then_branch:
+        BRANCH rle_join (rle_s)
+    );
+
+    define_block!((vm, rle_v1) rle_then(rle_t_b) {
+        rle_then_load,
+        rle_then_add,
+        rle_then_branch
+    });
+
+    // %rle_else(<@int64> %e_b):
+    //     BRANCH %rle_join(%e_b)
+    ssa!        ((vm, rle_v1) <int64> rle_e_b);
+    inst!       ((vm, rle_v1) rle_else_branch:
+        BRANCH rle_join (rle_e_b)
+    );
+
+    define_block!((vm, rle_v1) rle_else(rle_e_b) {
+        rle_else_branch
+    });
+
+    // %rle_join(<@int64> %j):
+    ssa!        ((vm, rle_v1) <int64> rle_j);
+
+    // %z = LOAD @g
+    ssa!        ((vm, rle_v1) <int64> rle_z);
+    inst!       ((vm, rle_v1) rle_join_load:
+        rle_z = LOAD rle_g_local (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %r = ADD %z %j
+    ssa!        ((vm, rle_v1) <int64> rle_r);
+    inst!       ((vm, rle_v1) rle_join_add:
+        rle_r = BINOP (BinOp::Add) rle_z rle_j
+    );
+
+    // RET %r
+    inst!       ((vm, rle_v1) rle_join_ret:
+        RET (rle_r)
+    );
+
+    define_block!((vm, rle_v1) rle_join(rle_j) {
+        rle_join_load,
+        rle_join_add,
+        rle_join_ret
+    });
+
+    define_func_ver!((vm) rle_v1 (entry: rle_entry) {
+        rle_entry, rle_then, rle_else, rle_join
+    });
+
+    // 2 + (2 + 3)
+    emit_test! ((vm)
+        rle, rle_test1, rle_test1_v1,
+        Int, Int RET Int,
+        EQ,
+        rle_sig,
+        int64(2), int64(3) RET int64(7),
+    );
+    // 5 + 1
+    emit_test! ((vm)
+        rle, rle_test2, rle_test2_v1,
+        Int, Int RET Int,
+        EQ,
+        rle_sig,
+        int64(5), int64(1) RET int64(6),
+    );
+
+    vm
+}
+
+#[test]
+fn test_rle_loop_carried_store() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(rle_loop());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::RLE::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("rle_loop");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // %p is a different element in every iteration, so STORE %p 1 is not overwritten by the
+    // STORE %p 2 after the loop
+    let f_content = func_ver.content.as_ref().unwrap();
+    let ref header = f_content
+        .get_block(vm.id_of("rle_loop_header"))
+        .content
+        .as_ref()
+        .unwrap()
+        .body;
+    assert_eq!(header.len(), 3);
+    match header[0].as_inst().v {
+        Instruction_::Store { .. } => {}
+        _ => panic!("expected STORE")
+    }
+}
+
+fn rle_loop() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1       = mu_int(1));
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+    typedef!    ((vm) array4     = mu_array(int64, 4));
+    constdef!   ((vm) <int64> rle_loop_int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> rle_loop_int64_1 = Constant::Int(1));
+    constdef!   ((vm) <int64> rle_loop_int64_2 = Constant::Int(2));
+    constdef!   ((vm) <int64> rle_loop_int64_3 = Constant::Int(3));
+    globaldef!  ((vm) <array4> rle_loop_arr);
+
+    funcsig!    ((vm) rle_loop_sig = () -> ());
+    funcdecl!   ((vm) <rle_loop_sig> rle_loop);
+    funcdef!    ((vm) <rle_loop_sig> rle_loop VERSION rle_loop_v1);
+
+    block!      ((vm, rle_loop_v1) rle_loop_entry);
+    block!      ((vm, rle_loop_v1) rle_loop_header);
+    block!      ((vm, rle_loop_v1) rle_loop_latch);
+    block!      ((vm, rle_loop_v1) rle_loop_exit);
+    consta!     ((vm, rle_loop_v1) rle_loop_0 = rle_loop_int64_0);
+    consta!     ((vm, rle_loop_v1) rle_loop_1 = rle_loop_int64_1);
+    consta!     ((vm, rle_loop_v1) rle_loop_2 = rle_loop_int64_2);
+    consta!     ((vm, rle_loop_v1) rle_loop_3 = rle_loop_int64_3);
+    global!     ((vm, rle_loop_v1) rle_loop_arr_local = rle_loop_arr);
+
+    // %rle_loop_entry():
+    //     %p0 = GETELEMIREF @arr 0
+    //     BRANCH %rle_loop_header(%p0, 0)
+    ssa!        ((vm, rle_loop_v1) <iref_int64> rle_loop_p0);
+    inst!       ((vm, rle_loop_v1) rle_loop_entry_getelemiref:
+        rle_loop_p0 = GETELEMIREF rle_loop_arr_local rle_loop_0 (is_ptr: false)
+    );
+    inst!       ((vm, rle_loop_v1) rle_loop_entry_branch:
+        BRANCH rle_loop_header (rle_loop_p0, rle_loop_0)
+    );
+
+    define_block!((vm, rle_loop_v1) rle_loop_entry() {
+        rle_loop_entry_getelemiref, rle_loop_entry_branch
+    });
+
+    // %rle_loop_header(<@iref_int64> %p, <@int64> %i):
+    //     STORE %p 1
+    //     BRANCH2 (SLT %i 3) %rle_loop_latch() %rle_loop_exit()
+    ssa!        ((vm, rle_loop_v1) <iref_int64> rle_loop_p);
+    ssa!        ((vm, rle_loop_v1) <int64> rle_loop_i);
+    ssa!        ((vm, rle_loop_v1) <int1> rle_loop_cond);
+    inst!       ((vm, rle_loop_v1) rle_loop_header_store:
+        STORE rle_loop_p rle_loop_1 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, rle_loop_v1) rle_loop_header_slt:
+        rle_loop_cond = CMPOP (CmpOp::SLT) rle_loop_i rle_loop_3
+    );
+    inst!       ((vm, rle_loop_v1) rle_loop_header_branch2:
+        BRANCH2 (rle_loop_cond)
+            IF (OP 0)
+            THEN rle_loop_latch (vec![]) WITH 0.9f32,
+            ELSE rle_loop_exit (vec![])
+    );
+
+    define_block!((vm, rle_loop_v1) rle_loop_header(rle_loop_p, rle_loop_i) {
+        rle_loop_header_store, rle_loop_header_slt, rle_loop_header_branch2
+    });
+
+    // %rle_loop_latch():
+    //     BRANCH %rle_loop_header((SHIFTIREF %p 1), (ADD %i 1))
+    ssa!        ((vm, rle_loop_v1) <iref_int64> rle_loop_next_p);
+    ssa!        ((vm, rle_loop_v1) <int64> rle_loop_next_i);
+    inst!       ((vm, rle_loop_v1) rle_loop_latch_shiftiref:
+        rle_loop_next_p = SHIFTIREF rle_loop_p rle_loop_1 (is_ptr: false)
+    );
+    inst!       ((vm, rle_loop_v1) rle_loop_latch_add:
+        rle_loop_next_i = BINOP (BinOp::Add) rle_loop_i rle_loop_1
+    );
+    inst!       ((vm, rle_loop_v1) rle_loop_latch_branch:
+        BRANCH rle_loop_header (rle_loop_next_p, rle_loop_next_i)
+    );
+
+    define_block!((vm, rle_loop_v1) rle_loop_latch() {
+        rle_loop_latch_shiftiref, rle_loop_latch_add, rle_loop_latch_branch
+    });
+
+    // %rle_loop_exit():
+    //     STORE %p 2
+    //     RET
+    inst!       ((vm, rle_loop_v1) rle_loop_exit_store:
+        STORE rle_loop_p rle_loop_2 (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+    inst!       ((vm, rle_loop_v1) rle_loop_exit_ret:
+        RET
+    );
+
+    define_block!((vm, rle_loop_v1) rle_loop_exit() {
+        rle_loop_exit_store, rle_loop_exit_ret
+    });
+
+    define_func_ver!((vm) rle_loop_v1 (entry: rle_loop_entry) {
+        rle_loop_entry, rle_loop_header, rle_loop_latch, rle_loop_exit
+    });
+
+    vm
+}
+
+#[test]
+fn test_strength_reduction() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(induction());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::StrengthReduction::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("induction");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let f_content = func_ver.content.as_ref().unwrap();
+    let block_of = |name: &str| {
+        f_content
+            .get_block(vm.id_of(name))
+            .content
+            .as_ref()
+            .unwrap()
+            .clone()
+    };
+
+    // the address of @arr[0] is computed before the loop, and passed to the header
+    let entry = block_of("ind_entry");
+    assert_eq!(entry.body.len(), 2);
+    match entry.body[0].as_inst().v {
+        Instruction_::GetElementIRef { .. } => {}
+        _ => panic!("expected GETELEMIREF")
+    }
+    let fill_header = block_of("ind_fill_header");
+    assert_eq!(fill_header.args.len(), 4);
+
+    // the store uses the address from the header, which is shifted for the next iteration
+    let fill_body = block_of("ind_fill_body");
+    assert_eq!(fill_body.body.len(), 5);
+    let store = fill_body
+        .body
+        .iter()
+        .find(|node| match node.as_inst().v {
+            Instruction_::Store { .. } => true,
+            _ => false
+        })
+        .unwrap()
+        .clone();
+    assert_eq!(
+        store.as_inst().ops[0].extract_ssa_id(),
+        Some(fill_header.args[3].id())
+    );
+    match fill_body.body[3].as_inst().v {
+        Instruction_::ShiftIRef { .. } => {}
+        _ => panic!("expected SHIFTIREF")
+    }
+    assert!(fill_body.body.iter().all(|node| match node.as_inst().v {
+        Instruction_::GetElementIRef { .. } => false,
+        _ => true
+    }));
+
+    // so does the load in the second loop
+    assert_eq!(block_of("ind_sum_header").args.len(), 3);
+}
+
+#[test]
+fn test_loop_unrolling() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(induction());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::LoopUnrolling::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("induction");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the first loop runs %n times and stays. The second loop runs 4 times, and is replaced
+    // by 5 copies of its header and 4 copies of its body
+    let f_content = func_ver.content.as_ref().unwrap();
+    assert!(f_content.blocks.contains_key(&vm.id_of("ind_fill_header")));
+    assert!(!f_content.blocks.contains_key(&vm.id_of("ind_sum_header")));
+    assert!(!f_content.blocks.contains_key(&vm.id_of("ind_sum_body")));
+    assert_eq!(f_content.blocks.len(), 14);
+
+    // the copies form a straight line from ind_sum_entry to ind_sum_exit
+    let mut id = vm.id_of("ind_sum_entry");
+    let mut n_blocks = 0;
+    while id != vm.id_of("ind_sum_exit") {
+        let succs = f_content.get_successors(id);
+        assert_eq!(succs.len(), 1);
+        id = succs[0];
+        n_blocks += 1;
+    }
+    assert_eq!(n_blocks, 10);
+}
+
+#[test]
+fn test_induction_run() {
+    build_and_run_test!(induction, induction_test1);
+    build_and_run_test!(induction, induction_test2);
+}
+
+fn induction() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1       = mu_int(1));
+    typedef!    ((vm) int64      = mu_int(64));
+    typedef!    ((vm) iref_int64 = mu_iref(int64));
+    typedef!    ((vm) array4     = mu_array(int64, 4));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+    constdef!   ((vm) <int64> int64_1 = Constant::Int(1));
+    constdef!   ((vm) <int64> int64_4 = Constant::Int(4));
+    globaldef!  ((vm) <array4> ind_arr);
+
+    funcsig!    ((vm) ind_sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <ind_sig> induction);
+    funcdef!    ((vm) <ind_sig> induction VERSION induction_v1);
+
+    block!      ((vm, induction_v1) ind_entry);
+    block!      ((vm, induction_v1) ind_fill_header);
+    block!      ((vm, induction_v1) ind_fill_body);
+    block!      ((vm, induction_v1) ind_sum_entry);
+    block!      ((vm, induction_v1) ind_sum_header);
+    block!      ((vm, induction_v1) ind_sum_body);
+    block!      ((vm, induction_v1) ind_sum_exit);
+    consta!     ((vm, induction_v1) int64_0_local = int64_0);
+    consta!     ((vm, induction_v1) int64_1_local = int64_1);
+    consta!     ((vm, induction_v1) int64_4_local = int64_4);
+    global!     ((vm, induction_v1) ind_arr_local = ind_arr);
+
+    // %ind_entry(<@int64> %n, <@int64> %m):
+    //     BRANCH %ind_fill_header(%n, %m, 0)
+    ssa!        ((vm, induction_v1) <int64> ind_n);
+    ssa!        ((vm, induction_v1) <int64> ind_m);
+    inst!       ((vm, induction_v1) ind_entry_branch:
+        BRANCH ind_fill_header (ind_n, ind_m, int64_0_local)
+    );
+
+    define_block!((vm, induction_v1) ind_entry(ind_n, ind_m) {
+        ind_entry_branch
+    });
+
+    // %ind_fill_header(<@int64> %fh_n, <@int64> %fh_m, <@int64> %fh_i):
+    ssa!        ((vm, induction_v1) <int64> ind_fh_n);
+    ssa!        ((vm, induction_v1) <int64> ind_fh_m);
+    ssa!        ((vm, induction_v1) <int64> ind_fh_i);
+
+    // %fh_lt = SLT %fh_i %fh_n
+    ssa!        ((vm, induction_v1) <int1> ind_fh_lt);
+    inst!       ((vm, induction_v1) ind_fill_header_slt:
+        ind_fh_lt = CMPOP (CmpOp::SLT) ind_fh_i ind_fh_n
+    );
+
+    // BRANCH2 %fh_lt %ind_fill_body(%fh_n, %fh_m, %fh_i) %ind_sum_entry()
+    inst!       ((vm, induction_v1) ind_fill_header_branch2:
+        BRANCH2 (ind_fh_lt, ind_fh_n, ind_fh_m, ind_fh_i)
+            IF (OP 0)
+            THEN ind_fill_body (vec![1, 2, 3]) WITH 0.9f32,
+            ELSE ind_sum_entry (vec![])
+    );
+
+    define_block!((vm, induction_v1) ind_fill_header(ind_fh_n, ind_fh_m, ind_fh_i) {
+        ind_fill_header_slt,
+        ind_fill_header_branch2
+    });
+
+    // %ind_fill_body(<@int64> %fb_n, <@int64> %fb_m, <@int64> %fb_i):
+    ssa!        ((vm, induction_v1) <int64> ind_fb_n);
+    ssa!        ((vm, induction_v1) <int64> ind_fb_m);
+    ssa!        ((vm, induction_v1) <int64> ind_fb_i);
+
+    // %fb_p = GETELEMIREF @arr %fb_i
+    ssa!        ((vm, induction_v1) <iref_int64> ind_fb_p);
+    inst!       ((vm, induction_v1) ind_fill_body_getelemiref:
+        ind_fb_p = GETELEMIREF ind_arr_local ind_fb_i (is_ptr: false)
+    );
+
+    // %fb_x = ADD %fb_i %fb_m
+    ssa!        ((vm, induction_v1) <int64> ind_fb_x);
+    inst!       ((vm, induction_v1) ind_fill_body_add1:
+        ind_fb_x = BINOP (BinOp::Add) ind_fb_i ind_fb_m
+    );
+
+    // STORE %fb_p %fb_x
+    inst!       ((vm, induction_v1) ind_fill_body_store:
+        STORE ind_fb_p ind_fb_x (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %fb_next = ADD %fb_i 1
+    ssa!        ((vm, induction_v1) <int64> ind_fb_next);
+    inst!       ((vm, induction_v1) ind_fill_body_add2:
+        ind_fb_next = BINOP (BinOp::Add) ind_fb_i int64_1_local
+    );
+
+    // BRANCH %ind_fill_header(%fb_n, %fb_m, %fb_next)
+    inst!       ((vm, induction_v1) ind_fill_body_branch:
+        BRANCH ind_fill_header (ind_fb_n, ind_fb_m, ind_fb_next)
+    );
+
+    define_block!((vm, induction_v1) ind_fill_body(ind_fb_n, ind_fb_m, ind_fb_i) {
+        ind_fill_body_getelemiref,
+        ind_fill_body_add1,
+        ind_fill_body_store,
+        ind_fill_body_add2,
+        ind_fill_body_branch
+    });
+
+    // %ind_sum_entry():
+    //     BRANCH %ind_sum_header(0, 0)
+    inst!       ((vm, induction_v1) ind_sum_entry_branch:
+        BRANCH ind_sum_header (int64_0_local, int64_0_local)
+    );
+
+    define_block!((vm, induction_v1) ind_sum_entry() {
+        ind_sum_entry_branch
+    });
+
+    // %ind_sum_header(<@int64> %sh_i, <@int64> %sh_acc):
+    ssa!        ((vm, induction_v1) <int64> ind_sh_i);
+    ssa!        ((vm, induction_v1) <int64> ind_sh_acc);
+
+    // %sh_lt = SLT %sh_i 4
+    ssa!        ((vm, induction_v1) <int1> ind_sh_lt);
+    inst!       ((vm, induction_v1) ind_sum_header_slt:
+        ind_sh_lt = CMPOP (CmpOp::SLT) ind_sh_i int64_4_local
+    );
+
+    // BRANCH2 %sh_lt %ind_sum_body(%sh_i, %sh_acc) %ind_sum_exit(%sh_acc)
+    inst!       ((vm, induction_v1) ind_sum_header_branch2:
+        BRANCH2 (ind_sh_lt, ind_sh_i, ind_sh_acc)
+            IF (OP 0)
+            THEN ind_sum_body (vec![1, 2]) WITH 0.9f32,
+            ELSE ind_sum_exit (vec![2])
+    );
+
+    define_block!((vm, induction_v1) ind_sum_header(ind_sh_i, ind_sh_acc) {
+        ind_sum_header_slt,
+        ind_sum_header_branch2
+    });
+
+    // %ind_sum_body(<@int64> %sb_i, <@int64> %sb_acc):
+    ssa!        ((vm, induction_v1) <int64> ind_sb_i);
+    ssa!        ((vm, induction_v1) <int64> ind_sb_acc);
+
+    // %sb_p = GETELEMIREF @arr %sb_i
+    ssa!        ((vm, induction_v1) <iref_int64> ind_sb_p);
+    inst!       ((vm, induction_v1) ind_sum_body_getelemiref:
+        ind_sb_p = GETELEMIREF ind_arr_local ind_sb_i (is_ptr: false)
+    );
+
+    // %sb_v = LOAD %sb_p
+    ssa!        ((vm, induction_v1) <int64> ind_sb_v);
+    inst!       ((vm, induction_v1) ind_sum_body_load:
+        ind_sb_v = LOAD ind_sb_p (is_ptr: false, order: MemoryOrder::NotAtomic)
+    );
+
+    // %sb_next_acc = ADD %sb_acc %sb_v
+    ssa!        ((vm, induction_v1) <int64> ind_sb_next_acc);
+    inst!       ((vm, induction_v1) ind_sum_body_add1:
+        ind_sb_next_acc = BINOP (BinOp::Add) ind_sb_acc ind_sb_v
+    );
+
+    // %sb_next = ADD %sb_i 1
+    ssa!        ((vm, induction_v1) <int64> ind_sb_next);
+    inst!       ((vm, induction_v1) ind_sum_body_add2:
+        ind_sb_next = BINOP (BinOp::Add) ind_sb_i int64_1_local
+    );
+
+    // BRANCH %ind_sum_header(%sb_next, %sb_next_acc)
+    inst!       ((vm, induction_v1) ind_sum_body_branch:
+        BRANCH ind_sum_header (ind_sb_next, ind_sb_next_acc)
+    );
+
+    define_block!((vm, induction_v1) ind_sum_body(ind_sb_i, ind_sb_acc) {
+        ind_sum_body_getelemiref,
+        ind_sum_body_load,
+        ind_sum_body_add1,
+        ind_sum_body_add2,
+        ind_sum_body_branch
+    });
+
+    // %ind_sum_exit(<@int64> %r):
+    //     RET %r
+    ssa!        ((vm, induction_v1) <int64> ind_r);
+    inst!       ((vm, induction_v1) ind_sum_exit_ret:
+        RET (ind_r)
+    );
+
+    define_block!((vm, induction_v1) ind_sum_exit(ind_r) {
+        ind_sum_exit_ret
+    });
+
+    define_func_ver!((vm) induction_v1 (entry: ind_entry) {
+        ind_entry, ind_fill_header, ind_fill_body,
+        ind_sum_entry, ind_sum_header, ind_sum_body, ind_sum_exit
+    });
+
+    // @arr = {1, 2, 3, 4}
+    emit_test! ((vm)
+        induction, induction_test1, induction_test1_v1,
+        Int, Int RET Int,
+        EQ,
+        ind_sig,
+        int64(4), int64(1) RET int64(10),
+    );
+    // @arr = {5, 6, 0, 0}
+    emit_test! ((vm)
+        induction, induction_test2, induction_test2_v1,
+        Int, Int RET Int,
+        EQ,
+        ind_sig,
+        int64(2), int64(5) RET int64(11),
+    );
+
+    vm
+}
+
+#[test]
+fn test_profile_instrumentation() {
+    VM::start_logging_trace();
+
+    let profile_path = env::temp_dir().join("test_profile_instrumentation.profile");
+    let profile_path = profile_path.to_str().unwrap();
+    let vm = Arc::new(pgo(&format!("init_mu --profile-generate={}", profile_path)));
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::ProfileInstrumentation::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("pgo");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // every block starts by incrementing its counter
+    let f_content = func_ver.content.as_ref().unwrap();
+    for &(name, len) in [("pgo_entry", 2), ("pgo_then", 2), ("pgo_else", 1)].iter() {
+        let ref body = f_content
+            .get_block(vm.id_of(name))
+            .content
+            .as_ref()
+            .unwrap()
+            .body;
+        assert_eq!(body.len(), len + 2);
+        match body[1].as_inst().v {
+            Instruction_::AtomicRMW {
+                order: MemoryOrder::Relaxed,
+                op: AtomicRMWOp::ADD,
+                ..
+            } => {}
+            _ => panic!("expected ATOMICRMW RELAXED ADD")
+        }
+    }
+
+    // pgo_entry ran 5 times, and pgo_then 3 times
+    let global = {
+        let profile_counters = vm.profile_counters().read().unwrap();
+        let counters = profile_counters.get(&func_ver.id()).unwrap();
+        assert_eq!(*counters.func, "pgo");
+        assert_eq!(counters.blocks.len(), 3);
+        assert_eq!(*counters.blocks[1], "pgo_then");
+        assert_eq!(counters.callsites.len(), 1);
+        assert_eq!(counters.callsites[0].block, 1);
+        counters.global.id()
+    };
+    let counts = vm.global_locations()
+        .read()
+        .unwrap()
+        .get(&global)
+        .unwrap()
+        .to_address();
+    unsafe {
+        counts.store::<u64>(5);
+        counts.shift::<u64>(1).store::<u64>(3);
+    }
+    vm.write_profile();
+
+    let profile = profile::Profile::read(profile_path);
+    let func_profile = profile.get_func("pgo").unwrap();
+    assert_eq!(func_profile.block_count("pgo_entry"), Some(5));
+    assert_eq!(func_profile.block_count("pgo_else"), Some(0));
+    assert_eq!(func_profile.call_count("pgo_then", "pgo_inc"), Some(3));
+}
+
+#[test]
+fn test_profile_guided_trace() {
+    VM::start_logging_trace();
+
+    // pgo_then is more likely, but it never ran
+    let profile_path = env::temp_dir().join("test_profile_guided_trace.profile");
+    let profile_path = profile_path.to_str().unwrap();
+    let mut file = File::create(profile_path).unwrap();
+    writeln!(file, "func pgo").unwrap();
+    writeln!(file, "block pgo_entry 10").unwrap();
+    writeln!(file, "block pgo_then 0").unwrap();
+    writeln!(file, "block pgo_else 10").unwrap();
+
+    let vm = Arc::new(pgo(&format!("init_mu --profile-use={}", profile_path)));
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![
+            Box::new(passes::ProfileInstrumentation::new()),
+            Box::new(passes::ControlFlowAnalysis::new()),
+            Box::new(passes::TraceGen::new()),
+        ]),
+        &vm
+    );
+
+    let func_id = vm.id_of("pgo");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    let trace = func_ver.block_trace.as_ref().unwrap();
+    assert_eq!(
+        trace,
+        &vec![
+            vm.id_of("pgo_entry"),
+            vm.id_of("pgo_else"),
+            vm.id_of("pgo_then"),
+        ]
+    );
+    let f_content = func_ver.content.as_ref().unwrap();
+    assert!(f_content.get_block(vm.id_of("pgo_then")).trace_hint == TraceHint::SlowPath);
+}
+
+#[test]
+fn test_profile_guided_inlining() {
+    VM::start_logging_trace();
+
+    // the call to @pgo_inc never ran
+    let profile_path = env::temp_dir().join("test_profile_guided_inlining.profile");
+    let profile_path = profile_path.to_str().unwrap();
+    let mut file = File::create(profile_path).unwrap();
+    writeln!(file, "func pgo").unwrap();
+    writeln!(file, "block pgo_then 0").unwrap();
+    writeln!(file, "call pgo_then pgo_inc 0").unwrap();
+
+    let vm = Arc::new(pgo(&format!("init_mu --profile-use={}", profile_path)));
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![
+            Box::new(passes::ProfileInstrumentation::new()),
+            Box::new(passes::Inlining::new()),
+        ]),
+        &vm
+    );
+
+    let func_id = vm.id_of("pgo");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // @pgo_inc is small enough, but the call is kept
+    assert_eq!(func_ver.get_static_call_edges().len(), 1);
+    assert_eq!(func_ver.content.as_ref().unwrap().blocks.len(), 3);
+}
+
+#[test]
+fn test_profile_counts_after_inlining() {
+    VM::start_logging_trace();
+
+    // @pgo_inc is called from elsewhere as well, 4 of its 8 runs are from %pgo_then
+    let profile_path = env::temp_dir().join("test_profile_counts_after_inlining.profile");
+    let profile_path = profile_path.to_str().unwrap();
+    let mut file = File::create(profile_path).unwrap();
+    writeln!(file, "func pgo").unwrap();
+    writeln!(file, "block pgo_entry 10").unwrap();
+    writeln!(file, "block pgo_then 4").unwrap();
+    writeln!(file, "block pgo_else 6").unwrap();
+    writeln!(file, "call pgo_then pgo_inc 4").unwrap();
+    writeln!(file, "func pgo_inc").unwrap();
+    writeln!(file, "block pgo_inc_entry 8").unwrap();
+
+    let vm = Arc::new(pgo(&format!("init_mu --profile-use={}", profile_path)));
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![
+            Box::new(passes::ProfileInstrumentation::new()),
+            Box::new(passes::Inlining::new()),
+        ]),
+        &vm
+    );
+
+    let func_id = vm.id_of("pgo");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // the call is inlined, and the new blocks run as many times as the call
+    assert_eq!(func_ver.get_static_call_edges().len(), 0);
+    let f_content = func_ver.content.as_ref().unwrap();
+    assert_eq!(f_content.get_block(vm.id_of("pgo_entry")).exec_count, Some(10));
+    let mut new_blocks = 0;
+    for block in f_content.blocks.values() {
+        let name = block.hdr.name();
+        if name.contains("inlinedblock") || name.contains("_cont_after_inline_") {
+            assert_eq!(block.exec_count, Some(4));
+            new_blocks += 1;
+        }
+    }
+    assert_eq!(new_blocks, 2);
+}
+
+fn pgo(opts: &str) -> VM {
+    let vm = VM::new_with_opts(opts);
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> pgo_int64_1 = Constant::Int(1));
+
+    funcsig!    ((vm) pgo_inc_sig = (int64) -> (int64));
+    funcdecl!   ((vm) <pgo_inc_sig> pgo_inc);
+    funcsig!    ((vm) pgo_sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <pgo_sig> pgo);
+    typedef!    ((vm) pgo_inc_ref = mu_funcref(pgo_inc_sig));
+    constdef!   ((vm) <pgo_inc_ref> pgo_inc_const = Constant::FuncRef(pgo_inc));
+
+    {
+        // @pgo_inc(<@int64> %x): RET (%x + 1)
+        funcdef!    ((vm) <pgo_inc_sig> pgo_inc VERSION pgo_inc_v1);
+
+        block!      ((vm, pgo_inc_v1) pgo_inc_entry);
+        ssa!        ((vm, pgo_inc_v1) <int64> pgo_inc_x);
+        ssa!        ((vm, pgo_inc_v1) <int64> pgo_inc_r);
+        consta!     ((vm, pgo_inc_v1) pgo_inc_1 = pgo_int64_1);
+        inst!       ((vm, pgo_inc_v1) pgo_inc_add:
+            pgo_inc_r = BINOP (BinOp::Add) pgo_inc_x pgo_inc_1
+        );
+        inst!       ((vm, pgo_inc_v1) pgo_inc_ret:
+            RET (pgo_inc_r)
+        );
+
+        define_block!((vm, pgo_inc_v1) pgo_inc_entry(pgo_inc_x) {
+            pgo_inc_add, pgo_inc_ret
+        });
+
+        define_func_ver!((vm) pgo_inc_v1 (entry: pgo_inc_entry) {pgo_inc_entry});
+    }
+
+    {
+        funcdef!    ((vm) <pgo_sig> pgo VERSION pgo_v1);
+
+        block!      ((vm, pgo_v1) pgo_entry);
+        block!      ((vm, pgo_v1) pgo_then);
+        block!      ((vm, pgo_v1) pgo_else);
+
+        // %pgo_entry(<@int64> %a, <@int64> %b):
+        //     BRANCH2 (SLT %a %b) %pgo_then(%a) %pgo_else(%b)
+        ssa!        ((vm, pgo_v1) <int64> pgo_a);
+        ssa!        ((vm, pgo_v1) <int64> pgo_b);
+        ssa!        ((vm, pgo_v1) <int1> pgo_cond);
+        inst!       ((vm, pgo_v1) pgo_entry_slt:
+            pgo_cond = CMPOP (CmpOp::SLT) pgo_a pgo_b
+        );
+        inst!       ((vm, pgo_v1) pgo_entry_branch2:
+            BRANCH2 (pgo_cond, pgo_a, pgo_b)
+                IF (OP 0)
+                THEN pgo_then (vec![1]) WITH 0.9f32,
+                ELSE pgo_else (vec![2])
+        );
+
+        define_block!((vm, pgo_v1) pgo_entry(pgo_a, pgo_b) {
+            pgo_entry_slt, pgo_entry_branch2
+        });
+
+        // %pgo_then(<@int64> %t):
+        //     RET (CALL @pgo_inc(%t))
+        ssa!        ((vm, pgo_v1) <int64> pgo_t);
+        ssa!        ((vm, pgo_v1) <int64> pgo_t_r);
+        consta!     ((vm, pgo_v1) pgo_inc_local = pgo_inc_const);
+        inst!       ((vm, pgo_v1) pgo_then_call:
+            pgo_t_r = EXPRCALL (CallConvention::Mu, is_abort: false) pgo_inc_local (pgo_t)
+        );
+        inst!       ((vm, pgo_v1) pgo_then_ret:
+            RET (pgo_t_r)
+        );
+
+        define_block!((vm, pgo_v1) pgo_then(pgo_t) {
+            pgo_then_call, pgo_then_ret
+        });
+
+        // %pgo_else(<@int64> %e):
+        //     RET %e
+        ssa!        ((vm, pgo_v1) <int64> pgo_e);
+        inst!       ((vm, pgo_v1) pgo_else_ret:
+            RET (pgo_e)
+        );
+
+        define_block!((vm, pgo_v1) pgo_else(pgo_e) {
+            pgo_else_ret
+        });
+
+        define_func_ver!((vm) pgo_v1 (entry: pgo_entry) {
+            pgo_entry, pgo_then, pgo_else
+        });
+    }
+
+    vm
+}
+
+#[test]
+fn test_inline_cost() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(inline_cost());
+
+    // @ic_callee is too big to inline at a plain call site, but not in a loop, or when a
+    // constant argument feeds its arithmetic
+    assert_eq!(calls_after_inlining(&vm, "ic_top"), 1);
+    assert_eq!(calls_after_inlining(&vm, "ic_loop"), 0);
+    assert_eq!(calls_after_inlining(&vm, "ic_const"), 0);
+
+    // the budget of @ic_many allows inlining 3 of its 5 calls
+    assert_eq!(calls_after_inlining(&vm, "ic_many"), 2);
+
+    // @ic_mid is inlined, and then the call to @ic_leaf that came with it
+    assert_eq!(calls_after_inlining(&vm, "ic_nested"), 0);
+}
+
+#[test]
+fn test_inline_throw() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(inline_cost());
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::Inlining::new())]),
+        &vm
+    );
+
+    let func_id = vm.id_of("ic_catch");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+
+    // THROW in @ic_thrower branches to the handler, which takes the exception as a normal
+    // argument. Nothing else went to the handler, so there is no landing block for it
+    assert_eq!(func_ver.get_static_call_edges().len(), 0);
+    let f_content = func_ver.content.as_ref().unwrap();
+    assert_eq!(f_content.blocks.len(), 4);
+    let exn_id = vm.id_of("ic_catch_exn");
+    let exn = f_content.get_block(exn_id).content.as_ref().unwrap();
+    assert!(exn.exn_arg.is_none());
+    assert_eq!(exn.args.len(), 1);
+
+    let throwing = f_content
+        .blocks
+        .values()
+        .find(|block| block.name().contains("inlinedblock"))
+        .unwrap();
+    let branch = throwing.content.as_ref().unwrap().body.last().unwrap().clone();
+    match branch.as_inst().v {
+        Instruction_::Branch1(ref dest) => {
+            assert_eq!(dest.target.id(), exn_id);
+            assert_eq!(dest.args.len(), 1);
+        }
+        _ => panic!("expected BRANCH")
+    }
+}
+
+/// runs Inlining on a function, returns how many calls are left in it
+fn calls_after_inlining(vm: &VM, name: &str) -> usize {
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::Inlining::new())]),
+        vm
+    );
+
+    let func_id = vm.id_of(name);
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    compiler.compile(&mut func_ver);
+    func_ver.get_static_call_edges().len()
+}
+
+fn inline_cost() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1      = mu_int(1));
+    typedef!    ((vm) int64     = mu_int(64));
+    typedef!    ((vm) ref_int64 = mu_ref(int64));
+    constdef!   ((vm) <int64> ic_int64_0  = Constant::Int(0));
+    constdef!   ((vm) <int64> ic_int64_1  = Constant::Int(1));
+    constdef!   ((vm) <int64> ic_int64_10 = Constant::Int(10));
+
+    funcsig!    ((vm) ic_callee_sig = (int64, int64) -> (int64));
+    funcdecl!   ((vm) <ic_callee_sig> ic_callee);
+    funcsig!    ((vm) ic_sig = (int64) -> (int64));
+    funcdecl!   ((vm) <ic_sig> ic_top);
+    funcdecl!   ((vm) <ic_sig> ic_loop);
+    funcdecl!   ((vm) <ic_sig> ic_const);
+    funcdecl!   ((vm) <ic_sig> ic_many);
+    funcdecl!   ((vm) <ic_sig> ic_leaf);
+    funcdecl!   ((vm) <ic_sig> ic_mid);
+    funcdecl!   ((vm) <ic_sig> ic_nested);
+    funcsig!    ((vm) ic_thrower_sig = (ref_int64) -> ());
+    funcdecl!   ((vm) <ic_thrower_sig> ic_thrower);
+    funcsig!    ((vm) ic_catch_sig = (ref_int64) -> (int64));
+    funcdecl!   ((vm) <ic_catch_sig> ic_catch);
+
+    typedef!    ((vm) ic_callee_ref = mu_funcref(ic_callee_sig));
+    constdef!   ((vm) <ic_callee_ref> ic_callee_const = Constant::FuncRef(ic_callee));
+    typedef!    ((vm) ic_ref = mu_funcref(ic_sig));
+    constdef!   ((vm) <ic_ref> ic_leaf_const = Constant::FuncRef(ic_leaf));
+    constdef!   ((vm) <ic_ref> ic_mid_const = Constant::FuncRef(ic_mid));
+    typedef!    ((vm) ic_thrower_ref = mu_funcref(ic_thrower_sig));
+    constdef!   ((vm) <ic_thrower_ref> ic_thrower_const = Constant::FuncRef(ic_thrower));
+
+    {
+        // @ic_callee(<@int64> %x, <@int64> %y): 31 instructions
+        //     %t = MUL (ADD %x %y) %y
+        //     RET (%t + %x + %x ... (28 times))
+        funcdef!    ((vm) <ic_callee_sig> ic_callee VERSION ic_callee_v1);
+
+        block!      ((vm, ic_callee_v1) ic_callee_entry);
+        ssa!        ((vm, ic_callee_v1) <int64> ic_callee_x);
+        ssa!        ((vm, ic_callee_v1) <int64> ic_callee_y);
+        ssa!        ((vm, ic_callee_v1) <int64> ic_callee_s);
+        ssa!        ((vm, ic_callee_v1) <int64> ic_callee_t);
+        inst!       ((vm, ic_callee_v1) ic_callee_add:
+            ic_callee_s = BINOP (BinOp::Add) ic_callee_x ic_callee_y
+        );
+        inst!       ((vm, ic_callee_v1) ic_callee_mul:
+            ic_callee_t = BINOP (BinOp::Mul) ic_callee_s ic_callee_y
+        );
+
+        let mut body = vec![ic_callee_add, ic_callee_mul];
+        let mut last = ic_callee_t;
+        for _ in 0..28 {
+            let next = ic_callee_v1.new_ssa(MuEntityHeader::unnamed(vm.next_id()), int64.clone());
+            body.push(ic_callee_v1.new_inst(Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: Some(vec![next.clone_value()]),
+                ops: vec![last, ic_callee_x.clone()],
+                v: Instruction_::BinOp(BinOp::Add, 0, 1)
+            }));
+            last = next;
+        }
+        inst!       ((vm, ic_callee_v1) ic_callee_ret:
+            RET (last)
+        );
+        body.push(ic_callee_ret);
+
+        ic_callee_entry.content = Some(BlockContent {
+            args: vec![ic_callee_x.clone_value(), ic_callee_y.clone_value()],
+            exn_arg: None,
+            body: body,
+            keepalives: None
+        });
+
+        define_func_ver!((vm) ic_callee_v1 (entry: ic_callee_entry) {ic_callee_entry});
+    }
+
+    {
+        // @ic_top(<@int64> %a): RET (CALL @ic_callee(%a, %a))
+        funcdef!    ((vm) <ic_sig> ic_top VERSION ic_top_v1);
+
+        block!      ((vm, ic_top_v1) ic_top_entry);
+        ssa!        ((vm, ic_top_v1) <int64> ic_top_a);
+        ssa!        ((vm, ic_top_v1) <int64> ic_top_r);
+        consta!     ((vm, ic_top_v1) ic_top_callee = ic_callee_const);
+        inst!       ((vm, ic_top_v1) ic_top_call:
+            ic_top_r = EXPRCALL (CallConvention::Mu, is_abort: false)
+                ic_top_callee (ic_top_a, ic_top_a)
+        );
+        inst!       ((vm, ic_top_v1) ic_top_ret:
+            RET (ic_top_r)
+        );
+
+        define_block!((vm, ic_top_v1) ic_top_entry(ic_top_a) {ic_top_call, ic_top_ret});
+
+        define_func_ver!((vm) ic_top_v1 (entry: ic_top_entry) {ic_top_entry});
+    }
+
+    {
+        // @ic_loop(<@int64> %n):
+        //     BRANCH %header(%n, %n)
+        // %header(<@int64> %i, <@int64> %s):
+        //     BRANCH2 (SGT %i 0) %body(%i, %s) %exit(%s)
+        // %body(<@int64> %bi, <@int64> %bs):
+        //     BRANCH %header((SUB %bi 1), (CALL @ic_callee(%bs, %bi)))
+        // %exit(<@int64> %es):
+        //     RET %es
+        funcdef!    ((vm) <ic_sig> ic_loop VERSION ic_loop_v1);
+
+        block!      ((vm, ic_loop_v1) ic_loop_entry);
+        block!      ((vm, ic_loop_v1) ic_loop_header);
+        block!      ((vm, ic_loop_v1) ic_loop_body);
+        block!      ((vm, ic_loop_v1) ic_loop_exit);
+
+        ssa!        ((vm, ic_loop_v1) <int64> ic_loop_n);
+        inst!       ((vm, ic_loop_v1) ic_loop_entry_branch:
+            BRANCH ic_loop_header (ic_loop_n, ic_loop_n)
+        );
+        define_block!((vm, ic_loop_v1) ic_loop_entry(ic_loop_n) {ic_loop_entry_branch});
+
+        ssa!        ((vm, ic_loop_v1) <int64> ic_loop_i);
+        ssa!        ((vm, ic_loop_v1) <int64> ic_loop_s);
+        ssa!        ((vm, ic_loop_v1) <int1> ic_loop_cond);
+        consta!     ((vm, ic_loop_v1) ic_loop_0 = ic_int64_0);
+        inst!       ((vm, ic_loop_v1) ic_loop_sgt:
+            ic_loop_cond = CMPOP (CmpOp::SGT) ic_loop_i ic_loop_0
+        );
+        inst!       ((vm, ic_loop_v1) ic_loop_branch2:
+            BRANCH2 (ic_loop_cond, ic_loop_i, ic_loop_s)
+                IF (OP 0)
+                THEN ic_loop_body (vec![1, 2]) WITH 0.9f32,
+                ELSE ic_loop_exit (vec![2])
+        );
+        define_block!((vm, ic_loop_v1) ic_loop_header(ic_loop_i, ic_loop_s) {
+            ic_loop_sgt, ic_loop_branch2
+        });
+
+        ssa!        ((vm, ic_loop_v1) <int64> ic_loop_bi);
+        ssa!        ((vm, ic_loop_v1) <int64> ic_loop_bs);
+        ssa!        ((vm, ic_loop_v1) <int64> ic_loop_r);
+        ssa!        ((vm, ic_loop_v1) <int64> ic_loop_next);
+        consta!     ((vm, ic_loop_v1) ic_loop_callee = ic_callee_const);
+        consta!     ((vm, ic_loop_v1) ic_loop_1 = ic_int64_1);
+        inst!       ((vm, ic_loop_v1) ic_loop_call:
+            ic_loop_r = EXPRCALL (CallConvention::Mu, is_abort: false)
+                ic_loop_callee (ic_loop_bs, ic_loop_bi)
+        );
+        inst!       ((vm, ic_loop_v1) ic_loop_sub:
+            ic_loop_next = BINOP (BinOp::Sub) ic_loop_bi ic_loop_1
+        );
+        inst!       ((vm, ic_loop_v1) ic_loop_back:
+            BRANCH ic_loop_header (ic_loop_next, ic_loop_r)
+        );
+        define_block!((vm, ic_loop_v1) ic_loop_body(ic_loop_bi, ic_loop_bs) {
+            ic_loop_call, ic_loop_sub, ic_loop_back
+        });
+
+        ssa!        ((vm, ic_loop_v1) <int64> ic_loop_es);
+        inst!       ((vm, ic_loop_v1) ic_loop_ret:
+            RET (ic_loop_es)
+        );
+        define_block!((vm, ic_loop_v1) ic_loop_exit(ic_loop_es) {ic_loop_ret});
+
+        define_func_ver!((vm) ic_loop_v1 (entry: ic_loop_entry) {
+            ic_loop_entry, ic_loop_header, ic_loop_body, ic_loop_exit
+        });
+    }
+
+    {
+        // @ic_const(<@int64> %a): RET (CALL @ic_callee(%a, 10))
+        funcdef!    ((vm) <ic_sig> ic_const VERSION ic_const_v1);
+
+        block!      ((vm, ic_const_v1) ic_const_entry);
+        ssa!        ((vm, ic_const_v1) <int64> ic_const_a);
+        ssa!        ((vm, ic_const_v1) <int64> ic_const_r);
+        consta!     ((vm, ic_const_v1) ic_const_callee = ic_callee_const);
+        consta!     ((vm, ic_const_v1) ic_const_10 = ic_int64_10);
+        inst!       ((vm, ic_const_v1) ic_const_call:
+            ic_const_r = EXPRCALL (CallConvention::Mu, is_abort: false)
+                ic_const_callee (ic_const_a, ic_const_10)
+        );
+        inst!       ((vm, ic_const_v1) ic_const_ret:
+            RET (ic_const_r)
+        );
+
+        define_block!((vm, ic_const_v1) ic_const_entry(ic_const_a) {
+            ic_const_call, ic_const_ret
+        });
+
+        define_func_ver!((vm) ic_const_v1 (entry: ic_const_entry) {ic_const_entry});
+    }
+
+    {
+        // @ic_many(<@int64> %a):
+        //     %r1 = CALL @ic_callee(%a, 10)
+        //     ...
+        //     %r5 = CALL @ic_callee(%r4, 10)
+        //     RET %r5
+        funcdef!    ((vm) <ic_sig> ic_many VERSION ic_many_v1);
+
+        block!      ((vm, ic_many_v1) ic_many_entry);
+        ssa!        ((vm, ic_many_v1) <int64> ic_many_a);
+        consta!     ((vm, ic_many_v1) ic_many_callee = ic_callee_const);
+        consta!     ((vm, ic_many_v1) ic_many_10 = ic_int64_10);
+
+        let mut body = vec![];
+        let mut last = ic_many_a.clone();
+        for _ in 0..5 {
+            let next = ic_many_v1.new_ssa(MuEntityHeader::unnamed(vm.next_id()), int64.clone());
+            body.push(ic_many_v1.new_inst(Instruction {
+                hdr: MuEntityHeader::unnamed(vm.next_id()),
+                value: Some(vec![next.clone_value()]),
+                ops: vec![ic_many_callee.clone(), last, ic_many_10.clone()],
+                v: Instruction_::ExprCall {
+                    data: CallData {
+                        func: 0,
+                        args: vec![1, 2],
+                        convention: CallConvention::Mu
+                    },
+                    is_abort: false
+                }
+            }));
+            last = next;
+        }
+        inst!       ((vm, ic_many_v1) ic_many_ret:
+            RET (last)
+        );
+        body.push(ic_many_ret);
+
+        ic_many_entry.content = Some(BlockContent {
+            args: vec![ic_many_a.clone_value()],
+            exn_arg: None,
+            body: body,
+            keepalives: None
+        });
+
+        define_func_ver!((vm) ic_many_v1 (entry: ic_many_entry) {ic_many_entry});
+    }
+
+    {
+        // @ic_leaf(<@int64> %x): RET (%x + 1)
+        funcdef!    ((vm) <ic_sig> ic_leaf VERSION ic_leaf_v1);
+
+        block!      ((vm, ic_leaf_v1) ic_leaf_entry);
+        ssa!        ((vm, ic_leaf_v1) <int64> ic_leaf_x);
+        ssa!        ((vm, ic_leaf_v1) <int64> ic_leaf_r);
+        consta!     ((vm, ic_leaf_v1) ic_leaf_1 = ic_int64_1);
+        inst!       ((vm, ic_leaf_v1) ic_leaf_add:
+            ic_leaf_r = BINOP (BinOp::Add) ic_leaf_x ic_leaf_1
+        );
+        inst!       ((vm, ic_leaf_v1) ic_leaf_ret:
+            RET (ic_leaf_r)
+        );
+
+        define_block!((vm, ic_leaf_v1) ic_leaf_entry(ic_leaf_x) {ic_leaf_add, ic_leaf_ret});
+
+        define_func_ver!((vm) ic_leaf_v1 (entry: ic_leaf_entry) {ic_leaf_entry});
+    }
+
+    {
+        // @ic_mid(<@int64> %x): RET (CALL @ic_leaf(%x))
+        funcdef!    ((vm) <ic_sig> ic_mid VERSION ic_mid_v1);
+
+        block!      ((vm, ic_mid_v1) ic_mid_entry);
+        ssa!        ((vm, ic_mid_v1) <int64> ic_mid_x);
+        ssa!        ((vm, ic_mid_v1) <int64> ic_mid_r);
+        consta!     ((vm, ic_mid_v1) ic_mid_leaf = ic_leaf_const);
+        inst!       ((vm, ic_mid_v1) ic_mid_call:
+            ic_mid_r = EXPRCALL (CallConvention::Mu, is_abort: false) ic_mid_leaf (ic_mid_x)
+        );
+        inst!       ((vm, ic_mid_v1) ic_mid_ret:
+            RET (ic_mid_r)
+        );
+
+        define_block!((vm, ic_mid_v1) ic_mid_entry(ic_mid_x) {ic_mid_call, ic_mid_ret});
+
+        define_func_ver!((vm) ic_mid_v1 (entry: ic_mid_entry) {ic_mid_entry});
+    }
+
+    {
+        // @ic_nested(<@int64> %a): RET (CALL @ic_mid(%a))
+        funcdef!    ((vm) <ic_sig> ic_nested VERSION ic_nested_v1);
+
+        block!      ((vm, ic_nested_v1) ic_nested_entry);
+        ssa!        ((vm, ic_nested_v1) <int64> ic_nested_a);
+        ssa!        ((vm, ic_nested_v1) <int64> ic_nested_r);
+        consta!     ((vm, ic_nested_v1) ic_nested_mid = ic_mid_const);
+        inst!       ((vm, ic_nested_v1) ic_nested_call:
+            ic_nested_r = EXPRCALL (CallConvention::Mu, is_abort: false)
+                ic_nested_mid (ic_nested_a)
+        );
+        inst!       ((vm, ic_nested_v1) ic_nested_ret:
+            RET (ic_nested_r)
+        );
+
+        define_block!((vm, ic_nested_v1) ic_nested_entry(ic_nested_a) {
+            ic_nested_call, ic_nested_ret
+        });
+
+        define_func_ver!((vm) ic_nested_v1 (entry: ic_nested_entry) {ic_nested_entry});
+    }
+
+    {
+        // @ic_thrower(<@ref_int64> %e): THROW %e
+        funcdef!    ((vm) <ic_thrower_sig> ic_thrower VERSION ic_thrower_v1);
+
+        block!      ((vm, ic_thrower_v1) ic_thrower_entry);
+        ssa!        ((vm, ic_thrower_v1) <ref_int64> ic_thrower_e);
+        inst!       ((vm, ic_thrower_v1) ic_thrower_throw:
+            THROW ic_thrower_e
+        );
+
+        define_block!((vm, ic_thrower_v1) ic_thrower_entry(ic_thrower_e) {ic_thrower_throw});
+
+        define_func_ver!((vm) ic_thrower_v1 (entry: ic_thrower_entry) {ic_thrower_entry});
+    }
+
+    {
+        // @ic_catch(<@ref_int64> %e):
+        //     CALL @ic_thrower(%e) normal: %ic_catch_normal() exc: %ic_catch_exn()
+        // %ic_catch_normal(): RET 0
+        // %ic_catch_exn() [%exn]: RET 1
+        funcdef!    ((vm) <ic_catch_sig> ic_catch VERSION ic_catch_v1);
+
+        block!      ((vm, ic_catch_v1) ic_catch_entry);
+        block!      ((vm, ic_catch_v1) ic_catch_normal);
+        block!      ((vm, ic_catch_v1) ic_catch_exn);
+
+        ssa!        ((vm, ic_catch_v1) <ref_int64> ic_catch_e);
+        consta!     ((vm, ic_catch_v1) ic_catch_thrower = ic_thrower_const);
+        inst!       ((vm, ic_catch_v1) ic_catch_call:
+            CALL (ic_catch_thrower, ic_catch_e) FUNC(0) (vec![1]) CallConvention::Mu,
+                normal: ic_catch_normal (vec![]),
+                exc: ic_catch_exn (vec![])
+        );
+        define_block!((vm, ic_catch_v1) ic_catch_entry(ic_catch_e) {ic_catch_call});
+
+        consta!     ((vm, ic_catch_v1) ic_catch_0 = ic_int64_0);
+        inst!       ((vm, ic_catch_v1) ic_catch_normal_ret:
+            RET (ic_catch_0)
+        );
+        define_block!((vm, ic_catch_v1) ic_catch_normal() {ic_catch_normal_ret});
+
+        ssa!        ((vm, ic_catch_v1) <ref_int64> ic_catch_exn_arg);
+        consta!     ((vm, ic_catch_v1) ic_catch_1 = ic_int64_1);
+        inst!       ((vm, ic_catch_v1) ic_catch_exn_ret:
+            RET (ic_catch_1)
+        );
+        define_block!((vm, ic_catch_v1) ic_catch_exn() [ic_catch_exn_arg] {ic_catch_exn_ret});
+
+        define_func_ver!((vm) ic_catch_v1 (entry: ic_catch_entry) {
+            ic_catch_entry, ic_catch_normal, ic_catch_exn
+        });
+    }
+
+    vm
+}
@@ -0,0 +1,108 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate mu;
+extern crate libloading;
+extern crate libc;
+
+use self::mu::ast::types::*;
+use self::mu::ast::ir::*;
+use self::mu::ast::inst::*;
+use self::mu::ast::op::*;
+use self::mu::vm::*;
+use self::mu::utils::LinkedHashMap;
+
+use std::sync::Arc;
+use std::fs::File;
+use std::io::Read;
+use mu::linkutils;
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_perf_map() {
+    let lib = linkutils::aot::compile_fnc("perf_map_add", &perf_map_add);
+
+    let addr = unsafe {
+        let perf_map_add: libloading::Symbol<unsafe extern "C" fn(u64, u64) -> u64> =
+            lib.get(b"perf_map_add").unwrap();
+
+        let res = perf_map_add(1, 2);
+        println!("perf_map_add(1, 2) = {}", res);
+        assert!(res == 3);
+
+        *perf_map_add as usize
+    };
+
+    let pid = unsafe { libc::getpid() };
+
+    // perf map entry: <start> <size> <name>
+    let mut map = String::new();
+    File::open(format!("/tmp/perf-{}.map", pid))
+        .unwrap()
+        .read_to_string(&mut map)
+        .unwrap();
+    println!("{}", map);
+    assert!(map.lines().any(|line| {
+        let fields: Vec<&str> = line.split(' ').collect();
+        fields.len() == 3 && fields[2] == "perf_map_add" &&
+            usize::from_str_radix(fields[0], 16).unwrap() == addr
+    }));
+
+    // jitdump starts with a header with the magic number 'JiTD'
+    let mut dump = vec![];
+    File::open(format!("/tmp/jit-{}.dump", pid))
+        .unwrap()
+        .read_to_end(&mut dump)
+        .unwrap();
+    assert!(dump.len() > 40);
+    assert_eq!(&dump[0..4], &[0x44, 0x54, 0x69, 0x4A]);
+    assert!(dump.windows(13).any(|w| w == b"perf_map_add\0"));
+}
+
+fn perf_map_add() -> VM {
+    let vm = VM::new_with_opts("init_mu --perf-map --perf-jitdump");
+
+    typedef!        ((vm) int64 = mu_int(64));
+
+    funcsig!        ((vm) sig = (int64, int64) -> (int64));
+    funcdecl!       ((vm) <sig> perf_map_add);
+    funcdef!        ((vm) <sig> perf_map_add VERSION perf_map_add_v1);
+
+    // %entry(<@int64> %a, <@int64> %b):
+    block!          ((vm, perf_map_add_v1) blk_entry);
+    ssa!            ((vm, perf_map_add_v1) <int64> a);
+    ssa!            ((vm, perf_map_add_v1) <int64> b);
+
+    // %r = ADD %a %b
+    ssa!            ((vm, perf_map_add_v1) <int64> r);
+    inst!           ((vm, perf_map_add_v1) blk_entry_add:
+        r = BINOP (BinOp::Add) a b
+    );
+
+    // RET %r
+    inst!           ((vm, perf_map_add_v1) blk_entry_ret:
+        RET (r)
+    );
+
+    define_block!   ((vm, perf_map_add_v1) blk_entry(a, b) {
+        blk_entry_add,
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) perf_map_add_v1 (entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
@@ -0,0 +1,149 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use mu::ast::types::*;
+use mu::ast::ptr::*;
+use mu::ast::ir::*;
+use mu::ast::inst::*;
+use mu::ast::op::*;
+use mu::vm::*;
+use mu::compiler::*;
+use mu::runtime;
+use mu::linkutils::aot;
+use mu::utils::LinkedHashMap;
+
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::mem::transmute;
+
+#[test]
+fn test_redefine_compiled_func() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(VM::new());
+    let compiler = Compiler::new(CompilerPolicy::default(), &vm);
+
+    typedef!    ((vm) int64 = mu_int(64));
+    funcsig!    ((vm) sig = (int64) -> (int64));
+    funcdecl!   ((vm) <sig> redef_func);
+
+    // version 1: returns x + 1
+    let v1_id = define_add_const_version(&vm, &int64, &sig, &redef_func, 1);
+    compile_and_load(&vm, &compiler, redef_func.id(), v1_id, "redef_func_v1");
+
+    let entry = runtime::resolve_symbol(Arc::new("redef_func".to_string()));
+    let call = unsafe { transmute::<_, extern "C" fn(u64) -> u64>(entry.to_ptr::<u8>()) };
+    assert_eq!(call(1), 2);
+
+    // the entry starts with the nop that gets patched
+    if cfg!(target_arch = "x86_64") {
+        assert!(entry.is_aligned_to(8));
+        let nop = unsafe { entry.load::<[u8; 5]>() };
+        assert_eq!(nop, [0x0f, 0x1f, 0x44, 0x00, 0x00]);
+    } else {
+        assert_eq!(unsafe { entry.load::<u32>() }, 0xd503201f);
+    }
+
+    // version 2: returns x + 2
+    let v2_id = define_add_const_version(&vm, &int64, &sig, &redef_func, 2);
+    assert_eq!(vm.get_cur_version_for_func(redef_func.id()), Some(v2_id));
+    compile_and_load(&vm, &compiler, redef_func.id(), v2_id, "redef_func_v2");
+    vm.install_redefined_funcs();
+
+    // calling the old entry now runs the new version
+    assert_eq!(call(1), 3);
+    if cfg!(target_arch = "x86_64") {
+        assert_eq!(unsafe { entry.load::<u8>() }, 0xe9);
+    } else {
+        assert_eq!(unsafe { entry.load::<u32>() } >> 26, 0b000101);
+    }
+
+    // the old version still has its compiled function for unwinding
+    assert!(vm.compiled_funcs().read().unwrap().contains_key(&v1_id));
+    assert!(!vm.has_pending_redefinitions());
+}
+
+/// defines a new version for func that returns its argument plus the given constant,
+/// returns the ID of the version
+fn define_add_const_version(
+    vm: &Arc<VM>,
+    int64: &P<MuType>,
+    sig: &P<MuFuncSig>,
+    func: &MuEntityHeader,
+    n: u64
+) -> MuID {
+    let mut fv = MuFunctionVersion::new(
+        MuEntityHeader::named(vm.next_id(), Arc::new(format!("redef_func_v{}", n))),
+        func.id(),
+        sig.clone()
+    );
+    let fv_id = fv.id();
+
+    let c = vm.declare_const(
+        MuEntityHeader::named(vm.next_id(), Arc::new(format!("redef_const{}", n))),
+        int64.clone(),
+        Constant::Int(n)
+    );
+
+    let mut blk_entry = Block::new(MuEntityHeader::named(
+        vm.next_id(),
+        Arc::new(format!("redef_func_v{}.blk_entry", n))
+    ));
+    let x = fv.new_ssa(MuEntityHeader::unnamed(vm.next_id()), int64.clone());
+    let c_node = fv.new_constant(c.clone());
+    let res = fv.new_ssa(MuEntityHeader::unnamed(vm.next_id()), int64.clone());
+
+    let add = fv.new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: Some(vec![res.clone_value()]),
+        ops: vec![x.clone(), c_node],
+        v: Instruction_::BinOp(BinOp::Add, 0, 1)
+    });
+    let ret = fv.new_inst(Instruction {
+        hdr: MuEntityHeader::unnamed(vm.next_id()),
+        value: None,
+        ops: vec![res.clone()],
+        v: Instruction_::Return(vec![0])
+    });
+
+    blk_entry.content = Some(BlockContent {
+        args: vec![x.clone_value()],
+        exn_arg: None,
+        body: vec![add, ret],
+        keepalives: None
+    });
+
+    fv.define(FunctionContent::new(blk_entry.id(), {
+        let mut blocks = LinkedHashMap::new();
+        blocks.insert(blk_entry.id(), blk_entry);
+        blocks
+    }));
+    vm.define_func_version(fv);
+
+    fv_id
+}
+
+/// compiles a function version into its own dynamic library, and loads it
+fn compile_and_load(vm: &Arc<VM>, compiler: &Compiler, func_id: MuID, fv_id: MuID, lib: &str) {
+    {
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers.get(&fv_id).unwrap().write().unwrap();
+        compiler.compile(&mut func_ver);
+    }
+    backend::emit_context(&vm);
+
+    let symbol = vm.get_code_symbol_for_func_ver(func_id, fv_id);
+    let dylib: PathBuf = aot::link_dylib(vec![symbol], &format!("lib{}.so", lib), &vm);
+    runtime::load_library_globally(dylib.as_path());
+}
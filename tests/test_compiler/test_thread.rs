@@ -21,11 +21,14 @@ use self::mu::ast::ir::*;
 use self::mu::ast::inst::*;
 use self::mu::ast::op::*;
 use self::mu::vm::*;
+use self::mu::vm::profile;
 use self::mu::compiler::*;
 use self::mu::utils::LinkedHashMap;
 use self::mu::linkutils;
 use self::mu::linkutils::aot;
 
+use std::env;
+use std::fs;
 use std::sync::Arc;
 use std::sync::RwLock;
 
@@ -120,8 +123,416 @@ fn test_main_with_retval() {
     assert!(ret_code == 42);
 }
 
+#[test]
+fn test_main_with_retval_static() {
+    let vm = Arc::new(main_with_retval_opts("init_mu --aot-link-static"));
+
+    let func_id = vm.id_of("main_with_retval");
+    let func_handle = vm.handle_from_func(func_id);
+    vm.make_boot_image(
+        vec![func_id],
+        Some(&func_handle),
+        None,
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        "test_main_with_retval_static".to_string()
+    );
+
+    let executable = {
+        use std::path;
+        let mut path = path::PathBuf::new();
+        path.push(&vm.vm_options.flag_aot_emit_dir);
+        path.push("test_main_with_retval_static");
+        path
+    };
+
+    // the executable does not need libmu at run time
+    if cfg!(target_os = "linux") {
+        use std::process::Command;
+        let ldd = Command::new("ldd").arg(executable.as_os_str()).output().unwrap();
+        assert!(!String::from_utf8_lossy(&ldd.stdout).contains("libmu"));
+    }
+
+    // run
+    let output = linkutils::exec_path_nocheck(executable);
+
+    assert!(output.status.code().is_some());
+
+    let ret_code = output.status.code().unwrap();
+    println!("return code: {}", ret_code);
+    assert!(ret_code == 42);
+}
+
+#[test]
+fn test_strip_unreachable() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(strip_main());
+
+    let func_id = vm.id_of("strip_main");
+    let cur_ver = |name: &str| {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&vm.id_of(name)).unwrap().read().unwrap();
+        func.cur_ver.unwrap()
+    };
+    let callee_ver = cur_ver("strip_callee");
+    let unused_ver = cur_ver("strip_unused");
+
+    let func_handle = vm.handle_from_func(func_id);
+    vm.make_boot_image(
+        vec![func_id, vm.id_of("strip_callee"), vm.id_of("strip_unused")],
+        Some(&func_handle),
+        None,
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        "test_strip_unreachable".to_string()
+    );
+
+    // only the reachable functions are compiled
+    {
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        assert!(compiled_funcs.contains_key(&callee_ver));
+        assert!(!compiled_funcs.contains_key(&unused_ver));
+    }
+
+    let executable = {
+        use std::path;
+        let mut path = path::PathBuf::new();
+        path.push(&vm.vm_options.flag_aot_emit_dir);
+        path.push("test_strip_unreachable");
+        path
+    };
+    let output = linkutils::exec_path_nocheck(executable);
+
+    assert!(output.status.code().is_some());
+
+    let ret_code = output.status.code().unwrap();
+    println!("return code: {}", ret_code);
+    assert!(ret_code == 42);
+}
+
+#[test]
+fn test_strip_keeps_reloc_symbols() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(strip_main());
+
+    // a global cell that the client relocates to strip_unused
+    typedef!    ((vm) int64 = mu_int(64));
+    globaldef!  ((vm) <int64> strip_reloc_cell);
+
+    let func_id = vm.id_of("strip_main");
+    let unused_ver = {
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&vm.id_of("strip_unused")).unwrap().read().unwrap();
+        func.cur_ver.unwrap()
+    };
+
+    let func_handle = vm.handle_from_func(func_id);
+    let cell_handle = vm.handle_from_global(strip_reloc_cell.id());
+    vm.make_boot_image(
+        vec![func_id, vm.id_of("strip_callee"), vm.id_of("strip_unused")],
+        Some(&func_handle),
+        None,
+        None,
+        vec![],
+        vec![],
+        vec![&*cell_handle],
+        vec![Arc::new("strip_unused".to_string())],
+        "test_strip_keeps_reloc_symbols".to_string()
+    );
+
+    // strip_unused is named by a relocation field, so it is kept
+    {
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        assert!(compiled_funcs.contains_key(&unused_ver));
+    }
+
+    // and the boot image links
+    let executable = {
+        use std::path;
+        let mut path = path::PathBuf::new();
+        path.push(&vm.vm_options.flag_aot_emit_dir);
+        path.push("test_strip_keeps_reloc_symbols");
+        path
+    };
+    let output = linkutils::exec_path_nocheck(executable);
+
+    assert!(output.status.code().is_some());
+    assert!(output.status.code().unwrap() == 42);
+}
+
+#[test]
+fn test_profile_generate_boot_image() {
+    VM::start_logging_trace();
+
+    let profile_path = env::temp_dir().join("test_profile_generate_boot_image.profile");
+    let profile_path = profile_path.to_str().unwrap();
+    fs::remove_file(profile_path).ok();
+    let vm = Arc::new(strip_main_opts(&format!(
+        "init_mu --profile-generate={}",
+        profile_path
+    )));
+
+    let func_id = vm.id_of("strip_main");
+    let func_handle = vm.handle_from_func(func_id);
+    vm.make_boot_image(
+        vec![func_id, vm.id_of("strip_callee")],
+        Some(&func_handle),
+        None,
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        "test_profile_generate_boot_image".to_string()
+    );
+
+    let executable = {
+        use std::path;
+        let mut path = path::PathBuf::new();
+        path.push(&vm.vm_options.flag_aot_emit_dir);
+        path.push("test_profile_generate_boot_image");
+        path
+    };
+    let output = linkutils::exec_path_nocheck(executable);
+    assert!(output.status.code().is_some());
+    assert!(output.status.code().unwrap() == 42);
+
+    // the boot image wrote the counts when its threads exited
+    let profile = profile::Profile::read(profile_path);
+    let func_profile = profile.get_func("strip_main").unwrap();
+    assert_eq!(func_profile.block_count("blk_entry"), Some(1));
+    assert_eq!(func_profile.call_count("blk_entry", "strip_callee"), Some(1));
+}
+
+#[test]
+fn test_parallel_compile() {
+    VM::start_logging_trace();
+
+    // every function is compiled on its own thread
+    let vm = Arc::new(strip_main_opts("init_mu --compile-threads=3"));
+
+    let func_id = vm.id_of("strip_main");
+    let whitelist = vec![func_id, vm.id_of("strip_callee"), vm.id_of("strip_unused")];
+    let func_vers: Vec<MuID> = {
+        let funcs = vm.funcs().read().unwrap();
+        whitelist
+            .iter()
+            .map(|id| funcs.get(id).unwrap().read().unwrap().cur_ver.unwrap())
+            .collect()
+    };
+
+    let func_handle = vm.handle_from_func(func_id);
+    vm.make_boot_image(
+        whitelist,
+        Some(&func_handle),
+        None,
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        "test_parallel_compile".to_string()
+    );
+
+    {
+        let compiled_funcs = vm.compiled_funcs().read().unwrap();
+        for fv_id in func_vers.iter() {
+            assert!(compiled_funcs.contains_key(fv_id));
+        }
+    }
+
+    let executable = {
+        use std::path;
+        let mut path = path::PathBuf::new();
+        path.push(&vm.vm_options.flag_aot_emit_dir);
+        path.push("test_parallel_compile");
+        path
+    };
+    let output = linkutils::exec_path_nocheck(executable);
+
+    assert!(output.status.code().is_some());
+
+    let ret_code = output.status.code().unwrap();
+    println!("return code: {}", ret_code);
+    assert!(ret_code == 42);
+}
+
+#[test]
+fn test_code_cache() {
+    use std::fs;
+    use std::path::PathBuf;
+    use self::mu::compiler::code_cache::CodeCache;
+
+    VM::start_logging_trace();
+
+    let cache_dir = "emit/test_code_cache";
+    let _ = fs::remove_dir_all(cache_dir);
+
+    // builds strip_main, and returns whether the entries of the reachable functions were in
+    // the code cache before the build, and the exit code of the boot image
+    let build = |emit_dir: &str, callee_ret: u64, opts: &str| -> (bool, bool, i32) {
+        let vm = Arc::new(strip_main_ret(
+            &format!(
+                "init_mu --disable-inline --aot-strip-unreachable --aot-cache-dir={} \
+                 --aot-emit-dir={} {}",
+                cache_dir,
+                emit_dir,
+                opts
+            ),
+            callee_ret
+        ));
+        let func_id = vm.id_of("strip_main");
+
+        let cached = |name: &str| {
+            let cache = CodeCache::new(&vm).unwrap();
+            let fv_id = {
+                let funcs = vm.funcs().read().unwrap();
+                let func = funcs.get(&vm.id_of(name)).unwrap().read().unwrap();
+                func.cur_ver.unwrap()
+            };
+            let func_vers = vm.func_vers().read().unwrap();
+            let mut path = PathBuf::from(cache_dir);
+            path.push(cache.entry_name(fv_id, &func_vers, &CompilerPolicy::default(), &vm));
+            path.exists()
+        };
+        let main_cached = cached("strip_main");
+        let callee_cached = cached("strip_callee");
+
+        let func_handle = vm.handle_from_func(func_id);
+        vm.make_boot_image(
+            vec![func_id, vm.id_of("strip_callee"), vm.id_of("strip_unused")],
+            Some(&func_handle),
+            None,
+            None,
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            "test_code_cache".to_string()
+        );
+
+        let mut executable = PathBuf::from(emit_dir);
+        executable.push("test_code_cache");
+        let output = linkutils::exec_path_nocheck(executable);
+        assert!(output.status.code().is_some());
+
+        (main_cached, callee_cached, output.status.code().unwrap())
+    };
+
+    // the first build compiles everything
+    assert_eq!(build("emit/test_code_cache_1", 42, ""), (false, false, 42));
+    // the same IR is restored from the cache (into another emit directory)
+    assert_eq!(build("emit/test_code_cache_2", 42, ""), (true, true, 42));
+    // changing strip_callee invalidates it, and its caller
+    assert_eq!(build("emit/test_code_cache_3", 43, ""), (false, false, 43));
+    // so does an option that changes code generation
+    assert_eq!(build("emit/test_code_cache_4", 43, "--aot-pic"), (false, false, 43));
+
+    // the outdated entries are replaced
+    assert_eq!(fs::read_dir(cache_dir).unwrap().count(), 2);
+}
+
+fn strip_main() -> VM {
+    strip_main_opts("init_mu --disable-inline --aot-strip-unreachable")
+}
+
+fn strip_main_opts(opts: &str) -> VM {
+    strip_main_ret(opts, 42)
+}
+
+fn strip_main_ret(opts: &str, callee_ret: u64) -> VM {
+    let vm = VM::new_with_opts(opts);
+
+    typedef!    ((vm) int32 = mu_int(32));
+    constdef!   ((vm) <int32> int32_ret = Constant::Int(callee_ret));
+
+    // strip_callee() -> int32
+    funcsig!    ((vm) callee_sig = () -> (int32));
+    funcdecl!   ((vm) <callee_sig> strip_callee);
+    funcdef!    ((vm) <callee_sig> strip_callee VERSION strip_callee_v1);
+
+    block!      ((vm, strip_callee_v1) blk_entry);
+    consta!     ((vm, strip_callee_v1) int32_ret_local = int32_ret);
+    inst!       ((vm, strip_callee_v1) blk_entry_ret:
+        RET (int32_ret_local)
+    );
+
+    define_block!((vm, strip_callee_v1) blk_entry() {
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) strip_callee_v1(entry: blk_entry) {
+        blk_entry
+    });
+
+    // strip_unused() (not referred by anyone)
+    funcsig!    ((vm) sig = () -> ());
+    funcdecl!   ((vm) <sig> strip_unused);
+    funcdef!    ((vm) <sig> strip_unused VERSION strip_unused_v1);
+
+    block!      ((vm, strip_unused_v1) blk_entry);
+    inst!       ((vm, strip_unused_v1) blk_entry_ret:
+        RET
+    );
+
+    define_block!((vm, strip_unused_v1) blk_entry() {
+        blk_entry_ret
+    });
+
+    define_func_ver!((vm) strip_unused_v1(entry: blk_entry) {
+        blk_entry
+    });
+
+    // strip_main() calls strip_callee, and exits with its result
+    typedef!    ((vm) funcref_callee = mu_funcref(callee_sig));
+    constdef!   ((vm) <funcref_callee> const_funcref_callee =
+        Constant::FuncRef(strip_callee.clone()));
+
+    funcdecl!   ((vm) <sig> strip_main);
+    funcdef!    ((vm) <sig> strip_main VERSION strip_main_v1);
+
+    block!      ((vm, strip_main_v1) blk_entry);
+    consta!     ((vm, strip_main_v1) const_funcref_callee_local = const_funcref_callee);
+    ssa!        ((vm, strip_main_v1) <int32> res);
+    inst!       ((vm, strip_main_v1) blk_entry_call:
+        res = EXPRCALL (CallConvention::Mu, is_abort: false) const_funcref_callee_local ()
+    );
+
+    inst!       ((vm, strip_main_v1) blk_entry_set_retval:
+        SET_RETVAL res
+    );
+
+    inst!       ((vm, strip_main_v1) blk_entry_threadexit:
+        THREADEXIT
+    );
+
+    define_block!((vm, strip_main_v1) blk_entry() {
+        blk_entry_call,
+        blk_entry_set_retval,
+        blk_entry_threadexit
+    });
+
+    define_func_ver!((vm) strip_main_v1(entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
+
 fn main_with_retval() -> VM {
-    let vm = VM::new();
+    main_with_retval_opts("init_mu")
+}
+
+fn main_with_retval_opts(opts: &str) -> VM {
+    let vm = VM::new_with_opts(opts);
 
     typedef!    ((vm) int32 = mu_int(32));
     constdef!   ((vm) <int32> int32_42 = Constant::Int(42));
@@ -152,3 +563,97 @@ fn main_with_retval() -> VM {
 
     vm
 }
+
+#[test]
+fn test_primordial_stack() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(stack_main_with_retval());
+
+    let func_id = vm.id_of("stack_main_with_retval");
+    {
+        let compiler = Compiler::new(CompilerPolicy::default(), &vm);
+        let funcs = vm.funcs().read().unwrap();
+        let func = funcs.get(&func_id).unwrap().read().unwrap();
+        let func_vers = vm.func_vers().read().unwrap();
+        let mut func_ver = func_vers
+            .get(&func.cur_ver.unwrap())
+            .unwrap()
+            .write()
+            .unwrap();
+
+        compiler.compile(&mut func_ver);
+    }
+
+    // the code needs to be loaded so that we can create a stack for it
+    backend::emit_context(&vm);
+    let dylib = aot::link_dylib(
+        vec![Arc::new("stack_main_with_retval".to_string())],
+        &linkutils::get_dylib_name("stack_main_with_retval"),
+        &vm
+    );
+    mu::runtime::load_library_globally(dylib.as_path());
+
+    let func_handle = vm.handle_from_func(func_id);
+    let stack_handle = vm.handle_new_stack(&func_handle);
+    vm.make_boot_image(
+        vec![func_id],
+        None,
+        Some(&stack_handle),
+        None,
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+        "test_primordial_stack".to_string()
+    );
+
+    // run
+    let executable = {
+        use std::path;
+        let mut path = path::PathBuf::new();
+        path.push(&vm.vm_options.flag_aot_emit_dir);
+        path.push("test_primordial_stack");
+        path
+    };
+    let output = linkutils::exec_path_nocheck(executable);
+
+    assert!(output.status.code().is_some());
+
+    let ret_code = output.status.code().unwrap();
+    println!("return code: {}", ret_code);
+    assert!(ret_code == 42);
+}
+
+fn stack_main_with_retval() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int32 = mu_int(32));
+    constdef!   ((vm) <int32> int32_42 = Constant::Int(42));
+
+    funcsig!    ((vm) sig = () -> ());
+    funcdecl!   ((vm) <sig> stack_main_with_retval);
+    funcdef!    ((vm) <sig> stack_main_with_retval VERSION stack_main_with_retval_v1);
+
+    block!      ((vm, stack_main_with_retval_v1) blk_entry);
+
+    consta!     ((vm, stack_main_with_retval_v1) int32_42_local = int32_42);
+    inst!       ((vm, stack_main_with_retval_v1) blk_entry_set_retval:
+        SET_RETVAL int32_42_local
+    );
+
+    inst!       ((vm, stack_main_with_retval_v1) blk_entry_threadexit:
+        THREADEXIT
+    );
+
+    define_block!((vm, stack_main_with_retval_v1) blk_entry() {
+        blk_entry_set_retval,
+        blk_entry_threadexit
+    });
+
+    define_func_ver!((vm) stack_main_with_retval_v1(entry: blk_entry) {
+        blk_entry
+    });
+
+    vm
+}
@@ -15,3 +15,4 @@
 pub mod test_ir;
 mod test_types;
 pub mod test_builder_api;
+mod test_analysis;
@@ -0,0 +1,246 @@
+// Copyright 2017 The Australian National University
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate mu;
+
+use self::mu::ast::types::*;
+use self::mu::ast::ir::*;
+use self::mu::ast::inst::*;
+use self::mu::vm::*;
+use self::mu::compiler::*;
+use self::mu::utils::LinkedHashMap;
+
+use std::sync::Arc;
+
+#[test]
+fn test_domtree() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(loop_nest());
+    let func_id = vm.id_of("loop_nest");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    let id = |name: &str| vm.id_of(name);
+    let domtree = func_ver.domtree();
+
+    assert_eq!(domtree.entry(), id("ln_entry"));
+    assert_eq!(domtree.idom(id("ln_entry")), None);
+    assert_eq!(domtree.idom(id("ln_outer")), Some(id("ln_entry")));
+    assert_eq!(domtree.idom(id("ln_inner")), Some(id("ln_outer")));
+    assert_eq!(domtree.idom(id("ln_inner_body")), Some(id("ln_inner")));
+    assert_eq!(domtree.idom(id("ln_outer_latch")), Some(id("ln_inner")));
+    assert_eq!(domtree.idom(id("ln_exit")), Some(id("ln_outer")));
+
+    assert!(domtree.dominates(id("ln_outer"), id("ln_inner_body")));
+    assert!(domtree.dominates(id("ln_inner"), id("ln_inner")));
+    assert!(!domtree.strictly_dominates(id("ln_inner"), id("ln_inner")));
+    assert!(!domtree.dominates(id("ln_inner"), id("ln_exit")));
+
+    let frontier = |name: &str| {
+        let mut ret: Vec<MuID> = domtree.frontier(id(name)).iter().cloned().collect();
+        ret.sort();
+        ret
+    };
+    let mut inner_outer = vec![id("ln_inner"), id("ln_outer")];
+    inner_outer.sort();
+    assert_eq!(frontier("ln_entry"), Vec::<MuID>::new());
+    assert_eq!(frontier("ln_outer"), vec![id("ln_outer")]);
+    assert_eq!(frontier("ln_inner"), inner_outer);
+    assert_eq!(frontier("ln_inner_body"), vec![id("ln_inner")]);
+    assert_eq!(frontier("ln_outer_latch"), vec![id("ln_outer")]);
+    assert_eq!(frontier("ln_exit"), Vec::<MuID>::new());
+}
+
+#[test]
+fn test_loop_nest() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(loop_nest());
+    let func_id = vm.id_of("loop_nest");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    let id = |name: &str| vm.id_of(name);
+    let loop_nest = func_ver.loop_nest();
+    assert_eq!(loop_nest.loops().len(), 2);
+    assert_eq!(loop_nest.outermost_loops(), vec![0]);
+
+    let outer = loop_nest.get_loop(0);
+    assert_eq!(outer.header, id("ln_outer"));
+    assert_eq!(outer.blocks.len(), 4);
+    assert!(outer.contains(id("ln_inner_body")));
+    assert!(!outer.contains(id("ln_exit")));
+    assert_eq!(outer.latches, vec![id("ln_outer_latch")]);
+    assert_eq!(outer.exits, vec![(id("ln_outer"), id("ln_exit"))]);
+    assert_eq!(outer.preheader, Some(id("ln_entry")));
+    assert_eq!(outer.parent, None);
+    assert_eq!(outer.children, vec![1]);
+
+    let inner = loop_nest.get_loop(1);
+    assert_eq!(inner.header, id("ln_inner"));
+    assert_eq!(inner.blocks.len(), 2);
+    assert_eq!(inner.latches, vec![id("ln_inner_body")]);
+    assert_eq!(inner.exits, vec![(id("ln_inner"), id("ln_outer_latch"))]);
+    // ln_outer also branches to ln_exit, so it cannot be used as a preheader
+    assert_eq!(inner.preheader, None);
+    assert_eq!(inner.parent, Some(0));
+    assert_eq!(inner.depth, 2);
+
+    assert_eq!(loop_nest.innermost_loop(id("ln_outer_latch")), Some(0));
+    assert_eq!(loop_nest.innermost_loop(id("ln_inner_body")), Some(1));
+    assert_eq!(loop_nest.loop_depth(id("ln_entry")), 0);
+    assert_eq!(loop_nest.loop_depth(id("ln_outer")), 1);
+    assert_eq!(loop_nest.loop_depth(id("ln_inner_body")), 2);
+}
+
+#[test]
+fn test_analysis_cache() {
+    VM::start_logging_trace();
+
+    let vm = Arc::new(loop_nest());
+    let func_id = vm.id_of("loop_nest");
+    let funcs = vm.funcs().read().unwrap();
+    let func = funcs.get(&func_id).unwrap().read().unwrap();
+    let func_vers = vm.func_vers().read().unwrap();
+    let mut func_ver = func_vers
+        .get(&func.cur_ver.unwrap())
+        .unwrap()
+        .write()
+        .unwrap();
+
+    let domtree = func_ver.domtree();
+    assert!(Arc::ptr_eq(&domtree, &func_ver.domtree()));
+
+    // the Def-Use pass does not change the CFG, so the result stays cached
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::DefUse::new())]),
+        &vm
+    );
+    compiler.compile(&mut func_ver);
+    assert!(Arc::ptr_eq(&domtree, &func_ver.domtree()));
+
+    // DCE may remove blocks, so the result is dropped after it
+    let compiler = Compiler::new(
+        CompilerPolicy::new(vec![Box::new(passes::DCE::new())]),
+        &vm
+    );
+    compiler.compile(&mut func_ver);
+    let new_domtree = func_ver.domtree();
+    assert!(!Arc::ptr_eq(&domtree, &new_domtree));
+
+    func_ver.invalidate_cfg_analyses();
+    assert!(!Arc::ptr_eq(&new_domtree, &func_ver.domtree()));
+}
+
+/// a loop nested in another loop
+fn loop_nest() -> VM {
+    let vm = VM::new();
+
+    typedef!    ((vm) int1  = mu_int(1));
+    typedef!    ((vm) int64 = mu_int(64));
+    constdef!   ((vm) <int64> int64_0 = Constant::Int(0));
+
+    funcsig!    ((vm) ln_sig = (int1, int1) -> (int64));
+    funcdecl!   ((vm) <ln_sig> loop_nest);
+    funcdef!    ((vm) <ln_sig> loop_nest VERSION loop_nest_v1);
+
+    block!      ((vm, loop_nest_v1) ln_entry);
+    block!      ((vm, loop_nest_v1) ln_outer);
+    block!      ((vm, loop_nest_v1) ln_inner);
+    block!      ((vm, loop_nest_v1) ln_inner_body);
+    block!      ((vm, loop_nest_v1) ln_outer_latch);
+    block!      ((vm, loop_nest_v1) ln_exit);
+
+    // %ln_entry(<@int1> %c1, <@int1> %c2):
+    //     BRANCH %ln_outer()
+    ssa!        ((vm, loop_nest_v1) <int1> ln_c1);
+    ssa!        ((vm, loop_nest_v1) <int1> ln_c2);
+    inst!       ((vm, loop_nest_v1) ln_entry_branch:
+        BRANCH ln_outer ()
+    );
+    define_block!((vm, loop_nest_v1) ln_entry(ln_c1, ln_c2) {
+        ln_entry_branch
+    });
+
+    // %ln_outer():
+    //     BRANCH2 %c1 %ln_inner() %ln_exit()
+    inst!       ((vm, loop_nest_v1) ln_outer_branch2:
+        BRANCH2 (ln_c1)
+            IF (OP 0)
+            THEN ln_inner (vec![]) WITH 0.9f32,
+            ELSE ln_exit (vec![])
+    );
+    define_block!((vm, loop_nest_v1) ln_outer() {
+        ln_outer_branch2
+    });
+
+    // %ln_inner():
+    //     BRANCH2 %c2 %ln_inner_body() %ln_outer_latch()
+    inst!       ((vm, loop_nest_v1) ln_inner_branch2:
+        BRANCH2 (ln_c2)
+            IF (OP 0)
+            THEN ln_inner_body (vec![]) WITH 0.9f32,
+            ELSE ln_outer_latch (vec![])
+    );
+    define_block!((vm, loop_nest_v1) ln_inner() {
+        ln_inner_branch2
+    });
+
+    // %ln_inner_body():
+    //     BRANCH %ln_inner()
+    inst!       ((vm, loop_nest_v1) ln_inner_body_branch:
+        BRANCH ln_inner ()
+    );
+    define_block!((vm, loop_nest_v1) ln_inner_body() {
+        ln_inner_body_branch
+    });
+
+    // %ln_outer_latch():
+    //     BRANCH %ln_outer()
+    inst!       ((vm, loop_nest_v1) ln_outer_latch_branch:
+        BRANCH ln_outer ()
+    );
+    define_block!((vm, loop_nest_v1) ln_outer_latch() {
+        ln_outer_latch_branch
+    });
+
+    // %ln_exit():
+    //     RET %int64_0
+    consta!     ((vm, loop_nest_v1) int64_0_local = int64_0);
+    inst!       ((vm, loop_nest_v1) ln_exit_ret:
+        RET (int64_0_local)
+    );
+    define_block!((vm, loop_nest_v1) ln_exit() {
+        ln_exit_ret
+    });
+
+    define_func_ver!((vm) loop_nest_v1 (entry: ln_entry) {
+        ln_entry, ln_outer, ln_inner, ln_inner_body, ln_outer_latch, ln_exit
+    });
+
+    vm
+}
@@ -0,0 +1,251 @@
+# Copyright 2017 The Australian National University
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+
+import os
+import ctypes
+import subprocess as subp
+from util import fncptr_from_py_script, may_spawn_proc
+from rpython.rlib.rmu import zebu as rmu
+from rpython.rtyper.lltypesystem import rffi, lltype
+
+@may_spawn_proc
+def test_persist_suspended_stack():
+    def build_test_bundle(bldr, rmu):
+        """
+        Builds the following test bundle.
+            .typedef @i32 = int<32>
+            .typedef @i64 = int<64>
+            .typedef @stack = stackref
+            .typedef @ptrstack = uptr<@stack>
+            .const @c40 <@i64> = 40
+            .const @c2 <@i64> = 2
+            .funcsig @exit_sig = (@i32) -> ()
+            .typedef @exit_fp = ufuncptr<@exit_sig>
+            .const @exit <@exit_fp> = EXTERN "exit"
+            .global @cell <@stack>
+            .funcsig @body_sig = (@stack) -> ()
+            .funcsig @test_fnc_sig = (@ptrstack) -> ()
+            .funcdef @body VERSION @body.v1 <@body_sig> {
+                %blk0(<@stack> %creator):
+                    %obj = NEW <@i64>
+                    %ir = GETIREF <@i64> %obj
+                    STORE <@i64> %ir @c40
+                    %cs = COMMINST @uvm.current_stack
+                    SWAPSTACK %creator RET_WITH<> PASS_VALUES<@stack>(%cs)
+                    %ir2 = GETIREF <@i64> %obj
+                    %v = LOAD <@i64> %ir2
+                    %r = ADD <@i64> %v @c2
+                    %r32 = TRUNC <@i64 @i32> %r
+                    CCALL #DEFAULT <@exit_fp @exit_sig> @exit(%r32)
+                    COMMINST @uvm.thread_exit
+            }
+            .funcdef @test_fnc VERSION @test_fnc.v1 <@test_fnc_sig> {
+                %blk0(<@ptrstack> %p):
+                    %cs = COMMINST @uvm.current_stack
+                    %s = COMMINST @uvm.new_stack<[@body_sig]>(@body)
+                    STORE PTR <@stack> %p %s
+                    SWAPSTACK %s RET_WITH<> PASS_VALUES<@stack>(%cs)
+                    RET ()
+            }
+        The body suspends itself in the middle of the function, with a reference in its frame,
+        and the test function stores its stack to the cell that the pointer points to.
+        :type bldr: rpython.rlib.rmu.MuIRBuilder
+        :type rmu: rpython.rlib.rmu
+        """
+        i32 = bldr.gen_sym("@i32"); bldr.new_type_int(i32, 32)
+        i64 = bldr.gen_sym("@i64"); bldr.new_type_int(i64, 64)
+        stack = bldr.gen_sym("@stack"); bldr.new_type_stackref(stack)
+        ptrstack = bldr.gen_sym("@ptrstack"); bldr.new_type_uptr(ptrstack, stack)
+        c40 = bldr.gen_sym("@c40"); bldr.new_const_int(c40, i64, 40)
+        c2 = bldr.gen_sym("@c2"); bldr.new_const_int(c2, i64, 2)
+        exit_sig = bldr.gen_sym("@exit_sig"); bldr.new_funcsig(exit_sig, [i32], [])
+        exit_fp = bldr.gen_sym("@exit_fp"); bldr.new_type_ufuncptr(exit_fp, exit_sig)
+        exit = bldr.gen_sym("@exit"); bldr.new_const_extern(exit, exit_fp, "exit")
+        cell = bldr.gen_sym("@cell"); bldr.new_global_cell(cell, stack)
+        body_sig = bldr.gen_sym("@body_sig"); bldr.new_funcsig(body_sig, [stack], [])
+        test_fnc_sig = bldr.gen_sym("@test_fnc_sig"); bldr.new_funcsig(test_fnc_sig, [ptrstack], [])
+
+        body = bldr.gen_sym("@body"); bldr.new_func(body, body_sig)
+        body_v1 = bldr.gen_sym("@body.v1")
+        blk0 = bldr.gen_sym("@body.v1.blk0")
+        creator = bldr.gen_sym("@body.v1.blk0.creator")
+        obj = bldr.gen_sym("@body.v1.blk0.obj")
+        ir = bldr.gen_sym("@body.v1.blk0.ir")
+        cs = bldr.gen_sym("@body.v1.blk0.cs")
+        ir2 = bldr.gen_sym("@body.v1.blk0.ir2")
+        v = bldr.gen_sym("@body.v1.blk0.v")
+        r = bldr.gen_sym("@body.v1.blk0.r")
+        r32 = bldr.gen_sym("@body.v1.blk0.r32")
+        op_new = bldr.gen_sym(); bldr.new_new(op_new, obj, i64)
+        op_getiref = bldr.gen_sym(); bldr.new_getiref(op_getiref, ir, i64, obj)
+        op_store = bldr.gen_sym(); bldr.new_store(op_store, False, rmu.MuMemOrd.NOT_ATOMIC, i64, ir, c40)
+        op_cs = bldr.gen_sym(); bldr.new_comminst(op_cs, [cs], rmu.MuCommInst.CURRENT_STACK, [], [], [], [])
+        csc = bldr.gen_sym(); bldr.new_csc_ret_with(csc, [])
+        nsc = bldr.gen_sym(); bldr.new_nsc_pass_values(nsc, [stack], [cs])
+        op_swap = bldr.gen_sym(); bldr.new_swapstack(op_swap, [], creator, csc, nsc)
+        op_getiref2 = bldr.gen_sym(); bldr.new_getiref(op_getiref2, ir2, i64, obj)
+        op_load = bldr.gen_sym(); bldr.new_load(op_load, v, False, rmu.MuMemOrd.NOT_ATOMIC, i64, ir2)
+        op_add = bldr.gen_sym(); bldr.new_binop(op_add, r, rmu.MuBinOptr.ADD, i64, v, c2)
+        op_trunc = bldr.gen_sym(); bldr.new_conv(op_trunc, r32, rmu.MuConvOptr.TRUNC, i64, i32, r)
+        op_exit = bldr.gen_sym(); bldr.new_ccall(op_exit, [], rmu.MuCallConv.DEFAULT, exit_fp, exit_sig, exit, [r32])
+        op_thread_exit = bldr.gen_sym(); bldr.new_comminst(op_thread_exit, [], rmu.MuCommInst.THREAD_EXIT, [], [], [], [])
+        bldr.new_bb(blk0, [creator], [stack], rmu.MU_NO_ID,
+                    [op_new, op_getiref, op_store, op_cs, op_swap, op_getiref2, op_load, op_add,
+                     op_trunc, op_exit, op_thread_exit])
+        bldr.new_func_ver(body_v1, body, [blk0])
+
+        test_fnc = bldr.gen_sym("@test_fnc"); bldr.new_func(test_fnc, test_fnc_sig)
+        test_fnc_v1 = bldr.gen_sym("@test_fnc.v1")
+        blk0 = bldr.gen_sym("@test_fnc.v1.blk0")
+        p = bldr.gen_sym("@test_fnc.v1.blk0.p")
+        cs = bldr.gen_sym("@test_fnc.v1.blk0.cs")
+        s = bldr.gen_sym("@test_fnc.v1.blk0.s")
+        op_cs = bldr.gen_sym(); bldr.new_comminst(op_cs, [cs], rmu.MuCommInst.CURRENT_STACK, [], [], [], [])
+        op_ns = bldr.gen_sym(); bldr.new_comminst(op_ns, [s], rmu.MuCommInst.NEW_STACK, [], [], [body_sig], [body])
+        op_store = bldr.gen_sym(); bldr.new_store(op_store, True, rmu.MuMemOrd.NOT_ATOMIC, stack, p, s)
+        csc = bldr.gen_sym(); bldr.new_csc_ret_with(csc, [])
+        nsc = bldr.gen_sym(); bldr.new_nsc_pass_values(nsc, [stack], [cs])
+        op_swap = bldr.gen_sym(); bldr.new_swapstack(op_swap, [], s, csc, nsc)
+        op_ret = bldr.gen_sym(); bldr.new_ret(op_ret, [])
+        bldr.new_bb(blk0, [p], [ptrstack], rmu.MU_NO_ID, [op_cs, op_ns, op_store, op_swap, op_ret])
+        bldr.new_func_ver(test_fnc_v1, test_fnc, [blk0])
+
+        return {
+            "test_fnc": test_fnc,
+            "test_fnc_sig": test_fnc_sig,
+            "@body": body,
+            "@cell": cell,
+        }
+
+    (fnp, _), (mu, ctx, bldr) = fncptr_from_py_script(build_test_bundle, None, 'test_fnc',
+                                                      [ctypes.c_void_p], None)
+
+    mu.current_thread_as_mu_thread(rmu.null(rmu.MuCPtr))
+    id_dict = {name: ctx.id_of(name) for name in ["@body", "@test_fnc", "@cell"]}
+
+    # run the test function to suspend the body, and get its stack from the cell
+    cell_hdl = ctx.handle_from_global(id_dict["@cell"])
+    cell_ptr = ctx.handle_to_ptr(ctx.pin(cell_hdl))
+    fnp(rffi.cast(lltype.Signed, cell_ptr))
+    stack_hdl = ctx.load(rmu.MuMemOrd.NOT_ATOMIC, cell_hdl)
+
+    # persist the suspended stack, and resume it in the boot image
+    emit_dir = os.environ.get('MU_EMIT_DIR', 'emit')
+    exe = os.path.join(emit_dir, 'test_persist_suspended_stack')
+    ctx.make_boot_image([id_dict["@body"], id_dict["@test_fnc"]],
+                        rmu.null(rmu.MuFuncRefValue), stack_hdl, rmu.null(rmu.MuRefValue),
+                        [], [], [], [], 'test_persist_suspended_stack')
+
+    assert subp.call([exe]) == 42
+
+
+@may_spawn_proc
+def test_unwind_through_swapstack():
+    def build_test_bundle(bldr, rmu):
+        """
+        Builds the following test bundle.
+            .typedef @i32 = int<32>
+            .typedef @i64 = int<64>
+            .typedef @stack = stackref
+            .typedef @ptri64 = uptr<@i64>
+            .const @c64 <@i32> = 64
+            .funcsig @backtrace_sig = (@ptri64 @i32) -> (@i32)
+            .typedef @backtrace_fp = ufuncptr<@backtrace_sig>
+            .const @backtrace <@backtrace_fp> = EXTERN "backtrace"
+            .funcsig @body_sig = (@stack) -> ()
+            .funcsig @test_fnc_sig = (@ptri64 @ptri64) -> (@i32)
+            .funcdef @body VERSION @body.v1 <@body_sig> {
+                %blk0(<@stack> %creator):
+                    SWAPSTACK %creator KILL_OLD PASS_VALUES<>()
+            }
+            .funcdef @test_fnc VERSION @test_fnc.v1 <@test_fnc_sig> {
+                %blk0(<@ptri64> %before <@ptri64> %after):
+                    %n0 = CCALL #DEFAULT <@backtrace_fp @backtrace_sig> @backtrace(%before @c64)
+                    %cs = COMMINST @uvm.current_stack
+                    %s = COMMINST @uvm.new_stack<[@body_sig]>(@body)
+                    SWAPSTACK %s RET_WITH<> PASS_VALUES<@stack>(%cs)
+                    %n1 = CCALL #DEFAULT <@backtrace_fp @backtrace_sig> @backtrace(%after @c64)
+                    %r = SUB <@i32> %n1 %n0
+                    RET %r
+            }
+        The body kills its own stack when it swaps back, so the test function has run code
+        on another stack (and was resumed) before it unwinds for the second time.
+        :type bldr: rpython.rlib.rmu.MuIRBuilder
+        :type rmu: rpython.rlib.rmu
+        """
+        i32 = bldr.gen_sym("@i32"); bldr.new_type_int(i32, 32)
+        i64 = bldr.gen_sym("@i64"); bldr.new_type_int(i64, 64)
+        stack = bldr.gen_sym("@stack"); bldr.new_type_stackref(stack)
+        ptri64 = bldr.gen_sym("@ptri64"); bldr.new_type_uptr(ptri64, i64)
+        c64 = bldr.gen_sym("@c64"); bldr.new_const_int(c64, i32, 64)
+        backtrace_sig = bldr.gen_sym("@backtrace_sig"); bldr.new_funcsig(backtrace_sig, [ptri64, i32], [i32])
+        backtrace_fp = bldr.gen_sym("@backtrace_fp"); bldr.new_type_ufuncptr(backtrace_fp, backtrace_sig)
+        backtrace = bldr.gen_sym("@backtrace"); bldr.new_const_extern(backtrace, backtrace_fp, "backtrace")
+        body_sig = bldr.gen_sym("@body_sig"); bldr.new_funcsig(body_sig, [stack], [])
+        test_fnc_sig = bldr.gen_sym("@test_fnc_sig"); bldr.new_funcsig(test_fnc_sig, [ptri64, ptri64], [i32])
+
+        body = bldr.gen_sym("@body"); bldr.new_func(body, body_sig)
+        body_v1 = bldr.gen_sym("@body.v1")
+        blk0 = bldr.gen_sym("@body.v1.blk0")
+        creator = bldr.gen_sym("@body.v1.blk0.creator")
+        csc = bldr.gen_sym(); bldr.new_csc_kill_old(csc)
+        nsc = bldr.gen_sym(); bldr.new_nsc_pass_values(nsc, [], [])
+        op_swap = bldr.gen_sym(); bldr.new_swapstack(op_swap, [], creator, csc, nsc)
+        bldr.new_bb(blk0, [creator], [stack], rmu.MU_NO_ID, [op_swap])
+        bldr.new_func_ver(body_v1, body, [blk0])
+
+        test_fnc = bldr.gen_sym("@test_fnc"); bldr.new_func(test_fnc, test_fnc_sig)
+        test_fnc_v1 = bldr.gen_sym("@test_fnc.v1")
+        blk0 = bldr.gen_sym("@test_fnc.v1.blk0")
+        before = bldr.gen_sym("@test_fnc.v1.blk0.before")
+        after = bldr.gen_sym("@test_fnc.v1.blk0.after")
+        n0 = bldr.gen_sym("@test_fnc.v1.blk0.n0")
+        cs = bldr.gen_sym("@test_fnc.v1.blk0.cs")
+        s = bldr.gen_sym("@test_fnc.v1.blk0.s")
+        n1 = bldr.gen_sym("@test_fnc.v1.blk0.n1")
+        r = bldr.gen_sym("@test_fnc.v1.blk0.r")
+        op_bt0 = bldr.gen_sym(); bldr.new_ccall(op_bt0, [n0], rmu.MuCallConv.DEFAULT, backtrace_fp, backtrace_sig, backtrace, [before, c64])
+        op_cs = bldr.gen_sym(); bldr.new_comminst(op_cs, [cs], rmu.MuCommInst.CURRENT_STACK, [], [], [], [])
+        op_ns = bldr.gen_sym(); bldr.new_comminst(op_ns, [s], rmu.MuCommInst.NEW_STACK, [], [], [body_sig], [body])
+        csc = bldr.gen_sym(); bldr.new_csc_ret_with(csc, [])
+        nsc = bldr.gen_sym(); bldr.new_nsc_pass_values(nsc, [stack], [cs])
+        op_swap = bldr.gen_sym(); bldr.new_swapstack(op_swap, [], s, csc, nsc)
+        op_bt1 = bldr.gen_sym(); bldr.new_ccall(op_bt1, [n1], rmu.MuCallConv.DEFAULT, backtrace_fp, backtrace_sig, backtrace, [after, c64])
+        op_sub = bldr.gen_sym(); bldr.new_binop(op_sub, r, rmu.MuBinOptr.SUB, i32, n1, n0)
+        op_ret = bldr.gen_sym(); bldr.new_ret(op_ret, [r])
+        bldr.new_bb(blk0, [before, after], [ptri64, ptri64], rmu.MU_NO_ID,
+                    [op_bt0, op_cs, op_ns, op_swap, op_bt1, op_sub, op_ret])
+        bldr.new_func_ver(test_fnc_v1, test_fnc, [blk0])
+
+        return {
+            "test_fnc": test_fnc,
+            "test_fnc_sig": test_fnc_sig,
+            "@body": body,
+        }
+
+    (fnp, _), (mu, ctx, bldr) = fncptr_from_py_script(build_test_bundle, None, 'test_fnc',
+                                                      [ctypes.c_void_p, ctypes.c_void_p],
+                                                      ctypes.c_int32)
+
+    mu.current_thread_as_mu_thread(rmu.null(rmu.MuCPtr))
+
+    # the unwinder walks through the test function to its callers in the same way before
+    # and after the stack switches
+    before = (ctypes.c_void_p * 64)()
+    after = (ctypes.c_void_p * 64)()
+    assert fnp(ctypes.cast(before, ctypes.c_void_p), ctypes.cast(after, ctypes.c_void_p)) == 0
+    n = len([addr for addr in before if addr])
+    assert n > 2
+    # (the first address is the return address of each backtrace() call)
+    assert list(after[1:n]) == list(before[1:n])